@@ -10,6 +10,25 @@ pub const SIZE_4KB: u64 = 0x00001000u64;
 pub const LOCAL_APIC_MODE_XAPIC: u64 = 0x1;
 pub const LOCAL_APIC_MODE_X2APIC: u64 = 0x2;
 
+// Offsets of the local APIC registers in the xAPIC page, the x2APIC MSR of a
+// register is 0x800 + offset / 16.
+pub const LOCAL_APIC_ID: u32 = 0x20;
+pub const LOCAL_APIC_EOI: u32 = 0xB0;
+pub const LOCAL_APIC_SIVR: u32 = 0xF0;
+pub const LOCAL_APIC_ICR_LOW: u32 = 0x300;
+pub const LOCAL_APIC_ICR_HIGH: u32 = 0x310;
+pub const LOCAL_APIC_LVT_TIMER: u32 = 0x320;
+pub const LOCAL_APIC_TIMER_INIT_COUNT: u32 = 0x380;
+pub const LOCAL_APIC_TIMER_CURRENT_COUNT: u32 = 0x390;
+pub const LOCAL_APIC_TIMER_DIVIDE: u32 = 0x3E0;
+
+const LOCAL_APIC_DEFAULT_BASE: u64 = 0xFEE0_0000;
+const LOCAL_APIC_BASE_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+const ICR_DELIVERY_STATUS_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
 pub fn local_apic_base_address_msr_supported() -> bool {
     let res = x86::cpuid::cpuid!(1u32);
     let res: u32 = res.eax.bit_range(11, 8);
@@ -37,6 +56,106 @@ pub fn get_apic_mode() -> u64 {
 }
 
 
+fn local_apic_base() -> u64 {
+    if local_apic_base_address_msr_supported() {
+        unsafe { msr::rdmsr(msr::IA32_APIC_BASE) & LOCAL_APIC_BASE_MASK }
+    } else {
+        LOCAL_APIC_DEFAULT_BASE
+    }
+}
+
+/// Read a local APIC register, in xAPIC or x2APIC mode.
+pub fn read_local_apic(offset: u32) -> u32 {
+    if get_apic_mode() == LOCAL_APIC_MODE_X2APIC {
+        unsafe { msr::rdmsr(X2APIC_MSR_BASE + offset / 16) as u32 }
+    } else {
+        unsafe { core::ptr::read_volatile((local_apic_base() + offset as u64) as *const u32) }
+    }
+}
+
+/// Write a local APIC register, in xAPIC or x2APIC mode.
+pub fn write_local_apic(offset: u32, value: u32) {
+    if get_apic_mode() == LOCAL_APIC_MODE_X2APIC {
+        unsafe { msr::wrmsr(X2APIC_MSR_BASE + offset / 16, value as u64) }
+    } else {
+        unsafe {
+            core::ptr::write_volatile((local_apic_base() + offset as u64) as *mut u32, value)
+        }
+    }
+}
+
+/// The ID of the local APIC, 8 bits in xAPIC mode and 32 bits in x2APIC mode.
+pub fn get_apic_id() -> u32 {
+    let id = read_local_apic(LOCAL_APIC_ID);
+    if get_apic_mode() == LOCAL_APIC_MODE_X2APIC {
+        id
+    } else {
+        id >> 24
+    }
+}
+
+/// IPI delivery modes.
+#[derive(Debug, Copy, Clone)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    Nmi = 0b100,
+    Init = 0b101,
+    Startup = 0b110,
+}
+
+/// IPI destinations, INIT and SIPI cannot be sent to self.
+#[derive(Debug, Copy, Clone)]
+pub enum IpiDestination {
+    /// The local APIC of the ID, physical destination mode.
+    Apic(u32),
+    SelfOnly,
+    AllIncludingSelf,
+    AllExcludingSelf,
+}
+
+pub fn send_ipi(destination: IpiDestination, delivery_mode: DeliveryMode, vector: u8) {
+    let (dest, shorthand) = match destination {
+        IpiDestination::Apic(id) => (id, 0b00u8),
+        IpiDestination::SelfOnly => (0, 0b01),
+        IpiDestination::AllIncludingSelf => (0, 0b10),
+        IpiDestination::AllExcludingSelf => (0, 0b11),
+    };
+    let mut icr_low = 0u32;
+    icr_low.set_bit_range(7, 0, vector);
+    icr_low.set_bit_range(10, 8, delivery_mode as u8);
+    icr_low.set_bit_range(19, 18, shorthand);
+    icr_low |= ICR_LEVEL_ASSERT;
+
+    if get_apic_mode() == LOCAL_APIC_MODE_X2APIC {
+        //
+        // The ICR is a single MSR with the 32-bit destination in the high
+        // half, and the write is the send.
+        //
+        let icr = (dest as u64) << 32 | icr_low as u64;
+        unsafe { msr::wrmsr(X2APIC_MSR_BASE + LOCAL_APIC_ICR_LOW / 16, icr) };
+    } else {
+        write_local_apic(LOCAL_APIC_ICR_HIGH, dest << 24);
+        write_local_apic(LOCAL_APIC_ICR_LOW, icr_low);
+        while read_local_apic(LOCAL_APIC_ICR_LOW) & ICR_DELIVERY_STATUS_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub fn send_fixed_ipi(apic_id: u32, vector: u8) {
+    send_ipi(IpiDestination::Apic(apic_id), DeliveryMode::Fixed, vector);
+}
+
+pub fn send_init_ipi(apic_id: u32) {
+    send_ipi(IpiDestination::Apic(apic_id), DeliveryMode::Init, 0);
+}
+
+/// Start the processor at vector * 4 KiB, below 1 MiB.
+pub fn send_startup_ipi(apic_id: u32, vector: u8) {
+    send_ipi(IpiDestination::Apic(apic_id), DeliveryMode::Startup, vector);
+}
+
 pub fn set_apic_mode(mode: u64) {
     let current_mode = get_apic_mode();
     if current_mode == LOCAL_APIC_MODE_XAPIC && mode == LOCAL_APIC_MODE_X2APIC {
@@ -85,8 +204,8 @@ pub enum TimerDivide {
     Div64 = 0b1001,
     /// Divide by 128.
     Div128 = 0b1010,
-    /// Divide by 256.
-    Div256 = 0b1011,
+    /// Divide by 1.
+    Div1 = 0b1011,
 }
 
 /// Local APIC timer modes.
@@ -114,49 +233,44 @@ pub fn initialize_apic_timer(
     //
     initialize_local_apic_software_enable(true);
 
-    //
-    // Program init-count register.
-    //
-    unsafe {
-        msr::wrmsr(msr::IA32_X2APIC_INIT_COUNT, init_count as u64);
-    }
-
     //
     // Enable APIC timer interrupt with specified timer mode.
     //
-    unsafe {
-        let _div_register = msr::rdmsr(msr::IA32_X2APIC_DIV_CONF);
-        msr::wrmsr(msr::IA32_X2APIC_DIV_CONF, divide_value as u64);
+    write_local_apic(LOCAL_APIC_TIMER_DIVIDE, divide_value as u32);
 
-        let mut lvt_timer_register = msr::rdmsr(msr::IA32_X2APIC_LVT_TIMER);
+    let mut lvt_timer_register = read_local_apic(LOCAL_APIC_LVT_TIMER);
 
-        lvt_timer_register.set_bit_range(18, 17, periodic_mode as u8);
+    lvt_timer_register.set_bit_range(18, 17, periodic_mode as u8);
 
-        lvt_timer_register.set_bit_range(7, 0, vector);
+    lvt_timer_register.set_bit(16, false);
 
-        msr::wrmsr(msr::IA32_X2APIC_LVT_TIMER, lvt_timer_register);
-    }
+    lvt_timer_register.set_bit_range(7, 0, vector);
+
+    write_local_apic(LOCAL_APIC_LVT_TIMER, lvt_timer_register);
+
+    //
+    // Program init-count register, which starts the timer.
+    //
+    write_local_apic(LOCAL_APIC_TIMER_INIT_COUNT, init_count);
 }
 
 
 pub fn disable_apic_timer_interrupt() {
-    unsafe {
-        let mut lvt_timer_register = msr::rdmsr(msr::IA32_X2APIC_LVT_TIMER);
-        lvt_timer_register.set_bit(16, true);
+    let mut lvt_timer_register = read_local_apic(LOCAL_APIC_LVT_TIMER);
+    lvt_timer_register.set_bit(16, true);
 
-        msr::wrmsr(msr::IA32_X2APIC_LVT_TIMER, lvt_timer_register);
-    }
+    write_local_apic(LOCAL_APIC_LVT_TIMER, lvt_timer_register);
 }
 
 fn initialize_local_apic_software_enable(b: bool) {
-    let mut srv = unsafe { msr::rdmsr(msr::IA32_X2APIC_SIVR) };
+    let mut srv = read_local_apic(LOCAL_APIC_SIVR);
     if b {
         if !srv.bit(8) {
             srv.set_bit(8, true);
-            unsafe { msr::wrmsr(msr::IA32_X2APIC_SIVR, srv) }
+            write_local_apic(LOCAL_APIC_SIVR, srv)
         }
     } else if srv.bit(8) {
         srv.set_bit(8, false);
-        unsafe { msr::wrmsr(msr::IA32_X2APIC_SIVR, srv) }
+        write_local_apic(LOCAL_APIC_SIVR, srv)
     }
 }
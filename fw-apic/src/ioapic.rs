@@ -0,0 +1,309 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! IO-APIC redirection of the GSIs, with the IO-APIC and the ISA IRQ
+//! overrides found in the MADT.
+//!
+
+pub const IO_APIC_DEFAULT_BASE: u64 = 0xFEC0_0000;
+
+// The registers are reached through an index and a data window
+const IO_APIC_INDEX: u64 = 0x00;
+const IO_APIC_DATA: u64 = 0x10;
+
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_MASKED: u64 = 1 << 16;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_DESTINATION_SHIFT: u64 = 56;
+
+// MADT: the ACPI header, the local APIC address and the flags, then the
+// interrupt controller structures { type: u8, length: u8, ... }
+const MADT_SIGNATURE: &[u8] = b"APIC";
+const MADT_HEADER_SIZE: usize = 44;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+
+// MPS INTI flags of an override
+const MPS_INTI_POLARITY_MASK: u16 = 0b11;
+const MPS_INTI_ACTIVE_LOW: u16 = 0b11;
+const MPS_INTI_TRIGGER_MASK: u16 = 0b1100;
+const MPS_INTI_LEVEL: u16 = 0b1100;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IoApic {
+    base: u64,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    /// The IO-APIC at base, its first input is gsi_base.
+    pub fn new(base: u64, gsi_base: u32) -> Self {
+        Self { base, gsi_base }
+    }
+
+    /// The first IO-APIC of the MADT, or the one at the default base.
+    pub fn from_madt(madt: Option<&[u8]>) -> Self {
+        madt.and_then(madt_io_apic)
+            .map_or(Self::new(IO_APIC_DEFAULT_BASE, 0), |(base, gsi_base)| {
+                Self::new(base, gsi_base)
+            })
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.base + IO_APIC_INDEX) as *mut u32, register);
+            core::ptr::read_volatile((self.base + IO_APIC_DATA) as *const u32)
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base + IO_APIC_INDEX) as *mut u32, register);
+            core::ptr::write_volatile((self.base + IO_APIC_DATA) as *mut u32, value);
+        }
+    }
+
+    /// The count of inputs.
+    pub fn inputs(&self) -> u32 {
+        ((self.read(IO_APIC_VERSION) >> 16) & 0xFF) + 1
+    }
+
+    fn input(&self, gsi: u32) -> Option<u32> {
+        let input = gsi.checked_sub(self.gsi_base)?;
+        if input < self.inputs() {
+            Some(input)
+        } else {
+            None
+        }
+    }
+
+    fn read_redirection(&self, input: u32) -> u64 {
+        let register = IO_APIC_REDIRECTION_TABLE + input * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    fn write_redirection(&self, input: u32, entry: u64) {
+        let register = IO_APIC_REDIRECTION_TABLE + input * 2;
+        // masked while the entry is half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    ///
+    /// Deliver the GSI at the vector to the local APIC of ID dest, fixed and
+    /// physical, and unmask it. Return false if the GSI is not an input of
+    /// this IO-APIC, or dest does not fit the 8 bits of the destination.
+    ///
+    pub fn route_irq(
+        &self,
+        gsi: u32,
+        vector: u8,
+        dest: u32,
+        trigger: Trigger,
+        polarity: Polarity,
+    ) -> bool {
+        let input = match self.input(gsi) {
+            Some(input) => input,
+            None => return false,
+        };
+        if dest > 0xFF {
+            return false;
+        }
+        let mut entry = vector as u64 | (dest as u64) << REDIRECTION_DESTINATION_SHIFT;
+        if trigger == Trigger::Level {
+            entry |= REDIRECTION_LEVEL;
+        }
+        if polarity == Polarity::ActiveLow {
+            entry |= REDIRECTION_ACTIVE_LOW;
+        }
+        self.write_redirection(input, entry);
+        true
+    }
+
+    /// Mask or unmask the GSI, return false if it is not an input.
+    pub fn set_irq_mask(&self, gsi: u32, masked: bool) -> bool {
+        let input = match self.input(gsi) {
+            Some(input) => input,
+            None => return false,
+        };
+        let entry = self.read_redirection(input);
+        let entry = if masked {
+            entry | REDIRECTION_MASKED
+        } else {
+            entry & !REDIRECTION_MASKED
+        };
+        self.write_redirection(input, entry);
+        true
+    }
+
+    pub fn mask_all(&self) {
+        for input in 0..self.inputs() {
+            self.write_redirection(input, REDIRECTION_MASKED);
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let mut value = [0u8; 2];
+    value.copy_from_slice(bytes.get(offset..offset + 2)?);
+    Some(u16::from_le_bytes(value))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+// The interrupt controller structures of the MADT, a structure too short for
+// its header ends them
+fn madt_entries(madt: &[u8]) -> Option<impl Iterator<Item = &[u8]>> {
+    if madt.get(0..4)? != MADT_SIGNATURE {
+        return None;
+    }
+    let length = (read_u32(madt, 4)? as usize).min(madt.len());
+    let mut entries = madt.get(MADT_HEADER_SIZE..length)?;
+    Some(core::iter::from_fn(move || {
+        let entry_length = *entries.get(1)? as usize;
+        if entry_length < 2 || entry_length > entries.len() {
+            return None;
+        }
+        let (entry, rest) = entries.split_at(entry_length);
+        entries = rest;
+        Some(entry)
+    }))
+}
+
+/// The address and the GSI base of the first IO-APIC of the MADT.
+pub fn madt_io_apic(madt: &[u8]) -> Option<(u64, u32)> {
+    madt_entries(madt)?
+        .filter(|entry| entry[0] == MADT_IO_APIC)
+        .find_map(|entry| Some((read_u32(entry, 4)? as u64, read_u32(entry, 8)?)))
+}
+
+///
+/// The GSI, trigger and polarity of an ISA IRQ. Without an override of the
+/// MADT, the IRQ is the GSI, edge triggered and active high.
+///
+pub fn madt_isa_irq(madt: Option<&[u8]>, irq: u8) -> (u32, Trigger, Polarity) {
+    let isa = (irq as u32, Trigger::Edge, Polarity::ActiveHigh);
+    let entries = match madt.and_then(madt_entries) {
+        Some(entries) => entries,
+        None => return isa,
+    };
+    // { type, length, bus, source: u8, gsi: u32, flags: u16 }
+    let overrides = entries
+        .filter(|entry| entry[0] == MADT_INTERRUPT_SOURCE_OVERRIDE)
+        .filter_map(|entry| Some((*entry.get(3)?, read_u32(entry, 4)?, read_u16(entry, 8)?)));
+    for (source, gsi, flags) in overrides {
+        if source != irq {
+            continue;
+        }
+        let trigger = if flags & MPS_INTI_TRIGGER_MASK == MPS_INTI_LEVEL {
+            Trigger::Level
+        } else {
+            Trigger::Edge
+        };
+        let polarity = if flags & MPS_INTI_POLARITY_MASK == MPS_INTI_ACTIVE_LOW {
+            Polarity::ActiveLow
+        } else {
+            Polarity::ActiveHigh
+        };
+        return (gsi, trigger, polarity);
+    }
+    isa
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The MADT of QEMU q35 with one CPU: a local APIC, the IO-APIC, the
+    // overrides of IRQ 0, 5, 9, 10 and 11, and a local APIC NMI
+    fn q35_madt() -> [u8; 120] {
+        let mut madt = [0u8; 120];
+        madt[0..4].copy_from_slice(b"APIC");
+        madt[4..8].copy_from_slice(&120u32.to_le_bytes());
+        madt[36..40].copy_from_slice(&0xFEE0_0000u32.to_le_bytes());
+        madt[40..44].copy_from_slice(&1u32.to_le_bytes());
+        let entries: [&[u8]; 8] = [
+            &[0, 8, 0, 0, 1, 0, 0, 0],
+            &[1, 12, 0, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0],
+            &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0],
+            &[2, 10, 0, 5, 5, 0, 0, 0, 0x0D, 0],
+            &[2, 10, 0, 9, 9, 0, 0, 0, 0x0D, 0],
+            &[2, 10, 0, 10, 10, 0, 0, 0, 0x0D, 0],
+            &[2, 10, 0, 11, 11, 0, 0, 0, 0x0D, 0],
+            &[4, 6, 0xFF, 0, 0, 1],
+        ];
+        let mut offset = MADT_HEADER_SIZE;
+        for entry in entries.iter() {
+            madt[offset..offset + entry.len()].copy_from_slice(entry);
+            offset += entry.len();
+        }
+        madt
+    }
+
+    #[test]
+    fn test_madt_io_apic() {
+        let madt = q35_madt();
+        assert_eq!(madt_io_apic(&madt), Some((0xFEC0_0000, 0)));
+        assert_eq!(
+            IoApic::from_madt(Some(&madt)),
+            IoApic::new(IO_APIC_DEFAULT_BASE, 0)
+        );
+        assert_eq!(madt_io_apic(&madt[..60]), None);
+        assert_eq!(madt_io_apic(b"FACP"), None);
+    }
+
+    #[test]
+    fn test_madt_isa_irq() {
+        let madt = q35_madt();
+        assert_eq!(
+            madt_isa_irq(Some(&madt), 0),
+            (2, Trigger::Edge, Polarity::ActiveHigh)
+        );
+        assert_eq!(
+            madt_isa_irq(Some(&madt), 9),
+            (9, Trigger::Level, Polarity::ActiveHigh)
+        );
+        assert_eq!(
+            madt_isa_irq(Some(&madt), 4),
+            (4, Trigger::Edge, Polarity::ActiveHigh)
+        );
+        assert_eq!(
+            madt_isa_irq(None, 1),
+            (1, Trigger::Edge, Polarity::ActiveHigh)
+        );
+    }
+
+    #[test]
+    fn test_madt_truncated() {
+        let mut madt = q35_madt();
+        // an entry of length 0 ends the walk
+        madt[MADT_HEADER_SIZE + 1] = 0;
+        assert_eq!(madt_io_apic(&madt), None);
+    }
+}
@@ -6,3 +6,9 @@
 
 mod apic;
 pub use apic::*;
+mod timer;
+pub use timer::*;
+mod ioapic;
+pub use ioapic::*;
+mod pic;
+pub use pic::*;
@@ -0,0 +1,97 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! The legacy 8259 pair. Its vectors after reset are those of the exceptions,
+//! so it is remapped, or masked when the IO-APIC delivers the IRQs.
+//!
+
+const PIC_MASTER_COMMAND_PORT: u16 = 0x20;
+const PIC_MASTER_DATA_PORT: u16 = 0x21;
+const PIC_SLAVE_COMMAND_PORT: u16 = 0xA0;
+const PIC_SLAVE_DATA_PORT: u16 = 0xA1;
+
+// ICW1: initialization, ICW4 follows
+const ICW1_INIT: u8 = 0x11;
+// ICW3: the slave is on IRQ 2 of the master
+const ICW3_MASTER: u8 = 0x04;
+const ICW3_SLAVE: u8 = 0x02;
+// ICW4: 8086 mode
+const ICW4_8086: u8 = 0x01;
+
+// A write to the POST port takes long enough for the 8259 between the ICWs
+fn io_wait() {
+    unsafe { x86::io::outb(0x80, 0) };
+}
+
+// Send the ICWs, which set the vector base of the IRQs
+fn initialize(command_port: u16, data_port: u16, vector: u8, icw3: u8) {
+    for (port, value) in [
+        (command_port, ICW1_INIT),
+        (data_port, vector),
+        (data_port, icw3),
+        (data_port, ICW4_8086),
+    ]
+    .iter()
+    {
+        unsafe { x86::io::outb(*port, *value) };
+        io_wait();
+    }
+}
+
+///
+/// Deliver IRQ 0-7 at master_vector and IRQ 8-15 at slave_vector, both
+/// multiples of 8. The masks are kept.
+///
+pub fn remap_pic(master_vector: u8, slave_vector: u8) {
+    let master_mask = unsafe { x86::io::inb(PIC_MASTER_DATA_PORT) };
+    let slave_mask = unsafe { x86::io::inb(PIC_SLAVE_DATA_PORT) };
+
+    initialize(
+        PIC_MASTER_COMMAND_PORT,
+        PIC_MASTER_DATA_PORT,
+        master_vector,
+        ICW3_MASTER,
+    );
+    initialize(
+        PIC_SLAVE_COMMAND_PORT,
+        PIC_SLAVE_DATA_PORT,
+        slave_vector,
+        ICW3_SLAVE,
+    );
+
+    unsafe {
+        x86::io::outb(PIC_MASTER_DATA_PORT, master_mask);
+        x86::io::outb(PIC_SLAVE_DATA_PORT, slave_mask);
+    }
+}
+
+/// Mask IRQ 0-15.
+pub fn mask_pic() {
+    unsafe {
+        x86::io::outb(PIC_MASTER_DATA_PORT, 0xFF);
+        x86::io::outb(PIC_SLAVE_DATA_PORT, 0xFF);
+    }
+}
+
+///
+/// Mask or unmask IRQ 0-15. An IRQ of the slave also needs IRQ 2 of the
+/// master unmasked.
+///
+pub fn set_pic_irq_mask(irq: u8, masked: bool) {
+    let (port, bit) = match irq {
+        0..=7 => (PIC_MASTER_DATA_PORT, irq),
+        8..=15 => (PIC_SLAVE_DATA_PORT, irq - 8),
+        _ => return,
+    };
+    unsafe {
+        let mask = x86::io::inb(port);
+        let mask = if masked {
+            mask | (1 << bit)
+        } else {
+            mask & !(1 << bit)
+        };
+        x86::io::outb(port, mask);
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Calibration of the TSC and of the local APIC timer against the HPET, the
+//! ACPI PM timer or the PIT, and a periodic tick from the local APIC timer.
+//!
+//! The time is read from a clock source: the TSC, the HPET or the ACPI PM
+//! timer work with interrupts disabled, the APIC timer needs the tick.
+//!
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use fw_exception::{Action, Handler, InterruptStack};
+use fw_hpet::Hpet;
+
+use crate::apic::*;
+
+/// The ACPI PM timer counts at 3.579545 MHz.
+pub const ACPI_PM_TIMER_FREQUENCY: u64 = 3_579_545;
+/// The 8254 PIT counts at 1.193182 MHz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+// The PM timer is 24 bits wide, or 32 bits with TMR_VAL_EXT
+const ACPI_PM_TIMER_MASK: u32 = 0x00FF_FFFF;
+
+const PIT_CHANNEL2_PORT: u16 = 0x42;
+const PIT_COMMAND_PORT: u16 = 0x43;
+// NMI status and control: bit 0 the gate of channel 2, bit 1 the speaker,
+// bit 5 the output of channel 2
+const PIT_CONTROL_PORT: u16 = 0x61;
+// channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+const PIT_CHANNEL2_ONE_SHOT: u8 = 0b1011_0000;
+
+// Long enough for a precise count, short enough for the 16 bits of the PIT
+const CALIBRATION_US: u64 = 10_000;
+
+const LVT_TIMER_MASKED: u32 = 1 << 16;
+
+const NS_PER_SECOND: u64 = 1_000_000_000;
+const US_PER_SECOND: u64 = 1_000_000;
+
+const MAX_CALLBACKS: usize = 8;
+
+/// The timer the TSC and the APIC timer are calibrated against.
+#[derive(Debug, Copy, Clone)]
+pub enum ReferenceTimer {
+    /// The ACPI PM timer at its I/O port, PM_TMR_BLK in the FADT.
+    AcpiPm(u16),
+    /// Channel 2 of the 8254 PIT.
+    Pit,
+    Hpet(Hpet),
+}
+
+impl ReferenceTimer {
+    // Busy wait, at most 54 ms with the PIT
+    fn wait_us(&self, us: u64) {
+        match *self {
+            ReferenceTimer::AcpiPm(port) => {
+                let ticks = ACPI_PM_TIMER_FREQUENCY * us / US_PER_SECOND;
+                let start = unsafe { x86::io::inl(port) };
+                let elapsed = || unsafe { x86::io::inl(port) }.wrapping_sub(start);
+                while u64::from(elapsed() & ACPI_PM_TIMER_MASK) < ticks {}
+            }
+            ReferenceTimer::Hpet(hpet) => {
+                hpet.enable();
+                let ticks = fw_hpet::ns_to_ticks(us * 1000, hpet.period_fs());
+                let start = hpet.counter();
+                let elapsed = || hpet.counter().wrapping_sub(start) & hpet.counter_mask();
+                while elapsed() < ticks {}
+            }
+            ReferenceTimer::Pit => {
+                let count = PIT_FREQUENCY * us / US_PER_SECOND;
+                unsafe {
+                    // gate on and speaker off, the output rises at the end of the count
+                    let control = x86::io::inb(PIT_CONTROL_PORT);
+                    x86::io::outb(PIT_CONTROL_PORT, (control & !0x02) | 0x01);
+                    x86::io::outb(PIT_COMMAND_PORT, PIT_CHANNEL2_ONE_SHOT);
+                    x86::io::outb(PIT_CHANNEL2_PORT, count as u8);
+                    x86::io::outb(PIT_CHANNEL2_PORT, (count >> 8) as u8);
+                    while x86::io::inb(PIT_CONTROL_PORT) & 0x20 == 0 {}
+                    x86::io::outb(PIT_CONTROL_PORT, control);
+                }
+            }
+        }
+    }
+}
+
+///
+/// Whether the ACPI PM timer at the port counts, it does not before the ACPI
+/// I/O space is enabled.
+///
+pub fn acpi_pm_timer_running(port: u16) -> bool {
+    let first = unsafe { x86::io::inl(port) };
+    // nothing decodes the port
+    if first == 0xFFFF_FFFF {
+        return false;
+    }
+    // a read takes longer than a tick
+    (0..1000).any(|_| unsafe { x86::io::inl(port) } != first)
+}
+
+/// Whether the TSC runs at a constant rate in all power states.
+pub fn invariant_tsc() -> bool {
+    let max_extended = x86::cpuid::cpuid!(0x8000_0000u32).eax;
+    max_extended >= 0x8000_0007 && x86::cpuid::cpuid!(0x8000_0007u32).edx & (1 << 8) != 0
+}
+
+fn rdtsc() -> u64 {
+    unsafe { x86::time::rdtsc() }
+}
+
+/// The frequencies in Hz of the TSC and of the APIC timer divided by 1.
+#[derive(Debug, Copy, Clone)]
+pub struct Frequencies {
+    pub tsc: u64,
+    pub apic_timer: u64,
+}
+
+///
+/// Count the TSC and the APIC timer during a wait on the reference timer.
+/// The APIC timer is stopped afterwards.
+///
+pub fn calibrate(reference: ReferenceTimer) -> Frequencies {
+    write_local_apic(LOCAL_APIC_TIMER_DIVIDE, TimerDivide::Div1 as u32);
+    write_local_apic(LOCAL_APIC_LVT_TIMER, LVT_TIMER_MASKED);
+    write_local_apic(LOCAL_APIC_TIMER_INIT_COUNT, u32::MAX);
+    let tsc_start = rdtsc();
+
+    reference.wait_us(CALIBRATION_US);
+
+    let apic_timer = u32::MAX - read_local_apic(LOCAL_APIC_TIMER_CURRENT_COUNT);
+    let tsc = rdtsc() - tsc_start;
+    write_local_apic(LOCAL_APIC_TIMER_INIT_COUNT, 0);
+
+    Frequencies {
+        tsc: tsc * US_PER_SECOND / CALIBRATION_US,
+        apic_timer: apic_timer as u64 * US_PER_SECOND / CALIBRATION_US,
+    }
+}
+
+/// What the time of a timer is read from.
+#[derive(Debug, Copy, Clone)]
+pub enum ClockSource {
+    Tsc,
+    /// The ticks and the count of the current period, the time stands
+    /// still until the tick starts and while interrupts are disabled.
+    ApicTimer,
+    /// The HPET, a 32-bit main counter is extended like the ACPI PM timer.
+    Hpet(Hpet),
+    /// The ACPI PM timer at its I/O port. Its 24 bits wrap every 4.7 s, it
+    /// must be read more often, as the tick does.
+    AcpiPm(u16),
+}
+
+/// Called on every tick with the time in ns.
+pub type TickCallback = fn(context: usize, now_ns: u64);
+
+///
+/// The time since its creation and a periodic tick running the callbacks.
+///
+/// The tick handler finds the timer with the handler context of its vector,
+/// so the timer must stay in place while it runs.
+///
+pub struct Timer {
+    frequencies: Frequencies,
+    source: ClockSource,
+    // the count of the source at creation
+    start: u64,
+    // the last count read, which extends the 24 bits of the ACPI PM timer
+    // and a 32-bit HPET, and keeps the APIC timer count from going back
+    // between the reload and the tick
+    last_count: AtomicU64,
+    // the APIC timer count of a tick
+    period_count: u32,
+    vector: u8,
+    ticks: AtomicU64,
+    callbacks: [Option<(TickCallback, usize)>; MAX_CALLBACKS],
+}
+
+impl Timer {
+    pub fn new(frequencies: Frequencies, source: ClockSource) -> Self {
+        if let ClockSource::Hpet(hpet) = source {
+            hpet.enable();
+        }
+        let last_count = match source {
+            ClockSource::AcpiPm(port) => {
+                u64::from(unsafe { x86::io::inl(port) } & ACPI_PM_TIMER_MASK)
+            }
+            ClockSource::Hpet(hpet) => hpet.counter(),
+            _ => 0,
+        };
+        let mut timer = Self {
+            frequencies,
+            source,
+            start: 0,
+            last_count: AtomicU64::new(last_count),
+            period_count: 0,
+            vector: 0,
+            ticks: AtomicU64::new(0),
+            callbacks: [None; MAX_CALLBACKS],
+        };
+        timer.start = timer.count();
+        timer
+    }
+
+    pub fn frequencies(&self) -> Frequencies {
+        self.frequencies
+    }
+
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    // The frequency in Hz of the count of the source
+    fn frequency(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc => self.frequencies.tsc,
+            ClockSource::ApicTimer => self.frequencies.apic_timer,
+            ClockSource::Hpet(hpet) => hpet.frequency(),
+            ClockSource::AcpiPm(_) => ACPI_PM_TIMER_FREQUENCY,
+        }
+    }
+
+    // The monotonic count of the source
+    fn count(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc => rdtsc(),
+            ClockSource::ApicTimer => loop {
+                // a tick between the reads makes the count of no use
+                let ticks = self.ticks();
+                let current = read_local_apic(LOCAL_APIC_TIMER_CURRENT_COUNT);
+                if ticks == self.ticks() {
+                    let period = self.period_count as u64;
+                    let count = ticks * period + (period - (current as u64).min(period));
+                    break count.max(self.last_count.fetch_max(count, Ordering::Relaxed));
+                }
+            },
+            ClockSource::Hpet(hpet) if hpet.counter_mask() == u64::MAX => hpet.counter(),
+            ClockSource::Hpet(hpet) => {
+                extend_count(&self.last_count, hpet.counter(), hpet.counter_mask())
+            }
+            ClockSource::AcpiPm(port) => {
+                let current = unsafe { x86::io::inl(port) } & ACPI_PM_TIMER_MASK;
+                extend_count(
+                    &self.last_count,
+                    u64::from(current),
+                    u64::from(ACPI_PM_TIMER_MASK),
+                )
+            }
+        }
+    }
+
+    /// The time since the creation of the timer.
+    pub fn now_ns(&self) -> u64 {
+        let elapsed = self.count().saturating_sub(self.start);
+        (elapsed as u128 * NS_PER_SECOND as u128 / self.frequency() as u128) as u64
+    }
+
+    pub fn delay_us(&self, us: u64) {
+        let end = self.now_ns().saturating_add(us.saturating_mul(1000));
+        while self.now_ns() < end {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// The count of ticks since start.
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    /// Run the callback on every tick, return false if all slots are taken.
+    pub fn register_callback(&mut self, callback: TickCallback, context: usize) -> bool {
+        match self.callbacks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((callback, context));
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///
+    /// Tick every period through the vector of the IDT in use, return false
+    /// if the IDT is not one of fw-exception.
+    ///
+    /// # Safety
+    ///
+    /// The timer must stay in place until it is stopped, and the callbacks
+    /// must be registered before it starts.
+    ///
+    pub unsafe fn start(&mut self, vector: u8, period_us: u64) -> bool {
+        let context = self as *mut Self as usize;
+        if !fw_exception::register_handler(vector, tick_handler as Handler)
+            || !fw_exception::set_handler_context(vector, context)
+        {
+            return false;
+        }
+        self.vector = vector;
+        let count = self.frequencies.apic_timer * period_us / US_PER_SECOND;
+        self.period_count = count.max(1).min(u32::MAX as u64) as u32;
+        initialize_apic_timer(
+            TimerDivide::Div1,
+            self.period_count,
+            TimerMode::Periodic,
+            vector,
+        );
+        true
+    }
+
+    pub fn stop(&mut self) {
+        disable_apic_timer_interrupt();
+        write_local_apic(LOCAL_APIC_TIMER_INIT_COUNT, 0);
+        fw_exception::unregister_handler(self.vector);
+        fw_exception::set_handler_context(self.vector, 0);
+    }
+
+    fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        let now = self.now_ns();
+        for (callback, context) in self.callbacks.iter().flatten() {
+            callback(*context, now);
+        }
+    }
+}
+
+///
+/// Extend the current count of a counter of the bits of mask past the last
+/// one, it must be read before it wraps twice.
+///
+fn extend_count(last_count: &AtomicU64, current: u64, mask: u64) -> u64 {
+    let last = last_count.load(Ordering::Relaxed);
+    let count = last + (current.wrapping_sub(last) & mask);
+    // the tick may have extended it meanwhile
+    count.max(last_count.fetch_max(count, Ordering::Relaxed))
+}
+
+fn tick_handler(stack: &mut InterruptStack) -> Action {
+    let timer = fw_exception::handler_context(stack.vector as u8) as *const Timer;
+    if !timer.is_null() {
+        unsafe { (*timer).tick() };
+    }
+    fw_exception::local_apic_eoi();
+    Action::Resume
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extend_count() {
+        let mask = u64::from(u32::MAX);
+        let last_count = AtomicU64::new(0xFFFF_FFF0);
+        assert_eq!(extend_count(&last_count, 0xFFFF_FFF8, mask), 0xFFFF_FFF8);
+        // wrapped
+        assert_eq!(extend_count(&last_count, 0x10, mask), 0x1_0000_0010);
+        assert_eq!(last_count.load(Ordering::Relaxed), 0x1_0000_0010);
+        assert_eq!(extend_count(&last_count, 0x8000_0000, mask), 0x1_8000_0000);
+        assert_eq!(extend_count(&last_count, 0x5, mask), 0x2_0000_0005);
+
+        // the 24 bits of the ACPI PM timer
+        let mask = u64::from(ACPI_PM_TIMER_MASK);
+        let last_count = AtomicU64::new(0xFF_FFFF);
+        assert_eq!(extend_count(&last_count, 0x1, mask), 0x100_0001);
+        assert_eq!(extend_count(&last_count, 0x1, mask), 0x100_0001);
+        assert_eq!(extend_count(&last_count, 0xFF_FFFF, mask), 0x1FF_FFFF);
+    }
+}
@@ -0,0 +1,76 @@
+# Copyright (c) 2021 Intel Corporation
+# SPDX-License-Identifier: BSD-2-Clause-Patent
+
+.section .text
+
+#  read_dr_call (
+#        IN UINT64 index     0-3, 6 or 7
+# )
+.global read_dr_call
+read_dr_call:
+    cmp     $0, %rcx
+    je      0f
+    cmp     $1, %rcx
+    je      1f
+    cmp     $2, %rcx
+    je      2f
+    cmp     $3, %rcx
+    je      3f
+    cmp     $6, %rcx
+    je      6f
+    mov     %dr7, %rax
+    ret
+0:
+    mov     %dr0, %rax
+    ret
+1:
+    mov     %dr1, %rax
+    ret
+2:
+    mov     %dr2, %rax
+    ret
+3:
+    mov     %dr3, %rax
+    ret
+6:
+    mov     %dr6, %rax
+    ret
+
+#  write_dr_call (
+#        IN UINT64 index     0-3, 6 or 7
+#        IN UINT64 value
+# )
+.global write_dr_call
+write_dr_call:
+    cmp     $0, %rcx
+    je      0f
+    cmp     $1, %rcx
+    je      1f
+    cmp     $2, %rcx
+    je      2f
+    cmp     $3, %rcx
+    je      3f
+    cmp     $6, %rcx
+    je      6f
+    mov     %rdx, %dr7
+    ret
+0:
+    mov     %rdx, %dr0
+    ret
+1:
+    mov     %rdx, %dr1
+    ret
+2:
+    mov     %rdx, %dr2
+    ret
+3:
+    mov     %rdx, %dr3
+    ret
+6:
+    mov     %rdx, %dr6
+    ret
+
+.global debugger_breakpoint_call
+debugger_breakpoint_call:
+    int3
+    ret
@@ -0,0 +1,25 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+global_asm!(include_str!("debug.s"));
+
+extern "win64" {
+    fn read_dr_call(index: usize) -> usize;
+    fn write_dr_call(index: usize, value: usize);
+    fn debugger_breakpoint_call();
+}
+
+/// Read DR0-DR3, DR6 or DR7.
+pub fn read_dr(index: usize) -> usize {
+    unsafe { read_dr_call(index) }
+}
+
+/// Write DR0-DR3, DR6 or DR7.
+pub fn write_dr(index: usize, value: usize) {
+    unsafe { write_dr_call(index, value) }
+}
+
+pub fn breakpoint() {
+    unsafe { debugger_breakpoint_call() }
+}
@@ -0,0 +1,643 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+use fw_exception::{Action, Handler, Idt, InterruptStack};
+
+use crate::asm;
+use crate::packet::{self, Response, PACKET_SIZE};
+use crate::serial::Serial;
+
+const DEBUG_VECTOR: u8 = 1;
+const BREAKPOINT_VECTOR: u8 = 3;
+
+const INT3: u8 = 0xCC;
+// what GDB sends to stop the target
+const BREAK_IN: u8 = 0x03;
+const SIGTRAP: u8 = 5;
+
+const RFLAGS_TF: usize = 1 << 8;
+const RFLAGS_RF: usize = 1 << 16;
+
+const DR6_BREAKPOINTS: usize = 0xF;
+const DR6_SINGLE_STEP: usize = 1 << 14;
+// DR7 fields of DRn: enable bit 2n, R/W and LEN at 16 + 4n
+const DR7_RW_EXECUTE: usize = 0b00;
+const DR7_RW_WRITE: usize = 0b01;
+const DR7_RW_ACCESS: usize = 0b11;
+
+const MAX_BREAKPOINTS: usize = 16;
+
+// rax rbx rcx rdx rsi rdi rbp rsp r8-r15 rip eflags cs ss ds es fs gs, the
+// general registers of the GDB amd64 target
+const REGISTER_COUNT: usize = 24;
+
+#[derive(Copy, Clone, Default)]
+struct Breakpoint {
+    address: usize,
+    original: u8,
+    active: bool,
+}
+
+#[derive(Copy, Clone)]
+enum StopReason {
+    Signal,
+    SoftwareBreakpoint,
+    HardwareBreakpoint,
+    Watchpoint(&'static str, usize),
+}
+
+///
+/// The GDB stub. It stops the target on #DB and #BP and serves GDB until it
+/// resumes, with GDB talking over its own serial port.
+///
+/// Software breakpoints are `int3` written in the code, so they need the
+/// code in RAM, hardware breakpoints and watchpoints use DR0-DR3.
+///
+pub struct Debugger {
+    serial: Serial,
+    breakpoints: [Breakpoint; MAX_BREAKPOINTS],
+    // the software breakpoint removed to step over it
+    step_over: Option<usize>,
+    // GDB asked for a single step, not a continue
+    stepping: bool,
+    // GDB is attached and waits for a stop reply
+    running: bool,
+    stop_reason: StopReason,
+    response: Response,
+}
+
+impl Debugger {
+    pub fn new(port: u16) -> Self {
+        let serial = Serial::new(port);
+        serial.init();
+        Self {
+            serial,
+            breakpoints: [Breakpoint::default(); MAX_BREAKPOINTS],
+            step_over: None,
+            stepping: false,
+            running: false,
+            stop_reason: StopReason::Signal,
+            response: Response::new(),
+        }
+    }
+
+    ///
+    /// Handle #DB and #BP of the IDT with the debugger.
+    ///
+    /// # Safety
+    ///
+    /// The debugger must stay in place while the IDT is in use.
+    ///
+    pub unsafe fn install(&mut self, idt: &mut Idt) {
+        let context = self as *mut Self as usize;
+        idt.register_handler(DEBUG_VECTOR, debug_handler as Handler);
+        idt.set_context(DEBUG_VECTOR, context);
+        idt.register_handler(BREAKPOINT_VECTOR, breakpoint_handler as Handler);
+        idt.set_context(BREAKPOINT_VECTOR, context);
+    }
+
+    ///
+    /// Handle #DB and #BP of the IDT in use with the debugger, return false
+    /// if the IDT is not one of fw-exception.
+    ///
+    /// # Safety
+    ///
+    /// The debugger must stay in place while the IDT is in use.
+    ///
+    pub unsafe fn install_current(&mut self) -> bool {
+        let context = self as *mut Self as usize;
+        fw_exception::register_handler(DEBUG_VECTOR, debug_handler as Handler)
+            && fw_exception::set_handler_context(DEBUG_VECTOR, context)
+            && fw_exception::register_handler(BREAKPOINT_VECTOR, breakpoint_handler as Handler)
+            && fw_exception::set_handler_context(BREAKPOINT_VECTOR, context)
+    }
+
+    ///
+    /// Take over the session of the debugger of an earlier phase, GDB then
+    /// gets a stop reply on the next stop. The hardware breakpoints stay in
+    /// DR0-DR3, the software ones are not carried over.
+    ///
+    pub fn resume_session(&mut self) {
+        self.running = true;
+    }
+
+    /// Stop in the debugger if GDB sent a break (Ctrl-C) meanwhile.
+    pub fn poll(&mut self) {
+        if self.serial.read_ready() && self.serial.read_byte() == BREAK_IN {
+            asm::breakpoint();
+        }
+    }
+
+    fn debug_exception(&mut self, stack: &mut InterruptStack) -> Action {
+        let dr6 = asm::read_dr(6);
+        asm::write_dr(6, dr6 & !(DR6_BREAKPOINTS | DR6_SINGLE_STEP));
+
+        if let Some(index) = self.step_over.take() {
+            self.insert(index);
+            if !self.stepping && dr6 & DR6_BREAKPOINTS == 0 {
+                stack.iret.rflags &= !RFLAGS_TF;
+                return Action::Resume;
+            }
+        }
+
+        self.stop_reason = StopReason::Signal;
+        let dr7 = asm::read_dr(7);
+        for index in 0..4 {
+            if dr6 & (1 << index) != 0 {
+                let address = asm::read_dr(index);
+                self.stop_reason = match (dr7 >> (16 + 4 * index)) & 0b11 {
+                    DR7_RW_EXECUTE => StopReason::HardwareBreakpoint,
+                    DR7_RW_WRITE => StopReason::Watchpoint("watch", address),
+                    _ => StopReason::Watchpoint("awatch", address),
+                };
+                break;
+            }
+        }
+        self.stop(stack)
+    }
+
+    fn breakpoint_exception(&mut self, stack: &mut InterruptStack) -> Action {
+        // rip is after the int3
+        let address = stack.iret.rip - 1;
+        self.stop_reason = if self.find(address).is_some() {
+            stack.iret.rip = address;
+            StopReason::SoftwareBreakpoint
+        } else {
+            StopReason::Signal
+        };
+        self.stop(stack)
+    }
+
+    // Serve GDB until it resumes the target
+    fn stop(&mut self, stack: &mut InterruptStack) -> Action {
+        stack.iret.rflags &= !RFLAGS_TF;
+        if self.running {
+            self.running = false;
+            self.stop_reply();
+            self.send_response();
+        }
+
+        let mut packet = [0u8; PACKET_SIZE];
+        loop {
+            let len = self.receive_packet(&mut packet);
+            let (command, data) = match packet[..len].split_first() {
+                Some((command, data)) => (*command, data),
+                None => continue,
+            };
+            self.response.clear();
+            match command {
+                b'?' => self.stop_reply(),
+                b'g' => self.read_registers(stack),
+                b'G' => self.write_registers(stack, data),
+                b'p' => self.read_register(stack, data),
+                b'P' => self.write_register(stack, data),
+                b'm' => self.read_memory(data),
+                b'M' => self.write_memory(data),
+                b'Z' => self.set_breakpoint(data, true),
+                b'z' => self.set_breakpoint(data, false),
+                b'c' | b's' => {
+                    if let Some(address) = packet::parse_hex(data) {
+                        stack.iret.rip = address as usize;
+                    }
+                    self.resume(stack, command == b's');
+                    return Action::Resume;
+                }
+                b'D' => {
+                    self.detach();
+                    self.response.push(b"OK");
+                    self.send_response();
+                    self.resume(stack, false);
+                    self.running = false;
+                    return Action::Resume;
+                }
+                b'k' => {
+                    self.detach();
+                    self.resume(stack, false);
+                    self.running = false;
+                    return Action::Resume;
+                }
+                b'q' => self.query(data),
+                b'H' | b'T' => self.response.push(b"OK"),
+                _ => {}
+            }
+            self.send_response();
+        }
+    }
+
+    fn resume(&mut self, stack: &mut InterruptStack, step: bool) {
+        self.running = true;
+        self.stepping = step;
+        let mut rflags = stack.iret.rflags & !RFLAGS_TF;
+        if step {
+            rflags |= RFLAGS_TF;
+        }
+        // step over the int3 of a breakpoint at rip, it is put back on #DB
+        if let Some(index) = self.find(stack.iret.rip) {
+            self.remove(index);
+            self.step_over = Some(index);
+            rflags |= RFLAGS_TF;
+        }
+        // no hardware breakpoint on the first instruction
+        stack.iret.rflags = rflags | RFLAGS_RF;
+    }
+
+    fn detach(&mut self) {
+        for index in 0..MAX_BREAKPOINTS {
+            if self.breakpoints[index].active {
+                self.remove(index);
+                self.breakpoints[index].active = false;
+            }
+        }
+        self.step_over = None;
+        asm::write_dr(7, 0);
+    }
+
+    fn stop_reply(&mut self) {
+        self.response.push(b"T");
+        self.response.push_hex(SIGTRAP);
+        match self.stop_reason {
+            StopReason::Signal => {}
+            StopReason::SoftwareBreakpoint => self.response.push(b"swbreak:;"),
+            StopReason::HardwareBreakpoint => self.response.push(b"hwbreak:;"),
+            StopReason::Watchpoint(kind, address) => {
+                self.response.push(kind.as_bytes());
+                self.response.push(b":");
+                push_hex_number(&mut self.response, address as u64);
+                self.response.push(b";");
+            }
+        }
+    }
+
+    fn query(&mut self, data: &[u8]) {
+        if data.starts_with(b"Supported") {
+            self.response.push(b"PacketSize=");
+            push_hex_number(&mut self.response, PACKET_SIZE as u64);
+            self.response.push(b";swbreak+;hwbreak+");
+        } else if data == b"Attached" {
+            self.response.push(b"1");
+        } else if data == b"C" {
+            self.response.push(b"QC1");
+        } else if data == b"fThreadInfo" {
+            self.response.push(b"m1");
+        } else if data == b"sThreadInfo" {
+            self.response.push(b"l");
+        }
+    }
+
+    fn read_registers(&mut self, stack: &InterruptStack) {
+        for number in 0..REGISTER_COUNT {
+            let (value, size) = register(stack, number);
+            self.response.push_le(value as u64, size);
+        }
+    }
+
+    fn write_registers(&mut self, stack: &mut InterruptStack, data: &[u8]) {
+        let mut offset = 0;
+        for number in 0..REGISTER_COUNT {
+            let size = register(stack, number).1;
+            let value = match data
+                .get(offset..offset + size * 2)
+                .and_then(packet::decode_le)
+            {
+                Some(value) => value,
+                None => break,
+            };
+            set_register(stack, number, value as usize);
+            offset += size * 2;
+        }
+        self.response.push(b"OK");
+    }
+
+    fn read_register(&mut self, stack: &InterruptStack, data: &[u8]) {
+        match packet::parse_hex(data) {
+            Some(number) if (number as usize) < REGISTER_COUNT => {
+                let (value, size) = register(stack, number as usize);
+                self.response.push_le(value as u64, size);
+            }
+            _ => self.response.push(b"E01"),
+        }
+    }
+
+    fn write_register(&mut self, stack: &mut InterruptStack, data: &[u8]) {
+        let equal = data.iter().position(|c| *c == b'=');
+        let register = equal.and_then(|equal| {
+            Some((
+                packet::parse_hex(&data[..equal])? as usize,
+                packet::decode_le(&data[equal + 1..])?,
+            ))
+        });
+        match register {
+            Some((number, value)) if number < REGISTER_COUNT => {
+                set_register(stack, number, value as usize);
+                self.response.push(b"OK");
+            }
+            _ => self.response.push(b"E01"),
+        }
+    }
+
+    fn read_memory(&mut self, data: &[u8]) {
+        let (address, length) = match packet::parse_address_length(data) {
+            Some(memory) => memory,
+            None => return self.response.push(b"E01"),
+        };
+        let length = (length as usize).min(PACKET_SIZE / 2);
+        for offset in 0..length {
+            let byte =
+                unsafe { core::ptr::read_volatile((address as usize + offset) as *const u8) };
+            self.response.push_hex(byte);
+        }
+    }
+
+    fn write_memory(&mut self, data: &[u8]) {
+        let colon = match data.iter().position(|c| *c == b':') {
+            Some(colon) => colon,
+            None => return self.response.push(b"E01"),
+        };
+        let mut bytes = [0u8; PACKET_SIZE / 2];
+        let memory = packet::parse_address_length(&data[..colon]).and_then(|(address, length)| {
+            let count = packet::decode_hex(&data[colon + 1..], &mut bytes)?;
+            if count as u64 != length {
+                return None;
+            }
+            Some((address as usize, count))
+        });
+        let (address, count) = match memory {
+            Some(memory) => memory,
+            None => return self.response.push(b"E01"),
+        };
+        for (offset, byte) in bytes[..count].iter().enumerate() {
+            unsafe { core::ptr::write_volatile((address + offset) as *mut u8, *byte) };
+        }
+        self.response.push(b"OK");
+    }
+
+    // Z/z type,addr,kind
+    fn set_breakpoint(&mut self, data: &[u8], insert: bool) {
+        let mut fields = data.splitn(3, |c| *c == b',');
+        let kind = fields.next().and_then(packet::parse_hex);
+        let address = fields.next().and_then(packet::parse_hex);
+        let length = fields.next().and_then(packet::parse_hex);
+        let (kind, address, length) = match (kind, address, length) {
+            (Some(kind), Some(address), Some(length)) => (kind, address as usize, length as usize),
+            _ => return self.response.push(b"E01"),
+        };
+        let done = match kind {
+            0 if insert => self.insert_software(address),
+            0 => self.remove_software(address),
+            1 => self.set_hardware(address, DR7_RW_EXECUTE, 1, insert),
+            2 => self.set_hardware(address, DR7_RW_WRITE, length, insert),
+            4 => self.set_hardware(address, DR7_RW_ACCESS, length, insert),
+            // read watchpoints are not supported by the debug registers
+            _ => return,
+        };
+        self.response.push(if done { b"OK" } else { b"E01" });
+    }
+
+    fn find(&self, address: usize) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| breakpoint.active && breakpoint.address == address)
+    }
+
+    // Write the int3 of the breakpoint
+    fn insert(&mut self, index: usize) {
+        let address = self.breakpoints[index].address as *mut u8;
+        unsafe { core::ptr::write_volatile(address, INT3) };
+    }
+
+    // Put the original byte of the breakpoint back
+    fn remove(&mut self, index: usize) {
+        let breakpoint = self.breakpoints[index];
+        unsafe { core::ptr::write_volatile(breakpoint.address as *mut u8, breakpoint.original) };
+    }
+
+    fn insert_software(&mut self, address: usize) -> bool {
+        if self.find(address).is_some() {
+            return true;
+        }
+        let index = match self
+            .breakpoints
+            .iter()
+            .position(|breakpoint| !breakpoint.active)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        let original = unsafe { core::ptr::read_volatile(address as *const u8) };
+        self.breakpoints[index] = Breakpoint {
+            address,
+            original,
+            active: true,
+        };
+        self.insert(index);
+        // the code is in flash
+        if unsafe { core::ptr::read_volatile(address as *const u8) } != INT3 {
+            self.breakpoints[index].active = false;
+            return false;
+        }
+        true
+    }
+
+    fn remove_software(&mut self, address: usize) -> bool {
+        match self.find(address) {
+            Some(index) => {
+                if self.step_over != Some(index) {
+                    self.remove(index);
+                }
+                if self.step_over == Some(index) {
+                    self.step_over = None;
+                }
+                self.breakpoints[index].active = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_hardware(&mut self, address: usize, rw: usize, length: usize, insert: bool) -> bool {
+        let len = match length {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            8 => 0b10,
+            _ => return false,
+        };
+        if address % length != 0 {
+            return false;
+        }
+        let fields = (len << 2 | rw) << 16;
+        let mut dr7 = asm::read_dr(7);
+        for index in 0..4 {
+            let enabled = dr7 & (1 << (2 * index)) != 0;
+            let field_mask = 0b1111 << (16 + 4 * index);
+            let same = enabled
+                && asm::read_dr(index) == address
+                && dr7 & field_mask == fields << (4 * index);
+            if insert && !enabled {
+                asm::write_dr(index, address);
+                dr7 = dr7 & !field_mask | fields << (4 * index) | 1 << (2 * index);
+                asm::write_dr(7, dr7);
+                return true;
+            }
+            if !insert && same {
+                asm::write_dr(7, dr7 & !(1 << (2 * index)));
+                return true;
+            }
+        }
+        false
+    }
+
+    fn receive_packet(&mut self, packet: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            // a break while stopped, as when GDB waits for the stop of the
+            // debugger of an earlier phase, is answered as a '?'
+            loop {
+                match self.serial.read_byte() {
+                    b'$' => break,
+                    BREAK_IN => {
+                        packet[0] = b'?';
+                        return 1;
+                    }
+                    _ => {}
+                }
+            }
+            let mut len = 0;
+            let mut overflow = false;
+            loop {
+                let byte = self.serial.read_byte();
+                match byte {
+                    b'#' => break,
+                    b'$' => {
+                        len = 0;
+                        overflow = false;
+                    }
+                    _ if len == PACKET_SIZE => overflow = true,
+                    _ => {
+                        packet[len] = byte;
+                        len += 1;
+                    }
+                }
+            }
+            let high = packet::hex_value(self.serial.read_byte());
+            let low = packet::hex_value(self.serial.read_byte());
+            let sum = high.and_then(|high| Some(high << 4 | low?));
+            if !overflow && sum == Some(packet::checksum(&packet[..len])) {
+                self.serial.write_byte(b'+');
+                return len;
+            }
+            self.serial.write_byte(b'-');
+        }
+    }
+
+    fn send_response(&mut self) {
+        let data = self.response.as_bytes();
+        let sum = packet::checksum(data);
+        loop {
+            self.serial.write_byte(b'$');
+            for byte in data {
+                self.serial.write_byte(*byte);
+            }
+            self.serial.write_byte(b'#');
+            self.serial.write_byte(packet::hex_digit(sum >> 4));
+            self.serial.write_byte(packet::hex_digit(sum));
+            loop {
+                match self.serial.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// A number without the leading zeros, as in the stop reply and qSupported
+fn push_hex_number(response: &mut Response, value: u64) {
+    let digits = (64 - value.leading_zeros() as usize + 3) / 4;
+    for index in (0..digits.max(1)).rev() {
+        response.push(&[packet::hex_digit((value >> (4 * index)) as u8)]);
+    }
+}
+
+// The value and the size in bytes of the GDB register
+fn register(stack: &InterruptStack, number: usize) -> (usize, usize) {
+    match number {
+        0 => (stack.scratch.rax, 8),
+        1 => (stack.preserved.rbx, 8),
+        2 => (stack.scratch.rcx, 8),
+        3 => (stack.scratch.rdx, 8),
+        4 => (stack.scratch.rsi, 8),
+        5 => (stack.scratch.rdi, 8),
+        6 => (stack.preserved.rbp, 8),
+        7 => (stack.iret.rsp, 8),
+        8 => (stack.scratch.r8, 8),
+        9 => (stack.scratch.r9, 8),
+        10 => (stack.scratch.r10, 8),
+        11 => (stack.scratch.r11, 8),
+        12 => (stack.preserved.r12, 8),
+        13 => (stack.preserved.r13, 8),
+        14 => (stack.preserved.r14, 8),
+        15 => (stack.preserved.r15, 8),
+        16 => (stack.iret.rip, 8),
+        17 => (stack.iret.rflags, 4),
+        18 => (stack.iret.cs, 4),
+        19 => (stack.iret.ss, 4),
+        // ds es fs gs, not saved
+        _ => (0, 4),
+    }
+}
+
+fn set_register(stack: &mut InterruptStack, number: usize, value: usize) {
+    match number {
+        0 => stack.scratch.rax = value,
+        1 => stack.preserved.rbx = value,
+        2 => stack.scratch.rcx = value,
+        3 => stack.scratch.rdx = value,
+        4 => stack.scratch.rsi = value,
+        5 => stack.scratch.rdi = value,
+        6 => stack.preserved.rbp = value,
+        7 => stack.iret.rsp = value,
+        8 => stack.scratch.r8 = value,
+        9 => stack.scratch.r9 = value,
+        10 => stack.scratch.r10 = value,
+        11 => stack.scratch.r11 = value,
+        12 => stack.preserved.r12 = value,
+        13 => stack.preserved.r13 = value,
+        14 => stack.preserved.r14 = value,
+        15 => stack.preserved.r15 = value,
+        16 => stack.iret.rip = value,
+        17 => stack.iret.rflags = value,
+        // the segments stay as they are
+        _ => {}
+    }
+}
+
+fn debugger(vector: u8) -> Option<&'static mut Debugger> {
+    let context = fw_exception::handler_context(vector);
+    if context == 0 {
+        return None;
+    }
+    Some(unsafe { &mut *(context as *mut Debugger) })
+}
+
+fn debug_handler(stack: &mut InterruptStack) -> Action {
+    match debugger(DEBUG_VECTOR) {
+        Some(debugger) => debugger.debug_exception(stack),
+        None => Action::Crash,
+    }
+}
+
+fn breakpoint_handler(stack: &mut InterruptStack) -> Action {
+    match debugger(BREAKPOINT_VECTOR) {
+        Some(debugger) => debugger.breakpoint_exception(stack),
+        None => Action::Crash,
+    }
+}
+
+/// Stop in the debugger of the IDT in use if GDB sent a break meanwhile.
+pub fn poll() {
+    if let Some(debugger) = debugger(BREAKPOINT_VECTOR) {
+        debugger.poll();
+    }
+}
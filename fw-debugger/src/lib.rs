@@ -0,0 +1,20 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! GDB remote serial protocol stub, on a 16550 UART of its own and on the
+//! #DB and #BP handlers of fw-exception.
+//!
+
+#![cfg_attr(not(test), no_std)]
+#![feature(global_asm)]
+
+mod asm;
+mod gdb;
+mod packet;
+mod serial;
+
+pub use asm::breakpoint;
+pub use gdb::{poll, Debugger};
+pub use serial::{Serial, COM1_PORT, COM2_PORT};
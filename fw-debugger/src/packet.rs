@@ -0,0 +1,173 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Hex encoding of the GDB remote serial protocol, a packet is
+//! `$<data>#<checksum>` with the checksum the modulo 256 sum of the data.
+//!
+
+/// The largest packet data, told to GDB in qSupported.
+pub const PACKET_SIZE: usize = 0x400;
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+pub fn hex_digit(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xF) as usize]
+}
+
+pub fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// A number written most significant digit first, such as an address.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        Some(value << 4 | hex_value(*digit)? as u64)
+    })
+}
+
+/// `addr,length`, as in the memory and breakpoint packets.
+pub fn parse_address_length(data: &[u8]) -> Option<(u64, u64)> {
+    let comma = data.iter().position(|c| *c == b',')?;
+    Some((parse_hex(&data[..comma])?, parse_hex(&data[comma + 1..])?))
+}
+
+/// Decode the hex pairs of data into bytes, return the count of bytes.
+pub fn decode_hex(data: &[u8], bytes: &mut [u8]) -> Option<usize> {
+    if data.len() % 2 != 0 || data.len() / 2 > bytes.len() {
+        return None;
+    }
+    for (byte, pair) in bytes.iter_mut().zip(data.chunks(2)) {
+        *byte = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    Some(data.len() / 2)
+}
+
+/// A register value, sent as its bytes in target (little endian) order.
+pub fn decode_le(data: &[u8]) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    let count = decode_hex(data, &mut bytes)?;
+    if count == 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// The data of the reply packet.
+pub struct Response {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        Self {
+            data: [0u8; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Append the bytes, what does not fit in the packet is dropped.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if self.len == PACKET_SIZE {
+                return;
+            }
+            self.data[self.len] = *byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_hex(&mut self, byte: u8) {
+        self.push(&[hex_digit(byte >> 4), hex_digit(byte)]);
+    }
+
+    /// Append the size low bytes of the value, in little endian order.
+    pub fn push_le(&mut self, value: u64, size: usize) {
+        for byte in value.to_le_bytes().iter().take(size) {
+            self.push_hex(*byte);
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported"), 0x37);
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex(b"0"), Some(0));
+        assert_eq!(parse_hex(b"fFfF0000"), Some(0xFFFF_0000));
+        assert_eq!(parse_hex(b"ffffffffffffffff"), Some(u64::MAX));
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12g"), None);
+        assert_eq!(
+            parse_address_length(b"ffe00000,40"),
+            Some((0xFFE0_0000, 0x40))
+        );
+        assert_eq!(parse_address_length(b"ffe00000"), None);
+        assert_eq!(parse_address_length(b",4"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut bytes = [0u8; 4];
+        assert_eq!(decode_hex(b"0aFf", &mut bytes), Some(2));
+        assert_eq!(bytes[..2], [0x0a, 0xff]);
+        assert_eq!(decode_hex(b"0a0", &mut bytes), None);
+        assert_eq!(decode_hex(b"0001020304", &mut bytes), None);
+        assert_eq!(decode_le(b"78563412"), Some(0x1234_5678));
+        assert_eq!(decode_le(b"0100000000000080"), Some(0x8000_0000_0000_0001));
+        assert_eq!(decode_le(b""), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let mut response = Response::new();
+        response.push(b"S");
+        response.push_hex(5);
+        assert_eq!(response.as_bytes(), b"S05");
+        response.clear();
+        response.push_le(0x1234_5678, 4);
+        response.push_le(0x10, 2);
+        assert_eq!(response.as_bytes(), b"785634121000");
+        response.clear();
+        for _ in 0..PACKET_SIZE {
+            response.push(b"ab");
+        }
+        assert_eq!(response.as_bytes().len(), PACKET_SIZE);
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Polled 16550 UART, the debugger has its own port apart from the log.
+//!
+
+pub const COM1_PORT: u16 = 0x3F8;
+pub const COM2_PORT: u16 = 0x2F8;
+
+// register offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_CONTROL_DLAB: u8 = 0x80;
+// 8 data bits, no parity, 1 stop bit
+const LINE_CONTROL_8N1: u8 = 0x03;
+// enable and clear the FIFOs
+const FIFO_CONTROL_ENABLE: u8 = 0x07;
+// DTR, RTS and OUT2
+const MODEM_CONTROL_READY: u8 = 0x0B;
+const LINE_STATUS_DATA_READY: u8 = 0x01;
+const LINE_STATUS_THR_EMPTY: u8 = 0x20;
+
+// 115200 baud from the 1.8432 MHz clock
+const DIVISOR_115200: u16 = 1;
+
+#[derive(Copy, Clone)]
+pub struct Serial {
+    port: u16,
+}
+
+impl Serial {
+    pub fn new(port: u16) -> Self {
+        Self { port }
+    }
+
+    /// Set 115200 8N1 with the FIFOs and no interrupt.
+    pub fn init(&self) {
+        unsafe {
+            x86::io::outb(self.port + INTERRUPT_ENABLE, 0);
+            x86::io::outb(self.port + LINE_CONTROL, LINE_CONTROL_DLAB);
+            x86::io::outb(self.port + DIVISOR_LOW, DIVISOR_115200 as u8);
+            x86::io::outb(self.port + DIVISOR_HIGH, (DIVISOR_115200 >> 8) as u8);
+            x86::io::outb(self.port + LINE_CONTROL, LINE_CONTROL_8N1);
+            x86::io::outb(self.port + FIFO_CONTROL, FIFO_CONTROL_ENABLE);
+            x86::io::outb(self.port + MODEM_CONTROL, MODEM_CONTROL_READY);
+        }
+    }
+
+    pub fn read_ready(&self) -> bool {
+        unsafe { x86::io::inb(self.port + LINE_STATUS) & LINE_STATUS_DATA_READY != 0 }
+    }
+
+    pub fn read_byte(&self) -> u8 {
+        while !self.read_ready() {}
+        unsafe { x86::io::inb(self.port + DATA) }
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        while unsafe { x86::io::inb(self.port + LINE_STATUS) } & LINE_STATUS_THR_EMPTY == 0 {}
+        unsafe { x86::io::outb(self.port + DATA, byte) }
+    }
+}
@@ -3,27 +3,21 @@
 
 .section .text
 
-#  sidt_call (
-#        OUT UINT64 addr
-# )
-.global sidt_call
-sidt_call:
-    sidt    (%rcx)
+.global read_cs_call
+read_cs_call:
+    mov   %cs, %rax
     ret
 
-
-#  lidt_call (
-#        IN UINT64 addr
-# )
-.global lidt_call
-lidt_call:
-    lidt    (%rcx)
+# rbp is not touched by the call, it is the one of the caller
+.global read_rbp_call
+read_rbp_call:
+    mov   %rbp, %rax
     ret
 
-
-.global read_cs_call
-read_cs_call:
-    mov   %cs, %rax
+# the return address, an instruction of the caller
+.global read_rip_call
+read_rip_call:
+    mov   (%rsp), %rax
     ret
 
 .global read_cr0_call
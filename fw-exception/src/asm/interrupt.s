@@ -0,0 +1,67 @@
+# Copyright (c) 2021 Intel Corporation
+# SPDX-License-Identifier: BSD-2-Clause-Patent
+
+.section .text
+
+# One 16-byte stub per vector. The stub pushes a zero error code if the CPU
+# does not push one, then the vector, so all vectors share the same frame.
+.global interrupt_stub_table
+.balign 16
+interrupt_stub_table:
+.set vector, 0
+.rept 256
+    .balign 16
+    .if (vector != 8) && ((vector < 10) || (vector > 14)) && (vector != 17) && (vector != 21) && (vector != 29) && (vector != 30)
+    pushq   $0
+    .endif
+    pushq   $vector
+    jmp     interrupt_common
+    .set vector, vector + 1
+.endr
+
+interrupt_common:
+    # InterruptStack: preserved, scratch, vector, code, iret
+    pushq   %rax
+    pushq   %rcx
+    pushq   %rdx
+    pushq   %rdi
+    pushq   %rsi
+    pushq   %r8
+    pushq   %r9
+    pushq   %r10
+    pushq   %r11
+    pushq   %rbx
+    pushq   %rbp
+    pushq   %r12
+    pushq   %r13
+    pushq   %r14
+    pushq   %r15
+
+    # interrupt_dispatch (
+    #       IN OUT InterruptStack *stack
+    # )
+    movq    %rsp, %rcx
+    cld
+    subq    $32, %rsp
+    call    interrupt_dispatch
+    addq    $32, %rsp
+
+    popq    %r15
+    popq    %r14
+    popq    %r13
+    popq    %r12
+    popq    %rbp
+    popq    %rbx
+    popq    %r11
+    popq    %r10
+    popq    %r9
+    popq    %r8
+    popq    %rsi
+    popq    %rdi
+    popq    %rdx
+    popq    %rcx
+    popq    %rax
+
+    # Skip the vector and the error code
+    addq    $16, %rsp
+    iretq
@@ -3,3 +3,4 @@
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
 global_asm!(include_str!("idt.s"));
+global_asm!(include_str!("interrupt.s"));
@@ -0,0 +1,355 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Frame-pointer stack unwinding, with the return addresses resolved to
+//! module+offset and to function names when the module has a symbol table.
+//!
+//! The code must be built with frame pointers (`-C force-frame-pointers=yes`),
+//! a frame is then `[rbp] = caller rbp, [rbp + 8] = return address`.
+//!
+
+use core::ops::Range;
+
+extern "win64" {
+    // rbp of the caller
+    pub(crate) fn read_rbp_call() -> usize;
+    // an address in the caller
+    pub(crate) fn read_rip_call() -> usize;
+}
+
+// Stops a walk through a corrupted stack which still passes the checks
+const MAX_FRAMES: usize = 32;
+
+// Compact symbol table, in the ".rsym" section of an image:
+//   magic "RSYM", count: u32,
+//   count entries { start: u32, size: u32, name: u32 } sorted by start, where
+//   start is the offset of the function in the image and name the offset of
+//   its null terminated name in the string pool,
+//   string pool.
+pub const SYMBOL_TABLE_SECTION: &[u8] = b".rsym";
+const SYMBOL_TABLE_MAGIC: &[u8] = b"RSYM";
+const SYMBOL_TABLE_HEADER_SIZE: usize = 8;
+const SYMBOL_ENTRY_SIZE: usize = 12;
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+#[derive(Copy, Clone)]
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.get(0..4)? != SYMBOL_TABLE_MAGIC {
+            return None;
+        }
+        let count = read_u32(bytes, 4)? as usize;
+        let names_offset = SYMBOL_TABLE_HEADER_SIZE + count.checked_mul(SYMBOL_ENTRY_SIZE)?;
+        Some(Self {
+            entries: bytes.get(SYMBOL_TABLE_HEADER_SIZE..names_offset)?,
+            names: bytes.get(names_offset..)?,
+        })
+    }
+
+    fn entry(&self, index: usize) -> (usize, usize, usize) {
+        let offset = index * SYMBOL_ENTRY_SIZE;
+        // in range, new() checked the size of the entries
+        let start = read_u32(self.entries, offset).unwrap_or(0) as usize;
+        let size = read_u32(self.entries, offset + 4).unwrap_or(0) as usize;
+        let name = read_u32(self.entries, offset + 8).unwrap_or(0) as usize;
+        (start, size, name)
+    }
+
+    fn name(&self, offset: usize) -> Option<&'a str> {
+        let name = self.names.get(offset..)?;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        core::str::from_utf8(&name[..len]).ok()
+    }
+
+    /// The function at the offset of the image, with the offset in it.
+    pub fn lookup(&self, offset: usize) -> Option<(&'a str, usize)> {
+        let count = self.entries.len() / SYMBOL_ENTRY_SIZE;
+        // the last function starting at or before the offset
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.entry(middle).0 <= offset {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        if low == 0 {
+            return None;
+        }
+        let (start, size, name) = self.entry(low - 1);
+        if offset - start >= size {
+            return None;
+        }
+        Some((self.name(name)?, offset - start))
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Module<'a> {
+    pub name: &'a str,
+    pub base: usize,
+    pub size: usize,
+    pub symbols: Option<SymbolTable<'a>>,
+}
+
+impl<'a> Module<'a> {
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+}
+
+///
+/// The return addresses of the frames from rbp. A frame outside of the
+/// stack, not aligned or not above the previous one ends the walk.
+///
+pub struct Frames {
+    rbp: usize,
+    stack: Range<usize>,
+    count: usize,
+}
+
+impl Frames {
+    pub fn new(rbp: usize, stack: Range<usize>) -> Self {
+        Self {
+            rbp,
+            stack,
+            count: 0,
+        }
+    }
+
+    /// The frames of the caller.
+    pub fn current(stack: Range<usize>) -> Self {
+        Self::new(unsafe { read_rbp_call() }, stack)
+    }
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let rbp = self.rbp;
+        if self.count == MAX_FRAMES
+            || rbp % 8 != 0
+            || rbp < self.stack.start
+            || rbp.checked_add(16)? > self.stack.end
+        {
+            return None;
+        }
+        let frame = unsafe { core::slice::from_raw_parts(rbp as *const usize, 2) };
+        let return_address = frame[1];
+        if return_address == 0 {
+            return None;
+        }
+        // the caller's frame is higher on the stack, 0 ends the walk
+        self.rbp = if frame[0] > rbp { frame[0] } else { 0 };
+        self.count += 1;
+        Some(return_address)
+    }
+}
+
+/// An address resolved to module+offset and function+offset.
+pub struct Location<'a> {
+    pub address: usize,
+    pub module: Option<(&'a str, usize)>,
+    pub function: Option<(&'a str, usize)>,
+}
+
+impl<'a> Location<'a> {
+    ///
+    /// Resolve the address with the modules. A return address may be the
+    /// first byte after the function which made the call, so its function is
+    /// the one of the byte before it.
+    ///
+    pub fn resolve(address: usize, return_address: bool, modules: &[Module<'a>]) -> Self {
+        let module = match modules.iter().find(|module| module.contains(address)) {
+            Some(module) => module,
+            None => {
+                return Self {
+                    address,
+                    module: None,
+                    function: None,
+                }
+            }
+        };
+        let offset = address - module.base;
+        let adjust = if return_address { 1 } else { 0 };
+        let function = module
+            .symbols
+            .and_then(|symbols| symbols.lookup(offset.saturating_sub(adjust)))
+            .map(|(name, function_offset)| (name, function_offset + adjust));
+        Self {
+            address,
+            module: Some((module.name, offset)),
+            function,
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Location<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:>016X}", self.address)?;
+        if let Some((name, offset)) = self.module {
+            write!(f, " {}+{:#X}", name, offset)?;
+        }
+        if let Some((name, offset)) = self.function {
+            write!(f, " {}+{:#X}", name, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print rip then the return addresses of the frames from rbp.
+pub fn print_backtrace(rip: usize, rbp: usize, stack: Range<usize>, modules: &[Module]) {
+    log::info!("Backtrace:\n");
+    log::info!("#00 {}\n", Location::resolve(rip, false, modules));
+    for (index, address) in Frames::new(rbp, stack).enumerate() {
+        log::info!(
+            "#{:02} {}\n",
+            index + 1,
+            Location::resolve(address, true, modules)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // "a" at 0x100..0x110, "bb" at 0x110..0x130, "ccc" at 0x200..0x208
+    fn symbol_table() -> Vec<u8> {
+        let mut bytes = SYMBOL_TABLE_MAGIC.to_vec();
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        for (start, size, name) in &[(0x100u32, 0x10u32, 0u32), (0x110, 0x20, 2), (0x200, 0x8, 5)] {
+            bytes.extend_from_slice(&start.to_le_bytes());
+            bytes.extend_from_slice(&size.to_le_bytes());
+            bytes.extend_from_slice(&name.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"a\0bb\0ccc\0");
+        bytes
+    }
+
+    #[test]
+    fn test_symbol_table_new() {
+        let bytes = symbol_table();
+        assert!(SymbolTable::new(&bytes).is_some());
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(SymbolTable::new(&bad_magic).is_none());
+        // the count is larger than the entries
+        assert!(
+            SymbolTable::new(&bytes[..SYMBOL_TABLE_HEADER_SIZE + 2 * SYMBOL_ENTRY_SIZE]).is_none()
+        );
+        assert!(SymbolTable::new(&bytes[..6]).is_none());
+        let mut huge_count = bytes;
+        huge_count[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(SymbolTable::new(&huge_count).is_none());
+    }
+
+    #[test]
+    fn test_symbol_table_lookup() {
+        let bytes = symbol_table();
+        let symbols = SymbolTable::new(&bytes).unwrap();
+        assert_eq!(symbols.lookup(0), None);
+        assert_eq!(symbols.lookup(0xFF), None);
+        assert_eq!(symbols.lookup(0x100), Some(("a", 0)));
+        assert_eq!(symbols.lookup(0x10F), Some(("a", 0xF)));
+        assert_eq!(symbols.lookup(0x110), Some(("bb", 0)));
+        // past the size of "bb", before "ccc"
+        assert_eq!(symbols.lookup(0x130), None);
+        assert_eq!(symbols.lookup(0x207), Some(("ccc", 7)));
+        assert_eq!(symbols.lookup(0x208), None);
+        assert_eq!(symbols.lookup(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_location_resolve() {
+        let bytes = symbol_table();
+        let modules = [Module {
+            name: "image",
+            base: 0x1000,
+            size: 0x1000,
+            symbols: SymbolTable::new(&bytes),
+        }];
+        // a call at the end of "a" returns to the first byte of "bb"
+        assert_eq!(
+            format!("{}", Location::resolve(0x1110, true, &modules)),
+            "0000000000001110 image+0x110 a+0x10"
+        );
+        assert_eq!(
+            format!("{}", Location::resolve(0x1110, false, &modules)),
+            "0000000000001110 image+0x110 bb+0x0"
+        );
+        assert_eq!(
+            format!("{}", Location::resolve(0x2000, false, &modules)),
+            "0000000000002000"
+        );
+    }
+
+    // Walk the fabricated stack, rbp and the bounds are offsets in it
+    fn walk(stack: &[usize], rbp: usize, bounds: Range<usize>) -> Vec<usize> {
+        let base = stack.as_ptr() as usize;
+        Frames::new(
+            base.wrapping_add(rbp),
+            base + bounds.start..base + bounds.end,
+        )
+        .collect()
+    }
+
+    #[test]
+    fn test_frames() {
+        // frames at the qwords 2, 6 and 10, the last one links below itself
+        let mut stack = [0usize; 16];
+        let base = stack.as_ptr() as usize;
+        let end = stack.len() * 8;
+        stack[2] = base + 6 * 8;
+        stack[3] = 0xA;
+        stack[6] = base + 10 * 8;
+        stack[7] = 0xB;
+        stack[10] = base;
+        stack[11] = 0xC;
+        assert_eq!(walk(&stack, 2 * 8, 0..end), [0xA, 0xB, 0xC]);
+
+        // misaligned
+        assert_eq!(walk(&stack, 2 * 8 + 4, 0..end), []);
+        // out of the stack, the second frame ends past it
+        assert_eq!(walk(&stack, 2 * 8, 0..7 * 8), [0xA]);
+        assert_eq!(walk(&stack, 2 * 8, 3 * 8..end), []);
+        assert_eq!(walk(&stack, usize::MAX - base - 7, 0..end), []);
+
+        // a frame linking to itself or below ends the walk
+        stack[6] = base + 6 * 8;
+        assert_eq!(walk(&stack, 2 * 8, 0..end), [0xA, 0xB]);
+        stack[6] = base + 2 * 8;
+        assert_eq!(walk(&stack, 2 * 8, 0..end), [0xA, 0xB]);
+
+        // a zero return address ends the walk
+        stack[7] = 0;
+        assert_eq!(walk(&stack, 2 * 8, 0..end), [0xA]);
+    }
+
+    #[test]
+    fn test_frames_max() {
+        // a chain longer than MAX_FRAMES
+        let mut stack = [0usize; 2 * (MAX_FRAMES + 8)];
+        let base = stack.as_ptr() as usize;
+        for frame in 0..MAX_FRAMES + 8 {
+            stack[2 * frame] = base + (2 * frame + 2) * 8;
+            stack[2 * frame + 1] = 0x1000 + frame;
+        }
+        let end = stack.len() * 8;
+        assert_eq!(walk(&stack, 0, 0..end).len(), MAX_FRAMES);
+    }
+}
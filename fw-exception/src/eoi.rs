@@ -0,0 +1,45 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! End of interrupt for the interrupt controllers, called by the handlers of
+//! external interrupts before they resume.
+//!
+
+use x86::msr;
+
+// xAPIC EOI register, offset from the APIC base
+const XAPIC_EOI_OFFSET: u64 = 0xB0;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+const PIC_MASTER_COMMAND_PORT: u16 = 0x20;
+const PIC_SLAVE_COMMAND_PORT: u16 = 0xA0;
+const PIC_EOI: u8 = 0x20;
+
+/// Signal the end of interrupt to the local APIC, in xAPIC or x2APIC mode.
+pub fn local_apic_eoi() {
+    unsafe {
+        let base = msr::rdmsr(msr::IA32_APIC_BASE);
+        if base & APIC_BASE_X2APIC_ENABLE != 0 {
+            msr::wrmsr(msr::IA32_X2APIC_EOI, 0);
+        } else {
+            let eoi = ((base & APIC_BASE_ADDRESS_MASK) + XAPIC_EOI_OFFSET) as *mut u32;
+            core::ptr::write_volatile(eoi, 0);
+        }
+    }
+}
+
+///
+/// Signal the end of interrupt to the 8259 for IRQ 0-15. An IRQ of the slave
+/// needs the EOI on both controllers.
+///
+pub fn pic_eoi(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            x86::io::outb(PIC_SLAVE_COMMAND_PORT, PIC_EOI);
+        }
+        x86::io::outb(PIC_MASTER_COMMAND_PORT, PIC_EOI);
+    }
+}
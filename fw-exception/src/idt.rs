@@ -3,85 +3,170 @@
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
 use core::mem;
+use core::ops::Range;
 
-use crate::interrupt;
+use crate::backtrace::Module;
+use crate::interrupt::{self, Handler};
+use crate::report::ExceptionPolicy;
 
 use bitflags::bitflags;
 use lazy_static::lazy_static;
-
-// extern "win64" {
-//     fn sidt_call(addr: usize);
-// }
+use x86::dtables::{self, DescriptorTablePointer};
 
 extern "win64" {
-    fn lidt_call(addr: usize);
+    fn read_cs_call() -> u16;
 }
 
 extern "win64" {
-    fn read_cs_call() -> u16;
+    // 256 stubs of STUB_SIZE bytes, one per vector
+    fn interrupt_stub_table();
 }
 
+const STUB_SIZE: usize = 16;
+
 lazy_static! {
     static ref INIT_IDT: Idt = Idt::new();
 }
 
-#[repr(C, packed)]
-pub struct DescriptorTablePointer {
-    limit: u16,
-    base: u64,
-}
-
 #[no_mangle]
 pub unsafe fn init() {
-    let mut idtr = DescriptorTablePointer { limit: 1, base: 0 };
+    INIT_IDT.load();
+}
 
-    let current_idt = &INIT_IDT.entries;
-    idtr.limit = (current_idt.len() * mem::size_of::<IdtEntry>() - 1) as u16;
-    idtr.base = current_idt.as_ptr() as u64;
+/// Load an empty IDT, any exception then shuts the CPU down.
+pub unsafe fn clear() {
+    let idtr = DescriptorTablePointer::<IdtEntry>::default();
+    dtables::lidt(&idtr);
+}
 
-    lidt_call(&idtr as *const DescriptorTablePointer as usize);
+///
+/// The IDT in use if it is one of ours, which is the case while an exception
+/// is being handled.
+///
+pub fn current() -> Option<&'static mut Idt> {
+    let mut idtr = DescriptorTablePointer::<IdtEntry>::default();
+    unsafe { dtables::sidt(&mut idtr) };
+    if { idtr.base }.is_null() || idtr.limit as usize != mem::size_of::<IdtEntries>() - 1 {
+        return None;
+    }
+    let idt = unsafe { &mut *(idtr.base as *mut Idt) };
+    if idt.entries[0].offset() != interrupt_stub_table as usize {
+        return None;
+    }
+    Some(idt)
+}
+
+pub fn current_policy() -> ExceptionPolicy {
+    current().map_or(ExceptionPolicy::Halt, |idt| idt.policy)
 }
 
 pub type IdtEntries = [IdtEntry; 256];
 
 // 8 alignment required
+// The dispatcher finds the handlers, the policy and what the backtrace needs
+// after the entries, from IDTR
 #[repr(C, align(8))]
 pub struct Idt {
     entries: IdtEntries,
+    handlers: [Option<Handler>; 256],
+    contexts: [usize; 256],
+    policy: ExceptionPolicy,
+    stack: Range<usize>,
+    module: Option<Module<'static>>,
+}
+
+impl Default for Idt {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Idt {
     pub fn new() -> Self {
         let mut idt = Self {
             entries: [IdtEntry::new(); 256],
+            handlers: [None; 256],
+            contexts: [0; 256],
+            policy: ExceptionPolicy::Halt,
+            stack: 0..0,
+            module: None,
         };
         idt.init();
         idt
     }
+
+    ///
+    /// Handle the vector with the handler. A vector without a handler gets the
+    /// crash report.
+    ///
+    pub fn register_handler(&mut self, vector: u8, handler: Handler) {
+        self.handlers[vector as usize] = Some(handler);
+    }
+
+    pub fn unregister_handler(&mut self, vector: u8) {
+        self.handlers[vector as usize] = None;
+    }
+
+    pub fn handler(&self, vector: u8) -> Option<Handler> {
+        self.handlers[vector as usize]
+    }
+
+    ///
+    /// A value for the handler of the vector, such as the address of its
+    /// state, since the IPL runs from flash without writable statics.
+    ///
+    pub fn set_context(&mut self, vector: u8, context: usize) {
+        self.contexts[vector as usize] = context;
+    }
+
+    pub fn context(&self, vector: u8) -> usize {
+        self.contexts[vector as usize]
+    }
+
+    /// What to do once an exception is reported.
+    pub fn set_policy(&mut self, policy: ExceptionPolicy) {
+        self.policy = policy;
+    }
+
+    ///
+    /// The stack the backtrace is allowed to walk, and the module its return
+    /// addresses are resolved with.
+    ///
+    pub fn set_backtrace(&mut self, stack: Range<usize>, module: Option<Module<'static>>) {
+        self.stack = stack;
+        self.module = module;
+    }
+
+    pub fn backtrace_stack(&self) -> Range<usize> {
+        self.stack.clone()
+    }
+
+    pub fn backtrace_modules(&self) -> &[Module<'static>] {
+        self.module.as_ref().map_or(&[], core::slice::from_ref)
+    }
+
+    ///
+    /// Load the IDT in IDTR.
+    ///
+    /// # Safety
+    ///
+    /// The IDT must stay in place until another one is loaded.
+    ///
+    pub unsafe fn load(&self) {
+        let idtr = DescriptorTablePointer {
+            limit: (self.entries.len() * mem::size_of::<IdtEntry>() - 1) as u16,
+            base: self.entries.as_ptr(),
+        };
+        dtables::lidt(&idtr);
+    }
     pub fn init(&mut self) {
-        let current_idt = &mut self.entries;
-        // Set up exceptions
-        current_idt[0].set_func(interrupt::divide_by_zero);
-        current_idt[1].set_func(interrupt::debug);
-        current_idt[2].set_func(interrupt::non_maskable);
-        current_idt[3].set_func(interrupt::breakpoint);
-        current_idt[4].set_func(interrupt::overflow);
-        current_idt[5].set_func(interrupt::bound_range);
-        current_idt[6].set_func(interrupt::invalid_opcode);
-        current_idt[7].set_func(interrupt::device_not_available);
-        current_idt[8].set_func(interrupt::double_fault);
-        // 9 no longer available
-        current_idt[10].set_func(interrupt::invalid_tss);
-        current_idt[11].set_func(interrupt::segment_not_present);
-        current_idt[12].set_func(interrupt::stack_segment);
-        current_idt[13].set_func(interrupt::protection);
-        current_idt[14].set_func(interrupt::page);
-        // 15 reserved
-        current_idt[16].set_func(interrupt::fpu);
-        current_idt[17].set_func(interrupt::alignment_check);
-        current_idt[18].set_func(interrupt::machine_check);
-        current_idt[19].set_func(interrupt::simd);
-        current_idt[20].set_func(interrupt::virtualization);
+        let cs = unsafe { read_cs_call() };
+        for (vector, entry) in self.entries.iter_mut().enumerate() {
+            entry.set_flags(IdtFlags::PRESENT | IdtFlags::RING_0 | IdtFlags::INTERRUPT);
+            entry.set_offset(cs, interrupt_stub_table as usize + vector * STUB_SIZE);
+        }
+        // Emulate the port IO which causes #VE
+        self.register_handler(20, interrupt::virtualization);
     }
 }
 
@@ -126,16 +211,14 @@ impl IdtEntry {
         self.attribute = flags.bits;
     }
 
+    pub fn offset(&self) -> usize {
+        self.offsetl as usize | (self.offsetm as usize) << 16 | (self.offseth as usize) << 32
+    }
+
     pub fn set_offset(&mut self, selector: u16, base: usize) {
         self.selector = selector;
         self.offsetl = base as u16;
         self.offsetm = (base >> 16) as u16;
         self.offseth = (base >> 32) as u32;
     }
-
-    // A function to set the offset more easily
-    pub fn set_func(&mut self, func: unsafe extern "C" fn()) {
-        self.set_flags(IdtFlags::PRESENT | IdtFlags::RING_0 | IdtFlags::INTERRUPT);
-        self.set_offset(unsafe { read_cs_call() }, func as usize); // GDT_KERNEL_CODE 1u16
-    }
 }
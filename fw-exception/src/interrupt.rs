@@ -2,6 +2,9 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
+use crate::idt;
+use crate::report;
+
 #[allow(dead_code)]
 #[repr(packed)]
 pub struct ScratchRegisters {
@@ -30,36 +33,6 @@ impl ScratchRegisters {
     }
 }
 
-macro_rules! scratch_push {
-    () => (llvm_asm!(
-        "push rax
-        push rcx
-        push rdx
-        push rdi
-        push rsi
-        push r8
-        push r9
-        push r10
-        push r11"
-        : : : : "intel", "volatile"
-    ));
-}
-
-macro_rules! scratch_pop {
-    () => (llvm_asm!(
-        "pop r11
-        pop r10
-        pop r9
-        pop r8
-        pop rsi
-        pop rdi
-        pop rdx
-        pop rcx
-        pop rax"
-        : : : : "intel", "volatile"
-    ));
-}
-
 #[allow(dead_code)]
 #[repr(packed)]
 pub struct PreservedRegisters {
@@ -82,36 +55,15 @@ impl PreservedRegisters {
     }
 }
 
-macro_rules! preserved_push {
-    () => (llvm_asm!(
-        "push rbx
-        push rbp
-        push r12
-        push r13
-        push r14
-        push r15"
-        : : : : "intel", "volatile"
-    ));
-}
-
-macro_rules! preserved_pop {
-    () => (llvm_asm!(
-        "pop r15
-        pop r14
-        pop r13
-        pop r12
-        pop rbp
-        pop rbx"
-        : : : : "intel", "volatile"
-    ));
-}
-
+// The CPU always pushes SS:RSP in 64-bit mode
 #[allow(dead_code)]
 #[repr(packed)]
 pub struct IretRegisters {
     pub rip: usize,
     pub cs: usize,
     pub rflags: usize,
+    pub rsp: usize,
+    pub ss: usize,
 }
 
 impl IretRegisters {
@@ -119,212 +71,68 @@ impl IretRegisters {
         log::info!("RFLAG: {:>016X}\n", { self.rflags });
         log::info!("CS:    {:>016X}\n", { self.cs });
         log::info!("RIP:   {:>016X}\n", { self.rip });
-    }
-}
-
-macro_rules! iret {
-    () => (llvm_asm!(
-        "iretq"
-        : : : : "intel", "volatile"
-    ));
-}
-
-#[allow(dead_code)]
-#[repr(packed)]
-pub struct InterruptNoErrorStack {
-    pub preserved: PreservedRegisters,
-    pub scratch: ScratchRegisters,
-    pub iret: IretRegisters,
-}
-
-impl InterruptNoErrorStack {
-    pub fn dump(&self) {
-        self.iret.dump();
-        self.scratch.dump();
-        self.preserved.dump();
+        log::info!("SS:    {:>016X}\n", { self.ss });
+        log::info!("RSP:   {:>016X}\n", { self.rsp });
     }
 }
 
 #[allow(dead_code)]
 #[repr(packed)]
-pub struct InterruptErrorStack {
+pub struct InterruptStack {
     pub preserved: PreservedRegisters,
     pub scratch: ScratchRegisters,
+    pub vector: usize,
     pub code: usize,
     pub iret: IretRegisters,
 }
 
-impl InterruptErrorStack {
+impl InterruptStack {
     pub fn dump(&self) {
         self.iret.dump();
-        log::info!("CODE:  {:>016X}\n", { self.code });
+        if has_error_code(self.vector as u8) {
+            log::info!("CODE:  {:>016X}\n", { self.code });
+        }
         self.scratch.dump();
         self.preserved.dump();
     }
 }
 
-#[macro_export]
-macro_rules! interrupt_no_error {
-    ($name:ident, $stack: ident, $func:block) => {
-        #[naked]
-        #[no_mangle]
-        pub unsafe extern fn $name () {
-            #[inline(never)]
-            unsafe fn inner($stack: &mut InterruptNoErrorStack) {
-                $func
-            }
-
-            // Push scratch registers
-            scratch_push!();
-            preserved_push!();
-
-            // Get reference to stack variables
-            let rsp: usize;
-            llvm_asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
-            llvm_asm!("cld" : : : : "intel", "volatile");
-
-            // Call inner rust function
-            llvm_asm!("sub rsp, 32" : : : : "intel", "volatile");
-            inner(&mut *(rsp as *mut InterruptNoErrorStack));
-            llvm_asm!("add rsp, 32" : : : : "intel", "volatile");
-
-            // Pop scratch registers and return
-            preserved_pop!();
-            scratch_pop!();
-            iret!();
-        }
-    };
+/// What the dispatcher does once a handler returns.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Return to the interrupted code, with the registers of the stack.
+    Resume,
+    /// Print the crash report, then halt or reset as the policy says.
+    Crash,
 }
 
-#[macro_export]
-macro_rules! interrupt_error {
-    ($name:ident, $stack:ident, $func:block) => {
-        #[naked]
-        #[no_mangle]
-        pub unsafe extern fn $name () {
-            #[inline(never)]
-            unsafe fn inner($stack: &mut InterruptErrorStack) {
-                $func
-            }
+pub type Handler = fn(&mut InterruptStack) -> Action;
 
-            // Push scratch registers
-            scratch_push!();
-            preserved_push!();
-
-            // Get reference to stack variables
-            let rsp: usize;
-            llvm_asm!("" : "={rsp}"(rsp) : : : "intel", "volatile");
+/// The exceptions for which the CPU pushes an error code.
+pub fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+}
 
-            // Call inner rust function
-            llvm_asm!("sub rsp, 40" : : : : "intel", "volatile");
-            inner(&mut *(rsp as *mut InterruptErrorStack));
-            llvm_asm!("add rsp, 40" : : : : "intel", "volatile");
-            // Pop scratch registers, error code, and return
-            preserved_pop!();
-            scratch_pop!();
-            llvm_asm!("add rsp, 8" : : : : "intel", "volatile");
-            iret!();
-        }
+// Called by interrupt_common of the stub table for every vector
+#[no_mangle]
+extern "win64" fn interrupt_dispatch(stack: &mut InterruptStack) {
+    let vector = stack.vector as u8;
+    let handler = idt::current().and_then(|idt| idt.handler(vector));
+    let action = match handler {
+        Some(handler) => handler(stack),
+        None => Action::Crash,
     };
+    if action == Action::Crash {
+        report::crash(stack);
+    }
 }
 
-interrupt_no_error!(divide_by_zero, stack, {
-    log::info!("Divide by zero\n");
-    stack.dump();
-});
-
-interrupt_no_error!(debug, stack, {
-    log::info!("Debug trap\n");
-    stack.dump();
-});
-
-interrupt_no_error!(non_maskable, stack, {
-    log::info!("Non-maskable interrupt\n");
-    stack.dump();
-});
-
-interrupt_no_error!(breakpoint, stack, {
-    log::info!("Breakpoint trap\n");
-    stack.dump();
-});
-
-interrupt_no_error!(overflow, stack, {
-    log::info!("Overflow trap\n");
-    stack.dump();
-});
-
-interrupt_no_error!(bound_range, stack, {
-    log::info!("Bound range exceeded fault\n");
-    stack.dump();
-});
-
-interrupt_no_error!(invalid_opcode, stack, {
-    log::info!("Invalid opcode fault\n");
-    stack.dump();
-});
-
-interrupt_no_error!(device_not_available, stack, {
-    log::info!("Device not available fault\n");
-    stack.dump();
-});
-
-interrupt_error!(double_fault, stack, {
-    log::info!("Double fault\n");
-    stack.dump();
-    loop {}
-});
-
-interrupt_error!(invalid_tss, stack, {
-    log::info!("Invalid TSS fault\n");
-    stack.dump();
-});
-
-interrupt_error!(segment_not_present, stack, {
-    log::info!("Segment not present fault\n");
-    stack.dump();
-});
-
-interrupt_error!(stack_segment, stack, {
-    log::info!("Stack segment fault\n");
-    stack.dump();
-});
-
-interrupt_error!(protection, stack, {
-    log::info!("Protection fault\n");
-    stack.dump();
-});
-
-interrupt_error!(page, stack, {
-    let cr2: usize;
-    llvm_asm!("mov rax, cr2" : "={rax}"(cr2) : : : "intel", "volatile");
-    log::info!("Page fault: {:>016X}\n", cr2);
-    stack.dump();
-    loop {}
-});
-
-interrupt_no_error!(fpu, stack, {
-    log::info!("FPU floating point fault\n");
-    stack.dump();
-});
-
-interrupt_error!(alignment_check, stack, {
-    log::info!("Alignment check fault");
-    stack.dump();
-    loop {}
-});
-
-interrupt_no_error!(machine_check, stack, {
-    log::info!("Machine check fault\n");
-    stack.dump();
-});
-
-interrupt_no_error!(simd, stack, {
-    log::info!("SIMD floating point fault\n");
-    stack.dump();
-});
-
-interrupt_no_error!(virtualization, stack, {
-    let op_code: u8 = *(stack.iret.rip as *const u8);
+///
+/// The default handler of #VE: emulate IN AL, DX and OUT DX, AL, report the
+/// other instructions.
+///
+pub fn virtualization(stack: &mut InterruptStack) -> Action {
+    let op_code: u8 = unsafe { *(stack.iret.rip as *const u8) };
     match op_code {
         // IN
         0xE4 => {
@@ -335,11 +143,11 @@ interrupt_no_error!(virtualization, stack, {
         }
         0xEC => {
             log::info!("<IN AL, DX>\n");
-            let al = x86::io::inb((stack.scratch.rdx & 0xFFFF) as u16);
+            let al = unsafe { x86::io::inb((stack.scratch.rdx & 0xFFFF) as u16) };
             stack.scratch.rax = (stack.scratch.rax & 0xFFFF_FFFF_FFFF_FF00_usize) | al as usize;
             stack.iret.rip += 1;
             // log::info!("Fault done\n");
-            return;
+            return Action::Resume;
         }
         0xED => {
             log::info!("<IN EAX, DX>")
@@ -353,13 +161,15 @@ interrupt_no_error!(virtualization, stack, {
         }
         0xEE => {
             log::info!("<OUT DX, AL>\n");
-            x86::io::outb(
-                (stack.scratch.rdx & 0xFFFF) as u16,
-                (stack.scratch.rax & 0xFF) as u8,
-            );
+            unsafe {
+                x86::io::outb(
+                    (stack.scratch.rdx & 0xFFFF) as u16,
+                    (stack.scratch.rax & 0xFF) as u8,
+                )
+            };
             stack.iret.rip += 1;
             // log::info!("Fault done\n");
-            return;
+            return Action::Resume;
         }
         0xEF => {
             log::info!("<OUT DX, EAX>")
@@ -367,7 +177,5 @@ interrupt_no_error!(virtualization, stack, {
         // Unknown
         _ => {}
     };
-    log::info!("Virtualization fault\n");
-    stack.dump();
-    loop {}
-});
+    Action::Crash
+}
@@ -2,15 +2,114 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
-#![no_std]
-#![feature(llvm_asm)]
-#![feature(naked_functions)]
+#![cfg_attr(not(test), no_std)]
 #![feature(global_asm)]
 
 mod asm;
+pub mod backtrace;
+mod eoi;
 mod idt;
 mod interrupt;
+mod report;
+
+use core::ops::Range;
+
+pub use backtrace::{Location, Module, SymbolTable};
+pub use eoi::{local_apic_eoi, pic_eoi};
+pub use idt::Idt;
+pub use interrupt::{
+    Action, Handler, InterruptStack, IretRegisters, PreservedRegisters, ScratchRegisters,
+};
+pub use report::{exception_name, ExceptionPolicy};
 
 pub fn setup_exception_handlers() {
     unsafe { idt::init() };
 }
+
+///
+/// Remove the exception handlers, for example before the memory of the IDT
+/// is given to another phase.
+///
+pub fn clear_exception_handlers() {
+    unsafe { idt::clear() };
+}
+
+///
+/// Handle the vector with the handler in the IDT in use.
+///
+/// Return false if the IDT in use is not one of this crate.
+///
+pub fn register_handler(vector: u8, handler: Handler) -> bool {
+    match idt::current() {
+        Some(idt) => {
+            idt.register_handler(vector, handler);
+            true
+        }
+        None => false,
+    }
+}
+
+///
+/// Give the vector back to the crash report in the IDT in use.
+///
+/// Return false if the IDT in use is not one of this crate.
+///
+pub fn unregister_handler(vector: u8) -> bool {
+    match idt::current() {
+        Some(idt) => {
+            idt.unregister_handler(vector);
+            true
+        }
+        None => false,
+    }
+}
+
+///
+/// Set the context of the vector in the IDT in use.
+///
+/// Return false if the IDT in use is not one of this crate.
+///
+pub fn set_handler_context(vector: u8, context: usize) -> bool {
+    match idt::current() {
+        Some(idt) => {
+            idt.set_context(vector, context);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The context of the vector in the IDT in use, 0 if none was set.
+pub fn handler_context(vector: u8) -> usize {
+    idt::current().map_or(0, |idt| idt.context(vector))
+}
+
+///
+/// Set the stack and the module of the backtraces in the IDT in use.
+///
+/// Return false if the IDT in use is not one of this crate.
+///
+pub fn set_backtrace(stack: Range<usize>, module: Option<Module<'static>>) -> bool {
+    match idt::current() {
+        Some(idt) => {
+            idt.set_backtrace(stack, module);
+            true
+        }
+        None => false,
+    }
+}
+
+///
+/// Print the backtrace of the caller, for example from a panic handler, with
+/// the stack and the module of the IDT in use.
+///
+// not inlined, rip and rbp are those of this function
+#[inline(never)]
+pub fn print_backtrace() {
+    let idt = match idt::current() {
+        Some(idt) => idt,
+        None => return,
+    };
+    let (rip, rbp) = unsafe { (backtrace::read_rip_call(), backtrace::read_rbp_call()) };
+    backtrace::print_backtrace(rip, rbp, idt.backtrace_stack(), idt.backtrace_modules());
+}
@@ -0,0 +1,212 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! The crash report of an exception, and what is done after it.
+//!
+
+use core::ops::Range;
+
+use crate::backtrace;
+use crate::idt;
+use crate::interrupt::{self, InterruptStack};
+
+// qwords of the interrupted stack in the report
+const STACK_DUMP_QWORDS: usize = 32;
+
+// reset control register, full reset
+const RESET_CONTROL_PORT: u16 = 0xCF9;
+const RESET_CONTROL_FULL_RESET: u8 = 0x06;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExceptionPolicy {
+    /// Halt the CPU, the report stays on the console.
+    Halt,
+    /// Reset the platform through the reset control register.
+    Reset,
+}
+
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "#DE Divide Error",
+        1 => "#DB Debug",
+        2 => "NMI Interrupt",
+        3 => "#BP Breakpoint",
+        4 => "#OF Overflow",
+        5 => "#BR BOUND Range Exceeded",
+        6 => "#UD Invalid Opcode",
+        7 => "#NM Device Not Available",
+        8 => "#DF Double Fault",
+        9 => "Coprocessor Segment Overrun",
+        10 => "#TS Invalid TSS",
+        11 => "#NP Segment Not Present",
+        12 => "#SS Stack-Segment Fault",
+        13 => "#GP General Protection",
+        14 => "#PF Page Fault",
+        16 => "#MF x87 FPU Floating-Point Error",
+        17 => "#AC Alignment Check",
+        18 => "#MC Machine Check",
+        19 => "#XM SIMD Floating-Point",
+        20 => "#VE Virtualization",
+        21 => "#CP Control Protection",
+        15 | 22..=31 => "Reserved",
+        _ => "Interrupt",
+    }
+}
+
+// page fault error code bits reported by name
+const PAGE_FAULT_CODE_BITS: [(usize, &str); 5] = [
+    (3, "reserved bit set"),
+    (4, "instruction fetch"),
+    (5, "protection key"),
+    (6, "shadow stack"),
+    (15, "SGX"),
+];
+
+fn dump_page_fault_code(code: usize) {
+    let present = code & (1 << 0) != 0;
+    let write = code & (1 << 1) != 0;
+    let user = code & (1 << 2) != 0;
+    log::info!(
+        "  {}, {}, {} mode\n",
+        if present {
+            "protection violation"
+        } else {
+            "page not present"
+        },
+        if write { "write" } else { "read" },
+        if user { "user" } else { "supervisor" },
+    );
+    for &(bit, name) in PAGE_FAULT_CODE_BITS.iter() {
+        if code & (1 << bit) != 0 {
+            log::info!("  {}\n", name);
+        }
+    }
+}
+
+fn dump_selector_code(code: usize) {
+    if code == 0 {
+        log::info!("  no selector\n");
+        return;
+    }
+    let table = if code & (1 << 1) != 0 {
+        "IDT"
+    } else if code & (1 << 2) != 0 {
+        "LDT"
+    } else {
+        "GDT"
+    };
+    log::info!(
+        "  {} index {:#X}{}\n",
+        table,
+        (code & 0xFFFF) >> 3,
+        if code & (1 << 0) != 0 {
+            ", external event"
+        } else {
+            ""
+        }
+    );
+}
+
+fn dump_control_registers() {
+    unsafe {
+        log::info!("CR0:   {:>016X}\n", x86::controlregs::cr0().bits());
+        log::info!("CR2:   {:>016X}\n", x86::controlregs::cr2());
+        log::info!("CR3:   {:>016X}\n", x86::controlregs::cr3());
+        log::info!("CR4:   {:>016X}\n", x86::controlregs::cr4().bits());
+    }
+}
+
+// Qwords separated by spaces
+struct Qwords<'a>(&'a [u64]);
+
+impl<'a> core::fmt::Display for Qwords<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, qword) in self.0.iter().enumerate() {
+            if index != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:>016X}", qword)?;
+        }
+        Ok(())
+    }
+}
+
+// Nothing outside of the stack is read, the fault may come from a bad rsp
+fn dump_stack(rsp: usize, stack: Range<usize>) {
+    log::info!("Stack at {:>016X}:\n", rsp);
+    if rsp % 8 != 0 || !stack.contains(&rsp) {
+        log::info!(
+            "  not in the stack {:>016X}-{:>016X}\n",
+            stack.start,
+            stack.end
+        );
+        return;
+    }
+    let qwords = ((stack.end - rsp) / 8).min(STACK_DUMP_QWORDS);
+    let dump = unsafe { core::slice::from_raw_parts(rsp as *const u64, qwords) };
+    for (index, line) in dump.chunks(4).enumerate() {
+        log::info!("{:>016X}: {}\n", rsp + index * 32, Qwords(line));
+    }
+}
+
+fn dump_exception(vector: u8, code: Option<usize>) {
+    log::info!(
+        "!!!! X64 Exception Type - {:02X}({}) CPU Apic ID - {:08X} !!!!\n",
+        vector,
+        exception_name(vector),
+        unsafe { core::arch::x86_64::__cpuid(1).ebx } >> 24
+    );
+    if let Some(code) = code {
+        log::info!("Error code: {:>016X}\n", code);
+        match vector {
+            14 => dump_page_fault_code(code),
+            10..=13 => dump_selector_code(code),
+            _ => {}
+        }
+    }
+    dump_control_registers();
+}
+
+fn end() -> ! {
+    match idt::current_policy() {
+        ExceptionPolicy::Reset => {
+            log::info!("Reset\n");
+            unsafe { x86::io::outb(RESET_CONTROL_PORT, RESET_CONTROL_FULL_RESET) };
+        }
+        ExceptionPolicy::Halt => log::info!("Halt\n"),
+    }
+    loop {
+        unsafe {
+            x86::irq::disable();
+            x86::halt();
+        }
+    }
+}
+
+pub fn crash(stack: &InterruptStack) -> ! {
+    let vector = stack.vector as u8;
+    let code = if interrupt::has_error_code(vector) {
+        Some(stack.code)
+    } else {
+        None
+    };
+    dump_exception(vector, code);
+    stack.dump();
+    let idt = idt::current();
+    dump_stack(
+        stack.iret.rsp,
+        idt.as_ref().map_or(0..0, |idt| idt.backtrace_stack()),
+    );
+    if let Some(idt) = idt {
+        backtrace::print_backtrace(
+            stack.iret.rip,
+            stack.preserved.rbp,
+            idt.backtrace_stack(),
+            idt.backtrace_modules(),
+        );
+    }
+    end()
+}
@@ -0,0 +1,289 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! High Precision Event Timer: a main counter of at least 10 MHz and
+//! comparators raising an interrupt on an IO-APIC input.
+//!
+
+/// The HPET of QEMU q35 and of most Intel chipsets.
+pub const HPET_DEFAULT_BASE: u64 = 0xFED0_0000;
+
+const HPET_CAPABILITIES: u64 = 0x000;
+const HPET_CONFIGURATION: u64 = 0x010;
+const HPET_MAIN_COUNTER: u64 = 0x0F0;
+const HPET_TIMER_CONFIGURATION: u64 = 0x100;
+const HPET_TIMER_COMPARATOR: u64 = 0x108;
+const HPET_TIMER_STRIDE: u64 = 0x20;
+
+// Capabilities: the period in fs in 63:32, a 64-bit main counter in 13,
+// the last timer in 12:8
+const CAPABILITIES_PERIOD_SHIFT: u64 = 32;
+const CAPABILITIES_COUNT_SIZE: u64 = 1 << 13;
+const CAPABILITIES_LAST_TIMER_SHIFT: u64 = 8;
+const CAPABILITIES_LAST_TIMER_MASK: u64 = 0x1F;
+// the specification caps the period at 100 ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+// the next comparator write sets the accumulator of a periodic timer
+const TIMER_VALUE_SET: u64 = 1 << 6;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+const TIMER_ROUTE_CAPABILITIES_SHIFT: u64 = 32;
+
+const FS_PER_NS: u64 = 1_000_000;
+
+// ACPI HPET table: the ACPI header, the event timer block ID, then the base
+// address as a GAS { space: u8, bit width, bit offset, access size, address: u64 }
+const HPET_TABLE_SIGNATURE: &[u8] = b"HPET";
+const HPET_TABLE_BASE_SPACE: usize = 40;
+const HPET_TABLE_BASE_ADDRESS: usize = 44;
+const GAS_SYSTEM_MEMORY: u8 = 0;
+
+/// The base address of the HPET of the ACPI HPET table.
+pub fn hpet_base_from_acpi(table: &[u8]) -> Option<u64> {
+    if table.get(0..4)? != HPET_TABLE_SIGNATURE
+        || *table.get(HPET_TABLE_BASE_SPACE)? != GAS_SYSTEM_MEMORY
+    {
+        return None;
+    }
+    let mut address = [0u8; 8];
+    address.copy_from_slice(table.get(HPET_TABLE_BASE_ADDRESS..HPET_TABLE_BASE_ADDRESS + 8)?);
+    Some(u64::from_le_bytes(address))
+}
+
+/// Counter ticks of period_fs to ns, saturated.
+pub fn ticks_to_ns(ticks: u64, period_fs: u64) -> u64 {
+    let ns = ticks as u128 * period_fs as u128 / FS_PER_NS as u128;
+    ns.min(u64::MAX as u128) as u64
+}
+
+/// ns to counter ticks of period_fs, at least 1.
+pub fn ns_to_ticks(ns: u64, period_fs: u64) -> u64 {
+    ((ns as u128 * FS_PER_NS as u128 / period_fs as u128) as u64).max(1)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hpet {
+    base: u64,
+    period_fs: u64,
+    // the bits of the main counter, a 32-bit one wraps
+    counter_mask: u64,
+}
+
+impl Hpet {
+    /// The HPET at base, None if nothing there reads as one.
+    pub fn new(base: u64) -> Option<Self> {
+        let capabilities = unsafe { core::ptr::read_volatile(base as *const u64) };
+        let period_fs = capabilities >> CAPABILITIES_PERIOD_SHIFT;
+        if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+            return None;
+        }
+        let counter_mask = if capabilities & CAPABILITIES_COUNT_SIZE != 0 {
+            u64::MAX
+        } else {
+            u64::from(u32::MAX)
+        };
+        Some(Self {
+            base,
+            period_fs,
+            counter_mask,
+        })
+    }
+
+    /// The HPET of the ACPI HPET table if any, else the one at the default base.
+    pub fn from_acpi(table: Option<&[u8]>) -> Option<Self> {
+        let base = table.and_then(hpet_base_from_acpi);
+        Self::new(base.unwrap_or(HPET_DEFAULT_BASE))
+    }
+
+    fn read(&self, register: u64) -> u64 {
+        unsafe { core::ptr::read_volatile((self.base + register) as *const u64) }
+    }
+
+    fn write(&self, register: u64, value: u64) {
+        unsafe { core::ptr::write_volatile((self.base + register) as *mut u64, value) }
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// The period of the main counter in fs.
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    ///
+    /// The bits of the main counter, u32::MAX for a 32-bit counter which
+    /// wraps in minutes, every 43 s at 100 MHz.
+    ///
+    pub fn counter_mask(&self) -> u64 {
+        self.counter_mask
+    }
+
+    /// The frequency of the main counter in Hz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000 * FS_PER_NS / self.period_fs
+    }
+
+    /// The count of comparators.
+    pub fn timers(&self) -> u8 {
+        ((self.read(HPET_CAPABILITIES) >> CAPABILITIES_LAST_TIMER_SHIFT)
+            & CAPABILITIES_LAST_TIMER_MASK) as u8
+            + 1
+    }
+
+    /// Start the main counter.
+    pub fn enable(&self) {
+        let configuration = self.read(HPET_CONFIGURATION);
+        self.write(HPET_CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+    }
+
+    pub fn disable(&self) {
+        let configuration = self.read(HPET_CONFIGURATION);
+        self.write(HPET_CONFIGURATION, configuration & !CONFIGURATION_ENABLE);
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.read(HPET_MAIN_COUNTER) & self.counter_mask
+    }
+
+    /// The time since the main counter started, modulo the counter wrap.
+    pub fn now_ns(&self) -> u64 {
+        ticks_to_ns(self.counter(), self.period_fs)
+    }
+
+    fn read_timer(&self, timer: u8, register: u64) -> u64 {
+        self.read(register + timer as u64 * HPET_TIMER_STRIDE)
+    }
+
+    fn write_timer(&self, timer: u8, register: u64, value: u64) {
+        self.write(register + timer as u64 * HPET_TIMER_STRIDE, value)
+    }
+
+    // The configuration of the timer routed to the IO-APIC input, None if
+    // the timer or the route does not exist
+    fn timer_configuration(&self, timer: u8, irq: u8) -> Option<u64> {
+        if timer >= self.timers() || irq >= 32 {
+            return None;
+        }
+        let configuration = self.read_timer(timer, HPET_TIMER_CONFIGURATION);
+        if (configuration >> TIMER_ROUTE_CAPABILITIES_SHIFT) & (1 << irq) == 0 {
+            return None;
+        }
+        Some(
+            configuration & !(TIMER_ROUTE_MASK | TIMER_PERIODIC | TIMER_INTERRUPT_ENABLE)
+                | (irq as u64) << TIMER_ROUTE_SHIFT,
+        )
+    }
+
+    ///
+    /// Raise IRQ once delta_ns from now on the IO-APIC input irq, edge
+    /// triggered. Return false if the timer cannot be routed there.
+    ///
+    pub fn set_one_shot(&self, timer: u8, delta_ns: u64, irq: u8) -> bool {
+        let configuration = match self.timer_configuration(timer, irq) {
+            Some(configuration) => configuration,
+            None => return false,
+        };
+        let comparator =
+            (self.counter() + ns_to_ticks(delta_ns, self.period_fs)) & self.counter_mask;
+        self.write_timer(timer, HPET_TIMER_CONFIGURATION, configuration);
+        self.write_timer(timer, HPET_TIMER_COMPARATOR, comparator);
+        let configuration = configuration | TIMER_INTERRUPT_ENABLE;
+        self.write_timer(timer, HPET_TIMER_CONFIGURATION, configuration);
+        true
+    }
+
+    ///
+    /// Raise IRQ every period_ns on the IO-APIC input irq, edge triggered.
+    /// Return false if the timer is not periodic capable or cannot be routed
+    /// there.
+    ///
+    pub fn set_periodic(&self, timer: u8, period_ns: u64, irq: u8) -> bool {
+        let configuration = match self.timer_configuration(timer, irq) {
+            Some(configuration) if configuration & TIMER_PERIODIC_CAPABLE != 0 => configuration,
+            _ => return false,
+        };
+        let period = ns_to_ticks(period_ns, self.period_fs);
+        let configuration = configuration | TIMER_PERIODIC | TIMER_INTERRUPT_ENABLE;
+        self.write_timer(
+            timer,
+            HPET_TIMER_CONFIGURATION,
+            configuration | TIMER_VALUE_SET,
+        );
+        // the first expiry, then the period added on each one
+        let first = (self.counter() + period) & self.counter_mask;
+        self.write_timer(timer, HPET_TIMER_COMPARATOR, first);
+        self.write_timer(timer, HPET_TIMER_COMPARATOR, period);
+        true
+    }
+
+    pub fn stop_timer(&self, timer: u8) {
+        if timer >= self.timers() {
+            return;
+        }
+        let configuration = self.read_timer(timer, HPET_TIMER_CONFIGURATION);
+        let configuration = configuration & !(TIMER_PERIODIC | TIMER_INTERRUPT_ENABLE);
+        self.write_timer(timer, HPET_TIMER_CONFIGURATION, configuration);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hpet_base_from_acpi() {
+        let mut table = [0u8; 56];
+        table[0..4].copy_from_slice(b"HPET");
+        table[44..52].copy_from_slice(&0xFED0_0000u64.to_le_bytes());
+        assert_eq!(hpet_base_from_acpi(&table), Some(HPET_DEFAULT_BASE));
+        // in I/O space
+        table[40] = 1;
+        assert_eq!(hpet_base_from_acpi(&table), None);
+        table[40] = 0;
+        assert_eq!(hpet_base_from_acpi(&table[..48]), None);
+        table[0..4].copy_from_slice(b"APIC");
+        assert_eq!(hpet_base_from_acpi(&table), None);
+    }
+
+    #[test]
+    fn test_counter_size() {
+        // the registers up to the main counter
+        let mut registers = vec![0u64; HPET_MAIN_COUNTER as usize / 8 + 1];
+        let base = registers.as_mut_ptr() as u64;
+        registers[HPET_MAIN_COUNTER as usize / 8] = 0x1_2345_6789;
+        registers[0] = (10_000_000 << CAPABILITIES_PERIOD_SHIFT) | CAPABILITIES_COUNT_SIZE;
+        let hpet = Hpet::new(base).unwrap();
+        assert_eq!(hpet.counter_mask(), u64::MAX);
+        assert_eq!(hpet.counter(), 0x1_2345_6789);
+        registers[0] = 10_000_000 << CAPABILITIES_PERIOD_SHIFT;
+        let hpet = Hpet::new(base).unwrap();
+        assert_eq!(hpet.counter_mask(), 0xFFFF_FFFF);
+        assert_eq!(hpet.counter(), 0x2345_6789);
+        registers[0] = 0;
+        assert_eq!(Hpet::new(base), None);
+    }
+
+    #[test]
+    fn test_ticks() {
+        // the 100 MHz counter of QEMU
+        let period_fs = 10_000_000;
+        assert_eq!(ticks_to_ns(100, period_fs), 1000);
+        assert_eq!(ns_to_ticks(1000, period_fs), 100);
+        assert_eq!(ns_to_ticks(0, period_fs), 1);
+        // 14.31818 MHz
+        let period_fs = 69_841_279;
+        assert_eq!(ticks_to_ns(14_318_180, period_fs), 1_000_000_004);
+        assert_eq!(ns_to_ticks(1_000_000_000, period_fs), 14_318_179);
+        assert_eq!(ticks_to_ns(u64::MAX, period_fs), u64::MAX);
+    }
+}
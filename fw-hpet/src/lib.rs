@@ -0,0 +1,8 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+#![cfg_attr(not(test),no_std)]
+
+mod hpet;
+pub use hpet::*;
@@ -0,0 +1,624 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! ECDSA SHA-384 verification on the NIST P-384 curve.
+//!
+//! As for RSA, the arithmetic is Montgomery multiplication on fixed size
+//! numbers, without allocation. It is not constant time, signing with it is
+//! only done on the build host.
+//!
+
+use core::cmp::Ordering;
+
+use crate::{
+    VerifyError, ECDSA_P384_PUBLIC_KEY_SIZE, ECDSA_P384_SIGNATURE_SIZE, P384_SCALAR_SIZE,
+    SHA384_DIGEST_SIZE,
+};
+
+const LIMBS: usize = P384_SCALAR_SIZE / 8;
+
+// least significant limb first
+pub(crate) type Uint = [u64; LIMBS];
+
+// p = 2^384 - 2^128 - 2^96 + 2^32 - 1
+const P: Uint = [
+    0x0000_0000_ffff_ffff,
+    0xffff_ffff_0000_0000,
+    0xffff_ffff_ffff_fffe,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+];
+// the order of G
+const N: Uint = [
+    0xecec_196a_ccc5_2973,
+    0x581a_0db2_48b0_a77a,
+    0xc763_4d81_f437_2ddf,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+];
+// y^2 = x^3 - 3x + b
+const B: Uint = [
+    0x2a85_c8ed_d3ec_2aef,
+    0xc656_398d_8a2e_d19d,
+    0x0314_088f_5013_875a,
+    0x181d_9c6e_fe81_4112,
+    0x988e_056b_e3f8_2d19,
+    0xb331_2fa7_e23e_e7e4,
+];
+const GX: Uint = [
+    0x3a54_5e38_7276_0ab7,
+    0x5502_f25d_bf55_296c,
+    0x59f7_41e0_8254_2a38,
+    0x6e1d_3b62_8ba7_9b98,
+    0x8eb1_c71e_f320_ad74,
+    0xaa87_ca22_be8b_0537,
+];
+const GY: Uint = [
+    0x7a43_1d7c_90ea_0e5f,
+    0x0a60_b1ce_1d7e_819d,
+    0xe9da_3113_b5f0_b8c0,
+    0xf8f4_1dbd_289a_147c,
+    0x5d9e_98bf_9292_dc29,
+    0x3617_de4a_9626_2c6f,
+];
+
+const ZERO: Uint = [0; LIMBS];
+
+pub(crate) fn from_be_bytes(bytes: &[u8]) -> Uint {
+    assert_eq!(bytes.len(), P384_SCALAR_SIZE);
+    let mut value = ZERO;
+    for (i, limb) in value.iter_mut().enumerate() {
+        let end = P384_SCALAR_SIZE - i * 8;
+        let mut limb_bytes = [0u8; 8];
+        limb_bytes.copy_from_slice(&bytes[end - 8..end]);
+        *limb = u64::from_be_bytes(limb_bytes);
+    }
+    value
+}
+
+#[cfg(any(test, feature = "sign"))]
+pub(crate) fn to_be_bytes(value: &Uint) -> [u8; P384_SCALAR_SIZE] {
+    let mut bytes = [0u8; P384_SCALAR_SIZE];
+    for (i, limb) in value.iter().enumerate() {
+        let end = P384_SCALAR_SIZE - i * 8;
+        bytes[end - 8..end].copy_from_slice(&limb.to_be_bytes());
+    }
+    bytes
+}
+
+fn compare(a: &Uint, b: &Uint) -> Ordering {
+    for i in (0..LIMBS).rev() {
+        match a[i].cmp(&b[i]) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+// a + b mod 2^384, and the carry
+fn add(a: &Uint, b: &Uint) -> (Uint, bool) {
+    let mut sum = ZERO;
+    let mut carry = 0u64;
+    for i in 0..LIMBS {
+        let (s1, c1) = a[i].overflowing_add(b[i]);
+        let (s2, c2) = s1.overflowing_add(carry);
+        sum[i] = s2;
+        carry = (c1 | c2) as u64;
+    }
+    (sum, carry != 0)
+}
+
+// a - b mod 2^384, and the borrow
+fn sub(a: &Uint, b: &Uint) -> (Uint, bool) {
+    let mut difference = ZERO;
+    let mut borrow = 0u64;
+    for i in 0..LIMBS {
+        let (d1, b1) = a[i].overflowing_sub(b[i]);
+        let (d2, b2) = d1.overflowing_sub(borrow);
+        difference[i] = d2;
+        borrow = (b1 | b2) as u64;
+    }
+    (difference, borrow != 0)
+}
+
+// Arithmetic mod an odd m, the operands are below m
+struct Modulus {
+    m: Uint,
+    // -m^-1 mod 2^64
+    m0_inv: u64,
+    // R^2 mod m, R = 2^384
+    r2: Uint,
+}
+
+impl Modulus {
+    fn new(m: &Uint) -> Self {
+        // Newton iteration, each step doubles the number of correct low bits.
+        let mut inv = 1u64;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m[0].wrapping_mul(inv)));
+        }
+
+        // 2^(2 * 384) mod m by doubling 1.
+        let mut r2 = ZERO;
+        r2[0] = 1;
+        for _ in 0..2 * LIMBS * 64 {
+            let (doubled, carry) = add(&r2, &r2);
+            r2 = if carry || compare(&doubled, m) != Ordering::Less {
+                sub(&doubled, m).0
+            } else {
+                doubled
+            };
+        }
+
+        Modulus {
+            m: *m,
+            m0_inv: inv.wrapping_neg(),
+            r2,
+        }
+    }
+
+    // a mod m, with a < 2m
+    fn reduce(&self, a: &Uint) -> Uint {
+        if compare(a, &self.m) == Ordering::Less {
+            *a
+        } else {
+            sub(a, &self.m).0
+        }
+    }
+
+    fn add(&self, a: &Uint, b: &Uint) -> Uint {
+        let (sum, carry) = add(a, b);
+        if carry || compare(&sum, &self.m) != Ordering::Less {
+            sub(&sum, &self.m).0
+        } else {
+            sum
+        }
+    }
+
+    fn sub(&self, a: &Uint, b: &Uint) -> Uint {
+        let (difference, borrow) = sub(a, b);
+        if borrow {
+            add(&difference, &self.m).0
+        } else {
+            difference
+        }
+    }
+
+    // a * b * R^-1 mod m, with the names of the CIOS algorithm
+    #[allow(clippy::many_single_char_names)]
+    fn mul(&self, a: &Uint, b: &Uint) -> Uint {
+        let m = &self.m;
+        let mut t = [0u64; LIMBS + 2];
+        for &b_i in b.iter() {
+            let mut carry = 0u64;
+            for j in 0..LIMBS {
+                let x = t[j] as u128 + a[j] as u128 * b_i as u128 + carry as u128;
+                t[j] = x as u64;
+                carry = (x >> 64) as u64;
+            }
+            let x = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS] = x as u64;
+            t[LIMBS + 1] = (x >> 64) as u64;
+
+            let q = t[0].wrapping_mul(self.m0_inv);
+            let x = t[0] as u128 + q as u128 * m[0] as u128;
+            let mut carry = (x >> 64) as u64;
+            for j in 1..LIMBS {
+                let x = t[j] as u128 + q as u128 * m[j] as u128 + carry as u128;
+                t[j - 1] = x as u64;
+                carry = (x >> 64) as u64;
+            }
+            let x = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS - 1] = x as u64;
+            t[LIMBS] = t[LIMBS + 1] + (x >> 64) as u64;
+        }
+
+        let mut result = ZERO;
+        result.copy_from_slice(&t[..LIMBS]);
+        if t[LIMBS] != 0 || compare(&result, m) != Ordering::Less {
+            result = sub(&result, m).0;
+        }
+        result
+    }
+
+    fn to_montgomery(&self, a: &Uint) -> Uint {
+        self.mul(a, &self.r2)
+    }
+
+    fn to_canonical(&self, a: &Uint) -> Uint {
+        let mut one = ZERO;
+        one[0] = 1;
+        self.mul(a, &one)
+    }
+
+    // a^-1 = a^(m - 2) mod m for a prime m, in Montgomery form
+    fn inv(&self, a: &Uint) -> Uint {
+        let mut two = ZERO;
+        two[0] = 2;
+        let exponent = sub(&self.m, &two).0;
+        let mut one = ZERO;
+        one[0] = 1;
+        let mut result = self.to_montgomery(&one);
+        for bit in (0..LIMBS * 64).rev() {
+            result = self.mul(&result, &result);
+            if exponent[bit / 64] >> (bit % 64) & 1 != 0 {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+}
+
+// Jacobian coordinates in Montgomery form, (x / z^2, y / z^3), z is 0 at
+// infinity
+#[derive(Copy, Clone)]
+struct Point {
+    x: Uint,
+    y: Uint,
+    z: Uint,
+}
+
+impl Point {
+    fn is_infinity(&self) -> bool {
+        self.z == ZERO
+    }
+}
+
+struct Curve {
+    field: Modulus,
+    scalar: Modulus,
+    b: Uint,
+    g: Point,
+}
+
+impl Curve {
+    fn new() -> Self {
+        let field = Modulus::new(&P);
+        let b = field.to_montgomery(&B);
+        let mut one = ZERO;
+        one[0] = 1;
+        let g = Point {
+            x: field.to_montgomery(&GX),
+            y: field.to_montgomery(&GY),
+            z: field.to_montgomery(&one),
+        };
+        Curve {
+            field,
+            scalar: Modulus::new(&N),
+            b,
+            g,
+        }
+    }
+
+    fn infinity(&self) -> Point {
+        Point {
+            x: self.g.z,
+            y: self.g.z,
+            z: ZERO,
+        }
+    }
+
+    // The affine point (x, y), None if it is not on the curve
+    fn point(&self, x: &Uint, y: &Uint) -> Option<Point> {
+        if compare(x, &P) != Ordering::Less || compare(y, &P) != Ordering::Less {
+            return None;
+        }
+        let field = &self.field;
+        let x = field.to_montgomery(x);
+        let y = field.to_montgomery(y);
+        let x3 = field.mul(&field.mul(&x, &x), &x);
+        let three_x = field.add(&field.add(&x, &x), &x);
+        let rhs = field.add(&field.sub(&x3, &three_x), &self.b);
+        if field.mul(&y, &y) != rhs {
+            return None;
+        }
+        Some(Point { x, y, z: self.g.z })
+    }
+
+    // The affine coordinates, None at infinity
+    fn to_affine(&self, p: &Point) -> Option<(Uint, Uint)> {
+        if p.is_infinity() {
+            return None;
+        }
+        let field = &self.field;
+        let z_inv = field.inv(&p.z);
+        let z_inv2 = field.mul(&z_inv, &z_inv);
+        let x = field.mul(&p.x, &z_inv2);
+        let y = field.mul(&field.mul(&p.y, &z_inv2), &z_inv);
+        Some((field.to_canonical(&x), field.to_canonical(&y)))
+    }
+
+    // dbl-2001-b, a = -3, with the names of the formulas
+    #[allow(clippy::many_single_char_names)]
+    fn double(&self, p: &Point) -> Point {
+        if p.is_infinity() {
+            return *p;
+        }
+        let field = &self.field;
+        let delta = field.mul(&p.z, &p.z);
+        let gamma = field.mul(&p.y, &p.y);
+        let beta = field.mul(&p.x, &gamma);
+        let t = field.mul(&field.sub(&p.x, &delta), &field.add(&p.x, &delta));
+        let alpha = field.add(&field.add(&t, &t), &t);
+        let beta2 = field.add(&beta, &beta);
+        let beta4 = field.add(&beta2, &beta2);
+        let beta8 = field.add(&beta4, &beta4);
+        let x = field.sub(&field.mul(&alpha, &alpha), &beta8);
+        let yz = field.add(&p.y, &p.z);
+        let z = field.sub(&field.sub(&field.mul(&yz, &yz), &gamma), &delta);
+        let gamma2 = field.mul(&gamma, &gamma);
+        let gamma2_2 = field.add(&gamma2, &gamma2);
+        let gamma2_4 = field.add(&gamma2_2, &gamma2_2);
+        let gamma2_8 = field.add(&gamma2_4, &gamma2_4);
+        let y = field.sub(&field.mul(&alpha, &field.sub(&beta4, &x)), &gamma2_8);
+        Point { x, y, z }
+    }
+
+    // the names of the Jacobian addition formulas
+    #[allow(clippy::many_single_char_names)]
+    fn add(&self, p: &Point, q: &Point) -> Point {
+        if p.is_infinity() {
+            return *q;
+        }
+        if q.is_infinity() {
+            return *p;
+        }
+        let field = &self.field;
+        let z1z1 = field.mul(&p.z, &p.z);
+        let z2z2 = field.mul(&q.z, &q.z);
+        let u1 = field.mul(&p.x, &z2z2);
+        let u2 = field.mul(&q.x, &z1z1);
+        let s1 = field.mul(&field.mul(&p.y, &q.z), &z2z2);
+        let s2 = field.mul(&field.mul(&q.y, &p.z), &z1z1);
+        let h = field.sub(&u2, &u1);
+        let r = field.sub(&s2, &s1);
+        if h == ZERO {
+            return if r == ZERO {
+                self.double(p)
+            } else {
+                self.infinity()
+            };
+        }
+        let h2 = field.mul(&h, &h);
+        let h3 = field.mul(&h2, &h);
+        let u1h2 = field.mul(&u1, &h2);
+        let x = field.sub(
+            &field.sub(&field.mul(&r, &r), &h3),
+            &field.add(&u1h2, &u1h2),
+        );
+        let y = field.sub(&field.mul(&r, &field.sub(&u1h2, &x)), &field.mul(&s1, &h3));
+        let z = field.mul(&field.mul(&h, &p.z), &q.z);
+        Point { x, y, z }
+    }
+
+    // u1 * p + u2 * q, the bits of both scalars in one pass
+    fn mul_add(&self, u1: &Uint, p: &Point, u2: &Uint, q: &Point) -> Point {
+        let pq = self.add(p, q);
+        let mut result = self.infinity();
+        for bit in (0..LIMBS * 64).rev() {
+            result = self.double(&result);
+            let b1 = u1[bit / 64] >> (bit % 64) & 1 != 0;
+            let b2 = u2[bit / 64] >> (bit % 64) & 1 != 0;
+            result = match (b1, b2) {
+                (true, true) => self.add(&result, &pq),
+                (true, false) => self.add(&result, p),
+                (false, true) => self.add(&result, q),
+                (false, false) => result,
+            };
+        }
+        result
+    }
+
+    // 1 <= value < n
+    fn is_scalar(&self, value: &Uint) -> bool {
+        *value != ZERO && compare(value, &N) == Ordering::Less
+    }
+
+    // The digest as an integer mod n, it is as long as n
+    fn digest_scalar(&self, digest: &[u8; SHA384_DIGEST_SIZE]) -> Uint {
+        self.scalar.reduce(&from_be_bytes(digest))
+    }
+}
+
+///
+/// Verify an ECDSA P-384 signature of a SHA-384 digest.
+///
+/// public_key: the uncompressed point, 0x04 then the big endian x and y.
+/// signature: the big endian r followed by the big endian s.
+///
+// the names of SEC 1 section 4.1.4
+#[allow(clippy::many_single_char_names)]
+pub fn verify_sha384(
+    public_key: &[u8],
+    signature: &[u8],
+    digest: &[u8; SHA384_DIGEST_SIZE],
+) -> Result<(), VerifyError> {
+    if public_key.len() != ECDSA_P384_PUBLIC_KEY_SIZE
+        || signature.len() != ECDSA_P384_SIGNATURE_SIZE
+    {
+        return Err(VerifyError::InvalidSignatureFile);
+    }
+    let curve = Curve::new();
+    if public_key[0] != 0x04 {
+        return Err(VerifyError::InvalidPublicKey);
+    }
+    let q = curve
+        .point(
+            &from_be_bytes(&public_key[1..1 + P384_SCALAR_SIZE]),
+            &from_be_bytes(&public_key[1 + P384_SCALAR_SIZE..]),
+        )
+        .ok_or(VerifyError::InvalidPublicKey)?;
+
+    let r = from_be_bytes(&signature[..P384_SCALAR_SIZE]);
+    let s = from_be_bytes(&signature[P384_SCALAR_SIZE..]);
+    if !curve.is_scalar(&r) || !curve.is_scalar(&s) {
+        return Err(VerifyError::SignatureMismatch);
+    }
+
+    // w in Montgomery form, so the products with it are not
+    let scalar = &curve.scalar;
+    let w = scalar.inv(&scalar.to_montgomery(&s));
+    let u1 = scalar.mul(&curve.digest_scalar(digest), &w);
+    let u2 = scalar.mul(&r, &w);
+    let point = curve.mul_add(&u1, &curve.g, &u2, &q);
+    match curve.to_affine(&point) {
+        Some((x, _)) if scalar.reduce(&x) == r => Ok(()),
+        _ => Err(VerifyError::SignatureMismatch),
+    }
+}
+
+/// Whether the private key or the nonce is in [1, n - 1].
+#[cfg(any(test, feature = "sign"))]
+pub(crate) fn is_scalar(value: &Uint) -> bool {
+    Curve::new().is_scalar(value)
+}
+
+/// The digest as an integer mod n.
+#[cfg(any(test, feature = "sign"))]
+pub(crate) fn digest_scalar(digest: &[u8; SHA384_DIGEST_SIZE]) -> Uint {
+    Curve::new().digest_scalar(digest)
+}
+
+/// The uncompressed public key of the private key d.
+#[cfg(any(test, feature = "sign"))]
+pub(crate) fn public_key(d: &Uint) -> [u8; ECDSA_P384_PUBLIC_KEY_SIZE] {
+    let curve = Curve::new();
+    let point = curve.mul_add(d, &curve.g, &ZERO, &curve.g);
+    let (x, y) = curve.to_affine(&point).unwrap();
+    let mut public_key = [0u8; ECDSA_P384_PUBLIC_KEY_SIZE];
+    public_key[0] = 0x04;
+    public_key[1..1 + P384_SCALAR_SIZE].copy_from_slice(&to_be_bytes(&x));
+    public_key[1 + P384_SCALAR_SIZE..].copy_from_slice(&to_be_bytes(&y));
+    public_key
+}
+
+///
+/// Sign the digest with the private key d and the nonce k, both in
+/// [1, n - 1]. None if r or s is 0, another nonce must be taken.
+///
+#[cfg(any(test, feature = "sign"))]
+// the names of SEC 1 section 4.1.3
+#[allow(clippy::many_single_char_names)]
+pub(crate) fn sign_sha384(
+    d: &Uint,
+    k: &Uint,
+    digest: &[u8; SHA384_DIGEST_SIZE],
+) -> Option<[u8; ECDSA_P384_SIGNATURE_SIZE]> {
+    let curve = Curve::new();
+    let point = curve.mul_add(k, &curve.g, &ZERO, &curve.g);
+    let (x, _) = curve.to_affine(&point)?;
+    let scalar = &curve.scalar;
+    let r = scalar.reduce(&x);
+    if r == ZERO {
+        return None;
+    }
+    // s = k^-1 (e + r d), k^-1 in Montgomery form
+    let k_inv = scalar.inv(&scalar.to_montgomery(k));
+    let rd = scalar.mul(&r, &scalar.to_montgomery(d));
+    let s = scalar.mul(&scalar.add(&curve.digest_scalar(digest), &rd), &k_inv);
+    if s == ZERO {
+        return None;
+    }
+    let mut signature = [0u8; ECDSA_P384_SIGNATURE_SIZE];
+    signature[..P384_SCALAR_SIZE].copy_from_slice(&to_be_bytes(&r));
+    signature[P384_SCALAR_SIZE..].copy_from_slice(&to_be_bytes(&s));
+    Some(signature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::{Digest, Sha384};
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // RFC 6979 A.2.6, P-384 with SHA-384 of "sample"
+    const D: &str = "6b9d3dad2e1b8c1c05b19875b6659f4de23c3b667bf297ba9aa47740787137d896d5724e4c70a825f872c9ea60d2edf5";
+    const QX: &str = "ec3a4e415b4e19a4568618029f427fa5da9a8bc4ae92e02e06aae5286b300c64def8f0ea9055866064a254515480bc13";
+    const QY: &str = "8015d9b72d7d57244ea8ef9ac0c621896708a59367f9dfb9f54ca84b3f1c9db1288b231c3ae0d4fe7344fd2533264720";
+    const K: &str = "94ed910d1a099dad3254e9242ae85abde4ba15168eaf0ca87a555fd56d10fbca2907e3e83ba95368623b8c4686915cf9";
+    const R: &str = "94edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa73d64c4ea95ad133c81a648152e44acf96e36dd1e80fabe46";
+    const S: &str = "99ef4aeb15f178cea1fe40db2603138f130e740a19624526203b6351d0a3a94fa329c145786e679e7b82c71a38628ac8";
+
+    fn sample_digest() -> [u8; SHA384_DIGEST_SIZE] {
+        let mut digest = [0u8; SHA384_DIGEST_SIZE];
+        digest.copy_from_slice(&Sha384::digest(b"sample"));
+        digest
+    }
+
+    #[test]
+    fn test_modulus() {
+        let field = Modulus::new(&P);
+        let mut one = ZERO;
+        one[0] = 1;
+        let mut two = ZERO;
+        two[0] = 2;
+        let two_m = field.to_montgomery(&two);
+        let product = field.mul(&two_m, &field.inv(&two_m));
+        assert_eq!(field.to_canonical(&product), one);
+        // (p - 1) + 2 = 1, 1 - 2 = p - 1
+        let mut p_minus_1 = P;
+        p_minus_1[0] -= 1;
+        assert_eq!(field.add(&p_minus_1, &two), one);
+        assert_eq!(field.sub(&one, &two), p_minus_1);
+    }
+
+    #[test]
+    fn test_public_key() {
+        let d = from_be_bytes(&from_hex(D));
+        let public_key = public_key(&d);
+        assert_eq!(public_key[0], 0x04);
+        assert_eq!(&public_key[1..49], &from_hex(QX)[..]);
+        assert_eq!(&public_key[49..], &from_hex(QY)[..]);
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let d = from_be_bytes(&from_hex(D));
+        let k = from_be_bytes(&from_hex(K));
+        let digest = sample_digest();
+        let signature = sign_sha384(&d, &k, &digest).unwrap();
+        assert_eq!(&signature[..48], &from_hex(R)[..]);
+        assert_eq!(&signature[48..], &from_hex(S)[..]);
+
+        let public_key = public_key(&d);
+        assert_eq!(verify_sha384(&public_key, &signature, &digest), Ok(()));
+
+        let mut tampered = digest;
+        tampered[0] ^= 0x01;
+        assert_eq!(
+            verify_sha384(&public_key, &signature, &tampered),
+            Err(VerifyError::SignatureMismatch)
+        );
+        let mut tampered = signature;
+        tampered[95] ^= 0x01;
+        assert_eq!(
+            verify_sha384(&public_key, &tampered, &digest),
+            Err(VerifyError::SignatureMismatch)
+        );
+        // s = 0
+        let mut tampered = signature;
+        tampered[48..].iter_mut().for_each(|b| *b = 0);
+        assert_eq!(
+            verify_sha384(&public_key, &tampered, &digest),
+            Err(VerifyError::SignatureMismatch)
+        );
+        // not on the curve
+        let mut tampered = public_key;
+        tampered[96] ^= 0x01;
+        assert_eq!(
+            verify_sha384(&tampered, &signature, &digest),
+            Err(VerifyError::InvalidPublicKey)
+        );
+    }
+}
@@ -0,0 +1,438 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Firmware volume signing format and verification.
+//!
+//! A signed FV carries a RAW FFS file named FV_SIGNATURE_FILE_GUID:
+//!
+//! ```text
+//! FfsFileHeader | FvSignatureHeader | public key | signature
+//! ```
+//!
+//! The signature covers the whole FV except the signature file itself.
+//! The verifier is trusted through a RAW FFS file named
+//! PUBLIC_KEY_HASH_FILE_GUID holding a FvPublicKeyHash, which is placed in
+//! the IPL FV.
+//!
+
+#![cfg_attr(not(any(test, feature = "sign")), no_std)]
+#![forbid(unsafe_code)]
+
+use core::ops::Range;
+
+use r_efi::efi::Guid;
+use r_uefi_pi::fv::FfsFileHeader;
+use scroll::{Pread, Pwrite};
+use sha2::{Digest, Sha384};
+use uefi_pi::fv_lib;
+
+mod ecdsa_p384;
+mod rsa3072;
+
+#[cfg(any(test, feature = "sign"))]
+pub mod sign;
+
+// 4F2C6E1B-9A0D-4C59-8E3B-7D61A5C2F0E4
+pub const FV_SIGNATURE_FILE_GUID: Guid = Guid::from_fields(
+    0x4f2c6e1b,
+    0x9a0d,
+    0x4c59,
+    0x8e,
+    0x3b,
+    &[0x7d, 0x61, 0xa5, 0xc2, 0xf0, 0xe4],
+);
+
+// B7D5A3E9-61C2-4E0F-9A48-2F3C8D1E6B57
+pub const PUBLIC_KEY_HASH_FILE_GUID: Guid = Guid::from_fields(
+    0xb7d5a3e9,
+    0x61c2,
+    0x4e0f,
+    0x9a,
+    0x48,
+    &[0x2f, 0x3c, 0x8d, 0x1e, 0x6b, 0x57],
+);
+
+pub const FV_SIGNATURE_HEADER_SIGNATURE: u32 = 0x4753_5646; // 'F' 'V' 'S' 'G'
+pub const FV_PUBLIC_KEY_HASH_SIGNATURE: u32 = 0x4b50_5646; // 'F' 'V' 'P' 'K'
+pub const FV_SIGNATURE_VERSION: u16 = 1;
+
+/// RSASSA-PKCS1-v1_5 with SHA-384, 3072 bits modulus.
+pub const SIGNING_ALGORITHM_RSA3072_SHA384: u16 = 1;
+
+pub const SHA384_DIGEST_SIZE: usize = 48;
+pub const RSA3072_MODULUS_SIZE: usize = 384;
+/// Big endian modulus followed by the big endian 32 bits public exponent.
+pub const RSA3072_PUBLIC_KEY_SIZE: usize = RSA3072_MODULUS_SIZE + 4;
+pub const RSA3072_SIGNATURE_SIZE: usize = RSA3072_MODULUS_SIZE;
+
+/// ECDSA with SHA-384 on the NIST P-384 curve.
+pub const SIGNING_ALGORITHM_ECDSA_P384_SHA384: u16 = 2;
+
+pub const P384_SCALAR_SIZE: usize = 48;
+/// Uncompressed point: 0x04 followed by the big endian x and y.
+pub const ECDSA_P384_PUBLIC_KEY_SIZE: usize = 1 + 2 * P384_SCALAR_SIZE;
+/// Big endian r followed by the big endian s.
+pub const ECDSA_P384_SIGNATURE_SIZE: usize = 2 * P384_SCALAR_SIZE;
+
+///
+/// The public key size and the signature size of a signing algorithm, None
+/// if it is not supported.
+///
+pub fn signing_algorithm_sizes(algorithm: u16) -> Option<(usize, usize)> {
+    match algorithm {
+        SIGNING_ALGORITHM_RSA3072_SHA384 => Some((RSA3072_PUBLIC_KEY_SIZE, RSA3072_SIGNATURE_SIZE)),
+        SIGNING_ALGORITHM_ECDSA_P384_SHA384 => {
+            Some((ECDSA_P384_PUBLIC_KEY_SIZE, ECDSA_P384_SIGNATURE_SIZE))
+        }
+        _ => None,
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pread, Pwrite)]
+pub struct FvSignatureHeader {
+    pub signature: u32,
+    pub version: u16,
+    pub algorithm: u16,
+    pub public_key_size: u32,
+    pub signature_size: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite)]
+pub struct FvPublicKeyHash {
+    pub signature: u32,
+    pub version: u16,
+    pub algorithm: u16,
+    pub public_key_hash: [u8; 48],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// No signature file in the FV.
+    SignatureNotFound,
+    /// The signature file or its header is malformed.
+    InvalidSignatureFile,
+    UnsupportedAlgorithm,
+    InvalidPublicKey,
+    /// The public key is not the trusted one.
+    PublicKeyMismatch,
+    /// The signature does not match the FV content.
+    SignatureMismatch,
+}
+
+/// The signature file of an FV.
+pub struct FvSignature<'a> {
+    pub header: FvSignatureHeader,
+    /// Range of the whole FFS file in the FV.
+    pub file_range: Range<usize>,
+    pub public_key: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+impl<'a> FvSignature<'a> {
+    pub fn public_key_hash(&self) -> [u8; SHA384_DIGEST_SIZE] {
+        sha384(&[self.public_key])
+    }
+}
+
+fn sha384(data: &[&[u8]]) -> [u8; SHA384_DIGEST_SIZE] {
+    let mut hasher = Sha384::new();
+    for d in data {
+        hasher.update(d);
+    }
+    let mut digest = [0u8; SHA384_DIGEST_SIZE];
+    digest.copy_from_slice(&hasher.finalize());
+    digest
+}
+
+///
+/// Digest of the signed content: the whole FV except the signature file.
+///
+pub fn fv_digest(fv_data: &[u8], signature_file: Range<usize>) -> [u8; SHA384_DIGEST_SIZE] {
+    sha384(&[
+        &fv_data[..signature_file.start],
+        &fv_data[signature_file.end..],
+    ])
+}
+
+fn get_raw_file_data<'a>(fv_data: &'a [u8], name: &Guid) -> Option<(Range<usize>, &'a [u8])> {
+    let range = fv_lib::get_file_range_from_fv(fv_data, name.as_bytes())?;
+    let data = &fv_data[range.start + core::mem::size_of::<FfsFileHeader>()..range.end];
+    Some((range, data))
+}
+
+///
+/// Get the trusted public key hash, None if the FV has no such file.
+///
+pub fn get_trusted_public_key_hash(fv_data: &[u8]) -> Option<FvPublicKeyHash> {
+    let (_, data) = get_raw_file_data(fv_data, &PUBLIC_KEY_HASH_FILE_GUID)?;
+    let public_key_hash: FvPublicKeyHash = data.pread(0).ok()?;
+    if public_key_hash.signature != FV_PUBLIC_KEY_HASH_SIGNATURE
+        || public_key_hash.version != FV_SIGNATURE_VERSION
+    {
+        return None;
+    }
+    Some(public_key_hash)
+}
+
+pub fn get_fv_signature(fv_data: &[u8]) -> Result<FvSignature<'_>, VerifyError> {
+    let (file_range, data) = get_raw_file_data(fv_data, &FV_SIGNATURE_FILE_GUID)
+        .ok_or(VerifyError::SignatureNotFound)?;
+    let header: FvSignatureHeader = data
+        .pread(0)
+        .map_err(|_| VerifyError::InvalidSignatureFile)?;
+    if header.signature != FV_SIGNATURE_HEADER_SIGNATURE || header.version != FV_SIGNATURE_VERSION {
+        return Err(VerifyError::InvalidSignatureFile);
+    }
+    let (public_key_size, signature_size) =
+        signing_algorithm_sizes(header.algorithm).ok_or(VerifyError::UnsupportedAlgorithm)?;
+    if header.public_key_size as usize != public_key_size
+        || header.signature_size as usize != signature_size
+    {
+        return Err(VerifyError::InvalidSignatureFile);
+    }
+
+    let public_key_offset = core::mem::size_of::<FvSignatureHeader>();
+    let signature_offset = public_key_offset + header.public_key_size as usize;
+    let end = signature_offset + header.signature_size as usize;
+    if end > data.len() {
+        return Err(VerifyError::InvalidSignatureFile);
+    }
+    Ok(FvSignature {
+        header,
+        file_range,
+        public_key: &data[public_key_offset..signature_offset],
+        signature: &data[signature_offset..end],
+    })
+}
+
+///
+/// Verify the FV signature against the public key carried in the FV.
+///
+/// Return the hash of that public key, it must still be compared with a
+/// trusted one, see verify_fv().
+///
+pub fn verify_fv_signature(fv_data: &[u8]) -> Result<[u8; SHA384_DIGEST_SIZE], VerifyError> {
+    let fv_signature = get_fv_signature(fv_data)?;
+    let digest = fv_digest(fv_data, fv_signature.file_range.clone());
+    let (public_key, signature) = (fv_signature.public_key, fv_signature.signature);
+    match fv_signature.header.algorithm {
+        SIGNING_ALGORITHM_RSA3072_SHA384 => {
+            rsa3072::verify_pkcs1v15_sha384(public_key, signature, &digest)?
+        }
+        SIGNING_ALGORITHM_ECDSA_P384_SHA384 => {
+            ecdsa_p384::verify_sha384(public_key, signature, &digest)?
+        }
+        _ => return Err(VerifyError::UnsupportedAlgorithm),
+    }
+    Ok(fv_signature.public_key_hash())
+}
+
+///
+/// Verify the FV is signed by the key matching the trusted public key hash.
+///
+pub fn verify_fv(fv_data: &[u8], trusted: &FvPublicKeyHash) -> Result<(), VerifyError> {
+    let fv_signature = get_fv_signature(fv_data)?;
+    if fv_signature.header.algorithm != trusted.algorithm
+        || fv_signature.public_key_hash() != trusted.public_key_hash
+    {
+        return Err(VerifyError::PublicKeyMismatch);
+    }
+    verify_fv_signature(fv_data).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use r_uefi_pi::fv::{
+        FirmwareVolumeHeader, FvBlockMap, FFS_ATTRIB_CHECKSUM, FVH_SIGNATURE, FV_FILETYPE_DXE_CORE,
+    };
+    use sign::SigningKey;
+
+    const TEST_KEY: &str = include_str!("../test_keys/rsa3072_test.pem");
+    const OTHER_TEST_KEY: &str = include_str!("../test_keys/rsa3072_other_test.pem");
+    const ECDSA_TEST_KEY: &str = include_str!("../test_keys/ecdsa_p384_test.pem");
+
+    fn build_test_fv() -> Vec<u8> {
+        let mut fv = vec![0xffu8; 0x1000];
+        let header_length =
+            core::mem::size_of::<FirmwareVolumeHeader>() + core::mem::size_of::<[FvBlockMap; 2]>();
+        let fv_header = FirmwareVolumeHeader {
+            fv_length: fv.len() as u64,
+            signature: FVH_SIGNATURE,
+            attributes: 0x0004feff,
+            header_length: header_length as u16,
+            revision: 2,
+            ..Default::default()
+        };
+        fv.pwrite(fv_header, 0).unwrap();
+        fv.pwrite(
+            FvBlockMap {
+                num_blocks: 1,
+                length: 0x1000,
+            },
+            0x38,
+        )
+        .unwrap();
+        fv.pwrite(FvBlockMap::default(), 0x40).unwrap();
+
+        let ffs_header = FfsFileHeader {
+            name: [0x5a; 16],
+            r#type: FV_FILETYPE_DXE_CORE,
+            attributes: FFS_ATTRIB_CHECKSUM,
+            size: [0x60, 0, 0],
+            state: 0xF8,
+            ..Default::default()
+        };
+        fv.pwrite(ffs_header, header_length).unwrap();
+        for (i, b) in fv[header_length + 0x18..header_length + 0x60]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+        let ffs_file = &fv[header_length..header_length + 0x60];
+        let integrity_check = fv_lib::calculate_ffs_header_checksum(&ffs_file[..0x18]) as u16
+            | (fv_lib::calculate_ffs_file_checksum(FFS_ATTRIB_CHECKSUM, &ffs_file[0x18..]) as u16)
+                << 8;
+        fv.pwrite(integrity_check, header_length + 0x10).unwrap();
+        let checksum = fv_lib::calculate_fv_header_checksum(&fv[..header_length]);
+        fv.pwrite(checksum, 0x32).unwrap();
+        fv
+    }
+
+    #[test]
+    fn test_signed_fv() {
+        let key = SigningKey::from_bytes(TEST_KEY.as_bytes()).unwrap();
+        let mut fv = build_test_fv();
+        sign::sign_fv(&mut fv, &key).unwrap();
+        assert!(fv_lib::verify_fv_checksums(&fv));
+
+        let mut ipl_fv = build_test_fv();
+        sign::add_public_key_hash(&mut ipl_fv, &key).unwrap();
+        assert!(fv_lib::verify_fv_checksums(&ipl_fv));
+        let trusted = get_trusted_public_key_hash(&ipl_fv).unwrap();
+        assert_eq!(trusted.public_key_hash, key.public_key_hash());
+
+        assert_eq!(verify_fv_signature(&fv), Ok(key.public_key_hash()));
+        assert_eq!(verify_fv(&fv, &trusted), Ok(()));
+
+        // signing is deterministic
+        let mut fv2 = build_test_fv();
+        sign::sign_fv(&mut fv2, &key).unwrap();
+        assert_eq!(fv, fv2);
+
+        // already signed
+        assert!(sign::sign_fv(&mut fv2, &key).is_err());
+    }
+
+    #[test]
+    fn test_tampered_fv() {
+        let key = SigningKey::from_bytes(TEST_KEY.as_bytes()).unwrap();
+        let mut fv = build_test_fv();
+        sign::sign_fv(&mut fv, &key).unwrap();
+        let trusted = FvPublicKeyHash {
+            signature: FV_PUBLIC_KEY_HASH_SIGNATURE,
+            version: FV_SIGNATURE_VERSION,
+            algorithm: SIGNING_ALGORITHM_RSA3072_SHA384,
+            public_key_hash: key.public_key_hash(),
+        };
+        let signature_file = get_fv_signature(&fv).unwrap().file_range;
+
+        // payload data
+        let mut fv_tampered = fv.clone();
+        fv_tampered[0x48 + 0x30] ^= 0x01;
+        assert_eq!(
+            verify_fv(&fv_tampered, &trusted),
+            Err(VerifyError::SignatureMismatch)
+        );
+
+        // free space after the signature file
+        let mut fv_tampered = fv.clone();
+        fv_tampered[0xff0] = 0;
+        assert_eq!(
+            verify_fv(&fv_tampered, &trusted),
+            Err(VerifyError::SignatureMismatch)
+        );
+
+        // last byte of the signature
+        let mut fv_tampered = fv.clone();
+        fv_tampered[signature_file.end - 1] ^= 0x01;
+        assert_eq!(
+            verify_fv(&fv_tampered, &trusted),
+            Err(VerifyError::SignatureMismatch)
+        );
+
+        // re-signed with another key
+        let other_key = SigningKey::from_bytes(OTHER_TEST_KEY.as_bytes()).unwrap();
+        let mut fv_other = build_test_fv();
+        fv_other[0x48 + 0x30] ^= 0x01;
+        sign::sign_fv(&mut fv_other, &other_key).unwrap();
+        assert!(verify_fv_signature(&fv_other).is_ok());
+        assert_eq!(
+            verify_fv(&fv_other, &trusted),
+            Err(VerifyError::PublicKeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_ecdsa_signed_fv() {
+        let key = SigningKey::from_bytes(ECDSA_TEST_KEY.as_bytes()).unwrap();
+        let mut fv = build_test_fv();
+        sign::sign_fv(&mut fv, &key).unwrap();
+        assert!(fv_lib::verify_fv_checksums(&fv));
+        let fv_signature = get_fv_signature(&fv).unwrap();
+        assert_eq!(
+            fv_signature.header.algorithm,
+            SIGNING_ALGORITHM_ECDSA_P384_SHA384
+        );
+        assert_eq!(fv_signature.signature.len(), ECDSA_P384_SIGNATURE_SIZE);
+
+        let mut ipl_fv = build_test_fv();
+        sign::add_public_key_hash(&mut ipl_fv, &key).unwrap();
+        let trusted = get_trusted_public_key_hash(&ipl_fv).unwrap();
+        assert_eq!(verify_fv(&fv, &trusted), Ok(()));
+
+        // signing is deterministic
+        let mut fv2 = build_test_fv();
+        sign::sign_fv(&mut fv2, &key).unwrap();
+        assert_eq!(fv, fv2);
+
+        let mut fv_tampered = fv.clone();
+        fv_tampered[0x48 + 0x30] ^= 0x01;
+        assert_eq!(
+            verify_fv(&fv_tampered, &trusted),
+            Err(VerifyError::SignatureMismatch)
+        );
+
+        // trusted a RSA key
+        let rsa_key = SigningKey::from_bytes(TEST_KEY.as_bytes()).unwrap();
+        let mut rsa_ipl_fv = build_test_fv();
+        sign::add_public_key_hash(&mut rsa_ipl_fv, &rsa_key).unwrap();
+        let rsa_trusted = get_trusted_public_key_hash(&rsa_ipl_fv).unwrap();
+        assert_eq!(
+            verify_fv(&fv, &rsa_trusted),
+            Err(VerifyError::PublicKeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_unsigned_fv() {
+        let key = SigningKey::from_bytes(TEST_KEY.as_bytes()).unwrap();
+        let fv = build_test_fv();
+        let trusted = FvPublicKeyHash {
+            signature: FV_PUBLIC_KEY_HASH_SIGNATURE,
+            version: FV_SIGNATURE_VERSION,
+            algorithm: SIGNING_ALGORITHM_RSA3072_SHA384,
+            public_key_hash: key.public_key_hash(),
+        };
+        assert!(get_trusted_public_key_hash(&fv).is_none());
+        assert_eq!(
+            verify_fv(&fv, &trusted),
+            Err(VerifyError::SignatureNotFound)
+        );
+    }
+}
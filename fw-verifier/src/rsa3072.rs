@@ -0,0 +1,233 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! RSASSA-PKCS1-v1_5 SHA-384 verification for 3072 bits keys.
+//!
+//! Only the public operation is needed at boot time, it is done with
+//! Montgomery multiplication on fixed size numbers, without allocation.
+//!
+
+use core::cmp::Ordering;
+
+use crate::{
+    VerifyError, RSA3072_MODULUS_SIZE, RSA3072_PUBLIC_KEY_SIZE, RSA3072_SIGNATURE_SIZE,
+    SHA384_DIGEST_SIZE,
+};
+
+const LIMBS: usize = RSA3072_MODULUS_SIZE / 8;
+
+// least significant limb first
+type Uint = [u64; LIMBS];
+
+// DER encoded DigestInfo prefix of SHA-384, RFC 8017 section 9.2
+const SHA384_DIGEST_INFO_PREFIX: [u8; 19] = [
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+
+fn from_be_bytes(bytes: &[u8]) -> Uint {
+    assert_eq!(bytes.len(), RSA3072_MODULUS_SIZE);
+    let mut value = [0u64; LIMBS];
+    for (i, limb) in value.iter_mut().enumerate() {
+        let end = RSA3072_MODULUS_SIZE - i * 8;
+        let mut limb_bytes = [0u8; 8];
+        limb_bytes.copy_from_slice(&bytes[end - 8..end]);
+        *limb = u64::from_be_bytes(limb_bytes);
+    }
+    value
+}
+
+fn to_be_bytes(value: &Uint) -> [u8; RSA3072_MODULUS_SIZE] {
+    let mut bytes = [0u8; RSA3072_MODULUS_SIZE];
+    for (i, limb) in value.iter().enumerate() {
+        let end = RSA3072_MODULUS_SIZE - i * 8;
+        bytes[end - 8..end].copy_from_slice(&limb.to_be_bytes());
+    }
+    bytes
+}
+
+fn compare(a: &Uint, b: &Uint) -> Ordering {
+    for i in (0..LIMBS).rev() {
+        match a[i].cmp(&b[i]) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+// a = a - b mod 2^3072
+fn sub_assign(a: &mut Uint, b: &Uint) {
+    let mut borrow = 0u64;
+    for i in 0..LIMBS {
+        let (d1, b1) = a[i].overflowing_sub(b[i]);
+        let (d2, b2) = d1.overflowing_sub(borrow);
+        a[i] = d2;
+        borrow = (b1 | b2) as u64;
+    }
+}
+
+struct Montgomery {
+    n: Uint,
+    // -n^-1 mod 2^64
+    n0_inv: u64,
+    // R^2 mod n, R = 2^3072
+    r2: Uint,
+}
+
+impl Montgomery {
+    // n must be odd
+    fn new(n: &Uint) -> Self {
+        // Newton iteration, each step doubles the number of correct low bits.
+        let mut inv = 1u64;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(n[0].wrapping_mul(inv)));
+        }
+
+        // 2^(2 * 3072) mod n by doubling 1.
+        let mut r2 = [0u64; LIMBS];
+        r2[0] = 1;
+        for _ in 0..2 * LIMBS * 64 {
+            let carry = r2[LIMBS - 1] >> 63;
+            for i in (1..LIMBS).rev() {
+                r2[i] = (r2[i] << 1) | (r2[i - 1] >> 63);
+            }
+            r2[0] <<= 1;
+            if carry != 0 || compare(&r2, n) != Ordering::Less {
+                sub_assign(&mut r2, n);
+            }
+        }
+
+        Montgomery {
+            n: *n,
+            n0_inv: inv.wrapping_neg(),
+            r2,
+        }
+    }
+
+    // a * b * R^-1 mod n, with a, b < n, with the names of the CIOS algorithm
+    #[allow(clippy::many_single_char_names)]
+    fn mul(&self, a: &Uint, b: &Uint) -> Uint {
+        let n = &self.n;
+        let mut t = [0u64; LIMBS + 2];
+        for &b_i in b.iter() {
+            let mut carry = 0u64;
+            for j in 0..LIMBS {
+                let x = t[j] as u128 + a[j] as u128 * b_i as u128 + carry as u128;
+                t[j] = x as u64;
+                carry = (x >> 64) as u64;
+            }
+            let x = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS] = x as u64;
+            t[LIMBS + 1] = (x >> 64) as u64;
+
+            let m = t[0].wrapping_mul(self.n0_inv);
+            let x = t[0] as u128 + m as u128 * n[0] as u128;
+            let mut carry = (x >> 64) as u64;
+            for j in 1..LIMBS {
+                let x = t[j] as u128 + m as u128 * n[j] as u128 + carry as u128;
+                t[j - 1] = x as u64;
+                carry = (x >> 64) as u64;
+            }
+            let x = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS - 1] = x as u64;
+            t[LIMBS] = t[LIMBS + 1] + (x >> 64) as u64;
+        }
+
+        let mut result = [0u64; LIMBS];
+        result.copy_from_slice(&t[..LIMBS]);
+        if t[LIMBS] != 0 || compare(&result, n) != Ordering::Less {
+            sub_assign(&mut result, n);
+        }
+        result
+    }
+
+    // base^exponent mod n
+    fn pow(&self, base: &Uint, exponent: u32) -> Uint {
+        let base = self.mul(base, &self.r2);
+        let mut result = base;
+        let bits = 32 - exponent.leading_zeros();
+        for bit in (0..bits - 1).rev() {
+            result = self.mul(&result, &result);
+            if exponent & (1 << bit) != 0 {
+                result = self.mul(&result, &base);
+            }
+        }
+        let mut one = [0u64; LIMBS];
+        one[0] = 1;
+        self.mul(&result, &one)
+    }
+}
+
+///
+/// Verify a RSASSA-PKCS1-v1_5 signature of a SHA-384 digest.
+///
+/// public_key: big endian modulus followed by the big endian public exponent.
+///
+pub fn verify_pkcs1v15_sha384(
+    public_key: &[u8],
+    signature: &[u8],
+    digest: &[u8; SHA384_DIGEST_SIZE],
+) -> Result<(), VerifyError> {
+    if public_key.len() != RSA3072_PUBLIC_KEY_SIZE || signature.len() != RSA3072_SIGNATURE_SIZE {
+        return Err(VerifyError::InvalidSignatureFile);
+    }
+    let n = from_be_bytes(&public_key[..RSA3072_MODULUS_SIZE]);
+    let mut exponent = [0u8; 4];
+    exponent.copy_from_slice(&public_key[RSA3072_MODULUS_SIZE..]);
+    let exponent = u32::from_be_bytes(exponent);
+    // a 3072 bits odd modulus and an odd exponent > 1
+    if n[0] & 1 == 0 || n[LIMBS - 1] >> 63 == 0 || exponent & 1 == 0 || exponent == 1 {
+        return Err(VerifyError::InvalidPublicKey);
+    }
+
+    let s = from_be_bytes(signature);
+    if compare(&s, &n) != Ordering::Less {
+        return Err(VerifyError::SignatureMismatch);
+    }
+    let em = to_be_bytes(&Montgomery::new(&n).pow(&s, exponent));
+
+    // EM = 0x00 || 0x01 || PS (0xff) || 0x00 || DigestInfo
+    let digest_info_offset = RSA3072_MODULUS_SIZE - SHA384_DIGEST_SIZE - 19;
+    let mut mismatch = em[0] | (em[1] ^ 0x01) | em[digest_info_offset - 1];
+    for b in &em[2..digest_info_offset - 1] {
+        mismatch |= b ^ 0xff;
+    }
+    for (a, b) in em[digest_info_offset..]
+        .iter()
+        .zip(SHA384_DIGEST_INFO_PREFIX.iter().chain(digest.iter()))
+    {
+        mismatch |= a ^ b;
+    }
+    if mismatch != 0 {
+        return Err(VerifyError::SignatureMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_montgomery_pow() {
+        // n = 2^3071 + 3 is odd, check (n - 1)^e mod n = n - 1 for odd e
+        let mut n = [0u64; LIMBS];
+        n[0] = 3;
+        n[LIMBS - 1] = 1 << 63;
+        let mut n_minus_1 = n;
+        n_minus_1[0] = 2;
+        let mont = Montgomery::new(&n);
+        assert_eq!(mont.pow(&n_minus_1, 65537), n_minus_1);
+        assert_eq!(mont.pow(&n_minus_1, 3), n_minus_1);
+
+        // 2^17 mod n = 2^17
+        let mut two = [0u64; LIMBS];
+        two[0] = 2;
+        let mut expected = [0u64; LIMBS];
+        expected[0] = 1 << 17;
+        assert_eq!(mont.pow(&two, 17), expected);
+    }
+}
@@ -107,18 +107,66 @@ pub fn relocate_pe_mem(image: &[u8], loaded_buffer: &mut [u8]) -> (u64, u64, u64
     )
 }
 
+///
+/// Return the section table of a PE image.
+///
+pub fn sections(pe_image: &[u8]) -> Option<Sections<'_>> {
+    let pe_header_offset = pe_image.pread::<u32>(0x3c).ok()? as usize;
+    let pe_region = pe_image.get(pe_header_offset..)?;
+    let num_sections = pe_region.pread::<u16>(6).ok()? as usize;
+    let optional_header_size = pe_region.pread::<u16>(20).ok()? as usize;
+    let sections_offset = 24 + pe_header_offset + optional_header_size;
+    let sections_buffer = pe_image.get(sections_offset..sections_offset + num_sections * 40)?;
+    Sections::parse(sections_buffer, num_sections)
+}
+
+pub fn find_section(pe_image: &[u8], name: &[u8]) -> Option<Section> {
+    sections(pe_image)?.find(|section| section.name() == name)
+}
+
+///
+/// Return SizeOfImage of the optional header, the size of the image once
+/// loaded.
+///
+pub fn size_of_image(pe_image: &[u8]) -> Option<usize> {
+    let pe_header_offset = pe_image.pread::<u32>(0x3c).ok()? as usize;
+    let optional_region = pe_image.get(24 + pe_header_offset..)?;
+    if optional_region.pread::<u16>(0).ok()? != OPTIONAL_HDR64_MAGIC {
+        return None;
+    }
+    Some(optional_region.pread::<u32>(56).ok()? as usize)
+}
+
+///
+/// Return the content of a section of a loaded image, where the sections
+/// are at their virtual address.
+///
+pub fn find_loaded_section<'a>(loaded_image: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    let section = find_section(loaded_image, name)?;
+    let start = section.virtual_address as usize;
+    loaded_image.get(start..start + section.virtual_size as usize)
+}
+
 #[derive(Default, Pread, Pwrite)]
 pub struct Section {
-    name: [u8; 8],                // 8
-    virtual_size: u32,            //4
-    virtual_address: u32,         //4
-    size_of_raw_data: u32,        //4
-    pointer_to_raw_data: u32,     //4
-    pointer_to_relocations: u32,  //4
-    pointer_to_line_numbers: u32, //4
-    number_of_relocations: u16,   //2
-    number_of_line_numbers: u16,  //2
-    characteristics: u32,         //4
+    pub name: [u8; 8],                // 8
+    pub virtual_size: u32,            //4
+    pub virtual_address: u32,         //4
+    pub size_of_raw_data: u32,        //4
+    pub pointer_to_raw_data: u32,     //4
+    pub pointer_to_relocations: u32,  //4
+    pub pointer_to_line_numbers: u32, //4
+    pub number_of_relocations: u16,   //2
+    pub number_of_line_numbers: u16,  //2
+    pub characteristics: u32,         //4
+}
+
+impl Section {
+    /// The section name, without the null padding.
+    pub fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|c| *c == 0).unwrap_or(8);
+        &self.name[..len]
+    }
 }
 
 impl core::fmt::Debug for Section {
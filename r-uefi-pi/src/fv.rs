@@ -12,10 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use r_efi::efi::Guid;
 
 pub type FvbAttributes2 = u32;
 
+pub const FVB2_ERASE_POLARITY: u32 = 0x00000800;
+
 pub const FVH_SIGNATURE: u32 = 0x4856465F; // '_','F','V','H'
 use scroll::{Pread, Pwrite};
 
@@ -95,6 +96,32 @@ pub const FV_FILETYPE_FFS_PAD: u8 = 0xF0;
 pub type FfsFileAttributes = u8;
 pub type FfsFileState = u8;
 
+// FFS File Attributes.
+pub const FFS_ATTRIB_LARGE_FILE: u8 = 0x01;
+pub const FFS_ATTRIB_DATA_ALIGNMENT_2: u8 = 0x02;
+pub const FFS_ATTRIB_FIXED: u8 = 0x04;
+pub const FFS_ATTRIB_DATA_ALIGNMENT: u8 = 0x38;
+pub const FFS_ATTRIB_CHECKSUM: u8 = 0x40;
+
+// The largest size held by the 24-bit FFS size field. Larger files set
+// FFS_ATTRIB_LARGE_FILE and use FfsFileHeader2, with a zero size field.
+pub const MAX_FFS_SIZE: usize = 0xffffff;
+// Sections of MAX_SECTION_SIZE bytes or more use CommonSectionHeader2, the
+// 24-bit size field is 0xffffff.
+pub const MAX_SECTION_SIZE: usize = 0xffffff;
+
+// FFS_FIXED_CHECKSUM is the checksum value used when the
+// FFS_ATTRIB_CHECKSUM attribute bit is clear.
+pub const FFS_FIXED_CHECKSUM: u8 = 0xAA;
+
+// FFS File State Bits.
+pub const EFI_FILE_HEADER_CONSTRUCTION: u8 = 0x01;
+pub const EFI_FILE_HEADER_VALID: u8 = 0x02;
+pub const EFI_FILE_DATA_VALID: u8 = 0x04;
+pub const EFI_FILE_MARKED_FOR_UPDATE: u8 = 0x08;
+pub const EFI_FILE_DELETED: u8 = 0x10;
+pub const EFI_FILE_HEADER_INVALID: u8 = 0x20;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
 pub struct FfsFileHeader {
@@ -107,9 +134,9 @@ pub struct FfsFileHeader {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
 pub struct FfsFileHeader2 {
-    pub name: Guid,
+    pub name: [u8; 16], // Guid,
     pub integrity_check: u16,
     pub r#type: FvFileType,
     pub attributes: FfsFileAttributes,
@@ -147,9 +174,74 @@ pub struct CommonSectionHeader {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
 pub struct CommonSectionHeader2 {
     pub size: [u8; 3],
     pub r#type: SectionType,
     pub extended_size: u32,
 }
+
+// CompressionSection.compression_type
+pub const EFI_NOT_COMPRESSED: u8 = 0x00;
+pub const EFI_STANDARD_COMPRESSION: u8 = 0x01;
+
+// The PI section headers are packed, the compressed data follows
+// compression_type at these offsets.
+pub const COMPRESSION_SECTION_SIZE: usize = 9;
+pub const COMPRESSION_SECTION2_SIZE: usize = 13;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct CompressionSection {
+    pub common_header: CommonSectionHeader,
+    pub uncompressed_length: u32,
+    pub compression_type: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct CompressionSection2 {
+    pub common_header: CommonSectionHeader2,
+    pub uncompressed_length: u32,
+    pub compression_type: u8,
+}
+
+// GuidDefinedSection.attributes
+pub const EFI_GUIDED_SECTION_PROCESSING_REQUIRED: u16 = 0x01;
+pub const EFI_GUIDED_SECTION_AUTH_STATUS_VALID: u16 = 0x02;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct GuidDefinedSection {
+    pub common_header: CommonSectionHeader,
+    pub section_definition_guid: [u8; 16], // Guid
+    pub data_offset: u16,
+    pub attributes: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct GuidDefinedSection2 {
+    pub common_header: CommonSectionHeader2,
+    pub section_definition_guid: [u8; 16], // Guid
+    pub data_offset: u16,
+    pub attributes: u16,
+}
+
+pub const LZMA_CUSTOM_DECOMPRESS_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0xEE4E5898,
+    0x3914,
+    0x4259,
+    0x9D,
+    0x6E,
+    &[0xDC, 0x7B, 0xD7, 0x94, 0x03, 0xCF],
+);
+
+pub const TIANO_CUSTOM_DECOMPRESS_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0xA31280AD,
+    0x481E,
+    0x41B6,
+    0x95,
+    0xE8,
+    &[0x12, 0x7F, 0x4C, 0x98, 0x47, 0x79],
+);
@@ -21,9 +21,11 @@ pub mod r_efi_wrapper;
 pub mod boot_mode;
 pub mod fv;
 pub mod hob;
+pub mod variable;
 
 pub mod pi {
     pub use crate::boot_mode;
     pub use crate::fv;
     pub use crate::hob;
+    pub use crate::variable;
 }
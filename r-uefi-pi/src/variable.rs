@@ -0,0 +1,166 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! EDK2 variable store and fault tolerant write (FTW) working block, as
+//! defined in MdeModulePkg VariableFormat.h and
+//! SystemNvDataGuid.h.
+//!
+//! The NV storage FV holds the variable store, followed by the FTW working
+//! block. The FTW spare block follows the FV.
+//!
+
+use scroll::{Pread, Pwrite};
+
+// FirmwareVolumeHeader.file_system_guid of the NV storage FV
+pub const SYSTEM_NV_DATA_FV_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0xfff12b8d,
+    0x7696,
+    0x4c8b,
+    0xa9,
+    0x85,
+    &[0x27, 0x47, 0x07, 0x5b, 0x4f, 0x50],
+);
+
+// VariableStoreHeader.signature of a store of AuthenticatedVariableHeader
+pub const AUTHENTICATED_VARIABLE_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0xaaf32c78,
+    0x947b,
+    0x439a,
+    0xa1,
+    0x80,
+    &[0x2e, 0x14, 0x4e, 0xc3, 0x77, 0x92],
+);
+
+// VariableStoreHeader.signature of a store of VariableHeader
+pub const VARIABLE_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0xddcf3616,
+    0x3275,
+    0x4164,
+    0x98,
+    0xb6,
+    &[0xfe, 0x85, 0x70, 0x7f, 0xfe, 0x7d],
+);
+
+pub const WORKING_BLOCK_SIGNATURE_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0x9e58292b,
+    0x7c68,
+    0x497d,
+    0xa0,
+    0xce,
+    &[0x65, 0x00, 0xfd, 0x9f, 0x1b, 0x95],
+);
+
+pub const EFI_GLOBAL_VARIABLE_GUID: r_efi::base::Guid = r_efi::base::Guid::from_fields(
+    0x8be4df61,
+    0x93ca,
+    0x11d2,
+    0xaa,
+    0x0d,
+    &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+);
+
+// VariableStoreHeader.format
+pub const VARIABLE_STORE_FORMATTED: u8 = 0x5a;
+// VariableStoreHeader.state
+pub const VARIABLE_STORE_HEALTHY: u8 = 0xfe;
+
+// Variable headers start at HEADER_ALIGNMENT bytes aligned offsets.
+pub const HEADER_ALIGNMENT: usize = 4;
+pub const VARIABLE_DATA: u16 = 0x55aa;
+
+// Variable states, each one clears bits of the previous one.
+pub const VAR_IN_DELETED_TRANSITION: u8 = 0xfe;
+pub const VAR_DELETED: u8 = 0xfd;
+pub const VAR_HEADER_VALID_ONLY: u8 = 0x7f;
+pub const VAR_ADDED: u8 = 0x3f;
+
+// Variable attributes
+pub const EFI_VARIABLE_NON_VOLATILE: u32 = 0x00000001;
+pub const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x00000002;
+pub const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x00000004;
+pub const EFI_VARIABLE_HARDWARE_ERROR_RECORD: u32 = 0x00000008;
+pub const EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x00000020;
+pub const EFI_VARIABLE_APPEND_WRITE: u32 = 0x00000040;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct VariableStoreHeader {
+    pub signature: [u8; 16], // Guid
+    pub size: u32,
+    pub format: u8,
+    pub state: u8,
+    pub reserved: u16,
+    pub reserved1: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub pad1: u8,
+    pub nanosecond: u32,
+    pub time_zone: i16,
+    pub daylight: u8,
+    pub pad2: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct VariableHeader {
+    pub start_id: u16,
+    pub state: u8,
+    pub reserved: u8,
+    pub attributes: u32,
+    pub name_size: u32,
+    pub data_size: u32,
+    pub vendor_guid: [u8; 16], // Guid
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct AuthenticatedVariableHeader {
+    pub start_id: u16,
+    pub state: u8,
+    pub reserved: u8,
+    pub attributes: u32,
+    pub monotonic_count: u64,
+    pub time_stamp: EfiTime,
+    pub pub_key_index: u32,
+    pub name_size: u32,
+    pub data_size: u32,
+    pub vendor_guid: [u8; 16], // Guid
+}
+
+// size_of::<VariableStoreHeader>() = 28
+// size_of::<VariableHeader>() = 32
+// size_of::<AuthenticatedVariableHeader>() = 60, without the tail padding
+pub const VARIABLE_STORE_HEADER_SIZE: usize = 28;
+pub const VARIABLE_HEADER_SIZE: usize = 32;
+pub const AUTHENTICATED_VARIABLE_HEADER_SIZE: usize = 60;
+
+// FtwWorkingBlockHeader.working_block_state bits
+pub const WORKING_BLOCK_VALID: u8 = 0x01;
+pub const WORKING_BLOCK_INVALID: u8 = 0x02;
+
+///
+/// EFI_FAULT_TOLERANT_WORKING_BLOCK_HEADER, the WorkingBlockValid and
+/// WorkingBlockInvalid bit fields are in working_block_state.
+///
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
+pub struct FtwWorkingBlockHeader {
+    pub signature: [u8; 16], // Guid
+    pub crc: u32,
+    pub working_block_state: u8,
+    pub reserved: [u8; 3],
+    pub write_queue_size: u64,
+}
+
+pub const FTW_WORKING_BLOCK_HEADER_SIZE: usize = 32;
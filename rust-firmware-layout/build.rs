@@ -5,7 +5,7 @@
 use serde::Deserialize;
 use std::env;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, fs::File};
 
 macro_rules! BUILD_TIME_TEMPLATE {
@@ -96,6 +96,7 @@ pub const RUNTIME_PAGE_TABLE_SIZE: u32 = {pt_size:#X};
 pub const RUNTIME_PAYLOAD_SIZE: u32 = {payload_size:#X};
 pub const RUNTIME_STACK_SIZE: u32 = {stack_size:#X};
 pub const RUNTIME_HEAP_SIZE: u32 = {heap_size:#X};
+pub const RUNTIME_HEAP_ABOVE_4G: bool = {heap_above_4g};
 "
     };
 }
@@ -115,6 +116,13 @@ struct FirmwareImageLayoutConfig {
     ipl_size: u32,
     fsp_max_size: u32,
     reset_vector_size: u32,
+    // base alignment the FSP-T/M/S binaries need, 4K if not given
+    #[serde(default = "default_fsp_alignment")]
+    fsp_alignment: u32,
+}
+
+fn default_fsp_alignment() -> u32 {
+    SIZE_4K as u32
 }
 
 #[derive(Debug, PartialEq, Deserialize, Clone)]
@@ -124,6 +132,124 @@ struct FirmwareRuntimeLayoutConfig {
     heap_size: u32,
     payload_size: u32,
     page_table_size: u32,
+    #[serde(default)]
+    heap_above_4g: bool,
+}
+
+const SIZE_4K: u64 = 0x1000;
+const SIZE_4G: u64 = 0x1_0000_0000;
+// the CPU starts at 0xFFFFFFF0, in the reset vector region
+const RESET_VECTOR_ADDRESS: u64 = 0xFFFF_FFF0;
+// the FTW working block of the VAR region, see src/nv_storage.rs
+const NV_FTW_WORKING_SIZE: u64 = 0x2000;
+
+impl FirmwareLayoutConfig {
+    ///
+    /// Check the layout before generating anything from it, return the
+    /// errors found.
+    ///
+    fn check(&self) -> Vec<String> {
+        let image = &self.image_layout;
+        let runtime = &self.runtime_layout;
+        let mut errors = Vec::new();
+
+        // the regions in image order, with the alignment of their offset
+        let regions = [
+            ("reserved1", image.reserved1_size as u64, SIZE_4K),
+            ("padding", image.padding_size as u64, SIZE_4K),
+            ("payload", image.payload_size as u64, SIZE_4K),
+            ("ipl", image.ipl_size as u64, SIZE_4K),
+            (
+                "fsp_max",
+                image.fsp_max_size as u64,
+                image.fsp_alignment as u64,
+            ),
+            ("reset_vector", image.reset_vector_size as u64, SIZE_4K),
+        ];
+        let image_size = image.image_size as u64;
+        let mut offset = 0u64;
+        for (name, size, alignment) in regions.iter() {
+            if *name != "padding" && *size == 0 {
+                errors.push(format!("{}_size is 0", name));
+            }
+            if size % SIZE_4K != 0 {
+                errors.push(format!("{}_size {:#X} is not 4K aligned", name, size));
+            }
+            if !alignment.is_power_of_two() {
+                errors.push(format!(
+                    "{} alignment {:#X} is not a power of 2",
+                    name, alignment
+                ));
+            } else if offset % alignment != 0 {
+                errors.push(format!(
+                    "{} offset {:#X} is not aligned on {:#X}",
+                    name, offset, alignment
+                ));
+            }
+            if offset + size > image_size {
+                errors.push(format!(
+                    "{} {:#X}..{:#X} overlaps the end of the image at {:#X}",
+                    name,
+                    offset,
+                    offset + size,
+                    image_size
+                ));
+            }
+            offset += size;
+        }
+        if offset != image_size {
+            errors.push(format!(
+                "the regions make {:#X} bytes, image_size is {:#X}",
+                offset, image_size
+            ));
+        }
+
+        // the image ends at 4G, the reset vector is its last region
+        if image_size == 0 || image_size > SIZE_4G {
+            errors.push(format!("image_size {:#X} is not in 1..=4G", image_size));
+        } else {
+            let reset_vector_base = SIZE_4G - image.reset_vector_size as u64;
+            if reset_vector_base > RESET_VECTOR_ADDRESS {
+                errors.push(format!(
+                    "reset_vector {:#X}..{:#X} does not hold the reset vector at {:#X}",
+                    reset_vector_base, SIZE_4G, RESET_VECTOR_ADDRESS
+                ));
+            }
+        }
+
+        let reserved1_size = image.reserved1_size as u64;
+        if reserved1_size % (2 * SIZE_4K) != 0 || reserved1_size / 2 <= NV_FTW_WORKING_SIZE {
+            errors.push(format!(
+                "reserved1_size {:#X} cannot hold the variable store and its FTW blocks",
+                reserved1_size
+            ));
+        }
+
+        let runtime_regions = [
+            ("hob", runtime.hob_size),
+            ("page_table", runtime.page_table_size),
+            ("payload", runtime.payload_size),
+            ("stack", runtime.stack_size),
+            ("heap", runtime.heap_size),
+        ];
+        for (name, size) in runtime_regions.iter() {
+            if *size == 0 || *size as u64 % SIZE_4K != 0 {
+                errors.push(format!(
+                    "runtime {}_size {:#X} must be a non-zero multiple of 4K",
+                    name, size
+                ));
+            }
+        }
+        // rust-ipl reports the payload region with the size of the image one
+        if runtime.payload_size < image.payload_size {
+            errors.push(format!(
+                "runtime payload_size {:#X} is smaller than the image payload_size {:#X}",
+                runtime.payload_size, image.payload_size
+            ));
+        }
+
+        errors
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -194,6 +320,7 @@ impl FirmwareLayout {
             heap_size = self.config.runtime_layout.heap_size,
             stack_base = self.runtime.stack_base,
             stack_size = self.config.runtime_layout.stack_size,
+            heap_above_4g = self.config.runtime_layout.heap_above_4g,
         )
         .expect("Failed to generate configuration code from the template and JSON config");
 
@@ -333,26 +460,72 @@ impl FirmwareLayoutRuntime {
 }
 
 const FIRMWARE_LAYOUT_CONFIG_ENV: &str = "FIRMWARE_LAYOUT_CONFIG";
-const FIRMWARE_LAYOUT_CONFIG_JSON_DEFAULT_PATH: &str = "etc/config.json";
+const FIRMWARE_LAYOUT_PROFILE_ENV: &str = "FIRMWARE_LAYOUT_PROFILE";
+const FIRMWARE_LAYOUT_PROFILE_DIR: &str = "etc";
+const FIRMWARE_LAYOUT_PROFILE_DEFAULT: &str = "config";
+// (cargo feature, profile) of the profiles selectable with a feature
+const FIRMWARE_LAYOUT_PROFILE_FEATURES: [(&str, &str); 2] = [
+    ("CARGO_FEATURE_LAYOUT_8M", "config_8m"),
+    ("CARGO_FEATURE_LAYOUT_16M", "config_16m"),
+];
 const FIRMWARE_LAYOUT_CONFIG_RS_OUT_DIR: &str = "src";
 const FIRMWARE_LAYOUT_BUILD_TIME_RS_OUT: &str = "build_time.rs";
 const FIRMWARE_LAYOUT_RUNTIME_RS_OUT: &str = "runtime.rs";
 
+///
+/// The layout configuration file: FIRMWARE_LAYOUT_CONFIG if set, else
+/// etc/<profile>.json where the profile is FIRMWARE_LAYOUT_PROFILE, the
+/// profile of the layout_* feature enabled, or config.
+///
+fn get_config_path() -> PathBuf {
+    if let Ok(path) = env::var(FIRMWARE_LAYOUT_CONFIG_ENV) {
+        return PathBuf::from(path);
+    }
+    let profile = env::var(FIRMWARE_LAYOUT_PROFILE_ENV).unwrap_or_else(|_| {
+        let profiles: Vec<&str> = FIRMWARE_LAYOUT_PROFILE_FEATURES
+            .iter()
+            .filter(|(feature, _)| env::var(feature).is_ok())
+            .map(|(_, profile)| *profile)
+            .collect();
+        match profiles.as_slice() {
+            [] => FIRMWARE_LAYOUT_PROFILE_DEFAULT.to_string(),
+            [profile] => profile.to_string(),
+            _ => panic!(
+                "Only one layout_* feature may be enabled, got the profiles {:?}",
+                profiles
+            ),
+        }
+    });
+    Path::new(FIRMWARE_LAYOUT_PROFILE_DIR).join(format!("{}.json", profile))
+}
+
 fn main() {
     // Read and parse the Firmware layout configuration file.
     let mut data = String::new();
-    let firmware_layout_config_json_file_path = env::var(FIRMWARE_LAYOUT_CONFIG_ENV)
-        .unwrap_or_else(|_| FIRMWARE_LAYOUT_CONFIG_JSON_DEFAULT_PATH.to_string());
-    let mut firmware_layout_config_json_file = File::open(firmware_layout_config_json_file_path)
-        .expect("The Firmware layout configuration file does not exist");
+    let firmware_layout_config_json_file_path = get_config_path();
+    let mut firmware_layout_config_json_file = File::open(&firmware_layout_config_json_file_path)
+        .unwrap_or_else(|_| {
+            panic!(
+                "The Firmware layout configuration file {:?} does not exist",
+                firmware_layout_config_json_file_path
+            )
+        });
     firmware_layout_config_json_file
         .read_to_string(&mut data)
         .expect("Unable to read string");
     let firmware_layout_config: FirmwareLayoutConfig =
         json5::from_str(&data).expect("It is not a valid Firmware layout configuration file.");
 
+    let errors = firmware_layout_config.check();
+    if !errors.is_empty() {
+        panic!(
+            "Invalid Firmware layout configuration file {:?}:\n    {}",
+            firmware_layout_config_json_file_path,
+            errors.join("\n    ")
+        );
+    }
+
     let layout = FirmwareLayout::new_from_config(&firmware_layout_config);
-    // TODO: sanity checks on the layouts.
 
     // Generate config .rs file from the template and JSON inputs, then write to fs.
     layout.generate_build_time_rs();
@@ -364,7 +537,8 @@ fn main() {
     println!("cargo:rerun-if-changed=../Cargo.lock");
     println!(
         "cargo:rerun-if-changed={}",
-        FIRMWARE_LAYOUT_CONFIG_JSON_DEFAULT_PATH
+        firmware_layout_config_json_file_path.display()
     );
     println!("cargo:rerun-if-env-changed={}", FIRMWARE_LAYOUT_CONFIG_ENV);
+    println!("cargo:rerun-if-env-changed={}", FIRMWARE_LAYOUT_PROFILE_ENV);
 }
@@ -0,0 +1,29 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Layout of the VAR region, formatted by rust-firmware-tool as EDK2 NV
+//! storage. Offsets are relative to the start of the region.
+//!
+//! ```text
+//!             +------------------+ FIRMWARE_VAR_SIZE
+//!             |  FTW spare       |
+//!             +------------------+ NV_FTW_SPARE_OFFSET
+//!             |  FTW working     |
+//!             +------------------+ NV_FTW_WORKING_OFFSET
+//!             |  variable store  |
+//!             |  NV FV header    |
+//!             +------------------+ 0
+//! ```
+//!
+
+use crate::build_time::FIRMWARE_VAR_SIZE;
+
+pub const NV_FTW_WORKING_SIZE: u32 = 0x2000;
+// the spare block holds a copy of the variable store and the working block.
+pub const NV_FTW_SPARE_SIZE: u32 = FIRMWARE_VAR_SIZE / 2;
+pub const NV_VARIABLE_STORE_SIZE: u32 = FIRMWARE_VAR_SIZE - NV_FTW_SPARE_SIZE - NV_FTW_WORKING_SIZE;
+
+pub const NV_FTW_WORKING_OFFSET: u32 = NV_VARIABLE_STORE_SIZE;
+pub const NV_FTW_SPARE_OFFSET: u32 = NV_FTW_WORKING_OFFSET + NV_FTW_WORKING_SIZE;
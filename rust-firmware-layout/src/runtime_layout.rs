@@ -0,0 +1,407 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Place the runtime regions in the memory reported by the FSP HOB list.
+//!
+//! Every region is placed top-down, 4K aligned, in the highest system memory
+//! resource where it fits. The memory allocation HOBs, the PHIT memory (which
+//! holds the FSP HOB list) and the regions placed before are avoided.
+//!
+//! The HOB, page table, payload and stack regions must be below 4G, the IPL
+//! only has the low 4G mapped when it writes them. FSP reports the memory
+//! above 4G initialized but not tested, the heap may use it when asked to.
+//!
+
+use core::fmt;
+
+use r_uefi_pi::hob;
+use uefi_pi::hob_lib::{HobEnums, HobList};
+
+use crate::consts::{SIZE_1M, SIZE_4G, SIZE_4K};
+use crate::RuntimeMemoryLayout;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeRegion {
+    Hob,
+    PageTable,
+    Payload,
+    Stack,
+    Heap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The HOB list has no tested system memory resource.
+    NoSystemMemory,
+    /// No free system memory range is large enough for the region.
+    OutOfMemory { region: RuntimeRegion, size: u64 },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::NoSystemMemory => write!(f, "no tested system memory in the HOB list"),
+            LayoutError::OutOfMemory { region, size } => write!(
+                f,
+                "no free system memory for the {:?} region of 0x{:x} bytes",
+                region, size
+            ),
+        }
+    }
+}
+
+/// The sizes of the runtime regions, and where the heap may go.
+#[derive(Copy, Clone, Debug)]
+pub struct RuntimeMemorySizes {
+    pub hob_size: u64,
+    pub page_table_size: u64,
+    pub payload_size: u64,
+    pub stack_size: u64,
+    pub heap_size: u64,
+    pub heap_above_4g: bool,
+}
+
+// [base, end) of a memory range
+#[derive(Copy, Clone, Debug, Default)]
+struct MemoryRange {
+    base: u64,
+    end: u64,
+}
+
+impl MemoryRange {
+    fn overlaps(&self, base: u64, end: u64) -> bool {
+        self.base < end && base < self.end
+    }
+}
+
+fn align_down(value: u64) -> u64 {
+    value & !(SIZE_4K - 1)
+}
+
+// The part of a resource the region may use, None if it is not usable.
+fn usable_range(resource: &hob::ResourceDescription, above_4g: bool) -> Option<MemoryRange> {
+    if hob::ResourceType::from(resource.resource_type) != hob::ResourceType::SYSTEM_MEMORY {
+        return None;
+    }
+    let mut range = MemoryRange {
+        base: resource.physical_start.max(SIZE_1M),
+        end: resource.physical_start + resource.resource_length,
+    };
+    if resource
+        .resource_attribute
+        .contains(hob::ResourceAttributeType::TESTED)
+    {
+        if !above_4g {
+            range.end = range.end.min(SIZE_4G);
+        }
+    } else if above_4g
+        && resource
+            .resource_attribute
+            .contains(hob::ResourceAttributeType::PRESENT | hob::ResourceAttributeType::INITIALIZED)
+    {
+        range.base = range.base.max(SIZE_4G);
+    } else {
+        return None;
+    }
+    if range.base < range.end {
+        Some(range)
+    } else {
+        None
+    }
+}
+
+// The base of the lowest range in use overlapping [base, end).
+fn find_overlap(hob_list: &[u8], placed: &[MemoryRange], base: u64, end: u64) -> Option<u64> {
+    let mut overlap: Option<u64> = None;
+    let mut check = |range: MemoryRange| {
+        if range.overlaps(base, end) {
+            overlap = Some(overlap.map_or(range.base, |o| o.min(range.base)));
+        }
+    };
+    for h in HobList::new(hob_list) {
+        match h {
+            HobEnums::HandOff(handoff) => check(MemoryRange {
+                base: handoff.efi_memory_bottom,
+                end: handoff.efi_memory_top,
+            }),
+            HobEnums::MemoryAllocation(allocation) => {
+                let descriptor = allocation.alloc_descriptor;
+                check(MemoryRange {
+                    base: descriptor.memory_base_address,
+                    end: descriptor.memory_base_address + descriptor.memory_length,
+                })
+            }
+            _ => {}
+        }
+    }
+    placed.iter().for_each(|range| check(*range));
+    overlap
+}
+
+// The highest base of a free region of size bytes in range.
+fn find_top_down(
+    hob_list: &[u8],
+    placed: &[MemoryRange],
+    range: MemoryRange,
+    size: u64,
+) -> Option<u64> {
+    let mut top = range.end;
+    loop {
+        let base = align_down(top.checked_sub(size)?);
+        if base < range.base {
+            return None;
+        }
+        match find_overlap(hob_list, placed, base, base + size) {
+            // the overlapping range starts below top, search below it
+            Some(overlap_base) => top = overlap_base,
+            None => return Some(base),
+        }
+    }
+}
+
+fn place_region(
+    hob_list: &[u8],
+    placed: &[MemoryRange],
+    region: RuntimeRegion,
+    size: u64,
+    above_4g: bool,
+) -> Result<u64, LayoutError> {
+    let mut found: Option<u64> = None;
+    for h in HobList::new(hob_list) {
+        if let HobEnums::ResourceDescription(resource) = h {
+            let base = usable_range(&resource, above_4g)
+                .and_then(|range| find_top_down(hob_list, placed, range, size));
+            if let Some(base) = base {
+                found = Some(found.map_or(base, |f| f.max(base)));
+            }
+        }
+    }
+    found.ok_or(LayoutError::OutOfMemory { region, size })
+}
+
+impl RuntimeMemoryLayout {
+    ///
+    /// Place the runtime regions in the tested system memory of the HOB list,
+    /// with the sizes of the firmware layout config.
+    ///
+    pub fn new_from_hob_list(hob_list: &[u8], heap_above_4g: bool) -> Result<Self, LayoutError> {
+        use crate::runtime::*;
+        Self::new_from_hob_list_with_sizes(
+            hob_list,
+            &RuntimeMemorySizes {
+                hob_size: RUNTIME_HOB_SIZE as u64,
+                page_table_size: RUNTIME_PAGE_TABLE_SIZE as u64,
+                payload_size: RUNTIME_PAYLOAD_SIZE as u64,
+                stack_size: RUNTIME_STACK_SIZE as u64,
+                heap_size: RUNTIME_HEAP_SIZE as u64,
+                heap_above_4g,
+            },
+        )
+    }
+
+    pub fn new_from_hob_list_with_sizes(
+        hob_list: &[u8],
+        sizes: &RuntimeMemorySizes,
+    ) -> Result<Self, LayoutError> {
+        let has_system_memory = HobList::new(hob_list).any(|h| match h {
+            HobEnums::ResourceDescription(resource) => usable_range(&resource, false).is_some(),
+            _ => false,
+        });
+        if !has_system_memory {
+            return Err(LayoutError::NoSystemMemory);
+        }
+
+        let regions = [
+            (RuntimeRegion::Hob, sizes.hob_size, false),
+            (RuntimeRegion::PageTable, sizes.page_table_size, false),
+            (RuntimeRegion::Payload, sizes.payload_size, false),
+            (RuntimeRegion::Stack, sizes.stack_size, false),
+            (RuntimeRegion::Heap, sizes.heap_size, sizes.heap_above_4g),
+        ];
+        let mut placed = [MemoryRange::default(); 5];
+        for (index, (region, size, above_4g)) in regions.iter().enumerate() {
+            let base = place_region(hob_list, &placed[..index], *region, *size, *above_4g)?;
+            placed[index] = MemoryRange {
+                base,
+                end: base + size,
+            };
+        }
+
+        Ok(RuntimeMemoryLayout {
+            runtime_hob_base: placed[0].base,
+            runtime_page_table_base: placed[1].base,
+            runtime_payload_base: placed[2].base,
+            runtime_stack_top: placed[3].end,
+            runtime_stack_base: placed[3].base,
+            runtime_heap_base: placed[4].base,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "../../uefi-pi/test_data/fsp_hob_data.rs"]
+mod fsp_hob_data;
+
+#[cfg(test)]
+mod test {
+    use super::fsp_hob_data;
+    use super::*;
+    use scroll::{Pread, Pwrite};
+
+    const SIZES: RuntimeMemorySizes = RuntimeMemorySizes {
+        hob_size: 0x700000,
+        page_table_size: 0x100000,
+        payload_size: 0x800000,
+        stack_size: 0x800000,
+        heap_size: 0x1000000,
+        heap_above_4g: false,
+    };
+
+    // Apply f to every resource descriptor HOB of the list.
+    fn patch_resources<F: Fn(&mut hob::ResourceDescription)>(hob_list: &mut [u8], f: F) {
+        let mut offset = 0;
+        while offset < hob_list.len() {
+            let header: hob::GenericHeader = hob_list.pread(offset).unwrap();
+            match hob::HobType::from(header.r#type) {
+                hob::HobType::END_OF_HOB_LIST => break,
+                hob::HobType::RESOURCE_DESCRIPTOR => {
+                    let mut resource: hob::ResourceDescription = hob_list.pread(offset).unwrap();
+                    f(&mut resource);
+                    hob_list.pwrite(resource, offset).unwrap();
+                }
+                _ => {}
+            }
+            offset += header.length as usize;
+        }
+    }
+
+    fn regions(layout: &RuntimeMemoryLayout) -> [(u64, u64); 5] {
+        [
+            (layout.runtime_hob_base, SIZES.hob_size),
+            (layout.runtime_page_table_base, SIZES.page_table_size),
+            (layout.runtime_payload_base, SIZES.payload_size),
+            (layout.runtime_stack_base, SIZES.stack_size),
+            (layout.runtime_heap_base, SIZES.heap_size),
+        ]
+    }
+
+    fn check_no_overlap(layout: &RuntimeMemoryLayout) {
+        let regions = regions(layout);
+        for (index, (base, size)) in regions.iter().enumerate() {
+            assert_eq!(base % SIZE_4K, 0);
+            for (other_base, other_size) in regions[index + 1..].iter() {
+                assert!(base + size <= *other_base || other_base + other_size <= *base);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_2g() {
+        let hob_list = &fsp_hob_data::FSP_HOB_2G_EXAMPLE[..];
+        let layout = RuntimeMemoryLayout::new_from_hob_list_with_sizes(hob_list, &SIZES).unwrap();
+        check_no_overlap(&layout);
+        // the tested system memory ends at the FSP reserved memory
+        assert_eq!(layout.runtime_hob_base, 0x7ef00000 - SIZES.hob_size);
+        assert_eq!(layout.runtime_stack_top, layout.runtime_payload_base);
+        assert_eq!(
+            layout.runtime_heap_base + SIZES.heap_size,
+            layout.runtime_stack_base
+        );
+    }
+
+    #[test]
+    fn test_layout_8g() {
+        for hob_list in [
+            &fsp_hob_data::FSP_M_INIT_8G_HOB_EXAMPLE[..],
+            &fsp_hob_data::FSP_S_INIT_8G_HOB_EXAMPLE[..],
+        ]
+        .iter()
+        {
+            let layout =
+                RuntimeMemoryLayout::new_from_hob_list_with_sizes(hob_list, &SIZES).unwrap();
+            check_no_overlap(&layout);
+            for (base, size) in regions(&layout).iter() {
+                assert!(base + size <= 0x7ef00000);
+            }
+
+            // the untested memory above 4G is only for the heap
+            let sizes = RuntimeMemorySizes {
+                heap_above_4g: true,
+                ..SIZES
+            };
+            let layout =
+                RuntimeMemoryLayout::new_from_hob_list_with_sizes(hob_list, &sizes).unwrap();
+            check_no_overlap(&layout);
+            assert_eq!(layout.runtime_hob_base, 0x7ef00000 - SIZES.hob_size);
+            assert_eq!(layout.runtime_heap_base, 0x180000000 - SIZES.heap_size);
+        }
+    }
+
+    #[test]
+    fn test_layout_avoid_memory_allocation() {
+        let hob_list = &fsp_hob_data::FSP_HOB_2G_EXAMPLE[..];
+        let hob_list = &hob_list[..uefi_pi::hob_lib::get_hob_total_size(hob_list).unwrap()];
+        let mut hob_list = hob_list.to_vec();
+        hob_list.resize(
+            hob_list.len() + core::mem::size_of::<hob::MemoryAllocation>(),
+            0,
+        );
+
+        let allocation = hob::MemoryAllocation {
+            header: hob::GenericHeader::new(
+                hob::HobType::MEMORY_ALLOCATION,
+                core::mem::size_of::<hob::MemoryAllocation>(),
+            ),
+            alloc_descriptor: hob::MemoryAllocationHeader {
+                name: hob::Guid::from_fields(1, 2, 3, 0, 0, &[0, 0, 0, 0, 0, 0]),
+                memory_base_address: 0x7e000000,
+                memory_length: 0xa00000,
+                memory_type: 4,
+                reserved: [0u8; 4],
+            },
+        };
+        let mut allocation_buffer = [0u8; core::mem::size_of::<hob::MemoryAllocation>()];
+        allocation_buffer.pwrite(allocation, 0).unwrap();
+        assert!(uefi_pi::hob_lib::HobListMut::new(&mut hob_list).add(&allocation_buffer));
+
+        let layout = RuntimeMemoryLayout::new_from_hob_list_with_sizes(&hob_list, &SIZES).unwrap();
+        check_no_overlap(&layout);
+        for (base, size) in regions(&layout).iter() {
+            assert!(base + size <= 0x7e000000 || *base >= 0x7ea00000);
+        }
+        // the HOB region does not fit above the allocation, the page table does
+        assert_eq!(layout.runtime_hob_base, 0x7e000000 - SIZES.hob_size);
+        assert_eq!(
+            layout.runtime_page_table_base,
+            0x7ef00000 - SIZES.page_table_size
+        );
+    }
+
+    #[test]
+    fn test_layout_out_of_memory() {
+        let mut hob_list = fsp_hob_data::FSP_HOB_2G_EXAMPLE.to_vec();
+        // 32M of system memory above 1M
+        patch_resources(&mut hob_list, |resource| {
+            if resource.physical_start == 0x100000 {
+                resource.resource_length = 0x2000000;
+            }
+        });
+        assert_eq!(
+            RuntimeMemoryLayout::new_from_hob_list_with_sizes(&hob_list, &SIZES).unwrap_err(),
+            LayoutError::OutOfMemory {
+                region: RuntimeRegion::Heap,
+                size: SIZES.heap_size
+            }
+        );
+
+        patch_resources(&mut hob_list, |resource| {
+            resource.resource_attribute = hob::ResourceAttributeType::PRESENT;
+        });
+        assert_eq!(
+            RuntimeMemoryLayout::new_from_hob_list_with_sizes(&hob_list, &SIZES).unwrap_err(),
+            LayoutError::NoSystemMemory
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ToolError {
+    /// Missing or invalid command line arguments.
+    Usage(String),
+    /// Fail to read or write the file.
+    Io(String, io::Error),
+    /// The component does not fit the region.
+    ComponentTooLarge {
+        component: String,
+        size: usize,
+        region: &'static str,
+        capacity: usize,
+    },
+    /// The FSP binary size is not FIRMWARE_FSP_*_SIZE.
+    FspSizeMismatch {
+        name: &'static str,
+        size: usize,
+        region_size: usize,
+    },
+    /// pe_loader::pe::relocate fails.
+    PeRelocation {
+        component: &'static str,
+        base: usize,
+    },
+    /// The regions of rust-firmware-layout do not make up the flash.
+    Layout(String),
+    /// An input or a built region is malformed, or fails the verification.
+    InvalidData(String),
+    /// inspect found mismatches against rust-firmware-layout.
+    Mismatch(usize),
+}
+
+impl ToolError {
+    ///
+    /// Process exit code: 2 for command line errors, 1 for the others.
+    ///
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ToolError::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToolError::Usage(message) => write!(f, "{}", message),
+            ToolError::Io(path, e) => write!(f, "{}: {}", path, e),
+            ToolError::ComponentTooLarge {
+                component,
+                size,
+                region,
+                capacity,
+            } => write!(
+                f,
+                "{} is 0x{:x} bytes, {} allows 0x{:x}",
+                component, size, region, capacity
+            ),
+            ToolError::FspSizeMismatch {
+                name,
+                size,
+                region_size,
+            } => write!(
+                f,
+                "{} is 0x{:x} bytes, FIRMWARE_{}_SIZE is 0x{:x}, rebuild the FSP or update rust-firmware-layout",
+                name,
+                size,
+                name.replace('-', "_"),
+                region_size
+            ),
+            ToolError::PeRelocation { component, base } => write!(
+                f,
+                "fail to relocate {} to 0x{:x}, it must be a x64 PE image with a .reloc section",
+                component, base
+            ),
+            ToolError::Layout(reason) => write!(f, "invalid rust-firmware-layout: {}", reason),
+            ToolError::InvalidData(reason) => write!(f, "{}", reason),
+            ToolError::Mismatch(mismatches) => write!(f, "{} mismatches found", mismatches),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ToolError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = ToolError> = core::result::Result<T, E>;
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    fs::read(path).map_err(|e| ToolError::Io(path.display().to_string(), e))
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    fs::write(path, data).map_err(|e| ToolError::Io(path.display().to_string(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_message() {
+        let e = ToolError::ComponentTooLarge {
+            component: "rust payload".to_string(),
+            size: 0x2a0000,
+            region: "the payload region",
+            capacity: 0x27ff88,
+        };
+        assert_eq!(
+            e.to_string(),
+            "rust payload is 0x2a0000 bytes, the payload region allows 0x27ff88"
+        );
+        assert_eq!(e.exit_code(), 1);
+
+        let e = ToolError::FspSizeMismatch {
+            name: "FSP-M",
+            size: 0x60000,
+            region_size: 0x66000,
+        };
+        assert!(e
+            .to_string()
+            .starts_with("FSP-M is 0x60000 bytes, FIRMWARE_FSP_M_SIZE is 0x66000"));
+
+        assert_eq!(
+            ToolError::Usage("missing arguments".to_string()).exit_code(),
+            2
+        );
+    }
+}
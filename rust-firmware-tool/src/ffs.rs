@@ -0,0 +1,263 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Additional FFS files of the payload FV, given with
+//! `--add-file guid:type:path[:ui-name]`.
+//!
+//! Each file holds a PE32 or a RAW section and a USER_INTERFACE section
+//! with its name, it is placed after the payload file.
+//!
+
+use std::path::Path;
+
+use core::mem::size_of;
+use r_uefi_pi::fv::{
+    CommonSectionHeader2, FfsFileHeader, FfsFileHeader2, FvFileType, SectionType,
+    FFS_ATTRIB_CHECKSUM, FV_FILETYPE_APPLICATION, FV_FILETYPE_DRIVER, FV_FILETYPE_FFS_PAD,
+    FV_FILETYPE_FREEFORM, SECTION_PE32, SECTION_RAW, SECTION_USER_INTERFACE,
+};
+use scroll::Pread;
+use uefi_pi::fv_lib;
+
+use super::error::{read_file, Result, ToolError};
+use super::inspect::format_guid;
+use super::manifest::{self, ManifestPayloadFile};
+use super::{update_ffs_checksum, write_ffs_header, write_section_header};
+
+pub const ADD_FILE_USAGE: &str = "--add-file guid:type:path[:ui-name], \
+    type is application, driver or freeform";
+
+// (name, file type, section type of the file data)
+const PAYLOAD_FILE_TYPES: [(&str, FvFileType, SectionType); 3] = [
+    ("application", FV_FILETYPE_APPLICATION, SECTION_PE32),
+    ("driver", FV_FILETYPE_DRIVER, SECTION_PE32),
+    ("freeform", FV_FILETYPE_FREEFORM, SECTION_RAW),
+];
+
+pub struct PayloadFile {
+    pub name: [u8; 16],
+    pub file_type: FvFileType,
+    pub path: String,
+    pub ui_name: String,
+    pub data: Vec<u8>,
+}
+
+impl PayloadFile {
+    fn type_name(&self) -> &'static str {
+        PAYLOAD_FILE_TYPES
+            .iter()
+            .find(|(_, file_type, _)| *file_type == self.file_type)
+            .map_or("unknown", |(name, _, _)| name)
+    }
+
+    pub fn manifest_entry(&self) -> ManifestPayloadFile {
+        ManifestPayloadFile {
+            name: format_guid(&self.name),
+            file_type: self.type_name().to_string(),
+            ui_name: self.ui_name.clone(),
+            input: manifest::manifest_input(&self.path, &self.data),
+        }
+    }
+}
+
+///
+/// Parse a registry format GUID, 8C8CE578-8A3D-4F1C-9935-896185C32DD3.
+///
+pub fn parse_guid(guid: &str) -> Option<[u8; 16]> {
+    let fields: Vec<&str> = guid.split('-').collect();
+    let valid = [8, 4, 4, 4, 12]
+        .iter()
+        .zip(&fields)
+        .all(|(len, field)| field.len() == *len && field.chars().all(|c| c.is_ascii_hexdigit()));
+    if fields.len() != 5 || !valid {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    bytes[0..4].copy_from_slice(&u32::from_str_radix(fields[0], 16).ok()?.to_le_bytes());
+    bytes[4..6].copy_from_slice(&u16::from_str_radix(fields[1], 16).ok()?.to_le_bytes());
+    bytes[6..8].copy_from_slice(&u16::from_str_radix(fields[2], 16).ok()?.to_le_bytes());
+    let tail = format!("{}{}", fields[3], fields[4]);
+    for (i, byte) in bytes[8..].iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&tail[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn split_add_file(arg: &str) -> Option<(&str, &str, &str, Option<&str>)> {
+    let mut fields = arg.splitn(3, ':');
+    let (guid, file_type, rest) = (fields.next()?, fields.next()?, fields.next()?);
+    // a single letter before the last colon is a drive letter, not a path.
+    match rest.rfind(':') {
+        Some(colon) if colon > 1 => {
+            Some((guid, file_type, &rest[..colon], Some(&rest[colon + 1..])))
+        }
+        _ => Some((guid, file_type, rest, None)),
+    }
+}
+
+///
+/// Parse a `--add-file` argument and read the file.
+///
+/// The UI name defaults to the file name without extension.
+///
+pub fn read_payload_file(arg: &str) -> Result<PayloadFile> {
+    let invalid =
+        |message: String| ToolError::Usage(format!("{}\nusage: {}", message, ADD_FILE_USAGE));
+    let (guid, type_name, path, ui_name) =
+        split_add_file(arg).ok_or_else(|| invalid(format!("invalid --add-file {}", arg)))?;
+    let name = parse_guid(guid).ok_or_else(|| invalid(format!("invalid GUID {}", guid)))?;
+    let (_, file_type, section_type) = PAYLOAD_FILE_TYPES
+        .iter()
+        .find(|(name, _, _)| *name == type_name)
+        .ok_or_else(|| invalid(format!("unknown file type {}", type_name)))?;
+    let ui_name = match ui_name {
+        Some(ui_name) if !ui_name.is_empty() => ui_name.to_string(),
+        _ => Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+
+    let data = read_file(path)?;
+    if *section_type == SECTION_PE32 && !pe_loader::pe::is_pe(&data) {
+        return Err(ToolError::InvalidData(format!(
+            "{} is not a x64 PE image, add it as freeform",
+            path
+        )));
+    }
+    Ok(PayloadFile {
+        name,
+        file_type: *file_type,
+        path: path.to_string(),
+        ui_name,
+        data,
+    })
+}
+
+// sections are 4 bytes aligned from the start of the file data.
+fn append_section(sections: &mut Vec<u8>, section_type: SectionType, data: &[u8]) {
+    sections.resize((sections.len() + 3) & !3, 0);
+    let offset = sections.len();
+    sections.resize(offset + size_of::<CommonSectionHeader2>(), 0);
+    let header_size = write_section_header(&mut sections[offset..], section_type, data.len());
+    sections.truncate(offset + header_size);
+    sections.extend_from_slice(data);
+}
+
+///
+/// Build the FFS file of a payload file, with a checksum of its data.
+///
+pub fn build_ffs_file(file: &PayloadFile) -> Vec<u8> {
+    let section_type = PAYLOAD_FILE_TYPES
+        .iter()
+        .find(|(_, file_type, _)| *file_type == file.file_type)
+        .map_or(SECTION_RAW, |(_, _, section_type)| *section_type);
+
+    // the UI name is a null-terminated UCS-2 string
+    let ui_name: Vec<u8> = file
+        .ui_name
+        .encode_utf16()
+        .chain(core::iter::once(0))
+        .flat_map(|c| c.to_le_bytes().to_vec())
+        .collect();
+
+    let mut sections = Vec::new();
+    append_section(&mut sections, section_type, &file.data);
+    append_section(&mut sections, SECTION_USER_INTERFACE, &ui_name);
+
+    let mut ffs_file = vec![0u8; size_of::<FfsFileHeader2>()];
+    let header_size = write_ffs_header(
+        &mut ffs_file,
+        &file.name,
+        file.file_type,
+        FFS_ATTRIB_CHECKSUM,
+        sections.len(),
+    );
+    ffs_file.truncate(header_size);
+    ffs_file.extend_from_slice(&sections);
+    update_ffs_checksum(&mut ffs_file);
+    ffs_file
+}
+
+///
+/// Place FFS files at the FV free space, each one 8 bytes aligned.
+///
+/// The FV header checksum is not updated.
+///
+pub fn add_ffs_files(fv_data: &mut [u8], ffs_files: &[Vec<u8>]) -> Result<()> {
+    for ffs_file in ffs_files {
+        let mut name = [0u8; 16];
+        name.copy_from_slice(&ffs_file[..16]);
+        if fv_lib::get_file_range_from_fv(fv_data, &name).is_some() {
+            return Err(ToolError::InvalidData(format!(
+                "payload FV already contains file {}",
+                format_guid(&name)
+            )));
+        }
+        let offset = fv_lib::get_fv_free_space_offset(fv_data)
+            .ok_or_else(|| ToolError::InvalidData("payload FV is malformed".to_string()))?;
+        if offset + ffs_file.len() > fv_data.len() {
+            return Err(ToolError::ComponentTooLarge {
+                component: format!("file {}", format_guid(&name)),
+                size: ffs_file.len(),
+                region: "the payload FV free space",
+                capacity: fv_data.len().saturating_sub(offset),
+            });
+        }
+        fv_data[offset..offset + ffs_file.len()].copy_from_slice(ffs_file);
+    }
+    Ok(())
+}
+
+///
+/// Return the FFS files added after the payload file of a payload FV, the
+/// pad files and the signature file are skipped.
+///
+pub fn get_added_ffs_files(fv_data: &[u8], payload_file_type: FvFileType) -> Vec<Vec<u8>> {
+    fv_lib::get_file_ranges_from_fv(fv_data)
+        .map(|range| &fv_data[range])
+        .filter(|ffs_file| {
+            let header: FfsFileHeader = ffs_file.pread(0).unwrap();
+            header.r#type != payload_file_type
+                && header.r#type != FV_FILETYPE_FFS_PAD
+                && &header.name != fw_verifier::FV_SIGNATURE_FILE_GUID.as_bytes()
+        })
+        .map(|ffs_file| ffs_file.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_add_file() {
+        assert_eq!(
+            parse_guid("8C8CE578-8A3D-4F1C-9935-896185C32DD3").map(|guid| format_guid(&guid)),
+            Some("8C8CE578-8A3D-4F1C-9935-896185C32DD3".to_string())
+        );
+        assert_eq!(parse_guid("8C8CE578-8A3D-4F1C-9935-896185C32DD"), None);
+        assert_eq!(parse_guid("8C8CE578-8A3D-4F1C-993-5896185C32DD3"), None);
+        assert_eq!(parse_guid("8C8CE578-8A3D-4F1C-9935-896185C32DDX"), None);
+
+        assert_eq!(
+            split_add_file("guid:freeform:config.bin"),
+            Some(("guid", "freeform", "config.bin", None))
+        );
+        assert_eq!(
+            split_add_file("guid:application:shell/Shell.efi:Shell"),
+            Some(("guid", "application", "shell/Shell.efi", Some("Shell")))
+        );
+        assert_eq!(
+            split_add_file("guid:driver:C:\\drivers\\diag.efi"),
+            Some(("guid", "driver", "C:\\drivers\\diag.efi", None))
+        );
+        assert_eq!(
+            split_add_file("guid:driver:C:\\drivers\\diag.efi:Diag"),
+            Some(("guid", "driver", "C:\\drivers\\diag.efi", Some("Diag")))
+        );
+        assert_eq!(split_add_file("guid:driver"), None);
+    }
+}
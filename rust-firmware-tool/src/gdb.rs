@@ -0,0 +1,188 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! GDB scripts loading the symbols of the relocated IPL and payload, for
+//! debugging with the QEMU gdbstub:
+//!
+//! ```text
+//! gdb -ex 'target remote :1234' -x final.gdbinit
+//! ```
+//!
+//! The IPL base is known at build time, final.gdbinit loads its symbols.
+//! The payload is relocated at boot, rust-ipl logs a PAYLOAD_TEXT_BASE=0x...
+//! line and `gdb-payload` turns it into the command of the payload.
+//!
+//! GDB reads the symbols from the DWARF sections of the PE images.
+//!
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use scroll::Pread;
+
+use super::error::{read_file, write_file, Result, ToolError};
+
+/// The line rust-ipl logs with the .text base of the loaded payload.
+pub const PAYLOAD_TEXT_BASE_TAG: &str = "PAYLOAD_TEXT_BASE=";
+
+pub fn gdbinit_path(rust_firmware_name: &str) -> PathBuf {
+    Path::new(rust_firmware_name).with_extension("gdbinit")
+}
+
+// GDB runs from anywhere, give it the absolute path when possible.
+fn symbol_file_path(name: &str) -> String {
+    fs::canonicalize(name)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| name.to_string())
+}
+
+///
+/// The add-symbol-file command of a PE image loaded at image_base, with the
+/// address of every section: .text first, the others with -s.
+///
+pub fn add_symbol_file_command(
+    symbol_file: &str,
+    pe_image: &[u8],
+    image_base: u64,
+) -> Result<String> {
+    let sections = pe_loader::pe::sections(pe_image)
+        .ok_or_else(|| ToolError::InvalidData(format!("{}: invalid PE header", symbol_file)))?;
+    let mut text_address = None;
+    let mut section_addresses = String::new();
+    for section in sections {
+        let address = image_base + section.virtual_address as u64;
+        match section.name() {
+            b".text" => text_address = Some(address),
+            name => section_addresses.push_str(&format!(
+                " -s {} {:#x}",
+                String::from_utf8_lossy(name),
+                address
+            )),
+        }
+    }
+    let text_address = text_address
+        .ok_or_else(|| ToolError::InvalidData(format!("{} has no .text section", symbol_file)))?;
+    Ok(format!(
+        "add-symbol-file {} {:#x}{}",
+        symbol_file_path(symbol_file),
+        text_address,
+        section_addresses
+    ))
+}
+
+///
+/// Write the .gdbinit of the image, the IPL is relocated so that its entry
+/// point is ipl_entry.
+///
+pub fn write_gdbinit(
+    rust_firmware_name: &str,
+    rust_ipl_name: &str,
+    rust_ipl_bin: &[u8],
+    ipl_entry: u32,
+) -> Result<()> {
+    // AddressOfEntryPoint in the PE32+ optional header
+    let pe_header_offset = rust_ipl_bin.pread::<u32>(0x3c).unwrap() as usize;
+    let entry_point = rust_ipl_bin
+        .pread::<u32>(pe_header_offset + 24 + 16)
+        .unwrap();
+    let ipl_base = (ipl_entry - entry_point) as u64;
+
+    let gdbinit = format!(
+        "# symbols of {}\n\
+         # rust IPL relocated to {:#x}\n\
+         {}\n\
+         # the rust payload is relocated at boot, get its command with\n\
+         # rust-firmware-tool gdb-payload RUST_PAYLOAD_BIN SERIAL_LOG\n",
+        rust_firmware_name,
+        ipl_base,
+        add_symbol_file_command(rust_ipl_name, rust_ipl_bin, ipl_base)?
+    );
+    write_file(gdbinit_path(rust_firmware_name), gdbinit.as_bytes())
+}
+
+///
+/// Return the .text base of the last PAYLOAD_TEXT_BASE line of the log,
+/// the log may hold several boots.
+///
+pub fn find_payload_text_base(serial_log: &str) -> Option<u64> {
+    serial_log.lines().rev().find_map(|line| {
+        let value = &line[line.find(PAYLOAD_TEXT_BASE_TAG)? + PAYLOAD_TEXT_BASE_TAG.len()..];
+        let value = value.trim().trim_start_matches("0x");
+        u64::from_str_radix(value, 16).ok()
+    })
+}
+
+///
+/// Print the add-symbol-file command of the payload, loaded at the base
+/// logged by rust-ipl.
+///
+pub fn gdb_payload_command(rust_payload_name: &str, serial_log_name: &str) -> Result<()> {
+    let rust_payload_bin = read_file(rust_payload_name)?;
+    let serial_log = String::from_utf8_lossy(&read_file(serial_log_name)?).into_owned();
+    let text_base = find_payload_text_base(&serial_log).ok_or_else(|| {
+        ToolError::InvalidData(format!(
+            "{}: no {} line, rust-ipl logs it when the payload is a PE image",
+            serial_log_name, PAYLOAD_TEXT_BASE_TAG
+        ))
+    })?;
+    let text = pe_loader::pe::find_section(&rust_payload_bin, b".text").ok_or_else(|| {
+        ToolError::InvalidData(format!("{} has no .text section", rust_payload_name))
+    })?;
+    let payload_base = text_base - text.virtual_address as u64;
+    println!(
+        "{}",
+        add_symbol_file_command(rust_payload_name, &rust_payload_bin, payload_base)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scroll::Pwrite;
+
+    // PE headers with a .text section at 0x1000 and a .data section at 0x3000
+    fn build_pe() -> Vec<u8> {
+        let mut pe_image = vec![0u8; 0x200];
+        pe_image.pwrite(0x5a4du16, 0).unwrap();
+        pe_image.pwrite(0x40u32, 0x3c).unwrap();
+        pe_image.pwrite(0x4550u32, 0x40).unwrap();
+        pe_image.pwrite(0x8664u16, 0x44).unwrap();
+        pe_image.pwrite(2u16, 0x46).unwrap();
+        pe_image.pwrite(0xf0u16, 0x54).unwrap();
+        pe_image.pwrite(0x20bu16, 0x58).unwrap();
+        pe_image.pwrite(0x1234u32, 0x58 + 16).unwrap();
+        for (index, (name, rva)) in [(b".text\0\0\0", 0x1000u32), (b".data\0\0\0", 0x3000)]
+            .iter()
+            .enumerate()
+        {
+            let section = 0x58 + 0xf0 + index * 40;
+            pe_image[section..section + 8].copy_from_slice(*name);
+            pe_image.pwrite(*rva, section + 12).unwrap();
+        }
+        pe_image
+    }
+
+    #[test]
+    fn test_add_symbol_file_command() {
+        let pe_image = build_pe();
+        assert_eq!(
+            add_symbol_file_command("/ipl.efi", &pe_image, 0xffcc0000).unwrap(),
+            "add-symbol-file /ipl.efi 0xffcc1000 -s .data 0xffcc3000"
+        );
+        assert!(add_symbol_file_command("/ipl.efi", &pe_image[..0x100], 0).is_err());
+    }
+
+    #[test]
+    fn test_find_payload_text_base() {
+        let serial_log = "Payload is pe image\r\n\
+                          PAYLOAD_TEXT_BASE=0x1001000\r\n\
+                          Payload is pe image\r\n\
+                          INFO - PAYLOAD_TEXT_BASE=0x7e01000\r\n\
+                          Call payload entry - 0x7E02234\r\n";
+        assert_eq!(find_payload_text_base(serial_log), Some(0x7e01000));
+        assert_eq!(find_payload_text_base("Payload is elf image\n"), None);
+    }
+}
@@ -0,0 +1,630 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+use core::mem::size_of;
+use r_uefi_pi::fv::*;
+use scroll::Pread;
+use uefi_pi::decompress::{self, DecompressError};
+use uefi_pi::fv_lib;
+use uefi_pi::variable_lib::{self, VariableStore};
+
+use rust_firmware_layout::build_time::*;
+use rust_firmware_layout::fsp_build_time::*;
+use rust_firmware_layout::nv_storage::NV_FTW_WORKING_OFFSET;
+use rust_fsp_wrapper::fsp_info_header::{FspInfoHeader, FSP_INFO_HEADER_OFF};
+
+use fw_verifier::VerifyError;
+
+use super::check_payload_signature;
+use super::error::{read_file, Result, ToolError};
+use super::manifest::hex_string;
+use super::upd::{self, FSP_UPD_SIGNATURES};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionKind {
+    Raw,
+    NvStorage,
+    Fv,
+    Fsp,
+    ResetVector,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+    pub base: u32,
+    pub kind: RegionKind,
+}
+
+///
+/// All regions of the flash image, ordered by offset.
+///
+/// The FSP-T/M/S components are listed instead of the whole FSP region.
+///
+pub const FIRMWARE_REGIONS: [Region; 8] = [
+    Region {
+        name: "VAR",
+        offset: FIRMWARE_RESERVED1_OFFSET as usize,
+        size: FIRMWARE_VAR_SIZE as usize,
+        base: LOADED_RESERVED1_BASE,
+        kind: RegionKind::NvStorage,
+    },
+    Region {
+        name: "PADDING",
+        offset: FIRMWARE_PADDING_OFFSET as usize,
+        size: FIRMWARE_PADDING_SIZE as usize,
+        base: LOADED_PADDING_BASE,
+        kind: RegionKind::Raw,
+    },
+    Region {
+        name: "PAYLOAD",
+        offset: FIRMWARE_PAYLOAD_OFFSET as usize,
+        size: FIRMWARE_PAYLOAD_SIZE as usize,
+        base: LOADED_PAYLOAD_BASE,
+        kind: RegionKind::Fv,
+    },
+    Region {
+        name: "IPL",
+        offset: FIRMWARE_IPL_OFFSET as usize,
+        size: FIRMWARE_IPL_SIZE as usize,
+        base: LOADED_IPL_BASE,
+        kind: RegionKind::Fv,
+    },
+    Region {
+        name: "FSP-T",
+        offset: FIRMWARE_FSP_T_OFFSET as usize,
+        size: FIRMWARE_FSP_T_SIZE as usize,
+        base: LOADED_FSP_T_BASE,
+        kind: RegionKind::Fsp,
+    },
+    Region {
+        name: "FSP-M",
+        offset: FIRMWARE_FSP_M_OFFSET as usize,
+        size: FIRMWARE_FSP_M_SIZE as usize,
+        base: LOADED_FSP_M_BASE,
+        kind: RegionKind::Fsp,
+    },
+    Region {
+        name: "FSP-S",
+        offset: FIRMWARE_FSP_S_OFFSET as usize,
+        size: FIRMWARE_FSP_S_SIZE as usize,
+        base: LOADED_FSP_S_BASE,
+        kind: RegionKind::Fsp,
+    },
+    Region {
+        name: "RESET_VECTOR",
+        offset: FIRMWARE_RESET_VECTOR_OFFSET as usize,
+        size: FIRMWARE_RESET_VECTOR_SIZE as usize,
+        base: LOADED_RESET_VECTOR_BASE,
+        kind: RegionKind::ResetVector,
+    },
+];
+
+pub fn format_guid(guid: &[u8; 16]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8],
+        guid[9],
+        guid[10],
+        guid[11],
+        guid[12],
+        guid[13],
+        guid[14],
+        guid[15]
+    )
+}
+
+fn file_type_name(file_type: FvFileType) -> &'static str {
+    match file_type {
+        FV_FILETYPE_RAW => "RAW",
+        FV_FILETYPE_FREEFORM => "FREEFORM",
+        FV_FILETYPE_SECURITY_CORE => "SECURITY_CORE",
+        FV_FILETYPE_PEI_CORE => "PEI_CORE",
+        FV_FILETYPE_DXE_CORE => "DXE_CORE",
+        FV_FILETYPE_PEIM => "PEIM",
+        FV_FILETYPE_DRIVER => "DRIVER",
+        FV_FILETYPE_COMBINED_PEIM_DRIVER => "COMBINED_PEIM_DRIVER",
+        FV_FILETYPE_APPLICATION => "APPLICATION",
+        FV_FILETYPE_MM => "MM",
+        FV_FILETYPE_FIRMWARE_VOLUME_IMAGE => "FIRMWARE_VOLUME_IMAGE",
+        FV_FILETYPE_COMBINED_MM_DXE => "COMBINED_MM_DXE",
+        FV_FILETYPE_MM_CORE => "MM_CORE",
+        FV_FILETYPE_MM_STANDALONE => "MM_STANDALONE",
+        FV_FILETYPE_MM_CORE_STANDALONE => "MM_CORE_STANDALONE",
+        FV_FILETYPE_FFS_PAD => "FFS_PAD",
+        _ => "UNKNOWN",
+    }
+}
+
+fn section_type_name(section_type: SectionType) -> &'static str {
+    match section_type {
+        SECTION_COMPRESSION => "COMPRESSION",
+        SECTION_GUID_DEFINED => "GUID_DEFINED",
+        SECTION_DISPOSABLE => "DISPOSABLE",
+        SECTION_PE32 => "PE32",
+        SECTION_PIC => "PIC",
+        SECTION_TE => "TE",
+        SECTION_DXE_DEPEX => "DXE_DEPEX",
+        SECTION_VERSION => "VERSION",
+        SECTION_USER_INTERFACE => "USER_INTERFACE",
+        SECTION_COMPATIBILITY16 => "COMPATIBILITY16",
+        SECTION_FIRMWARE_VOLUME_IMAGE => "FIRMWARE_VOLUME_IMAGE",
+        SECTION_FREEFORM_SUBTYPE_GUID => "FREEFORM_SUBTYPE_GUID",
+        SECTION_RAW => "RAW",
+        SECTION_PEI_DEPEX => "PEI_DEPEX",
+        SECTION_MM_DEPEX => "MM_DEPEX",
+        _ => "UNKNOWN",
+    }
+}
+
+// The highest EFI_FILE_* bit set decides the file state, the state byte is
+// inverted first when the FV erase polarity is 1.
+fn file_state_name(state: FfsFileState, erase_polarity: bool) -> &'static str {
+    let state = if erase_polarity { !state } else { state };
+    if state & 0xC0 != 0 {
+        "UNKNOWN"
+    } else if state & EFI_FILE_HEADER_INVALID != 0 {
+        "HEADER_INVALID"
+    } else if state & EFI_FILE_DELETED != 0 {
+        "DELETED"
+    } else if state & EFI_FILE_MARKED_FOR_UPDATE != 0 {
+        "MARKED_FOR_UPDATE"
+    } else if state & EFI_FILE_DATA_VALID != 0 {
+        "DATA_VALID"
+    } else if state & EFI_FILE_HEADER_VALID != 0 {
+        "HEADER_VALID"
+    } else if state & EFI_FILE_HEADER_CONSTRUCTION != 0 {
+        "HEADER_CONSTRUCTION"
+    } else {
+        "NONE"
+    }
+}
+
+fn read_u24(buf: &[u8; 3]) -> usize {
+    buf[0] as usize + ((buf[1] as usize) << 8) + ((buf[2] as usize) << 16)
+}
+
+struct Inspector {
+    mismatches: usize,
+}
+
+impl Inspector {
+    fn mismatch(&mut self, message: String) {
+        self.mismatches += 1;
+        println!("    MISMATCH: {}", message);
+    }
+
+    fn inspect_sections(&mut self, sections: &[u8], indent: usize) {
+        let mut offset = 0;
+        while offset + size_of::<CommonSectionHeader>() <= sections.len() {
+            let header: CommonSectionHeader = sections.pread(offset).unwrap();
+            // a CommonSectionHeader2 follows the size in extended_size
+            let (header_size, section_size) = if header.size == [0xff, 0xff, 0xff] {
+                let extended_size = sections
+                    .pread::<CommonSectionHeader2>(offset)
+                    .map_or(0, |header| header.extended_size as usize);
+                (size_of::<CommonSectionHeader2>(), extended_size)
+            } else {
+                (size_of::<CommonSectionHeader>(), read_u24(&header.size))
+            };
+            println!(
+                "{:indent$}section +0x{:06x} {:<22} size 0x{:06x}",
+                "",
+                offset,
+                section_type_name(header.r#type),
+                section_size,
+                indent = indent
+            );
+            if section_size < header_size || offset + section_size > sections.len() {
+                self.mismatch(format!("section size 0x{:x} is out of file", section_size));
+                return;
+            }
+            let section = &sections[offset..offset + section_size];
+            match header.r#type {
+                SECTION_COMPRESSION => self.inspect_compression(section, header_size, indent + 4),
+                SECTION_GUID_DEFINED => self.inspect_guid_defined(section, header_size, indent + 4),
+                SECTION_USER_INTERFACE => {
+                    let name: Vec<u16> = section[header_size..]
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .take_while(|c| *c != 0)
+                        .collect();
+                    println!(
+                        "{:indent$}name \"{}\"",
+                        "",
+                        String::from_utf16_lossy(&name),
+                        indent = indent + 4
+                    );
+                }
+                _ => {}
+            }
+            // sections are 4 bytes aligned
+            offset = (offset + section_size + 3) & !3;
+        }
+    }
+
+    fn inspect_decompressed(
+        &mut self,
+        decompressed: Result<Vec<u8>, DecompressError>,
+        indent: usize,
+    ) {
+        match decompressed {
+            Ok(sections) => self.inspect_sections(&sections, indent),
+            Err(e) => self.mismatch(format!("fail to decompress section - {:?}", e)),
+        }
+    }
+
+    fn inspect_compression(&mut self, section: &[u8], header_size: usize, indent: usize) {
+        let data_offset = header_size + COMPRESSION_SECTION_SIZE - size_of::<CommonSectionHeader>();
+        if section.len() < data_offset {
+            self.mismatch("compression section is truncated".to_string());
+            return;
+        }
+        let uncompressed_length: u32 = section.pread(header_size).unwrap();
+        let compression_type: u8 = section.pread(header_size + 4).unwrap();
+        println!(
+            "{:indent$}compression type {} uncompressed length 0x{:06x}",
+            "",
+            compression_type,
+            uncompressed_length,
+            indent = indent
+        );
+        let data = &section[data_offset..];
+        match compression_type {
+            EFI_NOT_COMPRESSED => self.inspect_sections(data, indent),
+            EFI_STANDARD_COMPRESSION => {
+                let decompressed = decompress::uefi_decompress_get_info(data).and_then(|size| {
+                    let mut buffer = vec![0u8; size];
+                    decompress::uefi_decompress(data, &mut buffer).map(|_| buffer)
+                });
+                if let Ok(buffer) = &decompressed {
+                    if buffer.len() != uncompressed_length as usize {
+                        self.mismatch(format!(
+                            "decompressed size 0x{:x} does not match uncompressed length",
+                            buffer.len()
+                        ));
+                    }
+                }
+                self.inspect_decompressed(decompressed, indent);
+            }
+            _ => self.mismatch(format!("unknown compression type {}", compression_type)),
+        }
+    }
+
+    fn inspect_guid_defined(&mut self, section: &[u8], header_size: usize, indent: usize) {
+        let guid_defined_size = size_of::<GuidDefinedSection>() - size_of::<CommonSectionHeader>();
+        if section.len() < header_size + guid_defined_size {
+            self.mismatch("GUID defined section is truncated".to_string());
+            return;
+        }
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&section[header_size..header_size + 16]);
+        let data_offset = section.pread::<u16>(header_size + 16).unwrap() as usize;
+        let attributes: u16 = section.pread(header_size + 18).unwrap();
+        println!(
+            "{:indent$}guid {} data offset 0x{:x} attributes 0x{:x}",
+            "",
+            format_guid(&guid),
+            data_offset,
+            attributes,
+            indent = indent
+        );
+        if data_offset < header_size + guid_defined_size || data_offset > section.len() {
+            self.mismatch(format!("data offset 0x{:x} is out of section", data_offset));
+            return;
+        }
+        let data = &section[data_offset..];
+        if attributes & EFI_GUIDED_SECTION_PROCESSING_REQUIRED == 0 {
+            self.inspect_sections(data, indent);
+        } else if &guid == LZMA_CUSTOM_DECOMPRESS_GUID.as_bytes() {
+            let decompressed = decompress::lzma_decompress_get_info(data).and_then(|size| {
+                let mut buffer = vec![0u8; size];
+                decompress::lzma_decompress(data, &mut buffer).map(|_| buffer)
+            });
+            self.inspect_decompressed(decompressed, indent);
+        } else if &guid == TIANO_CUSTOM_DECOMPRESS_GUID.as_bytes() {
+            let decompressed = decompress::uefi_decompress_get_info(data).and_then(|size| {
+                let mut buffer = vec![0u8; size];
+                decompress::tiano_decompress(data, &mut buffer).map(|_| buffer)
+            });
+            self.inspect_decompressed(decompressed, indent);
+        }
+    }
+
+    fn inspect_ffs(
+        &mut self,
+        fv_data: &[u8],
+        offset: usize,
+        erase_polarity: bool,
+    ) -> Option<usize> {
+        let header: FfsFileHeader = fv_data.pread(offset).unwrap();
+        // a large file has a FfsFileHeader2 and the size in extended_size
+        let (header_size, file_size) = if header.attributes & FFS_ATTRIB_LARGE_FILE != 0 {
+            let extended_size = fv_data
+                .pread::<FfsFileHeader2>(offset)
+                .map_or(0, |header| header.extended_size as usize);
+            (size_of::<FfsFileHeader2>(), extended_size)
+        } else {
+            (size_of::<FfsFileHeader>(), read_u24(&header.size))
+        };
+        println!(
+            "    ffs +0x{:06x} {} {:<22} size 0x{:06x} state 0x{:02x} ({})",
+            offset,
+            format_guid(&header.name),
+            file_type_name(header.r#type),
+            file_size,
+            header.state,
+            file_state_name(header.state, erase_polarity)
+        );
+        if file_size < header_size || offset + file_size > fv_data.len() {
+            self.mismatch(format!("ffs size 0x{:x} is out of FV", file_size));
+            return None;
+        }
+        let ffs_file = &fv_data[offset..offset + file_size];
+        if !fv_lib::verify_ffs_checksum(ffs_file) {
+            self.mismatch(format!(
+                "ffs integrity check 0x{:04x} does not verify",
+                header.integrity_check
+            ));
+        }
+        if header.r#type != FV_FILETYPE_FFS_PAD && header.r#type != FV_FILETYPE_RAW {
+            self.inspect_sections(&ffs_file[header_size..], 8);
+        }
+        Some(file_size)
+    }
+
+    fn inspect_fv(&mut self, region: &Region, fv_data: &[u8]) {
+        let fv_header: FirmwareVolumeHeader = match fv_data.pread(0) {
+            Ok(fv_header) => fv_header,
+            Err(_) => {
+                self.mismatch("region is too small for a FV header".to_string());
+                return;
+            }
+        };
+        if fv_header.signature != FVH_SIGNATURE {
+            self.mismatch(format!("FV signature 0x{:08x}", fv_header.signature));
+            return;
+        }
+        println!(
+            "    fv  {} length 0x{:x} header_length 0x{:x} checksum 0x{:04x}",
+            format_guid(&fv_header.file_system_guid),
+            fv_header.fv_length,
+            fv_header.header_length,
+            fv_header.checksum
+        );
+        if fv_header.fv_length as usize != region.size {
+            self.mismatch(format!(
+                "fv_length 0x{:x} != region size 0x{:x}",
+                fv_header.fv_length, region.size
+            ));
+        }
+        if !fv_lib::verify_fv_header_checksum(fv_data) {
+            self.mismatch("FV header checksum does not verify".to_string());
+            return;
+        }
+
+        let erase_polarity = fv_header.attributes & FVB2_ERASE_POLARITY != 0;
+        let fv_length = core::cmp::min(fv_header.fv_length as usize, fv_data.len());
+        let fv_data = &fv_data[..fv_length];
+        let mut offset = fv_header.header_length as usize;
+        loop {
+            // required 8 bytes alginment
+            offset = (offset + 7) & !7;
+            if offset + size_of::<FfsFileHeader>() > fv_length {
+                break;
+            }
+            let header: FfsFileHeader = fv_data.pread(offset).unwrap();
+            if header.r#type == 0xff && header.size == [0xff, 0xff, 0xff] {
+                println!("    free space +0x{:06x} size 0x{:06x}", offset, fv_length - offset);
+                break;
+            }
+            match self.inspect_ffs(fv_data, offset, erase_polarity) {
+                Some(file_size) => offset += file_size,
+                None => break,
+            }
+        }
+        self.inspect_signature(fv_data);
+    }
+
+    fn inspect_signature(&mut self, fv_data: &[u8]) {
+        if let Some(trusted) = fw_verifier::get_trusted_public_key_hash(fv_data) {
+            println!(
+                "    trusted public key sha384 {}",
+                hex_string(&trusted.public_key_hash)
+            );
+        }
+        match fw_verifier::get_fv_signature(fv_data) {
+            Err(VerifyError::SignatureNotFound) => {}
+            Err(e) => self.mismatch(format!("FV signature file is invalid: {:?}", e)),
+            Ok(fv_signature) => {
+                println!(
+                    "    signed by public key sha384 {}",
+                    hex_string(&fv_signature.public_key_hash())
+                );
+                if let Err(e) = fw_verifier::verify_fv_signature(fv_data) {
+                    self.mismatch(format!("FV signature does not verify: {:?}", e));
+                }
+            }
+        }
+    }
+
+    fn inspect_nv_storage(&mut self, nv_storage: &[u8]) {
+        let store = match VariableStore::parse(nv_storage) {
+            Some(store) => store,
+            None => {
+                self.mismatch("variable store is not found".to_string());
+                return;
+            }
+        };
+        println!(
+            "    variable store size 0x{:x}{}",
+            store.size(),
+            if store.is_authenticated() {
+                " authenticated"
+            } else {
+                ""
+            }
+        );
+        for variable in store.iter() {
+            let name: String = core::char::decode_utf16(
+                variable
+                    .name
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0),
+            )
+            .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+            .collect();
+            println!(
+                "    variable {} {} attributes 0x{:x} size 0x{:x}",
+                format_guid(&variable.vendor_guid),
+                name,
+                variable.attributes,
+                variable.data.len()
+            );
+        }
+        if !variable_lib::verify_ftw_working_block(&nv_storage[NV_FTW_WORKING_OFFSET as usize..]) {
+            self.mismatch(format!(
+                "FTW working block at 0x{:x} is invalid",
+                NV_FTW_WORKING_OFFSET
+            ));
+        }
+    }
+
+    fn inspect_fsp(&mut self, region: &Region, fsp_data: &[u8]) {
+        self.inspect_fv(region, fsp_data);
+
+        let fsp_info_header = match fsp_data.pread::<FspInfoHeader>(FSP_INFO_HEADER_OFF) {
+            Ok(fsp_info_header) => fsp_info_header,
+            Err(_) => {
+                self.mismatch("region is too small for a FspInfoHeader".to_string());
+                return;
+            }
+        };
+        println!(
+            "    {}",
+            format!("{:#?}", fsp_info_header).replace('\n', "\n    ")
+        );
+        if &fsp_info_header.signature.to_le_bytes() != b"FSPH" {
+            self.mismatch("FspInfoHeader signature is not FSPH".to_string());
+            return;
+        }
+        if fsp_info_header.image_base != region.base {
+            self.mismatch(format!(
+                "FSP image_base 0x{:x} != LOADED base 0x{:x}",
+                fsp_info_header.image_base, region.base
+            ));
+        }
+        if fsp_info_header.image_size as usize != region.size {
+            self.mismatch(format!(
+                "FSP image_size 0x{:x} != region size 0x{:x}",
+                fsp_info_header.image_size, region.size
+            ));
+        }
+        if let Some((_, signature)) = FSP_UPD_SIGNATURES
+            .iter()
+            .find(|(name, _)| *name == region.name)
+        {
+            match upd::get_upd_range(region.name, fsp_data, *signature) {
+                Ok(upd_range) => println!(
+                    "    upd 0x{:x}..0x{:x} signature {}",
+                    upd_range.start,
+                    upd_range.end,
+                    upd::upd_signature_string(*signature)
+                ),
+                Err(e) => self.mismatch(e.to_string()),
+            }
+        }
+    }
+
+    fn inspect_reset_vector(&mut self, region: &Region, region_data: &[u8]) {
+        let ipl_entry: u32 = region_data.pread(0).unwrap();
+        println!("    ipl_entry 0x{:08x}", ipl_entry);
+        let ipl_range = LOADED_IPL_BASE..LOADED_IPL_BASE + FIRMWARE_IPL_SIZE;
+        if !ipl_range.contains(&ipl_entry) {
+            self.mismatch(format!(
+                "ipl_entry 0x{:x} is not in the IPL region 0x{:x}..0x{:x}",
+                ipl_entry, ipl_range.start, ipl_range.end
+            ));
+        }
+
+        // the reset vector file ends at the top of the flash, it has no
+        // alignment requirement since its size follows the reset vector binary.
+        let header_size = size_of::<FfsFileHeader>();
+        let reset_vector_ffs = (4..region.size - header_size).find(|offset| {
+            let header: FfsFileHeader = region_data.pread(*offset).unwrap();
+            header.r#type == FV_FILETYPE_RAW
+                && read_u24(&header.size) == region.size - offset
+                && fv_lib::verify_ffs_checksum(&region_data[*offset..])
+        });
+        match reset_vector_ffs {
+            Some(offset) => {
+                // the flash is erased to 0xFF
+                self.inspect_ffs(region_data, offset, true);
+            }
+            None => self.mismatch("reset vector FFS is not found".to_string()),
+        }
+    }
+}
+
+///
+/// Decode a flash image built by rust-firmware-tool and print its layout.
+///
+/// Every region is validated against rust_firmware_layout::build_time,
+/// an error is returned if any mismatch is found.
+///
+pub fn inspect_firmware(rust_firmware_name: &str) -> Result<()> {
+    let rust_firmware_image = read_file(rust_firmware_name)?;
+    let mut inspector = Inspector { mismatches: 0 };
+
+    println!(
+        "{}: size 0x{:x}",
+        rust_firmware_name,
+        rust_firmware_image.len()
+    );
+    if rust_firmware_image.len() != FIRMWARE_SIZE as usize {
+        inspector.mismatch(format!(
+            "image size 0x{:x} != FIRMWARE_SIZE 0x{:x}",
+            rust_firmware_image.len(),
+            FIRMWARE_SIZE
+        ));
+    }
+
+    for region in FIRMWARE_REGIONS.iter() {
+        println!(
+            "\n{:<12} offset 0x{:06x} size 0x{:06x} base 0x{:08x}",
+            region.name, region.offset, region.size, region.base
+        );
+        if region.offset + region.size > rust_firmware_image.len() {
+            inspector.mismatch("region is out of image".to_string());
+            continue;
+        }
+        let region_data = &rust_firmware_image[region.offset..region.offset + region.size];
+        match region.kind {
+            RegionKind::Raw => {}
+            RegionKind::NvStorage => inspector.inspect_nv_storage(region_data),
+            RegionKind::Fv => inspector.inspect_fv(region, region_data),
+            RegionKind::Fsp => inspector.inspect_fsp(region, region_data),
+            RegionKind::ResetVector => inspector.inspect_reset_vector(region, region_data),
+        }
+    }
+
+    if rust_firmware_image.len() == FIRMWARE_SIZE as usize {
+        if let Err(e) = check_payload_signature(&rust_firmware_image) {
+            println!();
+            inspector.mismatch(e.to_string());
+        }
+    }
+
+    if inspector.mismatches != 0 {
+        return Err(ToolError::Mismatch(inspector.mismatches));
+    }
+    println!("\nno mismatch found");
+    Ok(())
+}
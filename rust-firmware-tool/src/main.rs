@@ -3,19 +3,22 @@
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
 use std::env;
-use std::fs;
-use std::fs::File;
 use std::io::Write;
 
 use core::mem::size_of;
 use r_efi::efi::Guid;
 use r_uefi_pi::fv::{
-    CommonSectionHeader, FfsFileHeader, FirmwareVolumeExtHeader, FirmwareVolumeHeader, FvBlockMap,
+    CommonSectionHeader, CommonSectionHeader2, FfsFileAttributes, FfsFileHeader, FfsFileHeader2,
+    FirmwareVolumeExtHeader, FirmwareVolumeHeader, FvBlockMap, FvFileType, GuidDefinedSection,
+    SectionType, EFI_GUIDED_SECTION_PROCESSING_REQUIRED, FFS_ATTRIB_LARGE_FILE,
     FIRMWARE_FILE_SYSTEM2_GUID, FVH_SIGNATURE, FV_FILETYPE_DXE_CORE, FV_FILETYPE_FFS_PAD,
-    FV_FILETYPE_RAW, FV_FILETYPE_SECURITY_CORE, SECTION_PE32, SECTION_RAW,
+    FV_FILETYPE_RAW, FV_FILETYPE_SECURITY_CORE, LZMA_CUSTOM_DECOMPRESS_GUID, MAX_FFS_SIZE,
+    MAX_SECTION_SIZE, SECTION_GUID_DEFINED, SECTION_PE32, SECTION_RAW,
 };
 
+use fw_verifier::sign::SigningKey;
 use scroll::{Pread, Pwrite};
+use uefi_pi::fv_lib;
 
 use rust_firmware_layout::build_time::*;
 #[allow(unused_imports)]
@@ -24,6 +27,18 @@ use rust_firmware_layout::fsp_build_time::*;
 
 use rust_firmware_platform::{FsptUpd, TEMP_RAM_INIT_PARAM};
 
+use error::{read_file, Result, ToolError};
+
+mod error;
+mod ffs;
+mod gdb;
+mod inspect;
+mod manifest;
+mod replace;
+mod rsym;
+mod upd;
+mod var_store;
+
 const RUST_VAR_AND_PADDING_SIZE: usize = (FIRMWARE_VAR_SIZE + FIRMWARE_PADDING_SIZE) as usize;
 const RUST_PAYLOAD_MAX_SIZE: usize = FIRMWARE_PAYLOAD_SIZE as usize;
 const RUST_IPL_MAX_SIZE: usize = FIRMWARE_IPL_SIZE as usize;
@@ -38,6 +53,7 @@ const LOADED_IPL_ADDRESS: usize = LOADED_IPL_BASE as usize;
 // size_of::<FirmwareVolumeHeader> = 56
 // size_of::<FvBlockMap> = 8
 // size_of::<FfsFileHeader> = 24
+// size_of::<FfsFileHeader2> = 28
 // size_of::<FirmwareVolumeExtHeader> = 20
 // size_of::<PayloadFvHeader> = 120
 
@@ -51,34 +67,187 @@ struct PayloadFvHeader {
     pad: [u8; 4],
 }
 
-#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
-struct PayloadFvFfsHeader {
-    ffs_header: FfsFileHeader,
+///
+/// Return the sizes of the FFS header and of the section header of a
+/// component FFS which holds one section of section_data_size bytes.
+///
+/// Sections which do not fit the 24-bit size field get a CommonSectionHeader2,
+/// files which do not fit get a FfsFileHeader2.
+///
+fn component_header_sizes(section_data_size: usize) -> (usize, usize) {
+    let section_header_size =
+        if section_data_size + size_of::<CommonSectionHeader>() >= MAX_SECTION_SIZE {
+            size_of::<CommonSectionHeader2>()
+        } else {
+            size_of::<CommonSectionHeader>()
+        };
+    let section_size = section_data_size + section_header_size;
+    let ffs_header_size = if section_size + size_of::<FfsFileHeader>() > MAX_FFS_SIZE {
+        size_of::<FfsFileHeader2>()
+    } else {
+        size_of::<FfsFileHeader>()
+    };
+    (ffs_header_size, section_header_size)
 }
 
-#[derive(Copy, Clone, Debug, Pread, Pwrite, Default)]
-struct PayloadFvFfsSectionHeader {
-    section_header: CommonSectionHeader,
+///
+/// Write the FFS header and the section header of a component FFS, the
+/// section data follows them.
+///
+/// Return the size of both headers.
+///
+fn write_component_headers(
+    buffer: &mut [u8],
+    name: Guid,
+    file_type: FvFileType,
+    section_type: SectionType,
+    section_data_size: usize,
+) -> usize {
+    let (_, section_header_size) = component_header_sizes(section_data_size);
+    let ffs_header_size = write_ffs_header(
+        buffer,
+        name.as_bytes(),
+        file_type,
+        0,
+        section_data_size + section_header_size,
+    );
+    write_section_header(
+        &mut buffer[ffs_header_size..],
+        section_type,
+        section_data_size,
+    );
+    ffs_header_size + section_header_size
 }
 
-#[repr(C, align(4))]
-struct PayloadFvHeaderByte {
-    data: [u8; size_of::<PayloadFvHeader>()
-        + size_of::<PayloadFvFfsHeader>()
-        + size_of::<PayloadFvFfsSectionHeader>()],
+///
+/// Write a FfsFileHeader, or a FfsFileHeader2 for large files, in front of
+/// file_data_size bytes. The integrity check is left to update_ffs_checksum.
+///
+/// Return the size of the FFS header.
+///
+fn write_ffs_header(
+    buffer: &mut [u8],
+    name: &[u8; 16],
+    file_type: FvFileType,
+    attributes: FfsFileAttributes,
+    file_data_size: usize,
+) -> usize {
+    if file_data_size + size_of::<FfsFileHeader>() > MAX_FFS_SIZE {
+        let ffs_header = FfsFileHeader2 {
+            name: *name,
+            r#type: file_type,
+            attributes: attributes | FFS_ATTRIB_LARGE_FILE,
+            state: 0xF8u8,
+            extended_size: (file_data_size + size_of::<FfsFileHeader2>()) as u32,
+            ..Default::default()
+        };
+        buffer.pwrite(ffs_header, 0).unwrap();
+        size_of::<FfsFileHeader2>()
+    } else {
+        let mut ffs_header = FfsFileHeader {
+            name: *name,
+            r#type: file_type,
+            attributes,
+            state: 0xF8u8,
+            ..Default::default()
+        };
+        let file_size = file_data_size + size_of::<FfsFileHeader>();
+        write_u24(file_size as u32, &mut ffs_header.size);
+        buffer.pwrite(ffs_header, 0).unwrap();
+        size_of::<FfsFileHeader>()
+    }
 }
-impl Default for PayloadFvHeaderByte {
-    fn default() -> Self {
-        PayloadFvHeaderByte {
-            data: [0u8; size_of::<PayloadFvHeader>()
-                + size_of::<PayloadFvFfsHeader>()
-                + size_of::<PayloadFvFfsSectionHeader>()],
-        }
+
+///
+/// Write a CommonSectionHeader, or a CommonSectionHeader2 for large sections.
+///
+/// Return the size of the section header.
+///
+fn write_section_header(
+    buffer: &mut [u8],
+    section_type: SectionType,
+    section_data_size: usize,
+) -> usize {
+    let (_, section_header_size) = component_header_sizes(section_data_size);
+    let section_size = section_data_size + section_header_size;
+    if section_header_size == size_of::<CommonSectionHeader2>() {
+        let section_header = CommonSectionHeader2 {
+            size: [0xff, 0xff, 0xff],
+            r#type: section_type,
+            extended_size: section_size as u32,
+        };
+        buffer.pwrite(section_header, 0).unwrap();
+    } else {
+        let mut section_header = CommonSectionHeader {
+            r#type: section_type,
+            ..Default::default()
+        };
+        write_u24(section_size as u32, &mut section_header.size);
+        buffer.pwrite(section_header, 0).unwrap();
     }
+    section_header_size
 }
 
-fn build_payload_fv_header(payload_fv_header_buffer: &mut [u8], payload_bin: &[u8]) {
-    assert!(payload_bin.len() <= RUST_PAYLOAD_MAX_SIZE - size_of::<PayloadFvFfsHeader>());
+///
+/// LZMA compress the payload in a PE32 section, and return the data of the
+/// LZMA GUID defined section which holds it.
+///
+/// rust-ipl decompresses it to the runtime payload region.
+///
+fn build_compressed_payload_section(rust_payload_bin: &[u8]) -> Result<Vec<u8>> {
+    let mut pe32_section = vec![0u8; size_of::<CommonSectionHeader2>()];
+    let pe32_header_size =
+        write_section_header(&mut pe32_section, SECTION_PE32, rust_payload_bin.len());
+    pe32_section.truncate(pe32_header_size);
+    pe32_section.extend_from_slice(rust_payload_bin);
+
+    let compress_error =
+        |e: &dyn std::fmt::Display| invalid_data(format!("fail to compress rust payload: {}", e));
+    let options = xz2::stream::LzmaOptions::new_preset(9).map_err(|e| compress_error(&e))?;
+    let stream = xz2::stream::Stream::new_lzma_encoder(&options).map_err(|e| compress_error(&e))?;
+    let mut encoder = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+    encoder
+        .write_all(&pe32_section)
+        .map_err(|e| compress_error(&e))?;
+    let mut compressed = encoder.finish().map_err(|e| compress_error(&e))?;
+    // the LZMA_Alone header has an unknown uncompressed size, the decompressor needs it.
+    compressed[5..13].copy_from_slice(&(pe32_section.len() as u64).to_le_bytes());
+
+    // section_definition_guid, data_offset and attributes follow the common header.
+    let guid_defined_size = size_of::<GuidDefinedSection>() - size_of::<CommonSectionHeader>();
+    let (_, section_header_size) = component_header_sizes(guid_defined_size + compressed.len());
+    let mut section_data = vec![0u8; guid_defined_size];
+    section_data[..16].copy_from_slice(LZMA_CUSTOM_DECOMPRESS_GUID.as_bytes());
+    section_data
+        .pwrite((section_header_size + guid_defined_size) as u16, 16)
+        .unwrap();
+    section_data
+        .pwrite(EFI_GUIDED_SECTION_PROCESSING_REQUIRED, 18)
+        .unwrap();
+    section_data.extend_from_slice(&compressed);
+    log::info!(
+        "compress rust payload 0x{:x} -> 0x{:x}",
+        rust_payload_bin.len(),
+        section_data.len()
+    );
+    Ok(section_data)
+}
+
+///
+/// Size of the FV header and the component FFS headers in front of a
+/// component of component_size bytes.
+///
+fn component_fv_header_size(component_size: usize) -> usize {
+    let (ffs_header_size, section_header_size) = component_header_sizes(component_size);
+    size_of::<PayloadFvHeader>() + ffs_header_size + section_header_size
+}
+
+fn build_payload_fv_header(
+    payload_fv_header_buffer: &mut [u8],
+    payload_bin: &[u8],
+    section_type: SectionType,
+) {
+    assert!(payload_bin.len() <= RUST_PAYLOAD_MAX_SIZE - size_of::<PayloadFvHeader>());
 
     let mut payload_fv_header = PayloadFvHeader::default();
 
@@ -93,7 +262,7 @@ fn build_payload_fv_header(payload_fv_header_buffer: &mut [u8], payload_bin: &[u
     payload_fv_header.fv_header.signature = FVH_SIGNATURE;
     payload_fv_header.fv_header.attributes = 0x0004feff;
     payload_fv_header.fv_header.header_length = 0x0048;
-    payload_fv_header.fv_header.checksum = 0x6403;
+    payload_fv_header.fv_header.checksum = 0x0000;
     payload_fv_header.fv_header.ext_header_offset = 0x0060;
     payload_fv_header.fv_header.reserved = 0x00;
     payload_fv_header.fv_header.revision = 0x02;
@@ -114,7 +283,7 @@ fn build_payload_fv_header(payload_fv_header_buffer: &mut [u8], payload_bin: &[u
         )
         .as_bytes(),
     );
-    payload_fv_header.pad_ffs_header.integrity_check = 0xaae4;
+    payload_fv_header.pad_ffs_header.integrity_check = 0x0000;
     payload_fv_header.pad_ffs_header.r#type = FV_FILETYPE_FFS_PAD;
     payload_fv_header.pad_ffs_header.attributes = 0x00;
     write_u24(0x2c, &mut payload_fv_header.pad_ffs_header.size);
@@ -140,8 +309,8 @@ fn build_payload_fv_header(payload_fv_header_buffer: &mut [u8], payload_bin: &[u
         .unwrap();
     assert_eq!(res1, 120);
 
-    let mut tdx_payload_fv_ffs_header = PayloadFvFfsHeader::default();
-    tdx_payload_fv_ffs_header.ffs_header.name.copy_from_slice(
+    write_component_headers(
+        &mut payload_fv_header_buffer[fv_header_size..],
         //06948D4A-D359-4721-ADF6-5225485A6A3A
         Guid::from_fields(
             0x06948D4A,
@@ -150,45 +319,14 @@ fn build_payload_fv_header(payload_fv_header_buffer: &mut [u8], payload_bin: &[u
             0xAD,
             0xF6,
             &[0x52, 0x25, 0x48, 0x5A, 0x6A, 0x3A],
-        )
-        .as_bytes(),
+        ),
+        FV_FILETYPE_DXE_CORE,
+        section_type,
+        payload_bin.len(),
     );
-    tdx_payload_fv_ffs_header.ffs_header.integrity_check = 0xaa4c;
-    tdx_payload_fv_ffs_header.ffs_header.r#type = FV_FILETYPE_DXE_CORE;
-    tdx_payload_fv_ffs_header.ffs_header.attributes = 0x00;
-    write_u24(
-        (payload_bin.len()
-            + size_of::<PayloadFvFfsHeader>()
-            + size_of::<PayloadFvFfsSectionHeader>()) as u32,
-        &mut tdx_payload_fv_ffs_header.ffs_header.size,
-    );
-    tdx_payload_fv_ffs_header.ffs_header.state = 0xF8u8;
-
-    let res2 = payload_fv_header_buffer
-        .pwrite(tdx_payload_fv_ffs_header, fv_header_size)
-        .unwrap();
-    assert_eq!(res2, 24);
-
-    let mut tdx_payload_fv_ffs_section_header = PayloadFvFfsSectionHeader::default();
-    write_u24(
-        (payload_bin.len() + size_of::<PayloadFvFfsSectionHeader>()) as u32,
-        &mut tdx_payload_fv_ffs_section_header.section_header.size,
-    );
-    tdx_payload_fv_ffs_section_header.section_header.r#type = SECTION_PE32;
-
-    let res3 = payload_fv_header_buffer
-        .pwrite(
-            tdx_payload_fv_ffs_section_header,
-            fv_header_size + size_of::<PayloadFvFfsHeader>(),
-        )
-        .unwrap();
-    assert_eq!(res3, 4);
 }
 
 type IplFvHeader = PayloadFvHeader;
-type IplFvFfsHeader = PayloadFvFfsHeader;
-type IplFvFfsSectionHeader = PayloadFvFfsSectionHeader;
-type IplFvHeaderByte = PayloadFvHeaderByte;
 
 fn build_ipl_fv_header(
     ipl_fv_header_buffer: &mut [u8],
@@ -209,7 +347,7 @@ fn build_ipl_fv_header(
     ipl_fv_header.fv_header.signature = FVH_SIGNATURE;
     ipl_fv_header.fv_header.attributes = 0x0004feff;
     ipl_fv_header.fv_header.header_length = 0x0048;
-    ipl_fv_header.fv_header.checksum = 0xa528;
+    ipl_fv_header.fv_header.checksum = 0x0000;
     ipl_fv_header.fv_header.ext_header_offset = 0x0060;
     ipl_fv_header.fv_header.reserved = 0x00;
     ipl_fv_header.fv_header.revision = 0x02;
@@ -231,10 +369,10 @@ fn build_ipl_fv_header(
         )
         .as_bytes(),
     );
-    ipl_fv_header.pad_ffs_header.integrity_check = 0xaae4;
+    ipl_fv_header.pad_ffs_header.integrity_check = 0x0000;
     ipl_fv_header.pad_ffs_header.r#type = FV_FILETYPE_FFS_PAD;
     ipl_fv_header.pad_ffs_header.attributes = 0x00;
-    write_u24(0x0, &mut ipl_fv_header.pad_ffs_header.size);
+    write_u24(0x2c, &mut ipl_fv_header.pad_ffs_header.size);
     ipl_fv_header.pad_ffs_header.state = 0x07u8;
 
     ipl_fv_header.fv_ext_header.fv_name.copy_from_slice(
@@ -255,8 +393,8 @@ fn build_ipl_fv_header(
 
     let _res = ipl_fv_header_buffer.pwrite(ipl_fv_header, 0).unwrap();
 
-    let mut ipl_fv_ffs_header = IplFvFfsHeader::default();
-    ipl_fv_ffs_header.ffs_header.name.copy_from_slice(
+    write_component_headers(
+        &mut ipl_fv_header_buffer[fv_header_size..],
         // DF1CCEF6-F301-4A63-9661-FC6030DCC880
         Guid::from_fields(
             0xDF1CCEF6,
@@ -265,37 +403,11 @@ fn build_ipl_fv_header(
             0x96,
             0x61,
             &[0xFC, 0x60, 0x30, 0xDC, 0xC8, 0x80],
-        )
-        .as_bytes(),
-    );
-    ipl_fv_ffs_header.ffs_header.integrity_check = 0xaacc;
-    ipl_fv_ffs_header.ffs_header.r#type = FV_FILETYPE_SECURITY_CORE;
-    ipl_fv_ffs_header.ffs_header.attributes = 0x00;
-    write_u24(
-        (ipl_relocate_buffer.len()
-            + size_of::<IplFvFfsHeader>()
-            + size_of::<IplFvFfsSectionHeader>()) as u32,
-        &mut ipl_fv_ffs_header.ffs_header.size,
-    );
-    ipl_fv_ffs_header.ffs_header.state = 0xF8u8;
-
-    let _res = ipl_fv_header_buffer
-        .pwrite(ipl_fv_ffs_header, fv_header_size)
-        .unwrap();
-
-    let mut ipl_fv_ffs_section_header = IplFvFfsSectionHeader::default();
-    write_u24(
-        (ipl_relocate_buffer.len() + size_of::<IplFvFfsSectionHeader>()) as u32,
-        &mut ipl_fv_ffs_section_header.section_header.size,
+        ),
+        FV_FILETYPE_SECURITY_CORE,
+        SECTION_PE32,
+        ipl_relocate_buffer.len(),
     );
-    ipl_fv_ffs_section_header.section_header.r#type = SECTION_PE32;
-
-    let _res = ipl_fv_header_buffer
-        .pwrite(
-            ipl_fv_ffs_section_header,
-            fv_header_size + size_of::<IplFvFfsHeader>(),
-        )
-        .unwrap();
 }
 
 #[repr(C)]
@@ -335,7 +447,7 @@ fn build_reset_vector_header(reset_vector_header_buffer: &mut [u8], reset_vector
         )
         .as_bytes(),
     );
-    reset_vector_header.ffs_header.integrity_check = 0xaa5a;
+    reset_vector_header.ffs_header.integrity_check = 0x0000;
     reset_vector_header.ffs_header.r#type = FV_FILETYPE_RAW;
     reset_vector_header.ffs_header.attributes = 0x08;
     write_u24(
@@ -360,42 +472,421 @@ fn build_reset_vector_header(reset_vector_header_buffer: &mut [u8], reset_vector
         .unwrap();
 }
 
-fn main() -> std::io::Result<()> {
-    simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info).init().unwrap();
+fn update_fv_header_checksum(fv_buffer: &mut [u8]) {
+    let mut fv_header: FirmwareVolumeHeader = fv_buffer.pread(0).unwrap();
+    let header_length = fv_header.header_length as usize;
+    fv_header.checksum = fv_lib::calculate_fv_header_checksum(&fv_buffer[..header_length]);
+    fv_buffer.pwrite(fv_header, 0).unwrap();
+}
 
-    assert_eq!(
-        RUST_VAR_AND_PADDING_SIZE
-            + RUST_PAYLOAD_MAX_SIZE
-            + RUST_IPL_MAX_SIZE
-            + RUST_RESET_VECTOR_MAX_SIZE
-            + FIRMWARE_FSP_MAX_SIZE as usize,
-        FIRMWARE_SIZE as usize
+fn update_ffs_checksum(ffs_buffer: &mut [u8]) {
+    let (header_size, file_size) = fv_lib::get_ffs_file_sizes(ffs_buffer).unwrap();
+    let mut ffs_header: FfsFileHeader = ffs_buffer.pread(0).unwrap();
+    let header_checksum = fv_lib::calculate_ffs_header_checksum(&ffs_buffer[..header_size]);
+    let file_checksum = fv_lib::calculate_ffs_file_checksum(
+        ffs_header.attributes,
+        &ffs_buffer[header_size..file_size],
     );
-    assert!(RUST_PAYLOAD_MAX_SIZE > size_of::<PayloadFvHeader>());
+    ffs_header.integrity_check = header_checksum as u16 | (file_checksum as u16) << 8;
+    ffs_buffer.pwrite(ffs_header, 0).unwrap();
+}
 
-    let args: Vec<String> = env::args().collect();
+fn invalid_data(message: String) -> ToolError {
+    ToolError::InvalidData(message)
+}
 
-    let reset_vector_name = &args[1];
-    let rust_ipl_name = &args[2];
-    let rust_payload_name = &args[3];
-    let rust_firmware_name = &args[4];
+// reset vector params
+#[derive(Debug, Pread, Pwrite)]
+struct ResetVectorParams {
+    ipl_entry: u32,               // rust ipl entry
+    temp_ram_init_param: FsptUpd, // FSP_T TempRamInit Params
+}
 
-    let (rust_fsp_wrapper_t_bin, rust_fsp_wrapper_m_bin, rust_fsp_wrapper_s_bin) = (
-        fs::read(std::env::var("RUST_FIRMWARE_TOOL_FSP_T_FILE")
+///
+/// Build the whole payload region: FV header, payload FFS and 0xFF padding.
+///
+/// With compress, the payload FFS holds a LZMA GUID defined section instead
+/// of the PE32 section.
+///
+/// ffs_files are placed after the payload FFS.
+///
+fn build_payload_fv(
+    rust_payload_bin: &[u8],
+    compress: bool,
+    ffs_files: &[Vec<u8>],
+    signing: Option<&FvSigning>,
+) -> Result<Vec<u8>> {
+    let (section_data, section_type) = if compress {
+        (
+            build_compressed_payload_section(rust_payload_bin)?,
+            SECTION_GUID_DEFINED,
+        )
+    } else {
+        (rust_payload_bin.to_vec(), SECTION_PE32)
+    };
+    let rust_payload_bin = section_data.as_slice();
+    let header_size = component_fv_header_size(rust_payload_bin.len());
+    let mut rust_payload_header_buffer = vec![0u8; header_size];
+
+    if rust_payload_bin.len() > RUST_PAYLOAD_MAX_SIZE - header_size {
+        return Err(ToolError::ComponentTooLarge {
+            component: if compress {
+                "compressed rust payload".to_string()
+            } else {
+                "rust payload".to_string()
+            },
+            size: rust_payload_bin.len(),
+            region: "the payload region",
+            capacity: RUST_PAYLOAD_MAX_SIZE - header_size,
+        });
+    }
+    build_payload_fv_header(
+        &mut rust_payload_header_buffer,
+        rust_payload_bin,
+        section_type,
+    );
+
+    let mut payload_fv = vec![0xFFu8; RUST_PAYLOAD_MAX_SIZE];
+    payload_fv[..header_size].copy_from_slice(&rust_payload_header_buffer);
+    payload_fv[header_size..header_size + rust_payload_bin.len()]
+        .copy_from_slice(rust_payload_bin);
+    ffs::add_ffs_files(&mut payload_fv, ffs_files)?;
+
+    update_fv_checksums(&mut payload_fv);
+    if !fv_lib::verify_fv_checksums(&payload_fv) {
+        return Err(invalid_data(
+            "rust payload FV checksum verification failed".to_string(),
+        ));
+    }
+    if let Some(signing) = signing {
+        sign_fv("rust payload", &mut payload_fv, &signing.key)?;
+    }
+    Ok(payload_fv)
+}
+
+///
+/// Relocate rust IPL to LOADED_IPL_BASE and build the whole IPL region.
+///
+/// Return the IPL region and the relocated IPL entry point.
+///
+/// When signing, the IPL FV gets the hash of the public key which rust-ipl
+/// trusts to verify the payload FV.
+///
+fn build_ipl_fv(rust_ipl_bin: &[u8], signing: Option<&FvSigning>) -> Result<(Vec<u8>, u32)> {
+    if !pe_loader::pe::is_pe(rust_ipl_bin) {
+        return Err(invalid_data("rust IPL is not a x64 PE image".to_string()));
+    }
+    // SizeOfImage in the PE32+ optional header
+    let pe_header_offset = rust_ipl_bin.pread::<u32>(0x3c).unwrap() as usize;
+    let size_of_image = rust_ipl_bin
+        .pread::<u32>(pe_header_offset + 24 + 56)
+        .map_err(|_| invalid_data("rust IPL PE header is truncated".to_string()))?
+        as usize;
+    let header_size = component_fv_header_size(size_of_image);
+    let mut rust_ipl_header_buffer = vec![0u8; header_size];
+    let ipl_capacity = RUST_IPL_MAX_SIZE - header_size;
+    if size_of_image > ipl_capacity {
+        return Err(ToolError::ComponentTooLarge {
+            component: "rust IPL image".to_string(),
+            size: size_of_image,
+            region: "the IPL region",
+            capacity: ipl_capacity,
+        });
+    }
+    // the IPL FFS only covers the loaded image, the rest of the FV is free
+    // space for the public key hash and signature files.
+    let mut new_rust_ipl_buf = vec![0x00u8; size_of_image];
+    let ipl_base = LOADED_IPL_ADDRESS + header_size;
+    let ipl_entry = pe_loader::pe::relocate(rust_ipl_bin, &mut new_rust_ipl_buf, ipl_base).ok_or(
+        ToolError::PeRelocation {
+            component: "rust IPL",
+            base: ipl_base,
+        },
+    )?;
+
+    build_ipl_fv_header(&mut rust_ipl_header_buffer, new_rust_ipl_buf.as_slice());
+
+    let mut ipl_fv = vec![0xFFu8; RUST_IPL_MAX_SIZE];
+    ipl_fv[..header_size].copy_from_slice(&rust_ipl_header_buffer);
+    ipl_fv[header_size..header_size + new_rust_ipl_buf.len()]
+        .copy_from_slice(&new_rust_ipl_buf[..]);
+
+    update_fv_checksums(&mut ipl_fv);
+    if !fv_lib::verify_fv_checksums(&ipl_fv) {
+        return Err(invalid_data(
+            "rust IPL FV checksum verification failed".to_string(),
+        ));
+    }
+    if let Some(signing) = signing {
+        fw_verifier::sign::add_public_key_hash(&mut ipl_fv, &signing.key).map_err(|e| {
+            invalid_data(format!("fail to add public key hash to rust IPL FV: {}", e))
+        })?;
+        if signing.sign_ipl {
+            sign_fv("rust IPL", &mut ipl_fv, &signing.key)?;
+        }
+    }
+    Ok((ipl_fv, ipl_entry as u32))
+}
+
+///
+/// Build the reset vector region: ResetVectorParams at the bottom, the
+/// reset vector FFS at the top of the flash.
+///
+fn build_reset_vector(
+    reset_vector_bin: &[u8],
+    reset_vector_info: ResetVectorParams,
+) -> Result<Vec<u8>> {
+    let mut rust_reset_vector_header_buffer = [0u8; size_of::<ResetVectorByte>()];
+
+    let reset_vector_info_buffer = &mut [0u8; 256];
+    let writen = reset_vector_info_buffer
+        .pwrite(reset_vector_info, 0)
+        .unwrap();
+
+    let used_size = rust_reset_vector_header_buffer.len() + reset_vector_bin.len() + writen;
+    if used_size > RUST_RESET_VECTOR_MAX_SIZE {
+        return Err(ToolError::ComponentTooLarge {
+            component: "reset vector".to_string(),
+            size: reset_vector_bin.len(),
+            region: "the reset vector region",
+            capacity: RUST_RESET_VECTOR_MAX_SIZE - rust_reset_vector_header_buffer.len() - writen,
+        });
+    }
+    build_reset_vector_header(
+        &mut rust_reset_vector_header_buffer,
+        reset_vector_bin,
+    );
+
+    let mut reset_vector = vec![0xFFu8; RUST_RESET_VECTOR_MAX_SIZE];
+    reset_vector[..writen].copy_from_slice(&reset_vector_info_buffer[..writen]);
+
+    let reset_vector_ffs_offset = RUST_RESET_VECTOR_MAX_SIZE
+        - rust_reset_vector_header_buffer.len()
+        - reset_vector_bin.len();
+    let reset_vector_bin_offset = RUST_RESET_VECTOR_MAX_SIZE - reset_vector_bin.len();
+    reset_vector[reset_vector_ffs_offset..reset_vector_bin_offset]
+        .copy_from_slice(&rust_reset_vector_header_buffer[..]);
+    reset_vector[reset_vector_bin_offset..].copy_from_slice(reset_vector_bin);
+
+    update_ffs_checksum(&mut reset_vector[reset_vector_ffs_offset..]);
+    if !fv_lib::verify_ffs_checksum(&reset_vector[reset_vector_ffs_offset..]) {
+        return Err(invalid_data(
+            "reset vector FFS checksum verification failed".to_string(),
+        ));
+    }
+    Ok(reset_vector)
+}
+
+/// Signing key of the payload FV, and optionally of the IPL FV.
+struct FvSigning {
+    key: SigningKey,
+    sign_ipl: bool,
+}
+
+fn load_fv_signing(sign_key_name: Option<&str>, sign_ipl: bool) -> Result<Option<FvSigning>> {
+    let sign_key_name = match sign_key_name {
+        Some(sign_key_name) => sign_key_name,
+        None if sign_ipl => return Err(usage_error("--sign-ipl requires --sign-key".to_string())),
+        None => return Ok(None),
+    };
+    let key = read_file(sign_key_name)?;
+    let key = SigningKey::from_bytes(&key)
+        .map_err(|e| invalid_data(format!("{}: {}", sign_key_name, e)))?;
+    Ok(Some(FvSigning { key, sign_ipl }))
+}
+
+fn sign_fv(name: &str, fv: &mut [u8], key: &SigningKey) -> Result<()> {
+    fw_verifier::sign::sign_fv(fv, key)
+        .map_err(|e| invalid_data(format!("fail to sign {} FV: {}", name, e)))?;
+    if !fv_lib::verify_fv_checksums(fv) || fw_verifier::verify_fv_signature(fv).is_err() {
+        return Err(invalid_data(format!(
+            "{} FV signature verification failed",
+            name
+        )));
+    }
+    Ok(())
+}
+
+///
+/// rust-ipl only boots a payload FV signed by the key whose hash is in the
+/// IPL FV, an image without that hash is not verified.
+///
+fn check_payload_signature(rust_firmware_image: &[u8]) -> Result<()> {
+    let ipl_fv = &rust_firmware_image[RUST_IPL_OFFSET..RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE];
+    let payload_fv =
+        &rust_firmware_image[RUST_PAYLOAD_OFFSET..RUST_PAYLOAD_OFFSET + RUST_PAYLOAD_MAX_SIZE];
+    if let Some(trusted) = fw_verifier::get_trusted_public_key_hash(ipl_fv) {
+        fw_verifier::verify_fv(payload_fv, &trusted).map_err(|e| {
+            invalid_data(format!(
+                "rust payload FV does not verify with the IPL trusted key: {:?}",
+                e
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+fn check_fsp_size(name: &'static str, fsp_bin: &[u8], fsp_size: u32) -> Result<()> {
+    if fsp_bin.len() != fsp_size as usize {
+        return Err(ToolError::FspSizeMismatch {
+            name,
+            size: fsp_bin.len(),
+            region_size: fsp_size as usize,
+        });
+    }
+    Ok(())
+}
+
+// update the pad FFS, the component FFS and then the FV header.
+fn update_fv_checksums(fv_buffer: &mut [u8]) {
+    let pad_ffs_offset = size_of::<FirmwareVolumeHeader>() + size_of::<[FvBlockMap; 2]>();
+    update_ffs_checksum(&mut fv_buffer[pad_ffs_offset..]);
+    update_ffs_checksum(&mut fv_buffer[size_of::<PayloadFvHeader>()..]);
+    update_fv_header_checksum(fv_buffer);
+}
+
+const USAGE: &str = "\
+usage:
+    rust-firmware-tool RESET_VECTOR_BIN RUST_IPL_BIN RUST_PAYLOAD_BIN RUST_FIRMWARE_BIN [OPTIONS]
+    rust-firmware-tool inspect RUST_FIRMWARE_BIN
+    rust-firmware-tool replace [REPLACE_OPTIONS] RUST_FIRMWARE_BIN
+    rust-firmware-tool gdb-payload RUST_PAYLOAD_BIN SERIAL_LOG
+    rust-firmware-tool rsym PE_IMAGE [OUTPUT]
+    rust-firmware-tool --help
+
+options:
+    --compress          LZMA compress the payload
+    --add-file guid:type:path[:ui-name]
+                        add a file to the payload FV, type is application, driver or freeform
+    --sign-key KEY      sign the payload FV with a RSA 3072 or ECDSA P-384 private key
+    --sign-ipl          sign the IPL FV too, requires --sign-key
+    --var-store FILE    add the variables of the JSON file to the variable store of the VAR region
+    --upd FILE          patch the FsptUpd, FspmConfig and FspSConfig fields of the TOML or JSON5 file
+
+replace options:
+    --payload FILE, --ipl FILE, --fsp-t FILE, --fsp-m FILE, --fsp-s FILE,
+    --reset-vector FILE, --add-file (with --payload only) and the options above
+
+environment:
+    RUST_FIRMWARE_TOOL_FSP_T_FILE, RUST_FIRMWARE_TOOL_FSP_M_FILE, RUST_FIRMWARE_TOOL_FSP_S_FILE
+                        FSP binaries, FIRMWARE_FSP_*_PATH of rust-firmware-layout by default
+
+exit code:
+    0 on success, 1 on error, 2 on invalid command line
+";
+
+fn usage_error(message: String) -> ToolError {
+    ToolError::Usage(format!(
+        "{}\nrun `rust-firmware-tool --help` for usage",
+        message
+    ))
+}
+
+///
+/// The regions of rust-firmware-layout must follow each other and make up
+/// the whole flash.
+///
+fn check_layout() -> Result<()> {
+    let rules = [
+        (
+            "VAR + PADDING + PAYLOAD + IPL + FSP + RESET_VECTOR sizes != FIRMWARE_SIZE",
+            RUST_VAR_AND_PADDING_SIZE
+                + RUST_PAYLOAD_MAX_SIZE
+                + RUST_IPL_MAX_SIZE
+                + RUST_RESET_VECTOR_MAX_SIZE
+                + FIRMWARE_FSP_MAX_SIZE as usize
+                == FIRMWARE_SIZE as usize,
+        ),
+        (
+            "PAYLOAD is smaller than the payload FV header",
+            RUST_PAYLOAD_MAX_SIZE > size_of::<PayloadFvHeader>(),
+        ),
+        (
+            "IPL does not follow PAYLOAD",
+            RUST_PAYLOAD_OFFSET + RUST_PAYLOAD_MAX_SIZE == RUST_IPL_OFFSET,
+        ),
+        (
+            "FSP does not follow IPL",
+            RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE == FIRMWARE_FSP_OFFSET as usize,
+        ),
+        (
+            "FSP-T is not at the start of FSP",
+            FIRMWARE_FSP_T_OFFSET == FIRMWARE_FSP_OFFSET,
+        ),
+        (
+            "FSP-M does not follow FSP-T",
+            FIRMWARE_FSP_T_OFFSET + FIRMWARE_FSP_T_SIZE == FIRMWARE_FSP_M_OFFSET,
+        ),
+        (
+            "FSP-S does not follow FSP-M",
+            FIRMWARE_FSP_M_OFFSET + FIRMWARE_FSP_M_SIZE == FIRMWARE_FSP_S_OFFSET,
+        ),
+        (
+            "RESET_VECTOR does not follow FSP",
+            FIRMWARE_FSP_OFFSET + FIRMWARE_FSP_MAX_SIZE == FIRMWARE_RESET_VECTOR_OFFSET,
+        ),
+    ];
+    match rules.iter().find(|(_, valid)| !valid) {
+        Some((rule, _)) => Err(ToolError::Layout(rule.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn build_firmware(args: &[String]) -> Result<()> {
+    let (reset_vector_name, rust_ipl_name, rust_payload_name, rust_firmware_name) = match args {
+        [reset_vector, ipl, payload, firmware, ..] => (reset_vector, ipl, payload, firmware),
+        _ => return Err(usage_error("missing arguments".to_string())),
+    };
+
+    let (mut sign_key_name, mut sign_ipl, mut compress) = (None, false, false);
+    let (mut var_store_name, mut upd_config) = (None, None);
+    let mut payload_files = Vec::new();
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--sign-key" if options.len() != 0 => {
+                sign_key_name = options.next().map(|name| name.as_str())
+            }
+            "--sign-ipl" => sign_ipl = true,
+            "--compress" => compress = true,
+            "--var-store" if options.len() != 0 => {
+                var_store_name = options.next().map(|name| name.as_str())
+            }
+            "--upd" if options.len() != 0 => {
+                upd_config = Some(upd::UpdConfig::load(options.next().unwrap())?)
+            }
+            "--add-file" if options.len() != 0 => {
+                payload_files.push(ffs::read_payload_file(options.next().unwrap())?)
+            }
+            "--sign-key" | "--add-file" | "--var-store" | "--upd" => {
+                return Err(usage_error(format!("{} requires an argument", option)))
+            }
+            unknown => return Err(usage_error(format!("invalid option {}", unknown))),
+        }
+    }
+    let signing = load_fv_signing(sign_key_name, sign_ipl)?;
+
+    let (fsp_t_name, fsp_m_name, fsp_s_name) = (
+        std::env::var("RUST_FIRMWARE_TOOL_FSP_T_FILE")
         .unwrap_or_else(|_|{
             log::info!("environment variable: RUST_FIRMWARE_TOOL_FSP_T_FILE not set, use default");
             FIRMWARE_FSP_T_PATH.to_string()
-        })).expect("fail to read fsp-t"),
-        fs::read(std::env::var("RUST_FIRMWARE_TOOL_FSP_M_FILE")
+        }),
+        std::env::var("RUST_FIRMWARE_TOOL_FSP_M_FILE")
         .unwrap_or_else(|_|{
             log::info!("environment variable: RUST_FIRMWARE_TOOL_FSP_M_FILE not set, use default");
             FIRMWARE_FSP_M_PATH.to_string()
-        })).expect("fail to read fsp-m"),
-        fs::read(std::env::var("RUST_FIRMWARE_TOOL_FSP_S_FILE")
+        }),
+        std::env::var("RUST_FIRMWARE_TOOL_FSP_S_FILE")
         .unwrap_or_else(|_|{
             log::info!("environment variable: RUST_FIRMWARE_TOOL_FSP_S_FILE not set, use default");
             FIRMWARE_FSP_S_PATH.to_string()
-        })).expect("fail to read fsp-s"),
+        }),
+    );
+    let (rust_fsp_wrapper_t_bin, rust_fsp_wrapper_m_bin, rust_fsp_wrapper_s_bin) = (
+        read_file(&fsp_t_name)?,
+        read_file(&fsp_m_name)?,
+        read_file(&fsp_s_name)?,
     );
     let (fsp_t_bin, fsp_m_bin, fsp_s_bin) = (
         rust_fsp_wrapper_t_bin.as_slice(),
@@ -408,165 +899,136 @@ fn main() -> std::io::Result<()> {
         reset_vector_name, rust_ipl_name, rust_payload_name, rust_firmware_name
     );
 
-    let reset_vector_bin = fs::read(reset_vector_name).expect("fail to read reset_vector");
+    let reset_vector_bin = read_file(reset_vector_name)?;
     //println!("{:?}", reset_vector_bin);
-    let rust_ipl_bin = fs::read(rust_ipl_name).expect("fail to read rust IPL");
-    let rust_payload_bin = fs::read(rust_payload_name).expect("fail to read rust payload");
-
-    let mut rust_firmware_file =
-        File::create(rust_firmware_name).expect("fail to create rust firmware");
-
-    let zero_buf = vec![0xFFu8; FIRMWARE_SIZE as usize];
-
-    let mut rust_payload_header_bytes = PayloadFvHeaderByte::default();
-
-    let rust_payload_header_buffer = &mut rust_payload_header_bytes.data[..];
-    build_payload_fv_header(rust_payload_header_buffer, rust_payload_bin.as_slice());
-
-    let mut rust_ipl_header_bytes = IplFvHeaderByte::default();
-    let rust_ipl_header_buffer = &mut rust_ipl_header_bytes.data[..];
-
-    let mut new_rust_ipl_buf = vec![
-        0x00u8;
-        RUST_IPL_MAX_SIZE
-            - size_of::<PayloadFvHeaderByte>()
-            - size_of::<PayloadFvFfsSectionHeader>()
-    ];
-    let ipl_entry = pe_loader::pe::relocate(
-        &rust_ipl_bin,
-        &mut new_rust_ipl_buf,
-        LOADED_IPL_ADDRESS + rust_ipl_header_buffer.len(),
-    )
-    .expect("fail to relocate PE image");
-    let ipl_entry = ipl_entry as u32;
-
-    build_ipl_fv_header(
-        rust_ipl_header_buffer,
-        new_rust_ipl_buf.as_slice(),
-    );
-
-    let mut rust_reset_vector_header_buffer = [0u8; size_of::<ResetVectorByte>()];
-    build_reset_vector_header(
-        &mut rust_reset_vector_header_buffer,
-        reset_vector_bin.as_slice(),
-    );
-
-    let mut total_writen = 0usize;
-
-    rust_firmware_file
-        .write_all(&zero_buf[..RUST_PAYLOAD_OFFSET])
-        .expect("fail to write pad");
-
-    total_writen += &zero_buf[..RUST_PAYLOAD_OFFSET].len();
-
-    rust_firmware_file
-        .write_all(&rust_payload_header_buffer[..])
-        .expect("fail to write rust payload header");
-    total_writen += &rust_payload_header_buffer[..].len();
-
-    rust_firmware_file
-        .write_all(&rust_payload_bin[..])
-        .expect("fail to write rust payload");
-    total_writen += &rust_payload_bin[..].len();
-    let pad_size =
-        RUST_PAYLOAD_MAX_SIZE - rust_payload_bin.len() - rust_payload_header_buffer.len();
-    rust_firmware_file
-        .write_all(&zero_buf[..pad_size])
-        .expect("fail to write pad");
-    total_writen += &zero_buf[..pad_size].len();
-    assert_eq!(total_writen, RUST_IPL_OFFSET);
-
-    rust_firmware_file
-        .write_all(&rust_ipl_header_buffer[..])
-        .expect("fail to write rust IPL header");
-    total_writen += &rust_ipl_header_buffer[..].len();
-    rust_firmware_file
-        .write_all(&new_rust_ipl_buf[..])
-        .expect("fail to write rust IPL");
-    total_writen += &new_rust_ipl_buf[..].len();
-
-    let pad_size = RUST_IPL_MAX_SIZE - new_rust_ipl_buf.len() - rust_ipl_header_buffer.len();
-    rust_firmware_file
-        .write_all(&zero_buf[..pad_size])
-        .expect("fail to write rust IPL");
-    total_writen += &zero_buf[..pad_size].len();
-
-    assert_eq!(total_writen, FIRMWARE_FSP_T_OFFSET as usize);
-    assert_eq!(total_writen, FIRMWARE_FSP_OFFSET as usize);
-
-    rust_firmware_file
-        .write_all(fsp_t_bin)
-        .expect("fail to write rust fsp_t");
-    assert_eq!(fsp_t_bin.len(), FIRMWARE_FSP_T_SIZE as usize);
-    total_writen += fsp_t_bin.len();
-    assert_eq!(FIRMWARE_FSP_M_OFFSET as usize, total_writen);
-    rust_firmware_file
-        .write_all(fsp_m_bin)
-        .expect("fail to write rust fsp_m");
-    total_writen += fsp_m_bin.len();
-    assert_eq!(FIRMWARE_FSP_S_OFFSET as usize, total_writen);
-    rust_firmware_file
-        .write_all(fsp_s_bin)
-        .expect("fail to write rust fsp_s");
-    total_writen += fsp_s_bin.len();
-
-    assert_eq!(total_writen, FIRMWARE_RESET_VECTOR_OFFSET as usize);
-
-    let pad_size = (FIRMWARE_FSP_MAX_SIZE
-        - FIRMWARE_FSP_T_SIZE - FIRMWARE_FSP_M_SIZE - FIRMWARE_FSP_S_SIZE) as usize;
-    if pad_size > 0 {
-        rust_firmware_file
-            .write_all(&zero_buf[..pad_size])
-            .expect("fail to write rust reset vector");
-        total_writen += pad_size;
+    let rust_ipl_bin = read_file(rust_ipl_name)?;
+    let rust_payload_bin = read_file(rust_payload_name)?;
+
+    // the image is assembled in memory, so that the checksums can be
+    // updated and verified before anything is written.
+    let mut rust_firmware_image = vec![0xFFu8; FIRMWARE_SIZE as usize];
+
+    let var_region = var_store::build_var_region(var_store_name)?;
+    let var_offset = FIRMWARE_RESERVED1_OFFSET as usize;
+    rust_firmware_image[var_offset..var_offset + var_region.len()].copy_from_slice(&var_region);
+
+    let ffs_files: Vec<Vec<u8>> = payload_files.iter().map(ffs::build_ffs_file).collect();
+    let payload_fv = build_payload_fv(&rust_payload_bin, compress, &ffs_files, signing.as_ref())?;
+    rust_firmware_image[RUST_PAYLOAD_OFFSET..RUST_PAYLOAD_OFFSET + RUST_PAYLOAD_MAX_SIZE]
+        .copy_from_slice(&payload_fv);
+
+    let (ipl_fv, ipl_entry) = build_ipl_fv(&rust_ipl_bin, signing.as_ref())?;
+    rust_firmware_image[RUST_IPL_OFFSET..RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE]
+        .copy_from_slice(&ipl_fv);
+
+    for (name, fsp_bin, fsp_offset, fsp_size) in &[
+        ("FSP-T", fsp_t_bin, FIRMWARE_FSP_T_OFFSET, FIRMWARE_FSP_T_SIZE),
+        ("FSP-M", fsp_m_bin, FIRMWARE_FSP_M_OFFSET, FIRMWARE_FSP_M_SIZE),
+        ("FSP-S", fsp_s_bin, FIRMWARE_FSP_S_OFFSET, FIRMWARE_FSP_S_SIZE),
+    ] {
+        check_fsp_size(name, fsp_bin, *fsp_size)?;
+        let fsp_offset = *fsp_offset as usize;
+        rust_firmware_image[fsp_offset..fsp_offset + fsp_bin.len()].copy_from_slice(fsp_bin);
     }
 
-    assert_eq!(total_writen, FIRMWARE_RESET_VECTOR_OFFSET as usize);
-    // reset vector params
-    #[derive(Debug, Pread, Pwrite)]
-    struct ResetVectorParams {
-        ipl_entry: u32,                 // rust ipl entry
-        temp_ram_init_param: FsptUpd,   // FSP_T TempRamInit Params
-    };
+    let mut temp_ram_init_param = TEMP_RAM_INIT_PARAM;
+    if let Some(upd_config) = &upd_config {
+        upd_config.patch_image(&mut rust_firmware_image)?;
+        upd_config.patch_fspt_upd(&mut temp_ram_init_param)?;
+    }
 
     let reset_vector_info = ResetVectorParams {
         ipl_entry,
-        temp_ram_init_param: {
-            TEMP_RAM_INIT_PARAM
-        },
+        temp_ram_init_param,
     };
-
-    let reset_vector_info_buffer = &mut [0u8; 256];
-    let writen = reset_vector_info_buffer
-        .pwrite(reset_vector_info, 0)
+    let reset_vector = build_reset_vector(&reset_vector_bin, reset_vector_info)?;
+    let reset_vector_offset = FIRMWARE_RESET_VECTOR_OFFSET as usize;
+    rust_firmware_image[reset_vector_offset..reset_vector_offset + RUST_RESET_VECTOR_MAX_SIZE]
+        .copy_from_slice(&reset_vector);
+
+    check_payload_signature(&rust_firmware_image)?;
+
+    error::write_file(rust_firmware_name, &rust_firmware_image)?;
+
+    let mut inputs = vec![
+        ("PAYLOAD", manifest::manifest_input(rust_payload_name, &rust_payload_bin)),
+        ("IPL", manifest::manifest_input(rust_ipl_name, &rust_ipl_bin)),
+        ("FSP-T", manifest::manifest_input(&fsp_t_name, fsp_t_bin)),
+        ("FSP-M", manifest::manifest_input(&fsp_m_name, fsp_m_bin)),
+        ("FSP-S", manifest::manifest_input(&fsp_s_name, fsp_s_bin)),
+        ("RESET_VECTOR", manifest::manifest_input(reset_vector_name, &reset_vector_bin)),
+    ];
+    if let Some(var_store_name) = var_store_name {
+        let var_store_json = read_file(var_store_name)?;
+        inputs.push((
+            "VAR",
+            manifest::manifest_input(var_store_name, &var_store_json),
+        ));
+    }
+    let payload_files: Vec<_> = payload_files
+        .iter()
+        .map(|payload_file| payload_file.manifest_entry())
+        .collect();
+    let reset_vector_info: ResetVectorParams = rust_firmware_image
+        .pread(reset_vector_offset)
         .unwrap();
-    rust_firmware_file
-        .write_all(&reset_vector_info_buffer[..writen])
-        .expect("fail to write rust reset vector");
-
-    let pad_size = RUST_RESET_VECTOR_MAX_SIZE
-        - rust_reset_vector_header_buffer.len()
-        - reset_vector_bin.len()
-        - writen;
-    rust_firmware_file
-        .write_all(&zero_buf[..pad_size])
-        .expect("fail to write rust reset vector");
-
-    rust_firmware_file
-        .write_all(&rust_reset_vector_header_buffer[..])
-        .expect("fail to write reset vector header");
+    let build_manifest = manifest::build_manifest(
+        rust_firmware_name,
+        &rust_firmware_image,
+        &inputs,
+        &payload_files,
+        ipl_entry,
+        &reset_vector_info.temp_ram_init_param,
+    );
+    manifest::write_manifest(rust_firmware_name, &build_manifest)?;
+    gdb::write_gdbinit(rust_firmware_name, rust_ipl_name, &rust_ipl_bin, ipl_entry)?;
 
-    rust_firmware_file
-        .write_all(&reset_vector_bin[..])
-        .expect("fail to write reset vector");
+    Ok(())
+}
 
-    rust_firmware_file.sync_data()?;
+fn main() {
+    simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info).init().unwrap();
 
-    Ok(())
+    let args: Vec<String> = env::args().collect();
+    let result = check_layout().and_then(|_| match args.get(1).map(|arg| arg.as_str()) {
+        Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        Some("inspect") => match &args[2..] {
+            [rust_firmware_name] => inspect::inspect_firmware(rust_firmware_name),
+            _ => Err(usage_error(
+                "inspect requires one firmware image".to_string(),
+            )),
+        },
+        Some("replace") => replace::replace_components(&args[2..]),
+        Some("gdb-payload") => match &args[2..] {
+            [rust_payload_name, serial_log_name] => {
+                gdb::gdb_payload_command(rust_payload_name, serial_log_name)
+            }
+            _ => Err(usage_error(
+                "gdb-payload requires the payload and the serial log of the boot".to_string(),
+            )),
+        },
+        Some("rsym") => match &args[2..] {
+            [pe_image_name] => rsym::add_symbol_table(pe_image_name, None),
+            [pe_image_name, output_name] => {
+                rsym::add_symbol_table(pe_image_name, Some(output_name))
+            }
+            _ => Err(usage_error(
+                "rsym requires a PE image and an optional output".to_string(),
+            )),
+        },
+        _ => build_firmware(&args[1..]),
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(e.exit_code());
+    }
 }
 
 fn write_u24(data: u32, buf: &mut [u8]) {
-    assert_eq!(data < 0xffffff, true);
+    assert!(data <= 0xffffff);
     buf[0] = (data & 0xFF) as u8;
     buf[1] = ((data >> 8) & 0xFF) as u8;
     buf[2] = ((data >> 16) & 0xFF) as u8;
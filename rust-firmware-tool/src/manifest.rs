@@ -0,0 +1,244 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+
+use rust_firmware_layout::build_time::*;
+use rust_firmware_layout::fsp_build_time::*;
+use rust_firmware_platform::FsptUpd;
+
+use super::error::{write_file, Result, ToolError};
+use super::inspect::{RegionKind, FIRMWARE_REGIONS};
+use super::{RUST_IPL_MAX_SIZE, RUST_IPL_OFFSET};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestInput {
+    pub path: String,
+    pub size: String,
+    pub sha256: String,
+    pub sha384: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestRegion {
+    pub name: String,
+    pub offset: String,
+    pub size: String,
+    pub base: String,
+    pub sha256: String,
+    pub input: Option<ManifestInput>,
+}
+
+/// A file added to the payload FV with --add-file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestPayloadFile {
+    pub name: String,
+    pub file_type: String,
+    pub ui_name: String,
+    pub input: ManifestInput,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestImage {
+    pub path: String,
+    pub size: String,
+    pub sha256: String,
+    pub sha384: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestLoadedBase {
+    pub name: String,
+    pub base: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestTempRamInitParam {
+    pub signature: String,
+    pub revision: u8,
+    pub common_upd_revision: u8,
+    pub microcode_region_base: String,
+    pub microcode_region_length: String,
+    pub code_region_base: String,
+    pub code_region_length: String,
+    pub upd_terminator: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestSignature {
+    pub region: String,
+    pub algorithm: String,
+    pub public_key_sha384: String,
+}
+
+///
+/// Machine-readable description of an image built by rust-firmware-tool.
+///
+/// Values are written as hex strings and all lists are ordered, so identical
+/// inputs give a byte-identical manifest.
+///
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub image: ManifestImage,
+    pub regions: Vec<ManifestRegion>,
+    pub ipl_entry: String,
+    pub loaded_bases: Vec<ManifestLoadedBase>,
+    pub temp_ram_init_param: ManifestTempRamInitParam,
+    /// the public key hash in the IPL FV, rust-ipl verifies the payload FV with it.
+    pub trusted_public_key_sha384: Option<String>,
+    #[serde(default)]
+    pub signatures: Vec<ManifestSignature>,
+    #[serde(default)]
+    pub payload_files: Vec<ManifestPayloadFile>,
+}
+
+pub fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_string(data: &[u8]) -> String {
+    hex_string(&Sha256::digest(data))
+}
+
+fn sha384_string(data: &[u8]) -> String {
+    hex_string(&Sha384::digest(data))
+}
+
+/// The manifest of final.bin is final.manifest.json in the same directory.
+pub fn manifest_path(rust_firmware_name: &str) -> PathBuf {
+    Path::new(rust_firmware_name).with_extension("manifest.json")
+}
+
+pub fn manifest_input(path: &str, data: &[u8]) -> ManifestInput {
+    ManifestInput {
+        path: path.to_string(),
+        size: format!("0x{:x}", data.len()),
+        sha256: sha256_string(data),
+        sha384: sha384_string(data),
+    }
+}
+
+fn loaded_bases() -> Vec<ManifestLoadedBase> {
+    [
+        ("LOADED_RESERVED1_BASE", LOADED_RESERVED1_BASE),
+        ("LOADED_PADDING_BASE", LOADED_PADDING_BASE),
+        ("LOADED_PAYLOAD_BASE", LOADED_PAYLOAD_BASE),
+        ("LOADED_IPL_BASE", LOADED_IPL_BASE),
+        ("LOADED_FSP_BASE", LOADED_FSP_BASE),
+        ("LOADED_FSP_T_BASE", LOADED_FSP_T_BASE),
+        ("LOADED_FSP_M_BASE", LOADED_FSP_M_BASE),
+        ("LOADED_FSP_S_BASE", LOADED_FSP_S_BASE),
+        ("LOADED_RESET_VECTOR_BASE", LOADED_RESET_VECTOR_BASE),
+    ]
+    .iter()
+    .map(|(name, base)| ManifestLoadedBase {
+        name: name.to_string(),
+        base: format!("0x{:08x}", base),
+    })
+    .collect()
+}
+
+fn temp_ram_init_param(fspt_upd: &FsptUpd) -> ManifestTempRamInitParam {
+    let common_upd = &fspt_upd.fspt_common_upd;
+    ManifestTempRamInitParam {
+        signature: format!("0x{:016x}", fspt_upd.fsp_upd_header.signature),
+        revision: fspt_upd.fsp_upd_header.revision,
+        common_upd_revision: common_upd.revision,
+        microcode_region_base: format!("0x{:08x}", common_upd.microcode_region_base),
+        microcode_region_length: format!("0x{:x}", common_upd.microcode_region_length),
+        code_region_base: format!("0x{:08x}", common_upd.code_region_base),
+        code_region_length: format!("0x{:x}", common_upd.code_region_length),
+        upd_terminator: format!("0x{:04x}", fspt_upd.upd_terminator),
+    }
+}
+
+fn algorithm_name(algorithm: u16) -> String {
+    match algorithm {
+        fw_verifier::SIGNING_ALGORITHM_RSA3072_SHA384 => "RSA3072-PKCS1v15-SHA384".to_string(),
+        fw_verifier::SIGNING_ALGORITHM_ECDSA_P384_SHA384 => "ECDSA-P384-SHA384".to_string(),
+        _ => format!("0x{:04x}", algorithm),
+    }
+}
+
+fn signatures(rust_firmware_image: &[u8]) -> Vec<ManifestSignature> {
+    FIRMWARE_REGIONS
+        .iter()
+        .filter(|region| region.kind == RegionKind::Fv)
+        .filter_map(|region| {
+            let fv_data = &rust_firmware_image[region.offset..region.offset + region.size];
+            let fv_signature = fw_verifier::get_fv_signature(fv_data).ok()?;
+            Some(ManifestSignature {
+                region: region.name.to_string(),
+                algorithm: algorithm_name(fv_signature.header.algorithm),
+                public_key_sha384: hex_string(&fv_signature.public_key_hash()),
+            })
+        })
+        .collect()
+}
+
+///
+/// Build the manifest of a whole image.
+///
+/// inputs: (region name, input file) of the components placed in the image.
+///
+pub fn build_manifest(
+    rust_firmware_name: &str,
+    rust_firmware_image: &[u8],
+    inputs: &[(&str, ManifestInput)],
+    payload_files: &[ManifestPayloadFile],
+    ipl_entry: u32,
+    temp_ram_init: &FsptUpd,
+) -> Manifest {
+    let regions = FIRMWARE_REGIONS
+        .iter()
+        .map(|region| ManifestRegion {
+            name: region.name.to_string(),
+            offset: format!("0x{:06x}", region.offset),
+            size: format!("0x{:06x}", region.size),
+            base: format!("0x{:08x}", region.base),
+            sha256: sha256_string(
+                &rust_firmware_image[region.offset..region.offset + region.size],
+            ),
+            input: inputs
+                .iter()
+                .find(|(name, _)| *name == region.name)
+                .map(|(_, input)| input.clone()),
+        })
+        .collect();
+
+    Manifest {
+        image: ManifestImage {
+            path: rust_firmware_name.to_string(),
+            size: format!("0x{:x}", rust_firmware_image.len()),
+            sha256: sha256_string(rust_firmware_image),
+            sha384: sha384_string(rust_firmware_image),
+        },
+        regions,
+        ipl_entry: format!("0x{:08x}", ipl_entry),
+        loaded_bases: loaded_bases(),
+        temp_ram_init_param: temp_ram_init_param(temp_ram_init),
+        trusted_public_key_sha384: fw_verifier::get_trusted_public_key_hash(
+            &rust_firmware_image[RUST_IPL_OFFSET..RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE],
+        )
+        .map(|trusted| hex_string(&trusted.public_key_hash)),
+        signatures: signatures(rust_firmware_image),
+        payload_files: payload_files.to_vec(),
+    }
+}
+
+pub fn read_manifest(rust_firmware_name: &str) -> Option<Manifest> {
+    let manifest = fs::read(manifest_path(rust_firmware_name)).ok()?;
+    serde_json::from_slice(&manifest).ok()
+}
+
+pub fn write_manifest(rust_firmware_name: &str, manifest: &Manifest) -> Result<()> {
+    let mut manifest = serde_json::to_string_pretty(manifest)
+        .map_err(|e| ToolError::InvalidData(format!("fail to serialize manifest: {}", e)))?;
+    manifest.push('\n');
+    write_file(manifest_path(rust_firmware_name), manifest.as_bytes())
+}
@@ -0,0 +1,294 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+use scroll::{Pread, Pwrite};
+
+use rust_firmware_layout::build_time::*;
+use rust_firmware_layout::fsp_build_time::*;
+
+use r_uefi_pi::fv::FV_FILETYPE_DXE_CORE;
+
+use super::error::{read_file, write_file, Result, ToolError};
+use super::ffs;
+use super::gdb;
+use super::manifest::{self, ManifestInput};
+use super::upd::UpdConfig;
+use super::var_store;
+use super::{
+    build_ipl_fv, build_payload_fv, build_reset_vector, check_fsp_size, check_payload_signature,
+    load_fv_signing, ResetVectorParams, RUST_IPL_MAX_SIZE, RUST_IPL_OFFSET, RUST_PAYLOAD_MAX_SIZE,
+    RUST_PAYLOAD_OFFSET, RUST_RESET_VECTOR_MAX_SIZE,
+};
+
+const REPLACE_USAGE: &str = "usage: rust-firmware-tool replace \
+    [--payload FILE [--add-file ARG]...] [--ipl FILE] [--fsp-t FILE] [--fsp-m FILE] [--fsp-s FILE] \
+    [--reset-vector FILE] [--var-store FILE] [--upd FILE] [--compress] [--sign-key KEY [--sign-ipl]] final.bin";
+
+#[derive(Default)]
+struct ReplaceArgs {
+    payload: Option<String>,
+    add_files: Vec<String>,
+    ipl: Option<String>,
+    fsp_t: Option<String>,
+    fsp_m: Option<String>,
+    fsp_s: Option<String>,
+    reset_vector: Option<String>,
+    var_store: Option<String>,
+    upd: Option<String>,
+    sign_key: Option<String>,
+    sign_ipl: bool,
+    compress: bool,
+    firmware: String,
+}
+
+fn parse_args(args: &[String]) -> Result<ReplaceArgs> {
+    let usage = |message: String| ToolError::Usage(format!("{}\n{}", message, REPLACE_USAGE));
+
+    let (firmware, options) = match args.split_last() {
+        Some((firmware, options)) if !firmware.starts_with("--") => (firmware, options),
+        _ => return Err(usage("firmware image is not specified".to_string())),
+    };
+
+    let mut replace_args = ReplaceArgs {
+        firmware: firmware.clone(),
+        ..Default::default()
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        if option == "--sign-ipl" {
+            replace_args.sign_ipl = true;
+            continue;
+        }
+        if option == "--compress" {
+            replace_args.compress = true;
+            continue;
+        }
+        let file = match options.next() {
+            Some(file) => Some(file.clone()),
+            None => return Err(usage(format!("{} requires a file", option))),
+        };
+        match option.as_str() {
+            "--payload" => replace_args.payload = file,
+            "--add-file" => replace_args.add_files.extend(file),
+            "--ipl" => replace_args.ipl = file,
+            "--fsp-t" => replace_args.fsp_t = file,
+            "--fsp-m" => replace_args.fsp_m = file,
+            "--fsp-s" => replace_args.fsp_s = file,
+            "--reset-vector" => replace_args.reset_vector = file,
+            "--var-store" => replace_args.var_store = file,
+            "--upd" => replace_args.upd = file,
+            "--sign-key" => replace_args.sign_key = file,
+            unknown => return Err(usage(format!("unknown option {}", unknown))),
+        }
+    }
+    if replace_args.payload.is_none() && !replace_args.add_files.is_empty() {
+        return Err(usage("--add-file requires --payload".to_string()));
+    }
+    Ok(replace_args)
+}
+
+fn replace_region(rust_firmware_image: &mut [u8], offset: usize, region: &[u8]) {
+    rust_firmware_image[offset..offset + region.len()].copy_from_slice(region);
+}
+
+///
+/// Replace components of an existing image built by rust-firmware-tool.
+///
+/// Only the regions of the given components are rebuilt, the IPL entry in
+/// the reset vector params and the .gdbinit are updated when the IPL is
+/// replaced.
+///
+/// The files added to the payload FV are kept when the payload is replaced
+/// without --add-file.
+///
+/// --var-store formats the VAR region again, the variables set at runtime
+/// are lost.
+///
+/// --upd patches the UPD of the FSP-T/M/S in the image, after the new FSP
+/// binaries are placed, and the FsptUpd of the reset vector params. A FSP
+/// replaced without --upd comes with its default UPD.
+///
+/// A signed image keeps verifying only if the new payload is signed by the
+/// key trusted by the IPL, or if the IPL is replaced with the new key too.
+///
+pub fn replace_components(args: &[String]) -> Result<()> {
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", REPLACE_USAGE);
+        return Ok(());
+    }
+    let replace_args = parse_args(args)?;
+    let signing = load_fv_signing(replace_args.sign_key.as_deref(), replace_args.sign_ipl)?;
+
+    let mut rust_firmware_image = read_file(&replace_args.firmware)?;
+    if rust_firmware_image.len() != FIRMWARE_SIZE as usize {
+        return Err(ToolError::InvalidData(format!(
+            "{} size 0x{:x} does not match FIRMWARE_SIZE 0x{:x}",
+            replace_args.firmware,
+            rust_firmware_image.len(),
+            FIRMWARE_SIZE
+        )));
+    }
+
+    let reset_vector_offset = FIRMWARE_RESET_VECTOR_OFFSET as usize;
+    let mut reset_vector_info: ResetVectorParams = rust_firmware_image
+        .pread(reset_vector_offset)
+        .unwrap();
+
+    let mut inputs: Vec<(&str, ManifestInput)> = Vec::new();
+    let mut payload_files = None;
+    let mut replaced_ipl = None;
+
+    if let Some(rust_payload_name) = &replace_args.payload {
+        log::info!("replace rust payload with {}", rust_payload_name);
+        let rust_payload_bin = read_file(rust_payload_name)?;
+        let ffs_files = if replace_args.add_files.is_empty() {
+            let old_payload_fv = &rust_firmware_image
+                [RUST_PAYLOAD_OFFSET..RUST_PAYLOAD_OFFSET + RUST_PAYLOAD_MAX_SIZE];
+            ffs::get_added_ffs_files(old_payload_fv, FV_FILETYPE_DXE_CORE)
+        } else {
+            let mut files = Vec::new();
+            for add_file in replace_args.add_files.iter() {
+                files.push(ffs::read_payload_file(add_file)?);
+            }
+            payload_files = Some(files.iter().map(|file| file.manifest_entry()).collect());
+            files.iter().map(ffs::build_ffs_file).collect()
+        };
+        let payload_fv = build_payload_fv(
+            &rust_payload_bin,
+            replace_args.compress,
+            &ffs_files,
+            signing.as_ref(),
+        )?;
+        inputs.push((
+            "PAYLOAD",
+            manifest::manifest_input(rust_payload_name, &rust_payload_bin),
+        ));
+        assert_eq!(payload_fv.len(), RUST_PAYLOAD_MAX_SIZE);
+        replace_region(&mut rust_firmware_image, RUST_PAYLOAD_OFFSET, &payload_fv);
+
+        let ipl_region = &rust_firmware_image[RUST_IPL_OFFSET..RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE];
+        if signing.is_some()
+            && replace_args.ipl.is_none()
+            && fw_verifier::get_trusted_public_key_hash(ipl_region).is_none()
+        {
+            log::warn!(
+                "the IPL has no trusted public key hash, payload signature is not verified at boot"
+            );
+        }
+    }
+
+    if let Some(rust_ipl_name) = &replace_args.ipl {
+        log::info!("replace rust IPL with {}", rust_ipl_name);
+        let rust_ipl_bin = read_file(rust_ipl_name)?;
+        let ipl_region = &rust_firmware_image[RUST_IPL_OFFSET..RUST_IPL_OFFSET + RUST_IPL_MAX_SIZE];
+        if signing.is_none() && fw_verifier::get_trusted_public_key_hash(ipl_region).is_some() {
+            return Err(ToolError::Usage(format!(
+                "the image is signed, --sign-key is required to replace the IPL\n{}",
+                REPLACE_USAGE
+            )));
+        }
+        let (ipl_fv, ipl_entry) = build_ipl_fv(&rust_ipl_bin, signing.as_ref())?;
+        inputs.push(("IPL", manifest::manifest_input(rust_ipl_name, &rust_ipl_bin)));
+        assert_eq!(ipl_fv.len(), RUST_IPL_MAX_SIZE);
+        replace_region(&mut rust_firmware_image, RUST_IPL_OFFSET, &ipl_fv);
+
+        log::info!(
+            "ipl entry 0x{:x} -> 0x{:x}",
+            reset_vector_info.ipl_entry,
+            ipl_entry
+        );
+        reset_vector_info.ipl_entry = ipl_entry;
+        // ipl_entry is outside of the reset vector FFS, no checksum to update.
+        rust_firmware_image[reset_vector_offset..reset_vector_offset + 4]
+            .copy_from_slice(&ipl_entry.to_le_bytes());
+        replaced_ipl = Some((rust_ipl_name, rust_ipl_bin, ipl_entry));
+    }
+
+    for (name, fsp_name, fsp_offset, fsp_size) in &[
+        ("FSP-T", &replace_args.fsp_t, FIRMWARE_FSP_T_OFFSET, FIRMWARE_FSP_T_SIZE),
+        ("FSP-M", &replace_args.fsp_m, FIRMWARE_FSP_M_OFFSET, FIRMWARE_FSP_M_SIZE),
+        ("FSP-S", &replace_args.fsp_s, FIRMWARE_FSP_S_OFFSET, FIRMWARE_FSP_S_SIZE),
+    ] {
+        if let Some(fsp_name) = fsp_name {
+            log::info!("replace {} with {}", name, fsp_name);
+            let fsp_bin = read_file(fsp_name)?;
+            check_fsp_size(name, &fsp_bin, *fsp_size)?;
+            inputs.push((name, manifest::manifest_input(fsp_name, &fsp_bin)));
+            replace_region(&mut rust_firmware_image, *fsp_offset as usize, &fsp_bin);
+        }
+    }
+
+    if let Some(upd_name) = &replace_args.upd {
+        log::info!("patch UPD with {}", upd_name);
+        let upd_config = UpdConfig::load(upd_name)?;
+        upd_config.patch_image(&mut rust_firmware_image)?;
+        upd_config.patch_fspt_upd(&mut reset_vector_info.temp_ram_init_param)?;
+        // the params are outside of the reset vector FFS, as ipl_entry.
+        rust_firmware_image
+            .pwrite(&reset_vector_info, reset_vector_offset)
+            .unwrap();
+    }
+
+    if let Some(reset_vector_name) = &replace_args.reset_vector {
+        log::info!("replace reset vector with {}", reset_vector_name);
+        let reset_vector_bin = read_file(reset_vector_name)?;
+        inputs.push((
+            "RESET_VECTOR",
+            manifest::manifest_input(reset_vector_name, &reset_vector_bin),
+        ));
+        let reset_vector = build_reset_vector(&reset_vector_bin, reset_vector_info)?;
+        assert_eq!(reset_vector.len(), RUST_RESET_VECTOR_MAX_SIZE);
+        replace_region(&mut rust_firmware_image, reset_vector_offset, &reset_vector);
+    }
+
+    if let Some(var_store_name) = &replace_args.var_store {
+        log::info!("format the VAR region with {}", var_store_name);
+        let var_region = var_store::build_var_region(Some(var_store_name))?;
+        inputs.push((
+            "VAR",
+            manifest::manifest_input(var_store_name, &read_file(var_store_name)?),
+        ));
+        replace_region(
+            &mut rust_firmware_image,
+            FIRMWARE_RESERVED1_OFFSET as usize,
+            &var_region,
+        );
+    }
+
+    check_payload_signature(&rust_firmware_image)?;
+    write_file(&replace_args.firmware, &rust_firmware_image)?;
+    if let Some((rust_ipl_name, rust_ipl_bin, ipl_entry)) = replaced_ipl {
+        gdb::write_gdbinit(
+            &replace_args.firmware,
+            rust_ipl_name,
+            &rust_ipl_bin,
+            ipl_entry,
+        )?;
+    }
+
+    // keep the inputs of the regions which are not replaced.
+    if let Some(old_manifest) = manifest::read_manifest(&replace_args.firmware) {
+        for region in old_manifest.regions.iter() {
+            if let Some(input) = &region.input {
+                if !inputs.iter().any(|(name, _)| *name == region.name) {
+                    inputs.push((&region.name, input.clone()));
+                }
+            }
+        }
+        let payload_files = payload_files.unwrap_or(old_manifest.payload_files);
+        let reset_vector_info: ResetVectorParams = rust_firmware_image
+            .pread(reset_vector_offset)
+            .unwrap();
+        let new_manifest = manifest::build_manifest(
+            &replace_args.firmware,
+            &rust_firmware_image,
+            &inputs,
+            &payload_files,
+            reset_vector_info.ipl_entry,
+            &reset_vector_info.temp_ram_init_param,
+        );
+        manifest::write_manifest(&replace_args.firmware, &new_manifest)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,526 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! The `.rsym` section fw-exception resolves the backtraces with, built from
+//! the COFF symbol table lld-link writes with `/debug:dwarf`:
+//!
+//! ```text
+//! rust-firmware-tool rsym rust_ipl.efi
+//! ```
+//!
+//! The section holds the functions of the executable sections, the size of
+//! a function is the distance to the next one.
+//!
+
+use scroll::{Pread, Pwrite};
+
+use super::error::{read_file, write_file, Result, ToolError};
+
+const SYMBOL_TABLE_SECTION: &[u8] = b".rsym";
+const SYMBOL_TABLE_MAGIC: &[u8] = b"RSYM";
+
+const COFF_SYMBOL_SIZE: usize = 18;
+const SECTION_HEADER_SIZE: usize = 40;
+const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+const IMAGE_SYM_CLASS_STATIC: u8 = 3;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x0000_0040;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+
+// Offsets in the PE headers, from the PE signature
+const NUMBER_OF_SECTIONS: usize = 6;
+const POINTER_TO_SYMBOL_TABLE: usize = 12;
+const NUMBER_OF_SYMBOLS: usize = 16;
+const SIZE_OF_OPTIONAL_HEADER: usize = 20;
+const OPTIONAL_HEADER: usize = 24;
+const SECTION_ALIGNMENT: usize = OPTIONAL_HEADER + 32;
+const FILE_ALIGNMENT: usize = OPTIONAL_HEADER + 36;
+const SIZE_OF_IMAGE: usize = OPTIONAL_HEADER + 56;
+const SIZE_OF_HEADERS: usize = OPTIONAL_HEADER + 60;
+
+/// A function of the image: its RVA and its name.
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub start: u32,
+    pub size: u32,
+    pub name: String,
+}
+
+fn invalid_image(name: &str, reason: &str) -> ToolError {
+    ToolError::InvalidData(format!("{}: {}", name, reason))
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) / alignment * alignment
+}
+
+// The character of a `$...$` escape of a legacy Rust symbol.
+fn unescape(code: &str) -> Option<char> {
+    match code {
+        "SP" => Some('@'),
+        "BP" => Some('*'),
+        "RF" => Some('&'),
+        "LT" => Some('<'),
+        "GT" => Some('>'),
+        "LP" => Some('('),
+        "RP" => Some(')'),
+        "C" => Some(','),
+        _ => u32::from_str_radix(code.strip_prefix('u')?, 16)
+            .ok()
+            .and_then(core::char::from_u32),
+    }
+}
+
+fn demangle_element(element: &str) -> String {
+    // a leading $ is escaped with _
+    let element = match element.strip_prefix('_') {
+        Some(rest) if rest.starts_with('$') => rest,
+        _ => element,
+    };
+    let mut demangled = String::new();
+    let mut rest = element;
+    while let Some(start) = rest.find(&['$', '.'][..]) {
+        demangled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(path) = rest.strip_prefix("..") {
+            demangled.push_str("::");
+            rest = path;
+            continue;
+        }
+        let c = rest[1..]
+            .find('$')
+            .and_then(|end| Some((unescape(&rest[1..end + 1])?, end + 2)));
+        match c {
+            Some((c, len)) => {
+                demangled.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                demangled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    demangled.push_str(rest);
+    demangled
+}
+
+///
+/// Demangle a legacy Rust symbol, `_ZN`, the length prefixed path elements
+/// then `E`, and drop its hash. Other names are kept as they are.
+///
+pub fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN") {
+        Some(rest) => rest,
+        None => return name.to_string(),
+    };
+    let mut elements = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        match rest[..digits].parse::<usize>() {
+            Ok(len) if len > 0 && rest.len() >= digits + len => {
+                elements.push(&rest[digits..digits + len]);
+                rest = &rest[digits + len..];
+            }
+            _ => return name.to_string(),
+        }
+    }
+    let is_hash = |element: &&str| {
+        element.len() == 17
+            && element.starts_with('h')
+            && element[1..].bytes().all(|c| c.is_ascii_hexdigit())
+    };
+    if elements.last().map_or(false, is_hash) {
+        elements.pop();
+    }
+    elements
+        .iter()
+        .map(|element| demangle_element(element))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+// The name of a COFF symbol, inline or in the string table.
+fn symbol_name<'a>(symbol: &'a [u8], strings: &'a [u8]) -> Option<&'a str> {
+    let name = if symbol[0..4] == [0; 4] {
+        let offset = symbol.pread::<u32>(4).ok()? as usize;
+        let name = strings.get(offset..)?;
+        &name[..name.iter().position(|c| *c == 0)?]
+    } else {
+        let len = symbol[..8].iter().position(|c| *c == 0).unwrap_or(8);
+        &symbol[..len]
+    };
+    core::str::from_utf8(name).ok()
+}
+
+///
+/// Return the functions of the COFF symbol table of a PE image, sorted by
+/// RVA, the symbols of the executable sections which are not section names.
+///
+pub fn find_functions(name: &str, pe_image: &[u8]) -> Result<Vec<Function>> {
+    let pe_header_offset = pe_image
+        .pread::<u32>(0x3c)
+        .map_err(|_| invalid_image(name, "invalid PE header"))? as usize;
+    let read_u32 = |offset: usize| {
+        pe_image
+            .pread::<u32>(pe_header_offset + offset)
+            .map_err(|_| invalid_image(name, "invalid PE header"))
+    };
+    let symbol_table = read_u32(POINTER_TO_SYMBOL_TABLE)? as usize;
+    let number_of_symbols = read_u32(NUMBER_OF_SYMBOLS)? as usize;
+    if symbol_table == 0 || number_of_symbols == 0 {
+        return Err(invalid_image(
+            name,
+            "no COFF symbol table, link it with -C link-arg=/debug:dwarf",
+        ));
+    }
+    let strings_offset = symbol_table + number_of_symbols * COFF_SYMBOL_SIZE;
+    let symbols = pe_image
+        .get(symbol_table..strings_offset)
+        .ok_or_else(|| invalid_image(name, "truncated COFF symbol table"))?;
+    let strings = pe_image.get(strings_offset..).unwrap_or(&[]);
+    let sections: Vec<_> = pe_loader::pe::sections(pe_image)
+        .ok_or_else(|| invalid_image(name, "invalid PE header"))?
+        .collect();
+
+    let mut starts = Vec::new();
+    let mut index = 0;
+    while index < number_of_symbols {
+        let symbol = &symbols[index * COFF_SYMBOL_SIZE..(index + 1) * COFF_SYMBOL_SIZE];
+        let value = symbol.pread::<u32>(8).unwrap();
+        let section_number = symbol.pread::<i16>(12).unwrap();
+        let storage_class = symbol[16];
+        let aux_symbols = symbol[17] as usize;
+        index += 1 + aux_symbols;
+
+        if storage_class != IMAGE_SYM_CLASS_EXTERNAL && storage_class != IMAGE_SYM_CLASS_STATIC {
+            continue;
+        }
+        let section = match sections.get((section_number as usize).wrapping_sub(1)) {
+            Some(section) if section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0 => section,
+            _ => continue,
+        };
+        match symbol_name(symbol, strings) {
+            Some(symbol_name) if !symbol_name.starts_with('.') => starts.push((
+                section.virtual_address + value,
+                section.virtual_address + section.virtual_size,
+                symbol_name,
+            )),
+            _ => {}
+        }
+    }
+    // the first of the aliases of a function is kept
+    starts.sort_by_key(|(start, _, _)| *start);
+    starts.dedup_by_key(|(start, _, _)| *start);
+
+    let mut functions = Vec::new();
+    for (index, (start, section_end, symbol_name)) in starts.iter().enumerate() {
+        let end = match starts.get(index + 1) {
+            Some((next, _, _)) if next < section_end => *next,
+            _ => *section_end,
+        };
+        if end > *start {
+            functions.push(Function {
+                start: *start,
+                size: end - start,
+                name: demangle(symbol_name),
+            });
+        }
+    }
+    Ok(functions)
+}
+
+/// The content of a .rsym section, the functions are sorted by start.
+pub fn build_symbol_table(functions: &[Function]) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    for function in functions {
+        entries.extend_from_slice(&function.start.to_le_bytes());
+        entries.extend_from_slice(&function.size.to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(function.name.as_bytes());
+        names.push(0);
+    }
+    let mut symbol_table = SYMBOL_TABLE_MAGIC.to_vec();
+    symbol_table.extend_from_slice(&(functions.len() as u32).to_le_bytes());
+    symbol_table.extend_from_slice(&entries);
+    symbol_table.extend_from_slice(&names);
+    symbol_table
+}
+
+///
+/// Return the PE image with a read-only data section added after its last
+/// section. The section header must fit in SizeOfHeaders.
+///
+pub fn add_section(
+    name: &str,
+    pe_image: &[u8],
+    section_name: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let pe_header_offset = pe_image
+        .pread::<u32>(0x3c)
+        .map_err(|_| invalid_image(name, "invalid PE header"))? as usize;
+    let read_u32 = |offset: usize| {
+        pe_image
+            .pread::<u32>(pe_header_offset + offset)
+            .map_err(|_| invalid_image(name, "invalid PE header"))
+    };
+    let section_alignment = read_u32(SECTION_ALIGNMENT)? as usize;
+    let file_alignment = read_u32(FILE_ALIGNMENT)? as usize;
+    let size_of_headers = read_u32(SIZE_OF_HEADERS)? as usize;
+    let number_of_sections = pe_image
+        .pread::<u16>(pe_header_offset + NUMBER_OF_SECTIONS)
+        .map_err(|_| invalid_image(name, "invalid PE header"))?
+        as usize;
+    let optional_header_size = pe_image
+        .pread::<u16>(pe_header_offset + SIZE_OF_OPTIONAL_HEADER)
+        .map_err(|_| invalid_image(name, "invalid PE header"))?
+        as usize;
+    if section_alignment == 0 || file_alignment == 0 {
+        return Err(invalid_image(name, "invalid PE header"));
+    }
+
+    let mut sections = pe_loader::pe::sections(pe_image)
+        .ok_or_else(|| invalid_image(name, "invalid PE header"))?;
+    if sections.any(|section| section.name() == section_name) {
+        return Err(invalid_image(
+            name,
+            &format!(
+                "it has a {} section already",
+                String::from_utf8_lossy(section_name)
+            ),
+        ));
+    }
+    let section_header = pe_header_offset
+        + OPTIONAL_HEADER
+        + optional_header_size
+        + number_of_sections * SECTION_HEADER_SIZE;
+    let first_raw_data = pe_loader::pe::sections(pe_image)
+        .unwrap()
+        .filter(|section| section.size_of_raw_data != 0)
+        .map(|section| section.pointer_to_raw_data as usize)
+        .min()
+        .unwrap_or(size_of_headers);
+    if section_header + SECTION_HEADER_SIZE > size_of_headers.min(first_raw_data) {
+        return Err(invalid_image(name, "no room for one more section header"));
+    }
+    let image_end = pe_loader::pe::sections(pe_image)
+        .unwrap()
+        .map(|section| (section.virtual_address + section.virtual_size) as usize)
+        .max()
+        .unwrap_or(size_of_headers);
+
+    let virtual_address = align_up(image_end, section_alignment);
+    let pointer_to_raw_data = align_up(pe_image.len(), file_alignment);
+    let size_of_raw_data = align_up(data.len(), file_alignment);
+    let mut name_field = [0u8; 8];
+    name_field[..section_name.len()].copy_from_slice(section_name);
+    let section = pe_loader::pe::Section {
+        name: name_field,
+        virtual_size: data.len() as u32,
+        virtual_address: virtual_address as u32,
+        size_of_raw_data: size_of_raw_data as u32,
+        pointer_to_raw_data: pointer_to_raw_data as u32,
+        characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+        ..Default::default()
+    };
+
+    let mut new_pe_image = pe_image.to_vec();
+    new_pe_image.resize(pointer_to_raw_data, 0);
+    new_pe_image.extend_from_slice(data);
+    new_pe_image.resize(pointer_to_raw_data + size_of_raw_data, 0);
+    new_pe_image.pwrite(section, section_header).unwrap();
+    new_pe_image
+        .pwrite(
+            (number_of_sections + 1) as u16,
+            pe_header_offset + NUMBER_OF_SECTIONS,
+        )
+        .unwrap();
+    new_pe_image
+        .pwrite(
+            align_up(virtual_address + data.len(), section_alignment) as u32,
+            pe_header_offset + SIZE_OF_IMAGE,
+        )
+        .unwrap();
+    Ok(new_pe_image)
+}
+
+///
+/// Add the .rsym section to the PE image, in place or in output_name.
+///
+pub fn add_symbol_table(pe_image_name: &str, output_name: Option<&str>) -> Result<()> {
+    let pe_image = read_file(pe_image_name)?;
+    let functions = find_functions(pe_image_name, &pe_image)?;
+    let symbol_table = build_symbol_table(&functions);
+    let new_pe_image = add_section(
+        pe_image_name,
+        &pe_image,
+        SYMBOL_TABLE_SECTION,
+        &symbol_table,
+    )?;
+    let output_name = output_name.unwrap_or(pe_image_name);
+    write_file(output_name, &new_pe_image)?;
+    log::info!(
+        "{}: .rsym section of {} functions, 0x{:x} bytes",
+        output_name,
+        functions.len(),
+        symbol_table.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // PE headers with a .text section at 0x1000 and a .data section at
+    // 0x3000, then a COFF symbol table of symbols (name, section, value)
+    fn build_pe(symbols: &[(&str, i16, u32)]) -> Vec<u8> {
+        let mut pe_image = vec![0u8; 0x400];
+        pe_image.pwrite(0x5a4du16, 0).unwrap();
+        pe_image.pwrite(0x40u32, 0x3c).unwrap();
+        pe_image.pwrite(0x4550u32, 0x40).unwrap();
+        pe_image.pwrite(0x8664u16, 0x44).unwrap();
+        pe_image.pwrite(2u16, 0x46).unwrap();
+        pe_image.pwrite(0xf0u16, 0x54).unwrap();
+        pe_image.pwrite(0x20bu16, 0x58).unwrap();
+        pe_image.pwrite(0x1000u32, 0x58 + 32).unwrap();
+        pe_image.pwrite(0x200u32, 0x58 + 36).unwrap();
+        pe_image.pwrite(0x4000u32, 0x58 + 56).unwrap();
+        pe_image.pwrite(0x400u32, 0x58 + 60).unwrap();
+        for (index, (name, rva, characteristics)) in [
+            (
+                b".text\0\0\0",
+                0x1000u32,
+                IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+            ),
+            (b".data\0\0\0", 0x3000, IMAGE_SCN_MEM_READ),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let section = 0x58 + 0xf0 + index * 40;
+            pe_image[section..section + 8].copy_from_slice(*name);
+            pe_image.pwrite(0x1000u32, section + 8).unwrap();
+            pe_image.pwrite(*rva, section + 12).unwrap();
+            pe_image.pwrite(*characteristics, section + 36).unwrap();
+        }
+
+        pe_image.pwrite(0x400u32, 0x40 + 12).unwrap();
+        pe_image.pwrite(symbols.len() as u32, 0x40 + 16).unwrap();
+        let mut strings = vec![0u8; 4];
+        for (name, section_number, value) in symbols {
+            let mut symbol = [0u8; COFF_SYMBOL_SIZE];
+            if name.len() <= 8 {
+                symbol[..name.len()].copy_from_slice(name.as_bytes());
+            } else {
+                symbol.pwrite(strings.len() as u32, 4).unwrap();
+                strings.extend_from_slice(name.as_bytes());
+                strings.push(0);
+            }
+            symbol.pwrite(*value, 8).unwrap();
+            symbol.pwrite(*section_number, 12).unwrap();
+            symbol[16] = IMAGE_SYM_CLASS_EXTERNAL;
+            pe_image.extend_from_slice(&symbol);
+        }
+        let strings_size = strings.len() as u32;
+        strings.pwrite(strings_size, 0).unwrap();
+        pe_image.extend_from_slice(&strings);
+        pe_image
+    }
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN8rust_ipl4main17h0123456789abcdefE"),
+            "rust_ipl::main"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE"
+            ),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN12fw_exception6report5crash28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"
+            ),
+            "fw_exception::report::crash::{{closure}}"
+        );
+        assert_eq!(demangle("_start"), "_start");
+        // truncated
+        assert_eq!(demangle("_ZN8rust_ipl4ma"), "_ZN8rust_ipl4ma");
+    }
+
+    #[test]
+    fn test_find_functions() {
+        let pe_image = build_pe(&[
+            ("_ZN8rust_ipl4main17h0123456789abcdefE", 1, 0x20),
+            ("_start", 1, 0),
+            // an alias of _start
+            ("efi_main", 1, 0),
+            // data, absolute and section names are not functions
+            ("DATA", 2, 0),
+            ("ABS", -1, 0x10),
+            (".text", 1, 0),
+        ]);
+        assert_eq!(
+            find_functions("ipl.efi", &pe_image).unwrap(),
+            [
+                Function {
+                    start: 0x1000,
+                    size: 0x20,
+                    name: "_start".to_string()
+                },
+                Function {
+                    start: 0x1020,
+                    size: 0xfe0,
+                    name: "rust_ipl::main".to_string()
+                },
+            ]
+        );
+        let mut no_symbols = pe_image.clone();
+        no_symbols.pwrite(0u32, 0x40 + 12).unwrap();
+        assert!(find_functions("ipl.efi", &no_symbols).is_err());
+        assert!(find_functions("ipl.efi", &pe_image[..0x410]).is_err());
+    }
+
+    #[test]
+    fn test_add_section() {
+        let pe_image = build_pe(&[("_start", 1, 0)]);
+        let functions = find_functions("ipl.efi", &pe_image).unwrap();
+        let symbol_table = build_symbol_table(&functions);
+        assert_eq!(&symbol_table[..8], b"RSYM\x01\0\0\0");
+        assert_eq!(
+            &symbol_table[8..20],
+            [0, 0x10, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(&symbol_table[20..], b"_start\0");
+
+        let new_pe_image =
+            add_section("ipl.efi", &pe_image, SYMBOL_TABLE_SECTION, &symbol_table).unwrap();
+        let section = pe_loader::pe::find_section(&new_pe_image, SYMBOL_TABLE_SECTION).unwrap();
+        assert_eq!(section.virtual_address, 0x4000);
+        assert_eq!(section.virtual_size as usize, symbol_table.len());
+        let start = section.pointer_to_raw_data as usize;
+        assert_eq!(start % 0x200, 0);
+        assert_eq!(
+            &new_pe_image[start..start + symbol_table.len()],
+            &symbol_table[..]
+        );
+        assert_eq!(pe_loader::pe::size_of_image(&new_pe_image), Some(0x5000));
+        // the symbols are still there
+        assert_eq!(find_functions("ipl.efi", &new_pe_image).unwrap(), functions);
+
+        assert!(add_section(
+            "ipl.efi",
+            &new_pe_image,
+            SYMBOL_TABLE_SECTION,
+            &symbol_table
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Patch the UPD (Updatable Product Data) of the FSP-T/M/S configuration
+//! regions from a TOML or JSON5 file given with `--upd`:
+//!
+//! ```text
+//! [FsptUpd]
+//! microcode_region_base = 0xFFF00000
+//! microcode_region_length = 0x10000
+//!
+//! [FspmConfig]
+//! serial_debug_port_type = 1
+//! serial_debug_port_address = 0x3F8
+//!
+//! [FspSConfig]
+//! pci_temp_resource_base = 0x80000000
+//! ```
+//!
+//! FspMemoryInit and FspSiliconInit are given the UPD of the FSP-M and
+//! FSP-S configuration regions. TempRamInit is given the FsptUpd of the
+//! reset vector params, so FsptUpd fields are patched there too.
+//!
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::path::Path;
+
+use r_uefi_pi::fv::{FfsFileHeader, FFS_ATTRIB_CHECKSUM};
+use scroll::ctx::{TryFromCtx, TryIntoCtx};
+use scroll::{Endian, Pread, Pwrite};
+use serde::Deserialize;
+use uefi_pi::fv_lib;
+
+use rust_firmware_layout::fsp_build_time::*;
+use rust_firmware_platform::{
+    FspmUpd, FspsUpd, FsptUpd, FSPM_UPD_SIGNATURE, FSPS_UPD_SIGNATURE, FSPT_UPD_SIGNATURE,
+};
+use rust_fsp_wrapper::fsp_info_header::{FspInfoHeader, FSP_INFO_HEADER_OFF};
+use rust_fsp_wrapper::fsp_upd_header::FspUpdHeader;
+
+use super::error::{read_file, Result, ToolError};
+use super::update_ffs_checksum;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdConfig {
+    #[serde(rename = "FsptUpd", default)]
+    fspt_upd: BTreeMap<String, u64>,
+    #[serde(rename = "FspmConfig", default)]
+    fspm_config: BTreeMap<String, u64>,
+    #[serde(rename = "FspSConfig", default)]
+    fsps_config: BTreeMap<String, u64>,
+}
+
+/// (FSP name, UPD signature) of the FSP-T/M/S components.
+pub const FSP_UPD_SIGNATURES: [(&str, u64); 3] = [
+    ("FSP-T", FSPT_UPD_SIGNATURE),
+    ("FSP-M", FSPM_UPD_SIGNATURE),
+    ("FSP-S", FSPS_UPD_SIGNATURE),
+];
+
+fn field_value<T: TryFrom<u64>>(section: &str, name: &str, value: u64) -> Result<T> {
+    T::try_from(value).map_err(|_| {
+        ToolError::InvalidData(format!(
+            "{}.{}: 0x{:x} does not fit the field",
+            section, name, value
+        ))
+    })
+}
+
+fn unknown_field(section: &str, name: &str) -> ToolError {
+    ToolError::InvalidData(format!("{}.{} is not a known UPD field", section, name))
+}
+
+pub fn upd_signature_string(signature: u64) -> String {
+    String::from_utf8_lossy(&signature.to_le_bytes()).into_owned()
+}
+
+///
+/// Return the range of the UPD in fsp_data, given by the cfg region of the
+/// FspInfoHeader. The UPD signature must match the expected one.
+///
+pub fn get_upd_range(name: &str, fsp_data: &[u8], signature: u64) -> Result<Range<usize>> {
+    let fsp_info_header = fsp_data
+        .pread::<FspInfoHeader>(FSP_INFO_HEADER_OFF)
+        .ok()
+        .filter(|fsp_info_header| &fsp_info_header.signature.to_le_bytes() == b"FSPH")
+        .ok_or_else(|| ToolError::InvalidData(format!("{} has no FspInfoHeader", name)))?;
+    let start = fsp_info_header.cfg_region_offset as usize;
+    let end = start + fsp_info_header.cfg_region_size as usize;
+    if end > fsp_data.len() {
+        return Err(ToolError::InvalidData(format!(
+            "{} cfg region 0x{:x}..0x{:x} is out of the FSP of 0x{:x} bytes",
+            name,
+            start,
+            end,
+            fsp_data.len()
+        )));
+    }
+    let upd_header: FspUpdHeader = fsp_data[start..end].pread(0).map_err(|_| {
+        ToolError::InvalidData(format!("{} cfg region is too small for a UPD", name))
+    })?;
+    if upd_header.signature != signature {
+        return Err(ToolError::InvalidData(format!(
+            "{} UPD signature is {}, {} is expected",
+            name,
+            upd_signature_string(upd_header.signature),
+            upd_signature_string(signature)
+        )));
+    }
+    Ok(start..end)
+}
+
+// The FSP files are built without FFS_ATTRIB_CHECKSUM, their checksum does
+// not change. Update it anyway if the file holding the UPD has one.
+fn update_upd_ffs_checksum(fsp_data: &mut [u8], upd_range: &Range<usize>) {
+    let ffs_range = fv_lib::get_file_ranges_from_fv(fsp_data)
+        .find(|range| range.start <= upd_range.start && upd_range.end <= range.end);
+    if let Some(ffs_range) = ffs_range {
+        let ffs_header: FfsFileHeader = fsp_data.pread(ffs_range.start).unwrap();
+        if ffs_header.attributes & FFS_ATTRIB_CHECKSUM != 0 {
+            update_ffs_checksum(&mut fsp_data[ffs_range]);
+        }
+    }
+}
+
+fn patch_fsp_upd<T, F>(name: &str, fsp_data: &mut [u8], signature: u64, patch: F) -> Result<()>
+where
+    T: for<'a> TryFromCtx<'a, Endian, Error = scroll::Error>
+        + TryIntoCtx<Endian, Error = scroll::Error>,
+    F: FnOnce(&mut T) -> Result<()>,
+{
+    let upd_range = get_upd_range(name, fsp_data, signature)?;
+    let upd_data = &mut fsp_data[upd_range.clone()];
+    let mut upd: T = upd_data.pread(0).map_err(|_| {
+        ToolError::InvalidData(format!("{} cfg region is too small for the UPD", name))
+    })?;
+    patch(&mut upd)?;
+    upd_data.pwrite(upd, 0).unwrap();
+    update_upd_ffs_checksum(fsp_data, &upd_range);
+    Ok(())
+}
+
+impl UpdConfig {
+    ///
+    /// Load the UPD config, upd_name is parsed as TOML if it ends with
+    /// .toml, as JSON5 otherwise.
+    ///
+    pub fn load(upd_name: &str) -> Result<Self> {
+        let upd_config = read_file(upd_name)?;
+        let upd_config = String::from_utf8(upd_config)
+            .map_err(|e| ToolError::InvalidData(format!("{}: {}", upd_name, e)))?;
+        let parse_error =
+            |e: &dyn std::fmt::Display| ToolError::InvalidData(format!("{}: {}", upd_name, e));
+        if Path::new(upd_name)
+            .extension()
+            .map_or(false, |ext| ext == "toml")
+        {
+            toml::from_str(&upd_config).map_err(|e| parse_error(&e))
+        } else {
+            json5::from_str(&upd_config).map_err(|e| parse_error(&e))
+        }
+    }
+
+    pub fn patch_fspt_upd(&self, fspt_upd: &mut FsptUpd) -> Result<()> {
+        let common = &mut fspt_upd.fspt_common_upd;
+        for (name, value) in self.fspt_upd.iter() {
+            let value = *value;
+            match name.as_str() {
+                "microcode_region_base" => {
+                    common.microcode_region_base = field_value("FsptUpd", name, value)?
+                }
+                "microcode_region_length" => {
+                    common.microcode_region_length = field_value("FsptUpd", name, value)?
+                }
+                "code_region_base" => {
+                    common.code_region_base = field_value("FsptUpd", name, value)?
+                }
+                "code_region_length" => {
+                    common.code_region_length = field_value("FsptUpd", name, value)?
+                }
+                _ => return Err(unknown_field("FsptUpd", name)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn patch_fspm_upd(&self, fspm_upd: &mut FspmUpd) -> Result<()> {
+        let config = &mut fspm_upd.fspm_config;
+        for (name, value) in self.fspm_config.iter() {
+            let value = *value;
+            match name.as_str() {
+                "serial_debug_port_address" => {
+                    config.serial_debug_port_address = field_value("FspmConfig", name, value)?
+                }
+                "serial_debug_port_type" => {
+                    config.serial_debug_port_type = field_value("FspmConfig", name, value)?
+                }
+                "serial_debug_port_device" => {
+                    config.serial_debug_port_device = field_value("FspmConfig", name, value)?
+                }
+                "serial_debug_port_stride_size" => {
+                    config.serial_debug_port_stride_size = field_value("FspmConfig", name, value)?
+                }
+                _ => return Err(unknown_field("FspmConfig", name)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn patch_fsps_upd(&self, fsps_upd: &mut FspsUpd) -> Result<()> {
+        let config = &mut fsps_upd.fsps_config;
+        for (name, value) in self.fsps_config.iter() {
+            let value = *value;
+            match name.as_str() {
+                "logo_size" => config.logo_size = field_value("FspSConfig", name, value)?,
+                "logo_ptr" => config.logo_ptr = field_value("FspSConfig", name, value)?,
+                "graphics_config_ptr" => {
+                    config.graphics_config_ptr = field_value("FspSConfig", name, value)?
+                }
+                "pci_temp_resource_base" => {
+                    config.pci_temp_resource_base = field_value("FspSConfig", name, value)?
+                }
+                _ => return Err(unknown_field("FspSConfig", name)),
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Patch the UPD of the FSP-T/M/S regions of the image, a FSP is left
+    /// untouched if the config has no field for it.
+    ///
+    pub fn patch_image(&self, rust_firmware_image: &mut [u8]) -> Result<()> {
+        let fsp_region = |offset: u32, size: u32| offset as usize..(offset + size) as usize;
+        if !self.fspt_upd.is_empty() {
+            let fsp_t = fsp_region(FIRMWARE_FSP_T_OFFSET, FIRMWARE_FSP_T_SIZE);
+            patch_fsp_upd(
+                "FSP-T",
+                &mut rust_firmware_image[fsp_t],
+                FSPT_UPD_SIGNATURE,
+                |upd| self.patch_fspt_upd(upd),
+            )?;
+        }
+        if !self.fspm_config.is_empty() {
+            let fsp_m = fsp_region(FIRMWARE_FSP_M_OFFSET, FIRMWARE_FSP_M_SIZE);
+            patch_fsp_upd(
+                "FSP-M",
+                &mut rust_firmware_image[fsp_m],
+                FSPM_UPD_SIGNATURE,
+                |upd| self.patch_fspm_upd(upd),
+            )?;
+        }
+        if !self.fsps_config.is_empty() {
+            let fsp_s = fsp_region(FIRMWARE_FSP_S_OFFSET, FIRMWARE_FSP_S_SIZE);
+            patch_fsp_upd(
+                "FSP-S",
+                &mut rust_firmware_image[fsp_s],
+                FSPS_UPD_SIGNATURE,
+                |upd| self.patch_fsps_upd(upd),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a FSP image with the FspInfoHeader and a UPD in a raw FFS file
+    fn build_fsp(signature: u64) -> Vec<u8> {
+        let mut fsp_data = vec![0u8; 0x400];
+        let fsp_info_header = FspInfoHeader {
+            signature: u32::from_le_bytes(*b"FSPH"),
+            cfg_region_offset: 0x200,
+            cfg_region_size: 0x80,
+            ..Default::default()
+        };
+        fsp_data
+            .pwrite(fsp_info_header, FSP_INFO_HEADER_OFF)
+            .unwrap();
+        fsp_data.pwrite(signature, 0x200).unwrap();
+        fsp_data
+    }
+
+    #[test]
+    fn test_patch_fspm_upd() {
+        let upd_config: UpdConfig = toml::from_str(
+            "[FspmConfig]\nserial_debug_port_address = 0x3F8\nserial_debug_port_type = 1\n",
+        )
+        .unwrap();
+        let mut fsp_data = build_fsp(FSPM_UPD_SIGNATURE);
+        patch_fsp_upd("FSP-M", &mut fsp_data, FSPM_UPD_SIGNATURE, |upd| {
+            upd_config.patch_fspm_upd(upd)
+        })
+        .unwrap();
+        let fspm_upd: FspmUpd = fsp_data.pread(0x200).unwrap();
+        assert_eq!(fspm_upd.fspm_config.serial_debug_port_address, 0x3f8);
+        assert_eq!(fspm_upd.fspm_config.serial_debug_port_type, 1);
+        // Offset 0x0040 - Debug Serial Port Base address
+        assert_eq!(fsp_data.pread::<u32>(0x240).unwrap(), 0x3f8);
+
+        let mut fsp_data = build_fsp(FSPS_UPD_SIGNATURE);
+        assert!(
+            patch_fsp_upd("FSP-M", &mut fsp_data, FSPM_UPD_SIGNATURE, |upd| {
+                upd_config.patch_fspm_upd(upd)
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_upd_config() {
+        let upd_config: UpdConfig = json5::from_str(
+            "{ FsptUpd: { microcode_region_base: 0xFFF00000, code_region_length: 0x100000 } }",
+        )
+        .unwrap();
+        let mut fspt_upd = FsptUpd::default();
+        upd_config.patch_fspt_upd(&mut fspt_upd).unwrap();
+        assert_eq!(fspt_upd.fspt_common_upd.microcode_region_base, 0xfff00000);
+        assert_eq!(fspt_upd.fspt_common_upd.code_region_length, 0x100000);
+
+        let upd_config: UpdConfig =
+            toml::from_str("[FspmConfig]\nserial_debug_port_type = 0x100\n").unwrap();
+        assert!(upd_config.patch_fspm_upd(&mut FspmUpd::default()).is_err());
+        let upd_config: UpdConfig = toml::from_str("[FspSConfig]\nlogo = 0\n").unwrap();
+        assert!(upd_config
+            .patch_fsps_upd(&mut build_fsp(0).pread(0x200).unwrap())
+            .is_err());
+        assert!(toml::from_str::<UpdConfig>("[FspmUpd]\nboot_mode = 0\n").is_err());
+    }
+}
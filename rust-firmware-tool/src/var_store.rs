@@ -0,0 +1,323 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! The VAR region, an EDK2 variable store optionally pre-populated from a
+//! JSON file given with `--var-store`:
+//!
+//! ```text
+//! {
+//!     "variables": [
+//!         { "name": "BootOrder", "u16": [0, 1] },
+//!         { "name": "PlatformLang", "ascii": "en-US" },
+//!         { "name": "Config", "guid": "8C8CE578-8A3D-4F1C-9935-896185C32DD3",
+//!           "attributes": ["NV", "BS"], "file": "config.bin" }
+//!     ]
+//! }
+//! ```
+//!
+//! The vendor GUID defaults to EFI_GLOBAL_VARIABLE and the attributes to
+//! NV, BS and RT. The data is one of hex, u16, u32, ascii or ucs2 (both
+//! null-terminated), or file, relative to the JSON file.
+//!
+
+use std::path::Path;
+
+use r_uefi_pi::variable::{
+    EFI_GLOBAL_VARIABLE_GUID, EFI_VARIABLE_BOOTSERVICE_ACCESS, EFI_VARIABLE_HARDWARE_ERROR_RECORD,
+    EFI_VARIABLE_NON_VOLATILE, EFI_VARIABLE_RUNTIME_ACCESS,
+};
+use serde::Deserialize;
+use uefi_pi::variable_lib::{self, VariableError};
+
+use rust_firmware_layout::build_time::FIRMWARE_VAR_SIZE;
+use rust_firmware_layout::nv_storage::{NV_FTW_WORKING_SIZE, NV_VARIABLE_STORE_SIZE};
+
+use super::error::{read_file, Result, ToolError};
+use super::ffs::parse_guid;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VarStoreConfig {
+    variables: Vec<VariableConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariableConfig {
+    name: String,
+    guid: Option<String>,
+    attributes: Option<Vec<String>>,
+    hex: Option<String>,
+    u16: Option<Vec<u16>>,
+    u32: Option<Vec<u32>>,
+    ascii: Option<String>,
+    ucs2: Option<String>,
+    file: Option<String>,
+}
+
+const ATTRIBUTE_NAMES: [(&str, u32); 4] = [
+    ("NV", EFI_VARIABLE_NON_VOLATILE),
+    ("BS", EFI_VARIABLE_BOOTSERVICE_ACCESS),
+    ("RT", EFI_VARIABLE_RUNTIME_ACCESS),
+    ("HR", EFI_VARIABLE_HARDWARE_ERROR_RECORD),
+];
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+        .collect()
+}
+
+impl VariableConfig {
+    fn vendor_guid(&self) -> Result<[u8; 16]> {
+        match &self.guid {
+            Some(guid) => parse_guid(guid).ok_or_else(|| {
+                ToolError::InvalidData(format!("variable {}: invalid GUID {}", self.name, guid))
+            }),
+            None => Ok(*EFI_GLOBAL_VARIABLE_GUID.as_bytes()),
+        }
+    }
+
+    fn attributes(&self) -> Result<u32> {
+        let names = match &self.attributes {
+            Some(names) => names,
+            None => {
+                return Ok(EFI_VARIABLE_NON_VOLATILE
+                    | EFI_VARIABLE_BOOTSERVICE_ACCESS
+                    | EFI_VARIABLE_RUNTIME_ACCESS)
+            }
+        };
+        let mut attributes = 0;
+        for name in names {
+            attributes |= ATTRIBUTE_NAMES
+                .iter()
+                .find(|(attribute_name, _)| attribute_name == name)
+                .map(|(_, attribute)| *attribute)
+                .ok_or_else(|| {
+                    ToolError::InvalidData(format!(
+                        "variable {}: unknown attribute {}, use NV, BS, RT or HR",
+                        self.name, name
+                    ))
+                })?;
+        }
+        // a runtime variable must be accessible at boot time too
+        if attributes & EFI_VARIABLE_RUNTIME_ACCESS != 0
+            && attributes & EFI_VARIABLE_BOOTSERVICE_ACCESS == 0
+        {
+            return Err(ToolError::InvalidData(format!(
+                "variable {}: RT requires BS",
+                self.name
+            )));
+        }
+        Ok(attributes)
+    }
+
+    fn data(&self, base_dir: &Path) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if let Some(hex) = &self.hex {
+            data.push(parse_hex(hex).ok_or_else(|| {
+                ToolError::InvalidData(format!("variable {}: invalid hex {}", self.name, hex))
+            })?);
+        }
+        if let Some(values) = &self.u16 {
+            data.push(
+                values
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes().to_vec())
+                    .collect(),
+            );
+        }
+        if let Some(values) = &self.u32 {
+            data.push(
+                values
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes().to_vec())
+                    .collect(),
+            );
+        }
+        if let Some(ascii) = &self.ascii {
+            if !ascii.is_ascii() {
+                return Err(ToolError::InvalidData(format!(
+                    "variable {}: {} is not ascii",
+                    self.name, ascii
+                )));
+            }
+            let mut ascii = ascii.as_bytes().to_vec();
+            ascii.push(0);
+            data.push(ascii);
+        }
+        if let Some(ucs2) = &self.ucs2 {
+            data.push(
+                ucs2.encode_utf16()
+                    .chain(core::iter::once(0))
+                    .flat_map(|c| c.to_le_bytes().to_vec())
+                    .collect(),
+            );
+        }
+        if let Some(file) = &self.file {
+            data.push(read_file(base_dir.join(file))?);
+        }
+        if data.len() != 1 {
+            return Err(ToolError::InvalidData(format!(
+                "variable {}: the data must be one of hex, u16, u32, ascii, ucs2 or file",
+                self.name
+            )));
+        }
+        Ok(data.pop().unwrap())
+    }
+}
+
+fn variable_error(name: &str, e: VariableError) -> ToolError {
+    ToolError::InvalidData(match e {
+        VariableError::InvalidParameter => format!("variable {} is defined twice", name),
+        VariableError::OutOfResources => format!(
+            "no space for variable {} in the variable store of 0x{:x} bytes",
+            name, NV_VARIABLE_STORE_SIZE
+        ),
+        VariableError::InvalidStore => "invalid NV storage layout".to_string(),
+    })
+}
+
+///
+/// Build the VAR region, the variables of the JSON file var_store_name are
+/// added to the store.
+///
+pub fn build_var_region(var_store_name: Option<&str>) -> Result<Vec<u8>> {
+    let mut var_region = vec![0xffu8; FIRMWARE_VAR_SIZE as usize];
+    variable_lib::format_nv_storage(
+        &mut var_region,
+        NV_VARIABLE_STORE_SIZE as usize,
+        NV_FTW_WORKING_SIZE as usize,
+    )
+    .map_err(|_| {
+        ToolError::Layout(format!(
+            "VAR size 0x{:x} is too small for the NV storage",
+            FIRMWARE_VAR_SIZE
+        ))
+    })?;
+
+    let var_store_name = match var_store_name {
+        Some(var_store_name) => var_store_name,
+        None => return Ok(var_region),
+    };
+    let config: VarStoreConfig = serde_json::from_slice(&read_file(var_store_name)?)
+        .map_err(|e| ToolError::InvalidData(format!("{}: {}", var_store_name, e)))?;
+    let base_dir = Path::new(var_store_name)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    for variable in config.variables.iter() {
+        if variable.name.is_empty() {
+            return Err(ToolError::InvalidData(format!(
+                "{}: variable name is empty",
+                var_store_name
+            )));
+        }
+        let vendor_guid = variable.vendor_guid()?;
+        let attributes = variable.attributes()?;
+        let data = variable.data(base_dir)?;
+        variable_lib::add_variable(
+            &mut var_region,
+            &variable.name,
+            &vendor_guid,
+            attributes,
+            &data,
+        )
+        .map_err(|e| variable_error(&variable.name, e))?;
+        log::info!(
+            "add variable {} attributes 0x{:x} size 0x{:x}",
+            variable.name,
+            attributes,
+            data.len()
+        );
+    }
+    Ok(var_region)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_firmware_layout::nv_storage::NV_FTW_WORKING_OFFSET;
+    use uefi_pi::variable_lib::VariableStore;
+
+    #[test]
+    fn test_build_var_region() {
+        let var_store_name = std::env::temp_dir().join("rust-firmware-tool-var-store.json");
+        std::fs::write(
+            &var_store_name,
+            r#"{
+                "variables": [
+                    { "name": "BootOrder", "u16": [1, 0] },
+                    { "name": "PlatformLang", "ascii": "en-US" },
+                    { "name": "Config", "guid": "8C8CE578-8A3D-4F1C-9935-896185C32DD3",
+                      "attributes": ["NV", "BS"], "hex": "01 02 03" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let var_region = build_var_region(var_store_name.to_str()).unwrap();
+        std::fs::remove_file(&var_store_name).unwrap();
+
+        let store = VariableStore::parse(&var_region).unwrap();
+        let global = EFI_GLOBAL_VARIABLE_GUID.as_bytes();
+        assert_eq!(store.iter().count(), 3);
+        let boot_order = store.find_variable("BootOrder", global).unwrap();
+        assert_eq!(boot_order.data, &[1, 0, 0, 0]);
+        assert_eq!(boot_order.attributes, 0x7);
+        assert_eq!(
+            store.find_variable("PlatformLang", global).unwrap().data,
+            b"en-US\0"
+        );
+        let config = store
+            .find_variable(
+                "Config",
+                &parse_guid("8C8CE578-8A3D-4F1C-9935-896185C32DD3").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(config.data, &[1, 2, 3]);
+        assert_eq!(config.attributes, 0x3);
+        assert!(variable_lib::verify_ftw_working_block(
+            &var_region[NV_FTW_WORKING_OFFSET as usize..]
+        ));
+
+        let empty = build_var_region(None).unwrap();
+        assert_eq!(VariableStore::parse(&empty).unwrap().iter().count(), 0);
+    }
+
+    #[test]
+    fn test_variable_data() {
+        let variable = |json: &str| serde_json::from_str::<VariableConfig>(json).unwrap();
+        let base_dir = Path::new("");
+        assert_eq!(
+            variable(r#"{ "name": "A", "ucs2": "ab" }"#)
+                .data(base_dir)
+                .unwrap(),
+            [b'a', 0, b'b', 0, 0, 0]
+        );
+        assert_eq!(
+            variable(r#"{ "name": "A", "u32": [1] }"#)
+                .data(base_dir)
+                .unwrap(),
+            [1, 0, 0, 0]
+        );
+        assert!(variable(r#"{ "name": "A" }"#).data(base_dir).is_err());
+        assert!(variable(r#"{ "name": "A", "hex": "0", "u16": [1] }"#)
+            .data(base_dir)
+            .is_err());
+        assert!(variable(r#"{ "name": "A", "hex": "0g" }"#)
+            .data(base_dir)
+            .is_err());
+        assert!(variable(r#"{ "name": "A", "attributes": ["RT"] }"#)
+            .attributes()
+            .is_err());
+        assert!(variable(r#"{ "name": "A", "attributes": ["XX"] }"#)
+            .attributes()
+            .is_err());
+        assert!(serde_json::from_str::<VariableConfig>(r#"{ "name": "A", "data": "" }"#).is_err());
+    }
+}
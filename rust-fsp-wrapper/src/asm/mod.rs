@@ -15,6 +15,7 @@ extern "win64" {
         gdtr: usize,
     ) -> usize;
     fn lidt_call(idtr: usize);
+    fn sidt_call(idtr: usize);
     fn sgdt_call(gdtr: usize);
 }
 
@@ -26,6 +27,10 @@ pub fn execute_32bit_code(entry_point: usize, param1: usize, param2: usize) -> u
         param2
     );
     unsafe {
+        // Keep the IDT of the caller, the FSP replaces it with its own
+        let idtr = dtables::DescriptorTablePointer::<usize>::default();
+        sidt_call(&idtr as *const dtables::DescriptorTablePointer<usize> as usize);
+
         // Let FSP to setup the IDT
         let idtr_null = x86::dtables::DescriptorTablePointer {
             base: core::ptr::null::<usize>(),
@@ -37,11 +42,14 @@ pub fn execute_32bit_code(entry_point: usize, param1: usize, param2: usize) -> u
         let gdtr = dtables::DescriptorTablePointer::<usize>::default();
         sgdt_call(&gdtr as *const dtables::DescriptorTablePointer<usize> as usize);
 
-        AsmExecute32BitCode(
+        let status = AsmExecute32BitCode(
             entry_point,
             param1,
             param2,
             &gdtr as *const dtables::DescriptorTablePointer<usize> as usize,
-        )
+        );
+
+        lidt_call(&idtr as *const dtables::DescriptorTablePointer<usize> as usize);
+        status
     }
 }
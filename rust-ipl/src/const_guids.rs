@@ -29,3 +29,15 @@ pub const MEMORY_ALLOCATION_STACK_GUID: Guid = Guid::from_fields(
     0x7D,
     &[0x52, 0x7B, 0x1D, 0x00, 0xC9, 0xBD],
 );
+
+// The name of a memory allocation of no particular purpose
+pub const ZERO_GUID: Guid = Guid::from_fields(0, 0, 0, 0, 0, &[0, 0, 0, 0, 0, 0]);
+
+pub const PAGING_MODE_GUID: Guid = Guid::from_fields(
+    0xB940F79E,
+    0xB559,
+    0x4C07,
+    0x99,
+    0x9C,
+    &[0x86, 0x25, 0x36, 0xC0, 0xD8, 0x0E],
+);
@@ -41,6 +41,20 @@ pub struct HobTemplate {
     pub end_off_hob: hob::GenericHeader,
 }
 
+/// The paging mode the payload is entered with.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pread, Pwrite)]
+pub struct PagingModeHob {
+    pub header: hob::GuidExtension,
+    /// 4, or 5 with CR4.LA57 set.
+    pub paging_levels: u32,
+    /// PAGING_MODE_1G_PAGE and PAGING_MODE_EXECUTE_DISABLE.
+    pub attributes: u32,
+}
+
+pub const PAGING_MODE_1G_PAGE: u32 = 0x1;
+pub const PAGING_MODE_EXECUTE_DISABLE: u32 = 0x2;
+
 #[cfg(target_os = "uefi")]
 use core::panic::PanicInfo;
 
@@ -49,6 +63,7 @@ use core::panic::PanicInfo;
 #[allow(clippy::empty_loop)]
 fn panic(info: &PanicInfo) -> ! {
     log::info!("panic ... {:?}\n", info);
+    fw_exception::print_backtrace();
     loop {}
 }
 
@@ -60,6 +75,50 @@ fn alloc_error(_info: core::alloc::Layout) -> ! {
     loop {}
 }
 
+///
+/// Halt after a crash report so it can be read, unless the build asks to
+/// reset the platform.
+///
+fn exception_policy() -> fw_exception::ExceptionPolicy {
+    if cfg!(feature = "exception_reset") {
+        fw_exception::ExceptionPolicy::Reset
+    } else {
+        fw_exception::ExceptionPolicy::Halt
+    }
+}
+
+#[cfg(target_os = "uefi")]
+extern "C" {
+    // The image base, defined by the linker
+    static __ImageBase: u8;
+}
+
+///
+/// The IPL image for the backtraces, with the symbol table of its .rsym
+/// section if it has one.
+///
+#[cfg(target_os = "uefi")]
+fn ipl_module() -> Option<fw_exception::Module<'static>> {
+    let base = unsafe { &__ImageBase as *const u8 as usize };
+    let headers = unsafe { core::slice::from_raw_parts(base as *const u8, SIZE_4K as usize) };
+    let size = pe_loader::pe::size_of_image(headers)?;
+    let image = unsafe { core::slice::from_raw_parts(base as *const u8, size) };
+    let symbols =
+        pe_loader::pe::find_loaded_section(image, fw_exception::backtrace::SYMBOL_TABLE_SECTION)
+            .and_then(fw_exception::SymbolTable::new);
+    Some(fw_exception::Module {
+        name: "rust-ipl",
+        base,
+        size,
+        symbols,
+    })
+}
+
+#[cfg(not(target_os = "uefi"))]
+fn ipl_module() -> Option<fw_exception::Module<'static>> {
+    None
+}
+
 #[no_mangle]
 #[export_name = "efi_main"]
 pub extern "win64" fn _start(
@@ -85,18 +144,29 @@ pub extern "win64" fn _start(
         initial_eax_value,
     );
 
-    // fw_exception::setup_exception_handlers();
-    // log::info!("setup_exception_handlers done\n");
+    // The IDT lives on the temporary stack until the switch to the runtime
+    // stack, where continue_function installs its own one.
+    let mut idt = fw_exception::Idt::new();
+    idt.set_policy(exception_policy());
+    idt.set_backtrace(temp_ram_base..temp_ram_top, ipl_module());
+    unsafe { idt.load() };
+    log::info!("setup_exception_handlers done\n");
+
+    #[cfg(feature = "debugger")]
+    let mut debugger = fw_debugger::Debugger::new(fw_debugger::COM2_PORT);
+    #[cfg(feature = "debugger")]
+    {
+        unsafe { debugger.install(&mut idt) };
+        log::info!("Waiting for GDB on COM2\n");
+        fw_debugger::breakpoint();
+    }
 
     dump_fsp_t_info();
 
     let hob_list = call_fsp_memory_init().expect("memory init failed");
 
-    // top of low usable memory
-    let memory_tolum = hob_lib::get_system_memory_size_below_4gb(hob_list);
-    log::trace!("memory lotum 0 - {:#X}\n", memory_tolum);
-
-    let runtime_memory_layout = RuntimeMemoryLayout::new(memory_tolum);
+    let runtime_memory_layout = get_runtime_memory_layout(hob_list);
+    log::trace!("{:?}\n", runtime_memory_layout);
 
     // switch_stack
     log::info!(
@@ -116,21 +186,48 @@ pub extern "win64" fn _start(
 pub extern "win64" fn continue_function(hob_address: usize, _tmp_stack_top: usize) -> ! {
     log::info!("Continue function - Hob address - {:#X}\n", hob_address);
 
+    // The temporary RAM goes away with the IDT of _start
+    let mut idt = fw_exception::Idt::new();
+    idt.set_policy(exception_policy());
+    unsafe { idt.load() };
+
+    // GDB carries on with the debugger on the runtime stack
+    #[cfg(feature = "debugger")]
+    let mut debugger = fw_debugger::Debugger::new(fw_debugger::COM2_PORT);
+    #[cfg(feature = "debugger")]
+    {
+        debugger.resume_session();
+        unsafe { debugger.install(&mut idt) };
+        fw_debugger::breakpoint();
+    }
+
     let fsp_hob_list = memslice::get_dynamic_mem_slice_mut(
         memslice::SliceType::RuntimePayloadHobSlice,
         hob_address,
     );
-    let memory_tolum = hob_lib::get_system_memory_size_below_4gb(fsp_hob_list);
-    log::trace!("memory lotum 1: {:#X}\n", memory_tolum);
-    let runtime_memory_layout = RuntimeMemoryLayout::new(memory_tolum);
+    let runtime_memory_layout = get_runtime_memory_layout(fsp_hob_list);
+    idt.set_backtrace(
+        runtime_memory_layout.runtime_stack_base as usize
+            ..runtime_memory_layout.runtime_stack_top as usize,
+        ipl_module(),
+    );
 
     // Set host Paging
-    let memory_size = 0x1000000000; // TODO: hardcoding to 64GiB for now
-    paging::setup_paging(
+    let memory_map = get_memory_map(&runtime_memory_layout, fsp_hob_list);
+    let paging_features = match paging::setup_paging(
         runtime_memory_layout.runtime_page_table_base as u64,
         RUNTIME_PAGE_TABLE_SIZE as u64,
-        memory_size,
-    );
+        &memory_map,
+        cfg!(feature = "la57"),
+    ) {
+        Ok(paging_features) => paging_features,
+        Err(e) => {
+            log::info!("Setup paging failed - {:?}, halt\n", e);
+            loop {
+                unsafe { x86::halt() };
+            }
+        }
+    };
     log::info!(
         "Migrate pagetable @ {:#X}\n",
         runtime_memory_layout.runtime_page_table_base
@@ -142,11 +239,86 @@ pub extern "win64" fn continue_function(hob_address: usize, _tmp_stack_top: usiz
     let memory_tolum = hob_lib::get_system_memory_size_below_4gb(fsp_hob_list);
     log::trace!("memory lotum 2: {:#X}\n", memory_tolum);
 
-    transfer_to_payload(&runtime_memory_layout, fsp_hob_list);
+    transfer_to_payload(&runtime_memory_layout, paging_features, fsp_hob_list);
 
     unreachable!();
 }
 
+///
+/// Place the runtime regions in the memory of the FSP HOB list, halt if
+/// the memory is not enough.
+///
+fn get_runtime_memory_layout(hob_list: &[u8]) -> RuntimeMemoryLayout {
+    match RuntimeMemoryLayout::new_from_hob_list(hob_list, RUNTIME_HEAP_ABOVE_4G) {
+        Ok(runtime_memory_layout) => runtime_memory_layout,
+        Err(e) => {
+            log::info!("Runtime memory layout failed - {}, halt\n", e);
+            loop {
+                unsafe { x86::halt() };
+            }
+        }
+    }
+}
+
+///
+/// The ranges to map: the resources of the FSP HOB list, the flash the IPL
+/// and the FSPs run from, the MMIO and PCI windows between the low memory and
+/// the flash, and the data regions of the runtime layout. The data regions
+/// are NX, migrate_hobs() reports them allocated to the payload.
+///
+fn get_memory_map(
+    runtime_memory_layout: &RuntimeMemoryLayout,
+    hob_list: &[u8],
+) -> paging::MemoryMap {
+    let mut memory_map = paging::MemoryMap::from_hob_list(hob_list);
+    let low_memory_top = memory_map
+        .ranges()
+        .iter()
+        .filter(|range| range.end <= LOADED_RESERVED1_BASE as u64)
+        .map(|range| range.end)
+        .max()
+        .unwrap_or(0);
+
+    let ranges = [
+        (
+            low_memory_top,
+            LOADED_RESERVED1_BASE as u64 - low_memory_top,
+            paging::MemoryAttribute::Mmio,
+        ),
+        (
+            LOADED_RESERVED1_BASE as u64,
+            FIRMWARE_SIZE as u64,
+            paging::MemoryAttribute::Code,
+        ),
+        (
+            runtime_memory_layout.runtime_hob_base,
+            RUNTIME_HOB_SIZE as u64,
+            paging::MemoryAttribute::Data,
+        ),
+        (
+            runtime_memory_layout.runtime_page_table_base,
+            RUNTIME_PAGE_TABLE_SIZE as u64,
+            paging::MemoryAttribute::Data,
+        ),
+        (
+            runtime_memory_layout.runtime_stack_base,
+            RUNTIME_STACK_SIZE as u64,
+            paging::MemoryAttribute::Data,
+        ),
+        (
+            runtime_memory_layout.runtime_heap_base,
+            RUNTIME_HEAP_SIZE as u64,
+            paging::MemoryAttribute::Data,
+        ),
+    ];
+    for (base, size, attribute) in ranges.iter() {
+        if !memory_map.add(*base, *size, *attribute) {
+            log::info!("Memory map is full, skip {:#X}+{:#X}\n", base, size);
+        }
+    }
+    memory_map
+}
+
 fn dump_fsp_t_info() {
     let fsp_t_fv_buffer = memslice::get_mem_slice(memslice::SliceType::FirmwareFspTSlice);
     let fsp_t_info_header = fsp_t_fv_buffer
@@ -155,7 +327,11 @@ fn dump_fsp_t_info() {
     log::trace!("Fsp-T: {:?}\n", fsp_t_info_header);
 }
 
-fn transfer_to_payload(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hob_list: &mut [u8]) {
+fn transfer_to_payload(
+    runtime_memory_layout: &RuntimeMemoryLayout,
+    paging_features: paging::PagingFeatures,
+    fsp_hob_list: &mut [u8],
+) {
     hob_lib::dump_hob(fsp_hob_list);
 
     let loaded_buffer = memslice::get_dynamic_mem_slice_mut(
@@ -168,6 +344,8 @@ fn transfer_to_payload(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hob_list
         payload_fv_buffer as *const [u8] as *const u8 as usize
     );
 
+    verify_payload_fv(payload_fv_buffer);
+
     let (payload_entry, basefw, basefwsize) =
         utils::find_and_report_entry_point(payload_fv_buffer, loaded_buffer);
     log::trace!(
@@ -177,12 +355,15 @@ fn transfer_to_payload(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hob_list
     );
     let payload_entry = payload_entry as usize;
 
-    migrate_hobs(runtime_memory_layout, fsp_hob_list);
+    migrate_hobs(runtime_memory_layout, paging_features, fsp_hob_list);
     log::info!(
         "Migrate hobs @ {:#X}\n",
         runtime_memory_layout.runtime_hob_base
     );
 
+    // The payload reuses the runtime stack which holds the IDT
+    fw_exception::clear_exception_handlers();
+
     log::info!("Call payload entry - {:#X}\n", payload_entry);
     asm::switch_stack(
         payload_entry,
@@ -193,7 +374,37 @@ fn transfer_to_payload(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hob_list
     unreachable!()
 }
 
-fn migrate_hobs(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hobs: &[u8]) {
+///
+/// Verify the payload FV with the public key hash in the IPL FV.
+///
+/// An IPL FV without the public key hash comes from an unsigned build and
+/// boots any payload, otherwise a payload which fails to verify is not run.
+///
+fn verify_payload_fv(payload_fv_buffer: &[u8]) {
+    let ipl_fv_buffer = memslice::get_mem_slice(memslice::SliceType::FirmwareIplSlice);
+    let trusted = match fw_verifier::get_trusted_public_key_hash(ipl_fv_buffer) {
+        Some(trusted) => trusted,
+        None => {
+            log::info!("No trusted public key hash in IPL FV, skip payload verification\n");
+            return;
+        }
+    };
+    match fw_verifier::verify_fv(payload_fv_buffer, &trusted) {
+        Ok(()) => log::info!("Payload FV verification pass\n"),
+        Err(e) => {
+            log::info!("Payload FV verification failed - {:?}, halt\n", e);
+            loop {
+                unsafe { x86::halt() };
+            }
+        }
+    }
+}
+
+fn migrate_hobs(
+    runtime_memory_layout: &RuntimeMemoryLayout,
+    paging_features: paging::PagingFeatures,
+    fsp_hobs: &[u8],
+) {
     let migrated_hob_list = memslice::get_dynamic_mem_slice_mut(
         memslice::SliceType::RuntimePayloadHobSlice,
         runtime_memory_layout.runtime_hob_base as usize,
@@ -247,6 +458,37 @@ fn migrate_hobs(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hobs: &[u8]) {
     };
     add_memory_allocation_to_ipl_hobs(&runtime_memory_layout, stack_hob);
 
+    // Mapped NX, the payload must not hand them out as code
+    let hob_region_hob = hob::MemoryAllocation {
+        header: hob::GenericHeader::new(
+            hob::HobType::MEMORY_ALLOCATION,
+            core::mem::size_of::<hob::MemoryAllocation>(),
+        ),
+        alloc_descriptor: hob::MemoryAllocationHeader {
+            name: const_guids::ZERO_GUID,
+            memory_base_address: runtime_memory_layout.runtime_hob_base,
+            memory_length: RUNTIME_HOB_SIZE as u64,
+            memory_type: efi::MemoryType::BootServicesData as u32,
+            reserved: [0u8; 4],
+        },
+    };
+    add_memory_allocation_to_ipl_hobs(&runtime_memory_layout, hob_region_hob);
+
+    let heap_hob = hob::MemoryAllocation {
+        header: hob::GenericHeader::new(
+            hob::HobType::MEMORY_ALLOCATION,
+            core::mem::size_of::<hob::MemoryAllocation>(),
+        ),
+        alloc_descriptor: hob::MemoryAllocationHeader {
+            name: const_guids::ZERO_GUID,
+            memory_base_address: runtime_memory_layout.runtime_heap_base,
+            memory_length: RUNTIME_HEAP_SIZE as u64,
+            memory_type: efi::MemoryType::BootServicesData as u32,
+            reserved: [0u8; 4],
+        },
+    };
+    add_memory_allocation_to_ipl_hobs(&runtime_memory_layout, heap_hob);
+
     let firmware_volume = hob::FirmwareVolume {
         header: hob::GenericHeader::new(
             hob::HobType::FV,
@@ -257,6 +499,30 @@ fn migrate_hobs(runtime_memory_layout: &RuntimeMemoryLayout, fsp_hobs: &[u8]) {
     };
     add_firmware_to_ipl_hobs(&runtime_memory_layout, firmware_volume);
 
+    let mut attributes = 0;
+    if paging_features.page_1g {
+        attributes |= PAGING_MODE_1G_PAGE;
+    }
+    if paging_features.execute_disable {
+        attributes |= PAGING_MODE_EXECUTE_DISABLE;
+    }
+    let paging_mode_hob = PagingModeHob {
+        header: hob::GuidExtension {
+            header: hob::GenericHeader::new(
+                hob::HobType::GUID_EXTENSION,
+                core::mem::size_of::<PagingModeHob>(),
+            ),
+            name: const_guids::PAGING_MODE_GUID,
+        },
+        paging_levels: paging_features.levels() as u32,
+        attributes,
+    };
+    let write_hob_buffer = &mut [0u8; core::mem::size_of::<PagingModeHob>()][..];
+    write_hob_buffer
+        .pwrite::<PagingModeHob>(paging_mode_hob, 0)
+        .expect("write paging mode hob failed");
+    add_hob_to_ipl_hobs(&runtime_memory_layout, write_hob_buffer);
+
     utils::dump_hob_buffer(migrated_hob_list);
 }
 
@@ -23,14 +23,17 @@ pub fn align_value(value: u64, align: u64, flag: bool) -> u64 {
     }
 }
 
+/// A compressed payload is decompressed to the end of loaded_buffer, then
+/// loaded at the start of it.
 pub fn find_and_report_entry_point(
     firmware_buffer: &[u8],
     loaded_buffer: &mut [u8],
 ) -> (u64, u64, u64) {
-    let image = uefi_pi::fv_lib::get_image_from_fv(
+    let (image, loaded_buffer) = uefi_pi::fv_lib::extract_image_from_fv(
         firmware_buffer,
         fv::FV_FILETYPE_DXE_CORE,
         fv::SECTION_PE32,
+        loaded_buffer,
     )
     .unwrap();
     log::trace!("found image len is: {:x}\n", image.len());
@@ -43,7 +46,15 @@ pub fn find_and_report_entry_point(
         elf_loader::elf::relocate_elf(image, loaded_buffer)
     } else if pe_loader::pe::is_pe(image) {
         log::info!("Payload is pe image\n");
-        pe_loader::pe::relocate_pe_mem(image, loaded_buffer)
+        let (entry, image_base, image_size) = pe_loader::pe::relocate_pe_mem(image, loaded_buffer);
+        // parsed by `rust-firmware-tool gdb-payload`, keep the format.
+        if let Some(text) = pe_loader::pe::find_section(image, b".text") {
+            log::info!(
+                "PAYLOAD_TEXT_BASE={:#x}\n",
+                image_base + text.virtual_address as u64
+            );
+        }
+        (entry, image_base, image_size)
     } else {
         panic!("format not support")
     }
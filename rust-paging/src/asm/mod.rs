@@ -0,0 +1,21 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+global_asm!(include_str!("switch_paging_mode.s"));
+
+extern "win64" {
+    fn switch_paging_mode_call(root_table: usize, la57: usize);
+}
+
+///
+/// Load root_table in CR3 and set CR4.LA57 to la57.
+///
+/// # Safety
+///
+/// The code, the stack and root_table must be below 4G and identity mapped
+/// by the current and the new page table.
+///
+pub unsafe fn switch_paging_mode(root_table: u64, la57: bool) {
+    switch_paging_mode_call(root_table as usize, la57 as usize)
+}
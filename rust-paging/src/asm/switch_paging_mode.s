@@ -0,0 +1,130 @@
+# Copyright (c) 2021 Intel Corporation
+# SPDX-License-Identifier: BSD-2-Clause-Patent
+
+.section .text
+
+.set    SWITCH_DS_SEL,      0x8
+.set    SWITCH_CS32_SEL,    0x10
+.set    SWITCH_CS64_SEL,    0x18
+
+# Flat segments with the accessed bit set, the GDT may be in flash
+.align 8
+SwitchPagingModeGdt:
+    .quad   0
+    .quad   0x00CF93000000FFFF      # data
+    .quad   0x00CF9B000000FFFF      # 32-bit code
+    .quad   0x00AF9B000000FFFF      # 64-bit code
+SwitchPagingModeGdtEnd:
+
+#  switch_paging_mode_call(
+#       root_table: usize,  // rcx
+#       la57: usize         // rdx
+#       );
+#
+# CR4.LA57 can only be changed with paging disabled: go to compatibility
+# mode, turn paging off, set CR4.LA57 and CR3, turn paging on with EFER.LME
+# still set and return to 64-bit mode.
+# The code, the stack and root_table must be below 4G and identity mapped by
+# the old and the new page table.
+.global switch_paging_mode_call
+switch_paging_mode_call:
+    pushfq
+    cli
+
+    #
+    # save DS/ES/SS, CS and GDTR
+    #
+    movl    %ds, %eax
+    pushq   %rax
+    movl    %es, %eax
+    pushq   %rax
+    movl    %ss, %eax
+    pushq   %rax
+    movl    %cs, %eax
+    pushq   %rax
+    subq    $0x10, %rsp
+    sgdt    (%rsp)
+
+    #
+    # load the GDT of the switch
+    #
+    subq    $0x10, %rsp
+    movw    $(SwitchPagingModeGdtEnd - SwitchPagingModeGdt - 1), (%rsp)
+    lea     SwitchPagingModeGdt(%rip), %rax
+    movq    %rax, 2(%rsp)
+    lgdt    (%rsp)
+    addq    $0x10, %rsp
+
+    #
+    # the CS and return address for the transition back to 64-bit mode
+    #
+    movq    $SWITCH_CS64_SEL, %rax
+    shlq    $32, %rax
+    lea     SwitchPagingModeLongMode(%rip), %r8
+    orq     %r8, %rax
+    pushq   %rax
+
+    movl    $SWITCH_DS_SEL, %eax
+    movl    %eax, %ds
+    movl    %eax, %es
+    movl    %eax, %ss
+
+    #
+    # go to compatibility mode
+    #
+    movq    $SWITCH_CS32_SEL, %rax
+    shlq    $32, %rax
+    lea     SwitchPagingModeCompatibilityMode(%rip), %r8
+    orq     %r8, %rax
+    pushq   %rax
+    lret
+
+.code32
+SwitchPagingModeCompatibilityMode:
+    #
+    # disable paging, the CPU leaves IA-32e mode
+    #
+    movl    %cr0, %eax
+    btrl    $31, %eax
+    movl    %eax, %cr0
+
+    movl    %cr4, %eax
+    btrl    $12, %eax
+    testl   %edx, %edx
+    jz      1f
+    btsl    $12, %eax
+1:
+    movl    %eax, %cr4
+    movl    %ecx, %cr3
+
+    #
+    # enable paging, EFER.LME is still set
+    #
+    movl    %cr0, %eax
+    btsl    $31, %eax
+    movl    %eax, %cr0
+    lret
+
+.code64
+SwitchPagingModeLongMode:
+    #
+    # restore GDTR, CS and DS/ES/SS
+    #
+    lgdt    (%rsp)
+    addq    $0x10, %rsp
+    popq    %rax
+    shlq    $32, %rax
+    lea     SwitchPagingModeEnd(%rip), %rcx
+    orq     %rcx, %rax
+    pushq   %rax
+    lret
+SwitchPagingModeEnd:
+    popq    %rax
+    movl    %eax, %ss
+    popq    %rax
+    movl    %eax, %es
+    popq    %rax
+    movl    %eax, %ds
+
+    popfq
+    ret
@@ -2,94 +2,60 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
-use bitmap_allocator::BitAlloc;
 use log::*;
 use x86_64::{
-    align_up,
+    align_down, align_up,
     structures::paging::{FrameAllocator, PhysFrame, Size4KiB},
     PhysAddr,
 };
 
 use super::consts::*;
 
-// Support max 256 * 4096 = 1MB memory.
-type FrameAlloc = bitmap_allocator::BitAlloc256;
-
-pub struct BMFrameAllocator {
-    base: usize,
-    inner: FrameAlloc,
+///
+/// Hands out the frames of the page table region in order, so any region
+/// size works. The page tables are never freed, they live until the payload
+/// takes over the memory.
+///
+pub struct PageTableFrameAllocator {
+    base: u64,
+    next: u64,
+    end: u64,
 }
 
-#[allow(dead_code)]
-impl BMFrameAllocator {
-    const fn empty() -> Self {
+impl PageTableFrameAllocator {
+    pub fn new(base: u64, size: u64) -> Self {
+        let end = align_down(base + size, PAGE_SIZE as u64);
+        let base = align_up(base, PAGE_SIZE as u64).min(end);
         Self {
-            base: 0,
-            inner: FrameAlloc::DEFAULT,
+            base,
+            next: base,
+            end,
         }
     }
 
-    pub fn new(base: usize, size: usize) -> Self {
-        let mut inner = FrameAlloc::DEFAULT;
-        let base = align_up(base as u64, PAGE_SIZE as u64) as usize;
-        let page_count = align_up(size as u64, PAGE_SIZE as u64) as usize / PAGE_SIZE;
-        inner.insert(0..page_count);
-        Self { base, inner }
-    }
-
-    /// # Safety
-    ///
-    /// This function is unsafe because manual deallocation is needed.
-    pub unsafe fn alloc(&mut self) -> Option<usize> {
-        let ret = self.inner.alloc().map(|idx| idx * PAGE_SIZE + self.base);
-        trace!("Allocate frame: {:x?}\n", ret);
-        ret
-    }
-
-    /// # Safety
-    ///
-    /// This function is unsafe because manual deallocation is needed.
-    unsafe fn alloc_contiguous(&mut self, frame_count: usize, align_log2: usize) -> Option<usize> {
-        let ret = self
-            .inner
-            .alloc_contiguous(frame_count, align_log2)
-            .map(|idx| idx * PAGE_SIZE + self.base);
-        trace!(
-            "Allocate {} frames with alignment {}: {:x?}",
-            frame_count,
-            1 << align_log2,
-            ret
-        );
-        ret
+    pub fn alloc(&mut self) -> Option<u64> {
+        if self.next == self.end {
+            return None;
+        }
+        let frame = self.next;
+        self.next += PAGE_SIZE as u64;
+        trace!("Allocate frame: {:x}\n", frame);
+        Some(frame)
     }
 
-    /// # Safety
-    ///
-    /// This function is unsafe because the frame must have been allocated.
-    unsafe fn dealloc(&mut self, target: usize) {
-        trace!("Deallocate frame: {:x}", target);
-        self.inner.dealloc((target - self.base) / PAGE_SIZE)
+    /// The number of frames of the region.
+    pub fn frame_count(&self) -> usize {
+        ((self.end - self.base) / PAGE_SIZE as u64) as usize
     }
 
-    /// # Safety
-    ///
-    /// This function is unsafe because the frames must have been allocated.
-    unsafe fn dealloc_contiguous(&mut self, target: usize, frame_count: usize) {
-        trace!("Deallocate {} frames: {:x}", frame_count, target);
-        let start_idx = (target - self.base) / PAGE_SIZE;
-        for i in start_idx..start_idx + frame_count {
-            self.inner.dealloc(i)
-        }
+    pub fn used_frame_count(&self) -> usize {
+        ((self.next - self.base) / PAGE_SIZE as u64) as usize
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BMFrameAllocator {
+unsafe impl FrameAllocator<Size4KiB> for PageTableFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        unsafe {
-            match self.alloc() {
-                None => None,
-                Some(addr) => Some(PhysFrame::containing_address(PhysAddr::new(addr as u64))),
-            }
-        }
+        self.alloc()
+            .map(|frame| PhysFrame::containing_address(PhysAddr::new(frame)))
     }
 }
@@ -1,47 +1,385 @@
 // Copyright (c) 2021 Intel Corporation
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
-#![no_std]
+#![feature(global_asm)]
+#![cfg_attr(not(test), no_std)]
 
+mod asm;
 mod consts;
 mod frame;
+pub mod memory_map;
+pub mod page_table;
 pub mod paging;
 
-use crate::frame::BMFrameAllocator;
+pub use crate::frame::PageTableFrameAllocator;
 pub use consts::*;
+pub use memory_map::{MemoryAttribute, MemoryMap, MemoryRange};
+pub use page_table::{page_table_frames, Mapping, PageTableManager, PagingError, PagingFeatures};
 
 use log::*;
-use x86_64::{
-    structures::paging::{OffsetPageTable, PageTable},
-    PhysAddr, VirtAddr,
-};
+use x86_64::structures::paging::PageTable;
 
 /// page_table_memory_base: page_table_memory_base
-/// system_memory_size
-pub fn setup_paging(page_table_memory_base: u64, page_table_size: u64, system_memory_size: u64) {
-    // Global variable not writable in rust-firmware environment, using a local allocator
-    let mut allocator = BMFrameAllocator::new(page_table_memory_base as usize, page_table_size as usize);
+/// memory_map: the ranges to identity map
+/// la57: use 5-level paging if the CPU supports it
+///
+/// Return the paging features in use.
+pub fn setup_paging(
+    page_table_memory_base: u64,
+    page_table_size: u64,
+    memory_map: &MemoryMap,
+    la57: bool,
+) -> Result<PagingFeatures, PagingError> {
+    let mut features = PagingFeatures::detect();
+    info!(
+        "1GiB page: {}, execute disable: {}, LA57: {}\n",
+        features.page_1g, features.execute_disable, features.la57
+    );
+    features.la57 &= la57;
 
-    // The first frame should've already been allocated to level 4 PT
-    unsafe { allocator.alloc() };
+    create_page_table(
+        page_table_memory_base,
+        page_table_size,
+        memory_map,
+        features,
+    )?;
+    if features.execute_disable {
+        paging::enable_execute_disable();
+    }
+    paging::set_page_table(page_table_memory_base, features.la57);
+    Ok(features)
+}
 
+///
+/// Build the page tables identity mapping memory_map in
+/// [page_table_memory_base, page_table_memory_base + page_table_size), the
+/// root table is the first page. Return the number of frames used, or
+/// FrameAllocationFailed if the region is too small for memory_map.
+///
+pub fn create_page_table(
+    page_table_memory_base: u64,
+    page_table_size: u64,
+    memory_map: &MemoryMap,
+    features: PagingFeatures,
+) -> Result<usize, PagingError> {
+    // Global variable not writable in rust-firmware environment, using a local allocator
+    let mut allocator = PageTableFrameAllocator::new(page_table_memory_base, page_table_size);
+
+    let frames = page_table_frames(memory_map.ranges(), features);
     info!(
-        "Frame allocator init done: {:#x?}\n",
-        page_table_memory_base..page_table_memory_base + page_table_size as u64
+        "Page table {:#x?}: {} frames needed, {} available\n",
+        page_table_memory_base..page_table_memory_base + page_table_size,
+        frames,
+        allocator.frame_count()
     );
+    if frames > allocator.frame_count() {
+        return Err(PagingError::FrameAllocationFailed);
+    }
+
+    // The first frame is the root PT
+    allocator.alloc();
 
-    let mut pt = unsafe {
-        OffsetPageTable::new(
-            &mut *(page_table_memory_base as *mut PageTable),
-            VirtAddr::new(PHYS_VIRT_OFFSET as u64),
+    let root_table = unsafe { &mut *(page_table_memory_base as *mut PageTable) };
+    root_table.zero();
+    let mut manager = unsafe {
+        PageTableManager::new(
+            page_table_memory_base,
+            PHYS_VIRT_OFFSET as u64,
+            &mut allocator,
+            features,
+            false,
         )
     };
-    paging::create_mapping(
-        &mut pt,
-        &mut allocator,
-        PhysAddr::new(0),
-        VirtAddr::new(0),
-        system_memory_size,
-    );
-    paging::cr3_write(page_table_memory_base);
+    for range in memory_map.ranges() {
+        info!(
+            "Map {:#x}..{:#x} {:?}\n",
+            range.base, range.end, range.attribute
+        );
+        manager.map_range(
+            range.base,
+            range.base,
+            range.size(),
+            paging::page_table_flags(range.attribute, features.execute_disable),
+        )?;
+    }
+    Ok(allocator.used_frame_count())
+}
+
+#[cfg(test)]
+#[path = "../../uefi-pi/test_data/fsp_hob_data.rs"]
+mod fsp_hob_data;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memory_map::MAX_MEMORY_RANGES;
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    const PAGE_TABLE_SIZE: usize = 0x100000;
+
+    // 4K aligned page table memory, host addresses are used as physical ones
+    fn alloc_page_table_memory(buffer: &mut Vec<u8>, size: usize) -> u64 {
+        buffer.resize(size + PAGE_SIZE, 0);
+        let page_mask = PAGE_SIZE as u64 - 1;
+        (buffer.as_ptr() as u64 + page_mask) & !page_mask
+    }
+
+    // (physical address, page size, flags) of the mapping of address
+    fn walk(page_table_base: u64, address: u64) -> Option<(u64, u64, Flags)> {
+        let mut table_address = page_table_base;
+        for level in (0..4).rev() {
+            let shift = 12 + 9 * level;
+            let table = unsafe { &*(table_address as *const PageTable) };
+            let entry = &table[(address >> shift) as usize & 0x1ff];
+            let flags = entry.flags();
+            if !flags.contains(Flags::PRESENT) {
+                return None;
+            }
+            if level == 0 || (level < 3 && flags.contains(Flags::HUGE_PAGE)) {
+                let page_size = 1u64 << shift;
+                return Some((
+                    entry.addr().as_u64() + (address & (page_size - 1)),
+                    page_size,
+                    flags,
+                ));
+            }
+            table_address = entry.addr().as_u64();
+        }
+        None
+    }
+
+    fn memory_map_2g() -> MemoryMap {
+        let mut memory_map = MemoryMap::from_hob_list(&fsp_hob_data::FSP_HOB_2G_EXAMPLE);
+        assert!(memory_map.add(0xFFC00000, 0x400000, MemoryAttribute::Code));
+        assert!(memory_map.add(0x80000000, 0x7FC00000, MemoryAttribute::Mmio));
+        assert!(memory_map.add(0x7E000000, 0x800000, MemoryAttribute::Data));
+        assert!(memory_map.add(0x7E900000, 0x1000, MemoryAttribute::Data));
+        memory_map
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let memory_map = MemoryMap::from_hob_list(&fsp_hob_data::FSP_HOB_2G_EXAMPLE);
+        // the system memory and the reserved memory are merged
+        assert_eq!(
+            memory_map.ranges(),
+            &[MemoryRange {
+                base: 0,
+                end: 0x80000000,
+                attribute: MemoryAttribute::Code
+            }]
+        );
+
+        let memory_map = memory_map_2g();
+        let ranges: Vec<(u64, u64, MemoryAttribute)> = memory_map
+            .ranges()
+            .iter()
+            .map(|range| (range.base, range.end, range.attribute))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 0x7E000000, MemoryAttribute::Code),
+                (0x7E000000, 0x7E800000, MemoryAttribute::Data),
+                (0x7E800000, 0x7E900000, MemoryAttribute::Code),
+                (0x7E900000, 0x7E901000, MemoryAttribute::Data),
+                (0x7E901000, 0x80000000, MemoryAttribute::Code),
+                (0x80000000, 0xFFC00000, MemoryAttribute::Mmio),
+                (0xFFC00000, 0x100000000, MemoryAttribute::Code),
+            ]
+        );
+
+        let mut memory_map = MemoryMap::new();
+        for index in 0..MAX_MEMORY_RANGES as u64 {
+            assert!(memory_map.add(index * 0x2000, 0x1000, MemoryAttribute::Data));
+        }
+        assert!(!memory_map.add(
+            MAX_MEMORY_RANGES as u64 * 0x2000,
+            0x1000,
+            MemoryAttribute::Data
+        ));
+        assert_eq!(memory_map.ranges().len(), MAX_MEMORY_RANGES);
+    }
+
+    #[test]
+    fn test_page_table_1g() {
+        let mut buffer = Vec::new();
+        let page_table_base = alloc_page_table_memory(&mut buffer, PAGE_TABLE_SIZE);
+        create_page_table(
+            page_table_base,
+            PAGE_TABLE_SIZE as u64,
+            &memory_map_2g(),
+            PagingFeatures {
+                page_1g: true,
+                execute_disable: true,
+                la57: false,
+            },
+        )
+        .unwrap();
+
+        let code = Flags::PRESENT | Flags::WRITABLE;
+        let (pa, page_size, flags) = walk(page_table_base, 0x1234).unwrap();
+        assert_eq!((pa, page_size), (0x1234, 0x40000000));
+        assert_eq!(flags & !Flags::HUGE_PAGE, code);
+
+        let (pa, page_size, flags) = walk(page_table_base, 0x7E123000).unwrap();
+        assert_eq!((pa, page_size), (0x7E123000, 0x200000));
+        assert_eq!(flags & !Flags::HUGE_PAGE, code | Flags::NO_EXECUTE);
+
+        let (pa, page_size, flags) = walk(page_table_base, 0x7E900000).unwrap();
+        assert_eq!((pa, page_size), (0x7E900000, 0x1000));
+        assert_eq!(flags, code | Flags::NO_EXECUTE);
+        let (_, page_size, flags) = walk(page_table_base, 0x7E901000).unwrap();
+        assert_eq!((page_size, flags), (0x1000, code));
+
+        let (pa, page_size, flags) = walk(page_table_base, 0x90000000).unwrap();
+        assert_eq!((pa, page_size), (0x90000000, 0x40000000));
+        assert_eq!(
+            flags & !Flags::HUGE_PAGE,
+            code | Flags::NO_CACHE | Flags::WRITE_THROUGH | Flags::NO_EXECUTE
+        );
+
+        let (pa, _, flags) = walk(page_table_base, 0xFFFFFFF0).unwrap();
+        assert_eq!(pa, 0xFFFFFFF0);
+        assert_eq!(flags & !Flags::HUGE_PAGE, code);
+
+        // nothing above the flash
+        assert_eq!(walk(page_table_base, 0x100000000), None);
+    }
+
+    #[test]
+    fn test_page_table_2m() {
+        let mut buffer = Vec::new();
+        let page_table_base = alloc_page_table_memory(&mut buffer, PAGE_TABLE_SIZE);
+        let memory_map = MemoryMap::from_hob_list(&fsp_hob_data::FSP_S_INIT_8G_HOB_EXAMPLE);
+        create_page_table(
+            page_table_base,
+            PAGE_TABLE_SIZE as u64,
+            &memory_map,
+            PagingFeatures {
+                page_1g: false,
+                execute_disable: false,
+                la57: false,
+            },
+        )
+        .unwrap();
+
+        for address in [0x0, 0x40000000, 0x7EF00000, 0x100000000, 0x17FFFFFFF].iter() {
+            let (pa, page_size, flags) = walk(page_table_base, *address).unwrap();
+            assert_eq!((pa, page_size), (*address, 0x200000));
+            assert!(!flags.contains(Flags::NO_EXECUTE));
+        }
+        // the hole between the low memory and 4G
+        assert_eq!(walk(page_table_base, 0x80000000), None);
+        assert_eq!(walk(page_table_base, 0x180000000), None);
+    }
+
+    #[test]
+    fn test_page_table_frames() {
+        let memory_maps = [
+            memory_map_2g(),
+            MemoryMap::from_hob_list(&fsp_hob_data::FSP_S_INIT_8G_HOB_EXAMPLE),
+        ];
+        for (page_1g, la57) in [(false, false), (true, false), (true, true)].iter() {
+            let features = PagingFeatures {
+                page_1g: *page_1g,
+                execute_disable: true,
+                la57: *la57,
+            };
+            for memory_map in memory_maps.iter() {
+                let mut buffer = Vec::new();
+                let page_table_base = alloc_page_table_memory(&mut buffer, PAGE_TABLE_SIZE);
+                let frames = page_table_frames(memory_map.ranges(), features);
+                assert_eq!(
+                    create_page_table(
+                        page_table_base,
+                        PAGE_TABLE_SIZE as u64,
+                        memory_map,
+                        features
+                    ),
+                    Ok(frames)
+                );
+
+                // one frame short
+                assert_eq!(
+                    create_page_table(
+                        page_table_base,
+                        (frames - 1) as u64 * PAGE_SIZE as u64,
+                        memory_map,
+                        features
+                    ),
+                    Err(PagingError::FrameAllocationFailed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_table_64g() {
+        const GIB: u64 = 0x40000000;
+        // 64 GiB of memory with a data page in 31 of the GiBs
+        let mut memory_map = MemoryMap::new();
+        assert!(memory_map.add(0, 64 * GIB, MemoryAttribute::Code));
+        for gib in 0..31 {
+            assert!(memory_map.add(gib * 2 * GIB + 0x201000, 0x1000, MemoryAttribute::Data));
+        }
+
+        // the level 4 and 3 tables, the level 2 and 1 tables of the GiBs
+        // with a data page, and the level 2 tables of the others without
+        // 1GiB pages
+        for (page_1g, frames) in [(true, 2 + 31 * 2), (false, 2 + 64 + 31)].iter() {
+            let features = PagingFeatures {
+                page_1g: *page_1g,
+                execute_disable: true,
+                la57: false,
+            };
+            assert_eq!(page_table_frames(memory_map.ranges(), features), *frames);
+
+            let mut buffer = Vec::new();
+            let page_table_base = alloc_page_table_memory(&mut buffer, PAGE_TABLE_SIZE);
+            assert_eq!(
+                create_page_table(
+                    page_table_base,
+                    PAGE_TABLE_SIZE as u64,
+                    &memory_map,
+                    features
+                ),
+                Ok(*frames)
+            );
+            let (_, page_size, flags) = walk(page_table_base, 60 * GIB + 0x201000).unwrap();
+            assert_eq!(page_size, 0x1000);
+            assert!(flags.contains(Flags::NO_EXECUTE));
+            let (_, page_size, _) = walk(page_table_base, 63 * GIB).unwrap();
+            assert_eq!(page_size, if *page_1g { GIB } else { 0x200000 });
+        }
+    }
+
+    #[test]
+    fn test_page_table_4k_64g() {
+        const SIZE: u64 = 0x10_0000_0000;
+        // 64 GiB mapped 4K off its physical address, with 4K pages only:
+        // the level 4 and 3 tables, 65 level 2 tables and 32769 level 1 tables
+        let frames = 2 + 65 + 32769;
+        let page_table_size = frames * PAGE_SIZE;
+        let mut buffer = Vec::new();
+        let page_table_base = alloc_page_table_memory(&mut buffer, page_table_size);
+        let mut allocator = PageTableFrameAllocator::new(page_table_base, page_table_size as u64);
+        let level_4_table = allocator.alloc().unwrap();
+        let features = PagingFeatures {
+            page_1g: true,
+            execute_disable: true,
+            la57: false,
+        };
+        let mut manager =
+            unsafe { PageTableManager::new(level_4_table, 0, &mut allocator, features, false) };
+        manager.map_range(0, 0x1000, SIZE, Flags::WRITABLE).unwrap();
+        assert_eq!(
+            manager.map_range(SIZE, SIZE + 0x1000, 0x200000, Flags::WRITABLE),
+            Err(PagingError::FrameAllocationFailed)
+        );
+        assert_eq!(allocator.used_frame_count(), frames);
+
+        let (pa, page_size, _) = walk(level_4_table, SIZE).unwrap();
+        assert_eq!((pa, page_size), (SIZE - 0x1000, 0x1000));
+    }
 }
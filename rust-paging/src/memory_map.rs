@@ -0,0 +1,175 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! The ranges to map in the page tables, with their attributes.
+//!
+//! The map starts from the resource descriptor HOBs, the caller then adds
+//! the ranges it knows better (the flash, the PCI windows, the data regions
+//! of the IPL). A range added later replaces the part of the map it covers.
+//! Ranges not in the map are left unmapped.
+//!
+
+use r_uefi_pi::hob;
+use uefi_pi::hob_lib::{HobEnums, HobList};
+
+use crate::consts::PAGE_SIZE;
+
+pub const MAX_MEMORY_RANGES: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryAttribute {
+    /// Write-back and executable: the RAM code may be loaded to, the flash.
+    Code,
+    /// Write-back and not executable.
+    Data,
+    /// Uncacheable and not executable.
+    Mmio,
+}
+
+/// [base, end) with the attribute of the range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub base: u64,
+    pub end: u64,
+    pub attribute: MemoryAttribute,
+}
+
+impl MemoryRange {
+    const EMPTY: MemoryRange = MemoryRange {
+        base: 0,
+        end: 0,
+        attribute: MemoryAttribute::Code,
+    };
+
+    pub fn size(&self) -> u64 {
+        self.end - self.base
+    }
+}
+
+/// Sorted, non-overlapping ranges, adjacent ranges have different attributes.
+pub struct MemoryMap {
+    ranges: [MemoryRange; MAX_MEMORY_RANGES],
+    count: usize,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        MemoryMap {
+            ranges: [MemoryRange::EMPTY; MAX_MEMORY_RANGES],
+            count: 0,
+        }
+    }
+
+    ///
+    /// The map of the resource descriptor HOBs: system memory and reserved
+    /// memory are Code, MMIO is Mmio. Other resources are not mapped.
+    ///
+    pub fn from_hob_list(hob_list: &[u8]) -> Self {
+        let mut memory_map = MemoryMap::new();
+        for h in HobList::new(hob_list) {
+            if let HobEnums::ResourceDescription(resource) = h {
+                let attribute = match hob::ResourceType::from(resource.resource_type) {
+                    hob::ResourceType::SYSTEM_MEMORY | hob::ResourceType::MEMORY_RESERVED => {
+                        MemoryAttribute::Code
+                    }
+                    hob::ResourceType::MEMORY_MAPPED_IO
+                    | hob::ResourceType::MEMORY_MAPPED_IO_PORT => MemoryAttribute::Mmio,
+                    _ => continue,
+                };
+                if !memory_map.add(resource.physical_start, resource.resource_length, attribute) {
+                    log::info!(
+                        "Memory map is full, skip resource {:#x}+{:#x}\n",
+                        resource.physical_start,
+                        resource.resource_length
+                    );
+                }
+            }
+        }
+        memory_map
+    }
+
+    pub fn ranges(&self) -> &[MemoryRange] {
+        &self.ranges[..self.count]
+    }
+
+    ///
+    /// Map [base, base + size) with attribute, rounded out to pages. Return
+    /// false if the map has no room for the range.
+    ///
+    pub fn add(&mut self, base: u64, size: u64, attribute: MemoryAttribute) -> bool {
+        let page_mask = PAGE_SIZE as u64 - 1;
+        let new = MemoryRange {
+            base: base & !page_mask,
+            end: (base + size + page_mask) & !page_mask,
+            attribute,
+        };
+        if new.base == new.end {
+            return true;
+        }
+
+        // the ranges before new, new, the parts of the ranges it overlaps
+        // which are out of it, and the ranges after new
+        let mut ranges = [MemoryRange::EMPTY; MAX_MEMORY_RANGES + 2];
+        let mut count = 0;
+        let mut inserted = false;
+        for range in self.ranges().iter() {
+            if range.end <= new.base {
+                ranges[count] = *range;
+                count += 1;
+                continue;
+            }
+            if range.base < new.base {
+                ranges[count] = MemoryRange {
+                    end: new.base,
+                    ..*range
+                };
+                count += 1;
+            }
+            if !inserted {
+                ranges[count] = new;
+                count += 1;
+                inserted = true;
+            }
+            if range.end > new.end {
+                ranges[count] = MemoryRange {
+                    base: range.base.max(new.end),
+                    ..*range
+                };
+                count += 1;
+            }
+        }
+        if !inserted {
+            ranges[count] = new;
+            count += 1;
+        }
+
+        // merge the adjacent ranges with the same attribute
+        let mut merged = 0;
+        for index in 0..count {
+            let range = ranges[index];
+            if merged > 0
+                && ranges[merged - 1].end == range.base
+                && ranges[merged - 1].attribute == range.attribute
+            {
+                ranges[merged - 1].end = range.end;
+            } else {
+                ranges[merged] = range;
+                merged += 1;
+            }
+        }
+        if merged > MAX_MEMORY_RANGES {
+            return false;
+        }
+        self.ranges[..merged].copy_from_slice(&ranges[..merged]);
+        self.count = merged;
+        true
+    }
+}
@@ -0,0 +1,571 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Map, unmap and change the attributes of ranges of a 4-level page table,
+//! or a 5-level one with LA57.
+//!
+//! 1GiB and 2MiB pages are split into smaller pages when a range covers only
+//! part of them. The tables are reached at their physical address plus
+//! phys_offset, 0 when the memory is identity mapped.
+//!
+
+use core::ops::Range;
+
+use crate::memory_map::MemoryRange;
+
+use x86_64::{
+    structures::paging::{
+        page_table::PageTableEntry, FrameAllocator, PageTable, PageTableFlags as Flags, Size4KiB,
+    },
+    PhysAddr,
+};
+
+const PAGE_SIZE_4K: u64 = 0x1000;
+// level 4 maps 512G per entry, level 1 4K, levels 4 and 5 have no pages
+const LEVEL_4: usize = 4;
+const LEVEL_5: usize = 5;
+const ENTRY_COUNT: u64 = 512;
+
+// the flags of the entries pointing to a table, the leaves restrict them
+const TABLE_FLAGS: Flags =
+    Flags::from_bits_truncate(Flags::PRESENT.bits() | Flags::WRITABLE.bits());
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PagingError {
+    /// The address or size is not 4K aligned.
+    NotAligned,
+    /// No frame is left for a page table.
+    FrameAllocationFailed,
+    /// Part of the range is not mapped.
+    NotMapped,
+}
+
+/// The CPU paging features the tables may use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PagingFeatures {
+    pub page_1g: bool,
+    pub execute_disable: bool,
+    /// 5-level paging.
+    pub la57: bool,
+}
+
+impl PagingFeatures {
+    pub fn detect() -> Self {
+        PagingFeatures {
+            page_1g: crate::paging::is_1g_page_supported(),
+            execute_disable: crate::paging::is_execute_disable_supported(),
+            la57: crate::paging::is_la57_supported(),
+        }
+    }
+
+    pub fn levels(&self) -> usize {
+        if self.la57 {
+            LEVEL_5
+        } else {
+            LEVEL_4
+        }
+    }
+}
+
+/// The leaf entry mapping an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub pa: u64,
+    pub page_size: u64,
+    pub flags: Flags,
+}
+
+fn page_size(level: usize) -> u64 {
+    PAGE_SIZE_4K << (9 * (level - 1))
+}
+
+fn entry_index(va: u64, level: usize) -> usize {
+    ((va / page_size(level)) % ENTRY_COUNT) as usize
+}
+
+fn is_leaf(entry: &PageTableEntry, level: usize) -> bool {
+    level == 1 || entry.flags().contains(Flags::HUGE_PAGE)
+}
+
+// the flags of a leaf of level, HUGE_PAGE is the PAT bit of the 4K pages
+fn leaf_flags(flags: Flags, level: usize) -> Flags {
+    if level == 1 {
+        flags - Flags::HUGE_PAGE
+    } else {
+        flags | Flags::HUGE_PAGE
+    }
+}
+
+// The level of the largest page mapping pa at va within size.
+fn leaf_level(pa: u64, va: u64, size: u64, page_1g: bool) -> usize {
+    let max_level = if page_1g { 3 } else { 2 };
+    (2..=max_level)
+        .rev()
+        .find(|level| {
+            let page_size = page_size(*level);
+            pa % page_size == 0 && va % page_size == 0 && size >= page_size
+        })
+        .unwrap_or(1)
+}
+
+///
+/// The page table frames, the root table included, to identity map ranges
+/// in an empty page table with map_range. The ranges are sorted and do not
+/// overlap, like those of a MemoryMap.
+///
+pub fn page_table_frames(ranges: &[MemoryRange], features: PagingFeatures) -> usize {
+    let mut frames = 1;
+    // the index of the last table counted at levels 1 to 4, ranges are
+    // sorted so a table is not used again once passed
+    let mut last_tables = [None; LEVEL_5 - 1];
+    for range in ranges {
+        let mut va = range.base;
+        while va < range.end {
+            let leaf = leaf_level(va, va, range.end - va, features.page_1g);
+            for level in leaf..features.levels() {
+                let table = Some(va / page_size(level + 1));
+                if last_tables[level - 1] != table {
+                    last_tables[level - 1] = table;
+                    frames += 1;
+                }
+            }
+            // the following pages up to the end of the table are leaves of
+            // the same level
+            let table_end = (va / page_size(leaf + 1) + 1) * page_size(leaf + 1);
+            let end = range.end.min(table_end);
+            va = end - end % page_size(leaf);
+        }
+    }
+    frames
+}
+
+pub struct PageTableManager<'a, A: FrameAllocator<Size4KiB>> {
+    root_table: u64,
+    phys_offset: u64,
+    allocator: &'a mut A,
+    features: PagingFeatures,
+    in_use: bool,
+}
+
+impl<'a, A: FrameAllocator<Size4KiB>> PageTableManager<'a, A> {
+    ///
+    /// Manage the page table at root_table, the level 5 table if features
+    /// has la57, the level 4 table otherwise. The frames of the new tables
+    /// come from allocator.
+    ///
+    /// # Safety
+    ///
+    /// root_table must be a valid page table, its tables must be
+    /// accessible at their physical address plus phys_offset. If the table is
+    /// the one in CR3, in_use must be set so that the TLB is flushed.
+    ///
+    pub unsafe fn new(
+        root_table: u64,
+        phys_offset: u64,
+        allocator: &'a mut A,
+        features: PagingFeatures,
+        in_use: bool,
+    ) -> Self {
+        PageTableManager {
+            root_table,
+            phys_offset,
+            allocator,
+            features,
+            in_use,
+        }
+    }
+
+    pub fn features(&self) -> PagingFeatures {
+        self.features
+    }
+
+    ///
+    /// Map [va, va + size) to [pa, pa + size) with the largest pages the
+    /// alignment allows. NO_EXECUTE is dropped if the CPU does not support it.
+    ///
+    pub fn map_range(
+        &mut self,
+        pa: u64,
+        va: u64,
+        size: u64,
+        flags: Flags,
+    ) -> Result<(), PagingError> {
+        Self::check_aligned(&[pa, va, size])?;
+        let flags = self.supported_flags(flags) | Flags::PRESENT;
+        let mut offset = 0;
+        while offset < size {
+            let mapped = self.map_page(pa + offset, va + offset, size - offset, flags)?;
+            offset += mapped;
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Unmap the pages of range, the pages not mapped are skipped.
+    pub fn unmap_range(&mut self, range: Range<u64>) -> Result<(), PagingError> {
+        Self::check_aligned(&[range.start, range.end])?;
+        let mut va = range.start;
+        while va < range.end {
+            va += self.update_page(va, range.end - va, None)?;
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Change the flags of the pages of range, which must be mapped.
+    pub fn set_attributes(&mut self, range: Range<u64>, flags: Flags) -> Result<(), PagingError> {
+        Self::check_aligned(&[range.start, range.end])?;
+        let flags = self.supported_flags(flags) | Flags::PRESENT;
+        let mut va = range.start;
+        while va < range.end {
+            va += self.update_page(va, range.end - va, Some(flags))?;
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// The mapping of va, None if it is not mapped.
+    pub fn query(&self, va: u64) -> Option<Mapping> {
+        let mut table = self.root_table;
+        for level in (1..=self.features.levels()).rev() {
+            let entry = &self.table(table)[entry_index(va, level)];
+            if !entry.flags().contains(Flags::PRESENT) {
+                return None;
+            }
+            if level < LEVEL_4 && is_leaf(entry, level) {
+                let page_size = page_size(level);
+                return Some(Mapping {
+                    pa: entry.addr().as_u64() + va % page_size,
+                    page_size,
+                    flags: entry.flags(),
+                });
+            }
+            table = entry.addr().as_u64();
+        }
+        None
+    }
+
+    fn check_aligned(values: &[u64]) -> Result<(), PagingError> {
+        if values.iter().any(|value| value % PAGE_SIZE_4K != 0) {
+            return Err(PagingError::NotAligned);
+        }
+        Ok(())
+    }
+
+    fn supported_flags(&self, flags: Flags) -> Flags {
+        if self.features.execute_disable {
+            flags
+        } else {
+            flags - Flags::NO_EXECUTE
+        }
+    }
+
+    // not borrowed from self, the entries are updated while allocating frames
+    fn table<'b>(&self, table: u64) -> &'b mut PageTable {
+        unsafe { &mut *((table + self.phys_offset) as *mut PageTable) }
+    }
+
+    fn allocate_table(&mut self) -> Result<u64, PagingError> {
+        let frame = self
+            .allocator
+            .allocate_frame()
+            .ok_or(PagingError::FrameAllocationFailed)?;
+        let table = frame.start_address().as_u64();
+        self.table(table).zero();
+        Ok(table)
+    }
+
+    // Replace the leaf entry of level by a table of 512 smaller pages
+    // mapping the same memory with the same flags.
+    fn split(&mut self, entry: &mut PageTableEntry, level: usize) -> Result<(), PagingError> {
+        let table = self.allocate_table()?;
+        let pa = entry.addr().as_u64();
+        let flags = leaf_flags(entry.flags(), level - 1);
+        let small_page_size = page_size(level - 1);
+        for (index, small_entry) in self.table(table).iter_mut().enumerate() {
+            small_entry.set_addr(PhysAddr::new(pa + index as u64 * small_page_size), flags);
+        }
+        entry.set_addr(PhysAddr::new(table), TABLE_FLAGS);
+        Ok(())
+    }
+
+    // The table entry of level leads to, created if unused, split if a leaf.
+    fn next_table(&mut self, entry: &mut PageTableEntry, level: usize) -> Result<u64, PagingError> {
+        if entry.is_unused() {
+            let table = self.allocate_table()?;
+            entry.set_addr(PhysAddr::new(table), TABLE_FLAGS);
+        } else if is_leaf(entry, level) {
+            self.split(entry, level)?;
+        }
+        Ok(entry.addr().as_u64())
+    }
+
+    // Map one page at va, as large as pa, va and size allow, return its size.
+    fn map_page(&mut self, pa: u64, va: u64, size: u64, flags: Flags) -> Result<u64, PagingError> {
+        let leaf = leaf_level(pa, va, size, self.features.page_1g);
+        let mut table = self.root_table;
+        for level in (1..=self.features.levels()).rev() {
+            let entry = &mut self.table(table)[entry_index(va, level)];
+            // a table already there may map other ranges, keep it
+            if level <= leaf && (entry.is_unused() || is_leaf(entry, level)) {
+                entry.set_addr(PhysAddr::new(pa), leaf_flags(flags, level));
+                return Ok(page_size(level));
+            }
+            table = self.next_table(entry, level)?;
+        }
+        unreachable!()
+    }
+
+    // Update the page at va, to flags or unmapped if flags is None. Pages
+    // larger than the size left are split. Return the size updated.
+    fn update_page(
+        &mut self,
+        va: u64,
+        size: u64,
+        flags: Option<Flags>,
+    ) -> Result<u64, PagingError> {
+        let mut table = self.root_table;
+        for level in (1..=self.features.levels()).rev() {
+            let page_size = page_size(level);
+            // the size left in the entry
+            let entry_size = (page_size - va % page_size).min(size);
+            let entry = &mut self.table(table)[entry_index(va, level)];
+            if !entry.flags().contains(Flags::PRESENT) {
+                return match flags {
+                    None => Ok(entry_size),
+                    Some(_) => Err(PagingError::NotMapped),
+                };
+            }
+            if level < LEVEL_4 && is_leaf(entry, level) {
+                if entry_size == page_size {
+                    match flags {
+                        None => entry.set_unused(),
+                        Some(flags) => entry.set_flags(leaf_flags(flags, level)),
+                    }
+                    return Ok(page_size);
+                }
+                self.split(entry, level)?;
+            }
+            table = entry.addr().as_u64();
+        }
+        unreachable!()
+    }
+
+    fn flush(&self) {
+        if self.in_use {
+            unsafe { x86::tlb::flush_all() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x86_64::structures::paging::PhysFrame;
+
+    const PAGE_TABLE_PAGES: usize = 16;
+
+    // Hands out the pages of a host buffer, host addresses are used as
+    // physical ones.
+    struct TestFrameAllocator {
+        buffer: Vec<u8>,
+        next: u64,
+        end: u64,
+    }
+
+    impl TestFrameAllocator {
+        fn new(pages: usize) -> Self {
+            let buffer = vec![0u8; (pages + 1) * PAGE_SIZE_4K as usize];
+            let next = (buffer.as_ptr() as u64 + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
+            TestFrameAllocator {
+                buffer,
+                next,
+                end: next + pages as u64 * PAGE_SIZE_4K,
+            }
+        }
+    }
+
+    unsafe impl FrameAllocator<Size4KiB> for TestFrameAllocator {
+        fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+            assert!(!self.buffer.is_empty());
+            if self.next == self.end {
+                return None;
+            }
+            let frame = PhysFrame::containing_address(PhysAddr::new(self.next));
+            self.next += PAGE_SIZE_4K;
+            Some(frame)
+        }
+    }
+
+    fn new_manager(
+        allocator: &mut TestFrameAllocator,
+        page_1g: bool,
+    ) -> PageTableManager<'_, TestFrameAllocator> {
+        let root_table = allocator.allocate_frame().unwrap().start_address().as_u64();
+        let features = PagingFeatures {
+            page_1g,
+            execute_disable: true,
+            la57: false,
+        };
+        unsafe { PageTableManager::new(root_table, 0, allocator, features, false) }
+    }
+
+    fn query(manager: &PageTableManager<TestFrameAllocator>, va: u64) -> Option<(u64, u64)> {
+        manager
+            .query(va)
+            .map(|mapping| (mapping.pa, mapping.page_size))
+    }
+
+    #[test]
+    fn test_map_range() {
+        let mut allocator = TestFrameAllocator::new(PAGE_TABLE_PAGES);
+        let mut manager = new_manager(&mut allocator, true);
+        let data = Flags::WRITABLE | Flags::NO_EXECUTE;
+
+        manager.map_range(0, 0, 0x80000000, data).unwrap();
+        assert_eq!(query(&manager, 0x12345), Some((0x12345, 0x40000000)));
+        let mapping = manager.query(0x40001000).unwrap();
+        assert_eq!(mapping.flags, data | Flags::PRESENT | Flags::HUGE_PAGE);
+
+        // 2M and 4K pages from the alignment of pa and va
+        manager
+            .map_range(0x80201000, 0x1_0000_0000, 0x400000, Flags::WRITABLE)
+            .unwrap();
+        assert_eq!(query(&manager, 0x1_0000_0000), Some((0x80201000, 0x1000)));
+        manager
+            .map_range(0x80400000, 0x1_0040_0000, 0x200000, Flags::WRITABLE)
+            .unwrap();
+        assert_eq!(query(&manager, 0x1_0050_0000), Some((0x80500000, 0x200000)));
+        assert_eq!(query(&manager, 0x1_0060_0000), None);
+
+        // no 1G page without the CPU support
+        let mut allocator = TestFrameAllocator::new(PAGE_TABLE_PAGES);
+        let mut manager = new_manager(&mut allocator, false);
+        manager.map_range(0, 0, 0x40000000, data).unwrap();
+        assert_eq!(query(&manager, 0x3FFFF000), Some((0x3FFFF000, 0x200000)));
+
+        assert_eq!(
+            manager.map_range(0x800, 0, 0x1000, data),
+            Err(PagingError::NotAligned)
+        );
+    }
+
+    #[test]
+    fn test_set_attributes() {
+        let mut allocator = TestFrameAllocator::new(PAGE_TABLE_PAGES);
+        let mut manager = new_manager(&mut allocator, true);
+        let code = Flags::PRESENT | Flags::WRITABLE;
+        manager.map_range(0, 0, 0x40000000, code).unwrap();
+
+        // the 1G page is split into 2M pages, then the first one into 4K pages
+        manager
+            .set_attributes(0x1000..0x3000, Flags::NO_EXECUTE)
+            .unwrap();
+        let mapping = manager.query(0x2000).unwrap();
+        assert_eq!((mapping.pa, mapping.page_size), (0x2000, 0x1000));
+        assert_eq!(mapping.flags, Flags::PRESENT | Flags::NO_EXECUTE);
+        let mapping = manager.query(0x3000).unwrap();
+        assert_eq!((mapping.pa, mapping.page_size), (0x3000, 0x1000));
+        assert_eq!(mapping.flags, code);
+        let mapping = manager.query(0x200000).unwrap();
+        assert_eq!((mapping.pa, mapping.page_size), (0x200000, 0x200000));
+        assert_eq!(mapping.flags, code | Flags::HUGE_PAGE);
+
+        // whole 2M pages are not split
+        manager
+            .set_attributes(0x400000..0x800000, Flags::WRITABLE | Flags::NO_CACHE)
+            .unwrap();
+        let mapping = manager.query(0x600000).unwrap();
+        assert_eq!(mapping.page_size, 0x200000);
+        assert!(mapping.flags.contains(Flags::NO_CACHE));
+
+        assert_eq!(
+            manager.set_attributes(0x3FFFF000..0x40001000, code),
+            Err(PagingError::NotMapped)
+        );
+    }
+
+    #[test]
+    fn test_unmap_range() {
+        let mut allocator = TestFrameAllocator::new(PAGE_TABLE_PAGES);
+        let mut manager = new_manager(&mut allocator, true);
+        manager
+            .map_range(0, 0, 0x40000000, Flags::WRITABLE)
+            .unwrap();
+
+        manager.unmap_range(0..0x1000).unwrap();
+        assert_eq!(query(&manager, 0), None);
+        assert_eq!(query(&manager, 0x1000), Some((0x1000, 0x1000)));
+        assert_eq!(query(&manager, 0x200000), Some((0x200000, 0x200000)));
+
+        // the holes are skipped
+        manager.unmap_range(0x3FE00000..0x80000000).unwrap();
+        assert_eq!(query(&manager, 0x3FE00000), None);
+        assert_eq!(query(&manager, 0x3FDFF000), Some((0x3FDFF000, 0x200000)));
+
+        // map again over the unmapped page
+        manager
+            .map_range(0x1000, 0, 0x1000, Flags::WRITABLE)
+            .unwrap();
+        assert_eq!(query(&manager, 0), Some((0x1000, 0x1000)));
+    }
+
+    #[test]
+    fn test_out_of_frames() {
+        // the level 4 table and one more table only
+        let mut allocator = TestFrameAllocator::new(2);
+        let mut manager = new_manager(&mut allocator, true);
+        assert_eq!(
+            manager.map_range(0, 0, 0x1000, Flags::WRITABLE),
+            Err(PagingError::FrameAllocationFailed)
+        );
+    }
+
+    #[test]
+    fn test_la57() {
+        let mut allocator = TestFrameAllocator::new(PAGE_TABLE_PAGES);
+        let root_table = allocator.allocate_frame().unwrap().start_address().as_u64();
+        let features = PagingFeatures {
+            page_1g: true,
+            execute_disable: true,
+            la57: true,
+        };
+        let mut manager =
+            unsafe { PageTableManager::new(root_table, 0, &mut allocator, features, false) };
+
+        // above the 256T of 4-level paging
+        let va = 0x1_0000_0000_0000;
+        manager
+            .map_range(0x40000000, va, 0x40000000, Flags::WRITABLE)
+            .unwrap();
+        manager
+            .map_range(0, 0, 0x40000000, Flags::WRITABLE)
+            .unwrap();
+        assert_eq!(query(&manager, va + 0x1234), Some((0x40001234, 0x40000000)));
+        assert_eq!(query(&manager, 0x1234), Some((0x1234, 0x40000000)));
+        assert_eq!(query(&manager, 0x40000000), None);
+
+        // the two ranges are in different level 4 tables
+        let table = manager.table(root_table);
+        assert!(!table[0].is_unused());
+        assert!(!table[1].is_unused());
+        drop(manager);
+        assert_eq!(allocator.next - root_table, 5 * PAGE_SIZE_4K);
+
+        let ranges = [
+            MemoryRange {
+                base: 0,
+                end: 0x40000000,
+                attribute: crate::memory_map::MemoryAttribute::Code,
+            },
+            MemoryRange {
+                base: va,
+                end: va + 0x40000000,
+                attribute: crate::memory_map::MemoryAttribute::Code,
+            },
+        ];
+        assert_eq!(page_table_frames(&ranges, features), 5);
+    }
+}
@@ -2,99 +2,85 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause-Patent
 
-use core::cmp::min;
-use log::*;
-use x86_64::{
-    structures::paging::PageTableFlags as Flags,
-    structures::paging::{
-        Mapper, OffsetPageTable, Page, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
-    },
-    PhysAddr,
-    VirtAddr,
-};
+use x86_64::structures::paging::PageTableFlags as Flags;
 
-use super::frame::BMFrameAllocator;
+use super::memory_map::MemoryAttribute;
 
-pub fn create_mapping(
-    pt: &mut OffsetPageTable,
-    allocator: &mut BMFrameAllocator,
-    mut pa: PhysAddr,
-    mut va: VirtAddr,
-    mut sz: u64,
-) {
-    // const ALIGN_4K_BITS: u64 = 12;
-    // const ALIGN_4K: u64 = 4096;
-    const ALIGN_2M_BITS: u64 = 21;
-    const ALIGN_2M: u64 = 1024 * 1024 * 2;
-    const ALIGN_1G_BITS: u64 = 30;
-    const ALIGN_1G: u64 = 1024 * 1024 * 1024;
+///
+/// The flags of the pages of a range, NO_EXECUTE is only set when the CPU
+/// supports it, it is a reserved bit otherwise.
+///
+pub fn page_table_flags(attribute: MemoryAttribute, execute_disable: bool) -> Flags {
+    let mut flags = match attribute {
+        MemoryAttribute::Code => Flags::PRESENT | Flags::WRITABLE,
+        MemoryAttribute::Data => Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+        // PCD and PWT select UC with the default PAT
+        MemoryAttribute::Mmio => {
+            Flags::PRESENT
+                | Flags::WRITABLE
+                | Flags::NO_CACHE
+                | Flags::WRITE_THROUGH
+                | Flags::NO_EXECUTE
+        }
+    };
+    if !execute_disable {
+        flags.remove(Flags::NO_EXECUTE);
+    }
+    flags
+}
 
-    // let allocator: &mut BMFrameAllocator = &mut FRAME_ALLOCATOR.lock();
+// EDX of CPUID 0x80000001, 0 if the leaf is not supported
+fn cpuid_extended_features() -> u32 {
+    use core::arch::x86_64::__cpuid;
+    unsafe {
+        if __cpuid(0x8000_0000).eax < 0x8000_0001 {
+            return 0;
+        }
+        __cpuid(0x8000_0001).edx
+    }
+}
 
-    while sz > 0 {
-        let addr_align = min(pa.as_u64().trailing_zeros(), va.as_u64().trailing_zeros()) as u64;
+pub fn is_la57_supported() -> bool {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+    unsafe { __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ecx & (1 << 16) != 0 }
+}
+
+pub fn is_la57_enabled() -> bool {
+    const CR4_LA57: usize = 1 << 12;
+    unsafe { x86::controlregs::cr4().bits() & CR4_LA57 != 0 }
+}
 
-        let mapped_size = if addr_align >= ALIGN_1G_BITS && sz >= ALIGN_1G {
-            trace!(
-                "1GB {} {:016x} /{:016x} {:016x}\n",
-                addr_align,
-                sz,
-                pa.as_u64(),
-                va.as_u64()
-            );
-            type S = Size1GiB;
-            let page: Page<S> = Page::containing_address(va);
-            let frame: PhysFrame<S> = PhysFrame::containing_address(pa);
-            let flags = Flags::PRESENT | Flags::WRITABLE;
-            unsafe {
-                pt.map_to(page, frame, flags, allocator)
-                    .expect("map_to failed")
-                    .flush();
-            }
-            S::SIZE
-        } else if addr_align >= ALIGN_2M_BITS && sz >= ALIGN_2M {
-            trace!(
-                "2MB {} {:016x} /{:016x} {:016x}\n",
-                addr_align,
-                sz,
-                pa.as_u64(),
-                va.as_u64()
-            );
-            type S = Size2MiB;
-            let page: Page<S> = Page::containing_address(va);
-            let frame: PhysFrame<S> = PhysFrame::containing_address(pa);
-            let flags = Flags::PRESENT | Flags::WRITABLE;
-            unsafe {
-                pt.map_to(page, frame, flags, allocator)
-                    .expect("map_to failed")
-                    .flush();
-            }
-            S::SIZE
-        } else {
-            trace!(
-                "4KB {} {:016x} /{:016x} {:016x}\n",
-                addr_align,
-                sz,
-                pa.as_u64(),
-                va.as_u64()
-            );
-            type S = Size4KiB;
-            let page: Page<S> = Page::containing_address(va);
-            let frame: PhysFrame<S> = PhysFrame::containing_address(pa);
-            let flags = Flags::PRESENT | Flags::WRITABLE;
-            unsafe {
-                pt.map_to(page, frame, flags, allocator)
-                    .expect("map_to failed")
-                    .flush();
-            }
-            S::SIZE
-        };
-        sz -= mapped_size;
-        pa += mapped_size;
-        va += mapped_size;
+pub fn is_1g_page_supported() -> bool {
+    cpuid_extended_features() & (1 << 26) != 0
+}
+
+pub fn is_execute_disable_supported() -> bool {
+    cpuid_extended_features() & (1 << 20) != 0
+}
+
+/// Set EFER.NXE, NO_EXECUTE is a reserved bit of the page tables before.
+pub fn enable_execute_disable() {
+    const EFER_NXE: u64 = 1 << 11;
+    unsafe {
+        let efer = x86::msr::rdmsr(x86::msr::IA32_EFER);
+        x86::msr::wrmsr(x86::msr::IA32_EFER, efer | EFER_NXE);
     }
 }
 
+///
+/// Load the page table, a level 5 table if la57 is set. CR4.LA57 is only
+/// switched if it does not match, that is done with paging disabled.
+///
+pub fn set_page_table(root_table: u64, la57: bool) {
+    if is_la57_enabled() == la57 {
+        cr3_write(root_table);
+        return;
+    }
+    log::info!("Switch to {}-level paging\n", if la57 { 5 } else { 4 });
+    unsafe { crate::asm::switch_paging_mode(root_table, la57) };
+    log::info!("Cr3 - {:x}\n", unsafe { x86::controlregs::cr3() });
+}
+
 pub fn cr3_write(page_table_base: u64) {
     unsafe {
         x86::controlregs::cr3_write(page_table_base);
@@ -34,8 +34,31 @@ struct EventStruct {
     notify_tpl: Tpl,
     notify_function: usize,
     notify_context: usize,
+    signaled: bool,
+    // time in ns of the next expiry of the timer, 0 when the timer is off
+    trigger_ns: u64,
+    // 0 for a relative timer
+    period_ns: u64,
+    // expired in the tick, the notify function runs out of the interrupt
+    notify_pending: bool,
 }
 
+impl EventStruct {
+    fn notify(&self, r#type: u32) -> Option<(Event, EventNotify, *mut c_void)> {
+        if self.r#type & r#type == 0 || self.notify_function == 0 {
+            return None;
+        }
+        Some((
+            self as *const EventStruct as Event,
+            unsafe { transmute::<usize, EventNotify>(self.notify_function) },
+            self.notify_context as *mut c_void,
+        ))
+    }
+}
+
+/// An event with its notify function and context.
+pub type Notify = (Event, EventNotify, *mut c_void);
+
 const MAX_EVENT_STRUCT: usize = 16;
 
 #[derive(Default)]
@@ -57,26 +80,148 @@ impl EventInfo {
             return (status, core::ptr::null_mut());
         }
 
+        // a reused slot keeps the signal and the timer of the closed event
         let event_struct = unsafe { transmute::<Event, &mut EventStruct>(new_event) };
-        event_struct.signature = EVENT_STRUCT_SIGNATURE;
-        event_struct.r#type = r#type;
-        event_struct.notify_tpl = notify_tpl;
-        event_struct.notify_function = notify_function as usize;
-        event_struct.notify_context = notify_context as usize;
+        *event_struct = EventStruct {
+            signature: EVENT_STRUCT_SIGNATURE,
+            r#type,
+            notify_tpl,
+            notify_function: notify_function as usize,
+            notify_context: notify_context as usize,
+            ..EventStruct::default()
+        };
 
         (Status::SUCCESS, new_event)
     }
     pub fn close_event(&mut self, event: Event) -> (Status) {
-        (Status::UNSUPPORTED)
+        match self.find_event(event) {
+            Some(event_struct) => {
+                // the slot is free for create_event, the timer must not fire
+                event_struct.signature = 0;
+                event_struct.trigger_ns = 0;
+                event_struct.notify_pending = false;
+                Status::SUCCESS
+            }
+            None => Status::INVALID_PARAMETER,
+        }
     }
-    fn get_new_event(&mut self) -> (Status, Event) {
-        if self.event_count >= MAX_EVENT_STRUCT {
-            return (Status::OUT_OF_RESOURCES, core::ptr::null_mut());
+
+    ///
+    /// Arm or cancel the timer of the event, trigger_time in 100ns units.
+    /// A periodic timer of period 0 expires on every tick.
+    ///
+    pub fn set_timer(
+        &mut self,
+        event: Event,
+        r#type: TimerDelay,
+        trigger_time: u64,
+        now_ns: u64,
+    ) -> Status {
+        let event_struct = match self.find_event(event) {
+            Some(event_struct) if event_struct.r#type & efi::EVT_TIMER != 0 => event_struct,
+            _ => return Status::INVALID_PARAMETER,
+        };
+        let time_ns = trigger_time.saturating_mul(100);
+        match r#type {
+            TimerDelay::TimerCancel => {
+                event_struct.trigger_ns = 0;
+                event_struct.period_ns = 0;
+            }
+            TimerDelay::TimerRelative => {
+                // 0 is off, expire on the next tick
+                event_struct.trigger_ns = now_ns.saturating_add(time_ns).max(1);
+                event_struct.period_ns = 0;
+            }
+            TimerDelay::TimerPeriodic => {
+                event_struct.trigger_ns = now_ns.saturating_add(time_ns).max(1);
+                event_struct.period_ns = time_ns.max(1);
+            }
         }
-        let event_struct = &mut self.event_struct[self.event_count];
-        self.event_count = self.event_count + 1;
+        Status::SUCCESS
+    }
 
-        event_struct.signature = 0;
+    ///
+    /// Signal the timer events expired at now_ns, from the tick. Their notify
+    /// functions are left to take_pending_notify().
+    ///
+    pub fn expire_timers(&mut self, now_ns: u64) {
+        for event_struct in self.event_struct[..self.event_count].iter_mut() {
+            if event_struct.trigger_ns == 0 || event_struct.trigger_ns > now_ns {
+                continue;
+            }
+            event_struct.trigger_ns = if event_struct.period_ns == 0 {
+                0
+            } else {
+                // the ticks missed are not caught up
+                (event_struct.trigger_ns + event_struct.period_ns).max(now_ns + 1)
+            };
+            event_struct.signaled = true;
+            if event_struct.notify(efi::EVT_NOTIFY_SIGNAL).is_some() {
+                event_struct.notify_pending = true;
+            }
+        }
+    }
+
+    ///
+    /// Take a notify function of an expired timer to call, with its TPL, if
+    /// its TPL is above tpl.
+    ///
+    pub fn take_pending_notify(&mut self, tpl: Tpl) -> Option<(Notify, Tpl)> {
+        let event_struct = self.event_struct[..self.event_count]
+            .iter_mut()
+            .find(|event_struct| event_struct.notify_pending && event_struct.notify_tpl > tpl)?;
+        event_struct.notify_pending = false;
+        let notify = event_struct.notify(efi::EVT_NOTIFY_SIGNAL)?;
+        Some((notify, event_struct.notify_tpl))
+    }
+
+    /// Signal the event, return its notify function to call if any.
+    pub fn signal_event(&mut self, event: Event) -> (Status, Option<Notify>) {
+        match self.find_event(event) {
+            Some(event_struct) => {
+                event_struct.signaled = true;
+                (Status::SUCCESS, event_struct.notify(efi::EVT_NOTIFY_SIGNAL))
+            }
+            None => (Status::INVALID_PARAMETER, None),
+        }
+    }
+
+    ///
+    /// Take the signal of the event. When it is not signaled, return the
+    /// notify function of a wait event to call before checking again.
+    ///
+    pub fn check_event(&mut self, event: Event) -> (Status, Option<Notify>) {
+        let event_struct = match self.find_event(event) {
+            Some(event_struct) if event_struct.r#type & efi::EVT_NOTIFY_SIGNAL == 0 => event_struct,
+            _ => return (Status::INVALID_PARAMETER, None),
+        };
+        if event_struct.signaled {
+            event_struct.signaled = false;
+            return (Status::SUCCESS, None);
+        }
+        (Status::NOT_READY, event_struct.notify(efi::EVT_NOTIFY_WAIT))
+    }
+
+    fn find_event(&mut self, event: Event) -> Option<&mut EventStruct> {
+        self.event_struct[..self.event_count]
+            .iter_mut()
+            .find(|event_struct| {
+                event_struct.signature == EVENT_STRUCT_SIGNATURE
+                    && *event_struct as *const EventStruct as Event == event
+            })
+    }
+    // The first slot never used or of a closed event
+    fn get_new_event(&mut self) -> (Status, Event) {
+        let index = match self
+            .event_struct
+            .iter()
+            .position(|event_struct| event_struct.signature == 0)
+        {
+            Some(index) => index,
+            None => return (Status::OUT_OF_RESOURCES, core::ptr::null_mut()),
+        };
+        self.event_count = self.event_count.max(index + 1);
+        let event_struct = &mut self.event_struct[index];
 
         (Status::SUCCESS, event_struct as *mut EventStruct as Event)
     }
@@ -88,3 +233,162 @@ impl EventInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EventInfo;
+    use core::ffi::c_void;
+    use r_efi::efi::{self, Event, Status, TimerDelay};
+
+    extern "win64" fn notify(_: Event, _: *mut c_void) {}
+
+    fn create_event(event_info: &mut EventInfo, r#type: u32) -> Event {
+        let (status, event) =
+            event_info.create_event(r#type, efi::TPL_CALLBACK, notify, core::ptr::null_mut());
+        assert_eq!(status, Status::SUCCESS);
+        event
+    }
+
+    // The count of the notify functions to call after the tick
+    fn expire_timers(event_info: &mut EventInfo, now_ns: u64) -> usize {
+        event_info.expire_timers(now_ns);
+        core::iter::from_fn(|| event_info.take_pending_notify(efi::TPL_APPLICATION)).count()
+    }
+
+    #[test]
+    fn test_relative_timer() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_TIMER);
+        // 1 us
+        assert_eq!(
+            event_info.set_timer(event, TimerDelay::TimerRelative, 10, 1000),
+            Status::SUCCESS
+        );
+        expire_timers(&mut event_info, 1999);
+        assert_eq!(event_info.check_event(event).0, Status::NOT_READY);
+        expire_timers(&mut event_info, 2000);
+        assert_eq!(event_info.check_event(event).0, Status::SUCCESS);
+        // expired once
+        expire_timers(&mut event_info, 100_000);
+        assert_eq!(event_info.check_event(event).0, Status::NOT_READY);
+    }
+
+    #[test]
+    fn test_periodic_timer() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL);
+        assert_eq!(
+            event_info.set_timer(event, TimerDelay::TimerPeriodic, 10, 0),
+            Status::SUCCESS
+        );
+        assert_eq!(expire_timers(&mut event_info, 999), 0);
+        assert_eq!(expire_timers(&mut event_info, 1000), 1);
+        // 4 periods late, notified once and re-armed after now
+        assert_eq!(expire_timers(&mut event_info, 5500), 1);
+        assert_eq!(expire_timers(&mut event_info, 5500), 0);
+        assert_eq!(expire_timers(&mut event_info, 5501), 1);
+        assert_eq!(expire_timers(&mut event_info, 6000), 0);
+        assert_eq!(expire_timers(&mut event_info, 6501), 1);
+    }
+
+    #[test]
+    fn test_pending_notify() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL);
+        event_info.set_timer(event, TimerDelay::TimerRelative, 10, 0);
+        event_info.expire_timers(1000);
+        // not at or above the TPL of the event
+        assert!(event_info.take_pending_notify(efi::TPL_CALLBACK).is_none());
+        let (notify, tpl) = event_info
+            .take_pending_notify(efi::TPL_APPLICATION)
+            .unwrap();
+        assert_eq!(notify.0, event);
+        assert_eq!(tpl, efi::TPL_CALLBACK);
+        assert!(event_info
+            .take_pending_notify(efi::TPL_APPLICATION)
+            .is_none());
+
+        // the notify function of a closed event does not run
+        event_info.set_timer(event, TimerDelay::TimerRelative, 10, 1000);
+        event_info.expire_timers(2000);
+        event_info.close_event(event);
+        assert!(event_info
+            .take_pending_notify(efi::TPL_APPLICATION)
+            .is_none());
+    }
+
+    #[test]
+    fn test_timer_cancel() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL);
+        event_info.set_timer(event, TimerDelay::TimerPeriodic, 10, 0);
+        assert_eq!(
+            event_info.set_timer(event, TimerDelay::TimerCancel, 0, 0),
+            Status::SUCCESS
+        );
+        assert_eq!(expire_timers(&mut event_info, 100_000), 0);
+
+        let event = create_event(&mut event_info, 0);
+        assert_eq!(
+            event_info.set_timer(event, TimerDelay::TimerRelative, 10, 0),
+            Status::INVALID_PARAMETER
+        );
+    }
+
+    #[test]
+    fn test_check_event() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_NOTIFY_SIGNAL);
+        assert_eq!(event_info.check_event(event).0, Status::INVALID_PARAMETER);
+
+        let event = create_event(&mut event_info, efi::EVT_NOTIFY_WAIT);
+        let (status, notify) = event_info.check_event(event);
+        assert_eq!(status, Status::NOT_READY);
+        assert_eq!(notify.map(|notify| notify.0), Some(event));
+        assert_eq!(event_info.signal_event(event).0, Status::SUCCESS);
+        let (status, notify) = event_info.check_event(event);
+        assert_eq!(status, Status::SUCCESS);
+        assert!(notify.is_none());
+        // the signal is consumed
+        assert_eq!(event_info.check_event(event).0, Status::NOT_READY);
+    }
+
+    #[test]
+    fn test_close_event() {
+        let mut event_info = EventInfo::new();
+        let event = create_event(&mut event_info, efi::EVT_TIMER);
+        event_info.set_timer(event, TimerDelay::TimerRelative, 10, 0);
+        event_info.signal_event(event);
+        assert_eq!(event_info.close_event(event), Status::SUCCESS);
+
+        assert_eq!(event_info.close_event(event), Status::INVALID_PARAMETER);
+        assert_eq!(event_info.check_event(event).0, Status::INVALID_PARAMETER);
+        assert_eq!(event_info.signal_event(event).0, Status::INVALID_PARAMETER);
+        assert_eq!(
+            event_info.set_timer(event, TimerDelay::TimerCancel, 0, 0),
+            Status::INVALID_PARAMETER
+        );
+        assert_eq!(expire_timers(&mut event_info, 100_000), 0);
+    }
+
+    #[test]
+    fn test_event_slots() {
+        let mut event_info = EventInfo::new();
+        let events: Vec<Event> = (0..super::MAX_EVENT_STRUCT)
+            .map(|_| create_event(&mut event_info, efi::EVT_TIMER))
+            .collect();
+        let (status, _) =
+            event_info.create_event(0, efi::TPL_CALLBACK, notify, core::ptr::null_mut());
+        assert_eq!(status, Status::OUT_OF_RESOURCES);
+
+        // the slot of a closed event is reused, without its signal and timer
+        event_info.set_timer(events[3], TimerDelay::TimerRelative, 10, 0);
+        event_info.signal_event(events[3]);
+        event_info.close_event(events[3]);
+        let event = create_event(&mut event_info, efi::EVT_TIMER);
+        assert_eq!(event, events[3]);
+        assert_eq!(event_info.check_event(event).0, Status::NOT_READY);
+        expire_timers(&mut event_info, 100_000);
+        assert_eq!(event_info.check_event(event).0, Status::NOT_READY);
+    }
+}
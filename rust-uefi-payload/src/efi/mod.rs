@@ -28,6 +28,7 @@ mod handle_database;
 mod image;
 mod init;
 mod peloader;
+mod timer;
 mod variable;
 
 use core::fmt;
@@ -113,6 +114,7 @@ pub struct PciDevicePath {
 use r_efi::{eficall, eficall_abi};
 
 use core::ffi::c_void;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::pi::hob::{
     Header, MemoryAllocation, ResourceDescription, HOB_TYPE_END_OF_HOB_LIST,
@@ -171,6 +173,9 @@ lazy_static! {
     pub static ref EVENT: Mutex<EventInfo> = Mutex::new(EventInfo::new());
 }
 
+// The TPL the boot services run at, raised by raise_tpl
+static CURRENT_TPL: AtomicUsize = AtomicUsize::new(efi::TPL_APPLICATION);
+
 lazy_static! {
     pub static ref CONOUT: Mutex<ConOut> = Mutex::new(ConOut::new());
 }
@@ -700,14 +705,33 @@ pub extern "win64" fn query_variable_info(_: u32, _: *mut u64, _: *mut u64, _: *
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn raise_tpl(_: Tpl) -> Tpl {
+pub extern "win64" fn raise_tpl(new_tpl: Tpl) -> Tpl {
     crate::log!("EFI_STUB: raise_tpl\n");
-    0
+    CURRENT_TPL.swap(new_tpl, Ordering::Relaxed)
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn restore_tpl(_: Tpl) {
+pub extern "win64" fn restore_tpl(old_tpl: Tpl) {
     crate::log!("EFI_STUB: restore_tpl\n");
+    CURRENT_TPL.store(old_tpl, Ordering::Relaxed);
+    dispatch_timer_notify();
+}
+
+// Run the notify functions of the timers expired in the tick above the
+// current TPL, each at the TPL of its event
+fn dispatch_timer_notify() {
+    loop {
+        let tpl = CURRENT_TPL.load(Ordering::Relaxed);
+        // the lock must be free while the notify function runs
+        let pending = EVENT.lock().take_pending_notify(tpl);
+        let ((event, notify_function, notify_context), notify_tpl) = match pending {
+            Some(pending) => pending,
+            None => break,
+        };
+        CURRENT_TPL.store(notify_tpl, Ordering::Relaxed);
+        notify_function(event, notify_context);
+        CURRENT_TPL.store(tpl, Ordering::Relaxed);
+    }
 }
 
 // #[cfg(not(test))]
@@ -829,32 +853,74 @@ pub extern "win64" fn create_event(
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn set_timer(_: Event, _: TimerDelay, _: u64) -> Status {
-    crate::log!("EFI_STUB: set_timer - UNSUPPORTED\n");
-    Status::UNSUPPORTED
+pub extern "win64" fn set_timer(event: Event, r#type: TimerDelay, trigger_time: u64) -> Status {
+    let status = EVENT
+        .lock()
+        .set_timer(event, r#type, trigger_time, timer::now_ns());
+    crate::log!(
+        "EFI_STUB: set_timer - type:{:?} trigger_time:{} - status: {:?}\n",
+        r#type,
+        trigger_time,
+        status
+    );
+    status
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn wait_for_event(_: usize, _: *mut Event, _: *mut usize) -> Status {
-    Status::UNSUPPORTED
+pub extern "win64" fn wait_for_event(
+    number_of_events: usize,
+    event: *mut Event,
+    index: *mut usize,
+) -> Status {
+    if number_of_events == 0 || event.is_null() || index.is_null() {
+        return Status::INVALID_PARAMETER;
+    }
+    let events = unsafe { core::slice::from_raw_parts(event, number_of_events) };
+    loop {
+        for (i, event) in events.iter().enumerate() {
+            match check_event(*event) {
+                Status::SUCCESS => {
+                    unsafe { *index = i };
+                    return Status::SUCCESS;
+                }
+                Status::NOT_READY => {}
+                status => {
+                    unsafe { *index = i };
+                    return status;
+                }
+            }
+        }
+        core::hint::spin_loop();
+    }
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn signal_event(_: Event) -> Status {
-    crate::log!("EFI_STUB: signal_event - UNSUPPORTED\n");
-    Status::UNSUPPORTED
+pub extern "win64" fn signal_event(event: Event) -> Status {
+    let (status, notify) = EVENT.lock().signal_event(event);
+    if let Some((event, notify_function, notify_context)) = notify {
+        notify_function(event, notify_context);
+    }
+    status
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn close_event(_: Event) -> Status {
-    crate::log!("EFI_STUB: close_event - UNSUPPORTED\n");
-    Status::UNSUPPORTED
+pub extern "win64" fn close_event(event: Event) -> Status {
+    EVENT.lock().close_event(event)
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn check_event(_: Event) -> Status {
-    crate::log!("EFI_STUB: check_event - UNSUPPORTED\n");
-    Status::UNSUPPORTED
+pub extern "win64" fn check_event(event: Event) -> Status {
+    // wait_for_event polls here, the expired timers are notified meanwhile
+    dispatch_timer_notify();
+    let (status, notify) = EVENT.lock().check_event(event);
+    match notify {
+        // the notify function of a wait event may signal it
+        Some((event, notify_function, notify_context)) => {
+            notify_function(event, notify_context);
+            EVENT.lock().check_event(event).0
+        }
+        None => status,
+    }
 }
 
 // #[cfg(not(test))]
@@ -1255,6 +1321,7 @@ pub extern "win64" fn unload_image(_: Handle) -> Status {
 // #[cfg(not(test))]
 pub extern "win64" fn exit_boot_services(_: Handle, _: usize) -> Status {
     crate::log!("EFI_STUB: exit_boot_services\n");
+    timer::stop();
     Status::SUCCESS
 }
 
@@ -1265,14 +1332,24 @@ pub extern "win64" fn get_next_monotonic_count(_: *mut u64) -> Status {
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn stall(_: usize) -> Status {
+pub extern "win64" fn stall(microseconds: usize) -> Status {
     crate::log!("EFI_STUB: stall - called\n");
+    timer::delay_us(microseconds as u64);
+    // boot loaders stall while waiting for a key, let GDB break in there
+    #[cfg(feature = "debugger")]
+    fw_debugger::poll();
     Status::SUCCESS
 }
 
 // #[cfg(not(test))]
-pub extern "win64" fn set_watchdog_timer(_: usize, _: u64, _: usize, _: *mut Char16) -> Status {
-    crate::log!("EFI_STUB: set_watchdog_timer\n");
+pub extern "win64" fn set_watchdog_timer(
+    timeout: usize,
+    _: u64,
+    _: usize,
+    _: *mut Char16,
+) -> Status {
+    crate::log!("EFI_STUB: set_watchdog_timer - timeout:{}\n", timeout);
+    timer::set_watchdog(timeout);
     Status::SUCCESS
 }
 
@@ -1805,6 +1882,8 @@ fn dup_device_path(device_path: *mut c_void) -> *mut core::ffi::c_void {
 }
 
 pub fn enter_uefi(hob: *const c_void) -> ! {
+    timer::init();
+
     unsafe {
         STDOUT.mode = &mut STDOUT_MODE;
         ST.con_in = &mut STDIN;
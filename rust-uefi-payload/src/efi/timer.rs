@@ -0,0 +1,139 @@
+// Copyright © 2019 Intel Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use fw_apic::{ClockSource, ReferenceTimer, Timer};
+use fw_hpet::Hpet;
+
+use crate::efi::EVENT;
+
+// PM_TMR_BLK of QEMU Q35, PMBASE 0x600
+const ACPI_PM_TIMER_PORT: u16 = 0x608;
+
+// The 8259 IRQs are moved off the exceptions, the tick is above them
+const PIC_MASTER_VECTOR: u8 = 0x20;
+const PIC_SLAVE_VECTOR: u8 = 0x28;
+const TIMER_VECTOR: u8 = 0x30;
+const TICK_US: u64 = 10_000;
+
+static mut TIMER: Option<Timer> = None;
+
+// Time in ns when the watchdog resets the platform, 0 when it is off
+static WATCHDOG_DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+///
+/// Calibrate the timer and start the tick, which needs the IDT set up by
+/// fw_exception::setup_exception_handlers().
+///
+/// The clock is the TSC when it is invariant, else the HPET, else the ACPI
+/// PM timer, else the TSC anyway.
+///
+pub fn init() {
+    // the payload has no ACPI tables, the HPET is looked for at its usual base
+    let hpet = Hpet::from_acpi(None);
+    match hpet {
+        Some(hpet) => crate::log!(
+            "Timer: no ACPI HPET table, HPET assumed at 0x{:x} - {}-bit counter\n",
+            hpet.base(),
+            64 - hpet.counter_mask().leading_zeros()
+        ),
+        None => crate::log!("Timer: no HPET at 0x{:x}\n", fw_hpet::HPET_DEFAULT_BASE),
+    }
+    let acpi_pm = fw_apic::acpi_pm_timer_running(ACPI_PM_TIMER_PORT);
+
+    let reference = match hpet {
+        Some(hpet) => ReferenceTimer::Hpet(hpet),
+        None if acpi_pm => ReferenceTimer::AcpiPm(ACPI_PM_TIMER_PORT),
+        None => ReferenceTimer::Pit,
+    };
+    let frequencies = fw_apic::calibrate(reference);
+    crate::log!(
+        "Timer: calibrated with {:?} - TSC {} Hz, APIC timer {} Hz\n",
+        reference,
+        frequencies.tsc,
+        frequencies.apic_timer
+    );
+
+    let source = match hpet {
+        _ if fw_apic::invariant_tsc() => ClockSource::Tsc,
+        Some(hpet) => ClockSource::Hpet(hpet),
+        None if acpi_pm => ClockSource::AcpiPm(ACPI_PM_TIMER_PORT),
+        None => ClockSource::Tsc,
+    };
+    crate::log!("Timer: clock source {:?}\n", source);
+
+    fw_apic::remap_pic(PIC_MASTER_VECTOR, PIC_SLAVE_VECTOR);
+    fw_apic::mask_pic();
+
+    unsafe {
+        TIMER = Some(Timer::new(frequencies, source));
+        let timer = TIMER.as_mut().unwrap();
+        timer.register_callback(tick, 0);
+        if timer.start(TIMER_VECTOR, TICK_US) {
+            asm!("sti");
+        } else {
+            crate::log!("Timer: no tick, the IDT is not the one of fw-exception\n");
+        }
+    }
+}
+
+///
+/// Stop the tick and the watchdog and leave the interrupts off, at
+/// ExitBootServices the OS owns the IDT and the local APIC.
+///
+pub fn stop() {
+    unsafe {
+        asm!("cli");
+        if let Some(timer) = TIMER.as_mut() {
+            timer.stop();
+        }
+    }
+    WATCHDOG_DEADLINE.store(0, Ordering::Relaxed);
+}
+
+/// The time since init, 0 before.
+pub fn now_ns() -> u64 {
+    unsafe { TIMER.as_ref() }.map_or(0, |timer| timer.now_ns())
+}
+
+pub fn delay_us(us: u64) {
+    if let Some(timer) = unsafe { TIMER.as_ref() } {
+        timer.delay_us(us);
+    }
+}
+
+/// Reset the platform after timeout seconds, 0 disables the watchdog.
+pub fn set_watchdog(timeout: usize) {
+    let deadline = if timeout == 0 {
+        0
+    } else {
+        now_ns().saturating_add((timeout as u64).saturating_mul(1_000_000_000))
+    };
+    WATCHDOG_DEADLINE.store(deadline, Ordering::Relaxed);
+}
+
+// Nothing here may wait for a lock the interrupted code could hold, so the
+// timers are only signaled, their notify functions run from the boot services
+fn tick(_: usize, now_ns: u64) {
+    let deadline = WATCHDOG_DEADLINE.load(Ordering::Relaxed);
+    if deadline != 0 && now_ns >= deadline {
+        crate::i8042_reset();
+    }
+
+    // else the timers expire on the next tick
+    if let Some(mut event) = EVENT.try_lock() {
+        event.expire_timers(now_ns);
+    }
+}
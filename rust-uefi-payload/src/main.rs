@@ -49,9 +49,38 @@ mod virtio;
 #[allow(clippy::empty_loop)]
 fn panic(_info: &PanicInfo) -> ! {
     log!("panic ... {:?}\n", _info);
+    fw_exception::print_backtrace();
     loop {}
 }
 
+///
+/// Find the stack and the payload image in the memory allocation HOBs, for
+/// the backtraces of the IDT in use.
+///
+fn init_backtrace(hob: *const c_void) {
+    use crate::pi::hob::{HYPERVISORFW_NAME_GUID, MEMORY_ALLOCATION_STACK_GUID};
+    use crate::pi::hob_lib::get_memory_allocation;
+
+    let stack = get_memory_allocation(hob, &MEMORY_ALLOCATION_STACK_GUID)
+        .map_or(0..0, |(base, length)| base as usize..(base + length) as usize);
+    let module = get_memory_allocation(hob, &HYPERVISORFW_NAME_GUID).map(|(base, length)| {
+        let image = unsafe { core::slice::from_raw_parts(base as *const u8, length as usize) };
+        let symbols = if pe_loader::pe::is_pe(image) {
+            pe_loader::pe::find_loaded_section(image, fw_exception::backtrace::SYMBOL_TABLE_SECTION)
+                .and_then(fw_exception::SymbolTable::new)
+        } else {
+            None
+        };
+        fw_exception::Module {
+            name: "rust-uefi-payload",
+            base: base as usize,
+            size: length as usize,
+            symbols,
+        }
+    });
+    fw_exception::set_backtrace(stack, module);
+}
+
 // #[cfg(not(test))]
 /// Reset the VM via the keyboard controller
 fn i8042_reset() -> ! {
@@ -85,6 +114,19 @@ fn i8042_reset() -> ! {
 pub extern "win64" fn _start(hob: *const c_void) -> ! {
     log!("Starting UEFI hob - {:p}\n", hob);
 
+    fw_exception::setup_exception_handlers();
+
+    init_backtrace(hob);
+
+    // A GDB still attached from the IPL waits for a stop, interrupting it
+    // gets the one of this breakpoint
+    #[cfg(feature = "debugger")]
+    let mut debugger = fw_debugger::Debugger::new(fw_debugger::COM2_PORT);
+    #[cfg(feature = "debugger")]
+    if unsafe { debugger.install_current() } {
+        fw_debugger::breakpoint();
+    }
+
     //enable_sse2();
 
     efi::enter_uefi(hob);
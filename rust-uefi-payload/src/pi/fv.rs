@@ -97,6 +97,9 @@ pub const FV_FILETYPE_FFS_PAD: u8 = 0xF0;
 pub type FfsFileAttributes = u8;
 pub type FfsFileState = u8;
 
+// FFS File Attributes.
+pub const FFS_ATTRIB_LARGE_FILE: u8 = 0x01;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct FfsFileHeader {
@@ -49,10 +49,16 @@ fn get_image_from_sections(
         }
         log!("    checking section - 0x{:x}\n", current_ptr);
         let section_header = unsafe { transmute::<usize, &CommonSectionHeader>(current_ptr) };
-        let section_size = section_header.size[0] as usize
+        let mut section_size = section_header.size[0] as usize
             + ((section_header.size[1] as usize) << 8)
             + ((section_header.size[2] as usize) << 16);
-        let section_header_size = size_of::<CommonSectionHeader>() as usize;
+        let mut section_header_size = size_of::<CommonSectionHeader>() as usize;
+        if section_size == 0xffffff {
+            let section_header2 =
+                unsafe { transmute::<usize, &CommonSectionHeader2>(current_ptr) };
+            section_size = section_header2.extended_size as usize;
+            section_header_size = size_of::<CommonSectionHeader2>() as usize;
+        }
         if section_size < section_header_size {
             break;
         }
@@ -104,11 +110,16 @@ fn get_image_from_fv(
         log!("  checking ffs - 0x{:x}\n", current_ptr);
 
         let ffs_header = unsafe { transmute::<usize, &FfsFileHeader>(current_ptr) };
-        let ffs_size = ffs_header.size[0] as usize
+        let mut ffs_size = ffs_header.size[0] as usize
             + ((ffs_header.size[1] as usize) << 8)
             + ((ffs_header.size[2] as usize) << 16);
+        let mut ffs_header_size = size_of::<FfsFileHeader>() as usize;
+        if ffs_header.attributes & FFS_ATTRIB_LARGE_FILE != 0 {
+            let ffs_header2 = unsafe { transmute::<usize, &FfsFileHeader2>(current_ptr) };
+            ffs_size = ffs_header2.extended_size as usize;
+            ffs_header_size = size_of::<FfsFileHeader2>() as usize;
+        }
         log!("    ffs size - 0x{:x}\n", ffs_size);
-        let ffs_header_size = size_of::<FfsFileHeader>() as usize;
         let section_header_size = size_of::<CommonSectionHeader>() as usize;
         if ffs_size < ffs_header_size + section_header_size {
             break;
@@ -27,6 +27,34 @@ pub const HOB_LIST_GUID: Guid = Guid::from_fields(
     &[0x00, 0x90, 0x27, 0x3F, 0xC1, 0x4D],
 );
 
+pub const PAGING_MODE_GUID: Guid = Guid::from_fields(
+    0xB940F79E,
+    0xB559,
+    0x4C07,
+    0x99,
+    0x9C,
+    &[0x86, 0x25, 0x36, 0xC0, 0xD8, 0x0E],
+);
+
+pub const MEMORY_ALLOCATION_STACK_GUID: Guid = Guid::from_fields(
+    0x4ED4BF27,
+    0x4092,
+    0x42E9,
+    0x80,
+    0x7D,
+    &[0x52, 0x7B, 0x1D, 0x00, 0xC9, 0xBD],
+);
+
+/// The memory allocation of the payload image.
+pub const HYPERVISORFW_NAME_GUID: Guid = Guid::from_fields(
+    0x6948d4a,
+    0xd359,
+    0x4721,
+    0xad,
+    0xf6,
+    &[0x52, 0x25, 0x48, 0x5a, 0x6a, 0x3a],
+);
+
 pub type ResourceType = u32;
 
 pub const RESOURCE_SYSTEM_MEMORY: u32 = 0x00;
@@ -175,3 +203,22 @@ pub struct Cpu {
     pub size_of_io_space: u8,
     pub reserved: [u8; 6],
 }
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GuidExtension {
+    pub header: Header,
+    pub name: Guid,
+}
+
+pub const PAGING_MODE_1G_PAGE: u32 = 0x1;
+pub const PAGING_MODE_EXECUTE_DISABLE: u32 = 0x2;
+
+/// The paging mode the IPL enters the payload with, PAGING_MODE_GUID.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PagingMode {
+    pub header: GuidExtension,
+    pub paging_levels: u32,
+    pub attributes: u32,
+}
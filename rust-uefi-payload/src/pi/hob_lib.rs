@@ -102,6 +102,20 @@ fn dump_cpu_hob(cpu_hob: &Cpu) {
     );
 }
 
+// #[cfg(not(test))]
+fn dump_guid_hob(guid_hob: &GuidExtension) {
+    if guid_hob.name == PAGING_MODE_GUID {
+        let paging_mode = unsafe { transmute::<&GuidExtension, &PagingMode>(guid_hob) };
+        log!(
+            "PagingMode : {}-level, attributes 0x{:x}\n",
+            paging_mode.paging_levels,
+            paging_mode.attributes
+        );
+    } else {
+        log!("GuidExtension : {:?}\n", guid_hob.name);
+    }
+}
+
 // #[cfg(not(test))]
 pub fn dump_hob(hob: *const c_void) {
     let mut hob_header: *const Header = hob as *const Header;
@@ -131,6 +145,10 @@ pub fn dump_hob(hob: *const c_void) {
                 let cpu_hob = unsafe { transmute::<*const Header, &Cpu>(hob_header) };
                 dump_cpu_hob(cpu_hob);
             }
+            HOB_TYPE_GUID_EXTENSION => {
+                let guid_hob = unsafe { transmute::<*const Header, &GuidExtension>(hob_header) };
+                dump_guid_hob(guid_hob);
+            }
             HOB_TYPE_END_OF_HOB_LIST => {
                 break;
             }
@@ -143,6 +161,31 @@ pub fn dump_hob(hob: *const c_void) {
     }
 }
 
+/// The base and the length of the memory allocation HOB with the name.
+pub fn get_memory_allocation(hob: *const c_void, name: &efi::Guid) -> Option<(u64, u64)> {
+    let mut hob_header: *const Header = hob as *const Header;
+
+    loop {
+        let header = unsafe { transmute::<*const Header, &Header>(hob_header) };
+        match header.r#type {
+            HOB_TYPE_MEMORY_ALLOCATION => {
+                let allocation_hob =
+                    unsafe { transmute::<*const Header, &MemoryAllocation>(hob_header) };
+                let descriptor = &allocation_hob.alloc_descriptor;
+                if descriptor.name == *name {
+                    return Some((descriptor.memory_base_address, descriptor.memory_length));
+                }
+            }
+            HOB_TYPE_END_OF_HOB_LIST => {
+                return None;
+            }
+            _ => {}
+        }
+        let addr = hob_header as usize + header.length as usize;
+        hob_header = addr as *const Header;
+    }
+}
+
 // #[cfg(not(test))]
 pub fn get_hob_total_size(hob: *const c_void) -> usize {
     let phit = unsafe { transmute::<*const c_void, &HandoffInfoTable>(hob) };
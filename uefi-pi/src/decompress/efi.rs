@@ -0,0 +1,478 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! UEFI (EFI_STANDARD_COMPRESSION) and Tiano decompression.
+//!
+//! Both formats are LZ77 with static Huffman coded blocks, they only differ
+//! in the number of bits of the position set. The source starts with the
+//! 32-bit compressed size and the 32-bit original size.
+//!
+
+use super::DecompressError;
+
+const BITBUFSIZ: u32 = 32;
+const MAXMATCH: usize = 256;
+const THRESHOLD: usize = 3;
+const CODE_BIT: usize = 16;
+
+// C: Char&Len Set, P: Position Set, T: exTra Set
+const NC: usize = 0xff + MAXMATCH + 2 - THRESHOLD;
+const CBIT: u32 = 9;
+const MAXPBIT: u32 = 5;
+const TBIT: u32 = 5;
+const MAXNP: usize = (1 << MAXPBIT) - 1;
+const NT: usize = CODE_BIT + 3;
+const NPT: usize = if NT > MAXNP { NT } else { MAXNP };
+
+const UEFI_PBIT: u32 = 4;
+const TIANO_PBIT: u32 = 5;
+
+const HEADER_SIZE: usize = 8;
+
+// where MakeTable stores the next node of a code longer than the table bits
+#[derive(Clone, Copy)]
+enum Node {
+    Table(usize),
+    Left(usize),
+    Right(usize),
+}
+
+struct Decoder<'a> {
+    source: &'a [u8],
+    in_pos: usize,
+    comp_size: usize,
+
+    bit_buf: u32,
+    sub_bit_buf: u32,
+    bit_count: u32,
+
+    p_bit: u32,
+    block_size: u16,
+
+    left: [u16; 2 * NC - 1],
+    right: [u16; 2 * NC - 1],
+    c_len: [u8; NC],
+    pt_len: [u8; NPT],
+    c_table: [u16; 4096],
+    pt_table: [u16; 256],
+}
+
+fn shl(value: u32, bits: u32) -> u32 {
+    value.checked_shl(bits).unwrap_or(0)
+}
+
+fn shr(value: u32, bits: u32) -> u32 {
+    value.checked_shr(bits).unwrap_or(0)
+}
+
+impl<'a> Decoder<'a> {
+    fn new(source: &'a [u8], comp_size: usize, p_bit: u32) -> Self {
+        Decoder {
+            source,
+            in_pos: 0,
+            comp_size,
+            bit_buf: 0,
+            sub_bit_buf: 0,
+            bit_count: 0,
+            p_bit,
+            block_size: 0,
+            left: [0; 2 * NC - 1],
+            right: [0; 2 * NC - 1],
+            c_len: [0; NC],
+            pt_len: [0; NPT],
+            c_table: [0; 4096],
+            pt_table: [0; 256],
+        }
+    }
+
+    // shift bits in, zeros are shifted in once the compressed data is consumed.
+    fn fill_buf(&mut self, bits: u32) {
+        let mut bits = bits;
+        self.bit_buf = shl(self.bit_buf, bits);
+        while bits > self.bit_count {
+            bits -= self.bit_count;
+            self.bit_buf |= shl(self.sub_bit_buf, bits);
+            if self.comp_size > 0 {
+                self.comp_size -= 1;
+                self.sub_bit_buf = self.source[self.in_pos] as u32;
+                self.in_pos += 1;
+            } else {
+                self.sub_bit_buf = 0;
+            }
+            self.bit_count = 8;
+        }
+        self.bit_count -= bits;
+        self.bit_buf |= self.sub_bit_buf >> self.bit_count;
+    }
+
+    fn get_bits(&mut self, bits: u32) -> u32 {
+        let out_bits = shr(self.bit_buf, BITBUFSIZ - bits);
+        self.fill_buf(bits);
+        out_bits
+    }
+
+    fn node(&self, table: &[u16], node: Node) -> u16 {
+        match node {
+            Node::Table(index) => table[index],
+            Node::Left(index) => self.left[index],
+            Node::Right(index) => self.right[index],
+        }
+    }
+
+    fn set_node(&mut self, table: &mut [u16], node: Node, value: u16) {
+        match node {
+            Node::Table(index) => table[index] = value,
+            Node::Left(index) => self.left[index] = value,
+            Node::Right(index) => self.right[index] = value,
+        }
+    }
+
+    // Build the lookup table of a canonical Huffman code, codes longer than
+    // table_bits continue in the left/right trees.
+    fn make_table(
+        &mut self,
+        bit_len: &[u8],
+        table_bits: u32,
+        table: &mut [u16],
+    ) -> Result<(), DecompressError> {
+        let mut count = [0u16; 17];
+        let mut weight = [0u16; 17];
+        let mut start = [0u16; 18];
+
+        for len in bit_len.iter() {
+            if *len > 16 {
+                return Err(DecompressError::CorruptedData);
+            }
+            count[*len as usize] += 1;
+        }
+
+        for index in 1..=16 {
+            start[index + 1] =
+                start[index].wrapping_add(count[index].wrapping_shl(16 - index as u32));
+        }
+        // the code space must be exactly full
+        if start[17] != 0 {
+            return Err(DecompressError::CorruptedData);
+        }
+
+        let ju_bits = 16 - table_bits;
+        for index in 1..=table_bits as usize {
+            start[index] >>= ju_bits;
+            weight[index] = 1 << (table_bits as usize - index);
+        }
+        for (index, weight) in weight.iter_mut().enumerate().skip(table_bits as usize + 1) {
+            *weight = 1 << (16 - index);
+        }
+
+        // entries past the short codes are the roots of the trees
+        let index = (start[table_bits as usize + 1] >> ju_bits) as usize;
+        let table_size = 1usize << table_bits;
+        if index < table_size {
+            table[index..table_size].iter_mut().for_each(|v| *v = 0);
+        }
+
+        let mut avail = bit_len.len() as u16;
+        let mask = 1u16 << (15 - table_bits);
+        for (char_c, len) in bit_len.iter().enumerate() {
+            let len = *len as usize;
+            if len == 0 {
+                continue;
+            }
+            let next_code = start[len].wrapping_add(weight[len]);
+            if len <= table_bits as usize {
+                if start[len] >= next_code || next_code as usize > table_size {
+                    return Err(DecompressError::CorruptedData);
+                }
+                table[start[len] as usize..next_code as usize]
+                    .iter_mut()
+                    .for_each(|v| *v = char_c as u16);
+            } else {
+                let mut index3 = start[len];
+                let mut node = Node::Table((index3 >> ju_bits) as usize);
+                for _ in 0..len - table_bits as usize {
+                    if self.node(table, node) == 0 && (avail as usize) < 2 * NC - 1 {
+                        self.right[avail as usize] = 0;
+                        self.left[avail as usize] = 0;
+                        self.set_node(table, node, avail);
+                        avail += 1;
+                    }
+                    let value = self.node(table, node) as usize;
+                    if value < 2 * NC - 1 {
+                        node = if index3 & mask != 0 {
+                            Node::Right(value)
+                        } else {
+                            Node::Left(value)
+                        };
+                    }
+                    index3 <<= 1;
+                }
+                self.set_node(table, node, char_c as u16);
+            }
+            start[len] = next_code;
+        }
+        Ok(())
+    }
+
+    // follow the left/right trees from a table entry
+    fn walk_tree(
+        &self,
+        mut value: u16,
+        table_bits: u32,
+        limit: usize,
+    ) -> Result<u16, DecompressError> {
+        let mut mask = 1u32 << (BITBUFSIZ - 1 - table_bits);
+        while value as usize >= limit {
+            if value as usize >= 2 * NC - 1 || mask == 0 {
+                return Err(DecompressError::CorruptedData);
+            }
+            value = if self.bit_buf & mask != 0 {
+                self.right[value as usize]
+            } else {
+                self.left[value as usize]
+            };
+            mask >>= 1;
+        }
+        Ok(value)
+    }
+
+    fn read_pt_len(&mut self, nn: usize, nbit: u32, special: usize) -> Result<(), DecompressError> {
+        let number = self.get_bits(nbit) as usize;
+        if number == 0 {
+            let char_c = self.get_bits(nbit) as u16;
+            self.pt_table.iter_mut().for_each(|v| *v = char_c);
+            self.pt_len[..nn].iter_mut().for_each(|v| *v = 0);
+            return Ok(());
+        }
+
+        let mut index = 0;
+        while index < number && index < NPT {
+            let mut char_c = self.bit_buf >> (BITBUFSIZ - 3);
+            if char_c == 7 {
+                let mut mask = 1u32 << (BITBUFSIZ - 1 - 3);
+                while mask & self.bit_buf != 0 {
+                    mask >>= 1;
+                    char_c += 1;
+                }
+            }
+            self.fill_buf(if char_c < 7 { 3 } else { char_c - 3 });
+            self.pt_len[index] = char_c as u8;
+            index += 1;
+            if index == special {
+                let zeros = self.get_bits(2) as usize;
+                for _ in 0..zeros {
+                    if index >= NPT {
+                        break;
+                    }
+                    self.pt_len[index] = 0;
+                    index += 1;
+                }
+            }
+        }
+        while index < nn && index < NPT {
+            self.pt_len[index] = 0;
+            index += 1;
+        }
+
+        let pt_len = self.pt_len;
+        let mut pt_table = self.pt_table;
+        self.make_table(&pt_len[..nn], 8, &mut pt_table)?;
+        self.pt_table = pt_table;
+        Ok(())
+    }
+
+    fn read_c_len(&mut self) -> Result<(), DecompressError> {
+        let number = self.get_bits(CBIT) as usize;
+        if number == 0 {
+            let char_c = self.get_bits(CBIT) as u16;
+            self.c_len.iter_mut().for_each(|v| *v = 0);
+            self.c_table.iter_mut().for_each(|v| *v = char_c);
+            return Ok(());
+        }
+
+        let mut index = 0;
+        while index < number && index < NC {
+            let char_c = self.pt_table[(self.bit_buf >> (BITBUFSIZ - 8)) as usize];
+            let char_c = self.walk_tree(char_c, 8, NT)? as usize;
+            self.fill_buf(self.pt_len[char_c] as u32);
+            if char_c <= 2 {
+                let zeros = match char_c {
+                    0 => 1,
+                    1 => self.get_bits(4) as usize + 3,
+                    _ => self.get_bits(CBIT) as usize + 20,
+                };
+                for _ in 0..zeros {
+                    if index >= NC {
+                        break;
+                    }
+                    self.c_len[index] = 0;
+                    index += 1;
+                }
+            } else {
+                self.c_len[index] = (char_c - 2) as u8;
+                index += 1;
+            }
+        }
+        self.c_len[index..].iter_mut().for_each(|v| *v = 0);
+
+        let c_len = self.c_len;
+        let mut c_table = self.c_table;
+        self.make_table(&c_len, 12, &mut c_table)?;
+        self.c_table = c_table;
+        Ok(())
+    }
+
+    fn decode_c(&mut self) -> Result<usize, DecompressError> {
+        if self.block_size == 0 {
+            self.block_size = self.get_bits(16) as u16;
+            self.read_pt_len(NT, TBIT, 3)?;
+            self.read_c_len()?;
+            self.read_pt_len(MAXNP, self.p_bit, usize::MAX)?;
+        }
+        self.block_size = self.block_size.wrapping_sub(1);
+
+        let index = self.c_table[(self.bit_buf >> (BITBUFSIZ - 12)) as usize];
+        let index = self.walk_tree(index, 12, NC)? as usize;
+        self.fill_buf(self.c_len[index] as u32);
+        Ok(index)
+    }
+
+    fn decode_p(&mut self) -> Result<usize, DecompressError> {
+        let value = self.pt_table[(self.bit_buf >> (BITBUFSIZ - 8)) as usize];
+        let value = self.walk_tree(value, 8, MAXNP)? as u32;
+        self.fill_buf(self.pt_len[value as usize] as u32);
+        if value > 1 {
+            Ok(((1u32 << (value - 1)) + self.get_bits(value - 1)) as usize)
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn decode(&mut self, destination: &mut [u8]) -> Result<(), DecompressError> {
+        let mut out_pos = 0;
+        self.fill_buf(BITBUFSIZ);
+        while out_pos < destination.len() {
+            let char_c = self.decode_c()?;
+            if char_c < 256 {
+                destination[out_pos] = char_c as u8;
+                out_pos += 1;
+            } else {
+                let length = char_c - (256 - THRESHOLD);
+                let distance = self.decode_p()? + 1;
+                if distance > out_pos {
+                    return Err(DecompressError::CorruptedData);
+                }
+                for _ in 0..length {
+                    if out_pos >= destination.len() {
+                        break;
+                    }
+                    destination[out_pos] = destination[out_pos - distance];
+                    out_pos += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// return (compressed size, original size)
+fn read_header(source: &[u8]) -> Result<(usize, usize), DecompressError> {
+    if source.len() < HEADER_SIZE {
+        return Err(DecompressError::InvalidHeader);
+    }
+    let comp_size = u32::from_le_bytes([source[0], source[1], source[2], source[3]]) as usize;
+    let orig_size = u32::from_le_bytes([source[4], source[5], source[6], source[7]]) as usize;
+    if comp_size > source.len() - HEADER_SIZE {
+        return Err(DecompressError::InvalidHeader);
+    }
+    Ok((comp_size, orig_size))
+}
+
+///
+/// Return the decompressed size of UEFI or Tiano compressed data.
+///
+pub fn uefi_decompress_get_info(source: &[u8]) -> Result<usize, DecompressError> {
+    read_header(source).map(|(_, orig_size)| orig_size)
+}
+
+fn decompress(source: &[u8], destination: &mut [u8], p_bit: u32) -> Result<(), DecompressError> {
+    let (comp_size, orig_size) = read_header(source)?;
+    if destination.len() < orig_size {
+        return Err(DecompressError::BufferTooSmall);
+    }
+    if orig_size == 0 {
+        return Ok(());
+    }
+    let mut decoder = Decoder::new(&source[HEADER_SIZE..], comp_size, p_bit);
+    decoder.decode(&mut destination[..orig_size])
+}
+
+///
+/// Decompress EFI_STANDARD_COMPRESSION data.
+///
+/// destination must hold the size returned by uefi_decompress_get_info.
+///
+pub fn uefi_decompress(source: &[u8], destination: &mut [u8]) -> Result<(), DecompressError> {
+    decompress(source, destination, UEFI_PBIT)
+}
+
+///
+/// Decompress Tiano compressed data, the data of a TIANO_CUSTOM_DECOMPRESS_GUID
+/// section.
+///
+pub fn tiano_decompress(source: &[u8], destination: &mut [u8]) -> Result<(), DecompressError> {
+    decompress(source, destination, TIANO_PBIT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compressed_data::*;
+
+    #[test]
+    fn test_uefi_decompress() {
+        for (compressed, decompress) in &[
+            (
+                &UEFI_COMPRESSED_DATA[..],
+                uefi_decompress as fn(&[u8], &mut [u8]) -> _,
+            ),
+            (&TIANO_COMPRESSED_DATA[..], tiano_decompress),
+        ] {
+            assert_eq!(
+                uefi_decompress_get_info(compressed),
+                Ok(UNCOMPRESSED_DATA.len())
+            );
+            let mut buffer = [0u8; UNCOMPRESSED_DATA.len()];
+            assert_eq!(decompress(compressed, &mut buffer), Ok(()));
+            assert_eq!(&buffer[..], &UNCOMPRESSED_DATA[..]);
+
+            assert_eq!(
+                decompress(compressed, &mut buffer[1..]),
+                Err(DecompressError::BufferTooSmall)
+            );
+            assert_eq!(
+                decompress(&compressed[..compressed.len() - 1], &mut buffer),
+                Err(DecompressError::InvalidHeader)
+            );
+        }
+
+        // the position codes of UEFI and Tiano compression differ.
+        let mut buffer = [0u8; UNCOMPRESSED_DATA.len()];
+        let result = tiano_decompress(&UEFI_COMPRESSED_DATA, &mut buffer);
+        assert!(result.is_err() || buffer[..] != UNCOMPRESSED_DATA[..]);
+    }
+
+    #[test]
+    fn test_uefi_decompress_corrupted() {
+        // corrupted data must fail or decompress to garbage, without panic.
+        let mut buffer = [0u8; UNCOMPRESSED_DATA.len()];
+        for offset in HEADER_SIZE..UEFI_COMPRESSED_DATA.len() {
+            for bit in 0..8 {
+                let mut compressed = UEFI_COMPRESSED_DATA;
+                compressed[offset] ^= 1 << bit;
+                let _ = uefi_decompress(&compressed, &mut buffer);
+            }
+        }
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! LZMA decompression of the data of a LZMA_CUSTOM_DECOMPRESS_GUID section.
+//!
+//! The data is the .lzma (LZMA_Alone) format: 1 byte of lc/lp/pb properties,
+//! the 32-bit dictionary size, the 64-bit decompressed size and then the
+//! range coded stream. The destination buffer is used as the dictionary, so
+//! no memory is allocated.
+//!
+
+use super::DecompressError;
+
+const HEADER_SIZE: usize = 13;
+
+const NUM_BIT_MODEL_TOTAL_BITS: u32 = 11;
+const BIT_MODEL_TOTAL: u32 = 1 << NUM_BIT_MODEL_TOTAL_BITS;
+const NUM_MOVE_BITS: u32 = 5;
+const PROB_INIT: u16 = (BIT_MODEL_TOTAL / 2) as u16;
+const TOP_VALUE: u32 = 1 << 24;
+
+const NUM_STATES: usize = 12;
+const NUM_POS_BITS_MAX: usize = 4;
+const NUM_LEN_TO_POS_STATES: usize = 4;
+const NUM_ALIGN_BITS: usize = 4;
+const START_POS_MODEL_INDEX: u32 = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const NUM_FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+
+// lc + lp is limited as LZMA2 does, it bounds the literal probabilities.
+const MAX_LC_PLUS_LP: u32 = 4;
+const LITERAL_CODER_SIZE: usize = 0x300;
+
+struct RangeDecoder<'a> {
+    source: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(source: &'a [u8]) -> Result<Self, DecompressError> {
+        if source.len() < 5 || source[0] != 0 {
+            return Err(DecompressError::CorruptedData);
+        }
+        let code = u32::from_be_bytes([source[1], source[2], source[3], source[4]]);
+        if code == 0xFFFF_FFFF {
+            return Err(DecompressError::CorruptedData);
+        }
+        Ok(RangeDecoder {
+            source,
+            pos: 5,
+            range: 0xFFFF_FFFF,
+            code,
+        })
+    }
+
+    fn normalize(&mut self) -> Result<(), DecompressError> {
+        if self.range < TOP_VALUE {
+            let byte = *self
+                .source
+                .get(self.pos)
+                .ok_or(DecompressError::CorruptedData)?;
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = (self.code << 8) | byte as u32;
+        }
+        Ok(())
+    }
+
+    fn decode_direct_bits(&mut self, num_bits: u32) -> Result<u32, DecompressError> {
+        let mut result = 0u32;
+        for _ in 0..num_bits {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let t = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & t);
+            if self.code == self.range {
+                return Err(DecompressError::CorruptedData);
+            }
+            self.normalize()?;
+            result = (result << 1).wrapping_add(t.wrapping_add(1));
+        }
+        Ok(result)
+    }
+
+    fn decode_bit(&mut self, prob: &mut u16) -> Result<u32, DecompressError> {
+        let bound = (self.range >> NUM_BIT_MODEL_TOTAL_BITS) * *prob as u32;
+        let symbol = if self.code < bound {
+            *prob += ((BIT_MODEL_TOTAL - *prob as u32) >> NUM_MOVE_BITS) as u16;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> NUM_MOVE_BITS;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+        Ok(symbol)
+    }
+
+    fn decode_bit_tree(
+        &mut self,
+        probs: &mut [u16],
+        num_bits: u32,
+    ) -> Result<u32, DecompressError> {
+        let mut m = 1usize;
+        for _ in 0..num_bits {
+            m = (m << 1) + self.decode_bit(&mut probs[m])? as usize;
+        }
+        Ok(m as u32 - (1 << num_bits))
+    }
+
+    fn decode_reverse_bit_tree(
+        &mut self,
+        probs: &mut [u16],
+        num_bits: u32,
+    ) -> Result<u32, DecompressError> {
+        let mut m = 1usize;
+        let mut symbol = 0u32;
+        for i in 0..num_bits {
+            let bit = self.decode_bit(&mut probs[m])?;
+            m = (m << 1) + bit as usize;
+            symbol |= bit << i;
+        }
+        Ok(symbol)
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    mid: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> Self {
+        LenDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            mid: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(
+        &mut self,
+        rc: &mut RangeDecoder,
+        pos_state: usize,
+    ) -> Result<usize, DecompressError> {
+        if rc.decode_bit(&mut self.choice)? == 0 {
+            return Ok(rc.decode_bit_tree(&mut self.low[pos_state], 3)? as usize);
+        }
+        if rc.decode_bit(&mut self.choice2)? == 0 {
+            return Ok(8 + rc.decode_bit_tree(&mut self.mid[pos_state], 3)? as usize);
+        }
+        Ok(16 + rc.decode_bit_tree(&mut self.high, 8)? as usize)
+    }
+}
+
+struct Decoder {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+
+    literal_probs: [u16; LITERAL_CODER_SIZE << MAX_LC_PLUS_LP],
+    pos_slot: [[u16; 1 << 6]; NUM_LEN_TO_POS_STATES],
+    pos_decoders: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << NUM_ALIGN_BITS],
+    is_match: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    is_rep: [u16; NUM_STATES],
+    is_rep_g0: [u16; NUM_STATES],
+    is_rep_g1: [u16; NUM_STATES],
+    is_rep_g2: [u16; NUM_STATES],
+    is_rep0_long: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    len_decoder: LenDecoder,
+    rep_len_decoder: LenDecoder,
+}
+
+impl Decoder {
+    fn new(properties: u8) -> Result<Self, DecompressError> {
+        let mut d = properties as u32;
+        if d >= 9 * 5 * 5 {
+            return Err(DecompressError::InvalidHeader);
+        }
+        let lc = d % 9;
+        d /= 9;
+        let lp = d % 5;
+        let pb = d / 5;
+        if lc + lp > MAX_LC_PLUS_LP {
+            return Err(DecompressError::Unsupported);
+        }
+        Ok(Decoder {
+            lc,
+            lp,
+            pb,
+            literal_probs: [PROB_INIT; LITERAL_CODER_SIZE << MAX_LC_PLUS_LP],
+            pos_slot: [[PROB_INIT; 1 << 6]; NUM_LEN_TO_POS_STATES],
+            pos_decoders: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROB_INIT; 1 << NUM_ALIGN_BITS],
+            is_match: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            len_decoder: LenDecoder::new(),
+            rep_len_decoder: LenDecoder::new(),
+        })
+    }
+
+    fn decode_literal(
+        &mut self,
+        rc: &mut RangeDecoder,
+        output: &mut [u8],
+        out_pos: usize,
+        state: usize,
+        rep0: usize,
+    ) -> Result<(), DecompressError> {
+        let prev_byte = if out_pos > 0 { output[out_pos - 1] } else { 0 };
+        let lit_state =
+            ((out_pos & ((1 << self.lp) - 1)) << self.lc) + (prev_byte as usize >> (8 - self.lc));
+        let probs = &mut self.literal_probs[LITERAL_CODER_SIZE * lit_state..];
+
+        let mut symbol = 1usize;
+        if state >= 7 {
+            // the literal is coded relative to the byte at the last match distance
+            let mut match_byte = output[out_pos - rep0 - 1] as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = rc.decode_bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                symbol = (symbol << 1) | bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | rc.decode_bit(&mut probs[symbol])? as usize;
+        }
+        output[out_pos] = (symbol - 0x100) as u8;
+        Ok(())
+    }
+
+    // return u32::MAX for the end marker
+    fn decode_distance(
+        &mut self,
+        rc: &mut RangeDecoder,
+        len: usize,
+    ) -> Result<u32, DecompressError> {
+        let len_state = core::cmp::min(len, NUM_LEN_TO_POS_STATES - 1);
+        let pos_slot = rc.decode_bit_tree(&mut self.pos_slot[len_state], 6)?;
+        if pos_slot < START_POS_MODEL_INDEX {
+            return Ok(pos_slot);
+        }
+        let num_direct_bits = (pos_slot >> 1) - 1;
+        let mut distance = (2 | (pos_slot & 1)) << num_direct_bits;
+        if pos_slot < END_POS_MODEL_INDEX {
+            let probs = &mut self.pos_decoders[(distance - pos_slot) as usize..];
+            distance += rc.decode_reverse_bit_tree(probs, num_direct_bits)?;
+        } else {
+            distance = distance.wrapping_add(
+                rc.decode_direct_bits(num_direct_bits - NUM_ALIGN_BITS as u32)? << NUM_ALIGN_BITS,
+            );
+            distance = distance
+                .wrapping_add(rc.decode_reverse_bit_tree(&mut self.align, NUM_ALIGN_BITS as u32)?);
+        }
+        Ok(distance)
+    }
+
+    // decode until the output is full, an end marker is only allowed at the end.
+    fn decode(&mut self, rc: &mut RangeDecoder, output: &mut [u8]) -> Result<(), DecompressError> {
+        let mut reps = [0usize; 4];
+        let mut state = 0usize;
+        let mut out_pos = 0usize;
+
+        while out_pos < output.len() {
+            let pos_state = out_pos & ((1 << self.pb) - 1);
+            let state2 = (state << NUM_POS_BITS_MAX) + pos_state;
+
+            if rc.decode_bit(&mut self.is_match[state2])? == 0 {
+                self.decode_literal(rc, output, out_pos, state, reps[0])?;
+                out_pos += 1;
+                state = if state < 4 {
+                    0
+                } else if state < 10 {
+                    state - 3
+                } else {
+                    state - 6
+                };
+                continue;
+            }
+
+            let len;
+            if rc.decode_bit(&mut self.is_rep[state])? != 0 {
+                if out_pos == 0 {
+                    return Err(DecompressError::CorruptedData);
+                }
+                if rc.decode_bit(&mut self.is_rep_g0[state])? == 0 {
+                    if rc.decode_bit(&mut self.is_rep0_long[state2])? == 0 {
+                        // short rep: one byte at rep0
+                        state = if state < 7 { 9 } else { 11 };
+                        output[out_pos] = output[out_pos - reps[0] - 1];
+                        out_pos += 1;
+                        continue;
+                    }
+                } else {
+                    let distance;
+                    if rc.decode_bit(&mut self.is_rep_g1[state])? == 0 {
+                        distance = reps[1];
+                    } else {
+                        if rc.decode_bit(&mut self.is_rep_g2[state])? == 0 {
+                            distance = reps[2];
+                        } else {
+                            distance = reps[3];
+                            reps[3] = reps[2];
+                        }
+                        reps[2] = reps[1];
+                    }
+                    reps[1] = reps[0];
+                    reps[0] = distance;
+                }
+                len = self.rep_len_decoder.decode(rc, pos_state)?;
+                state = if state < 7 { 8 } else { 11 };
+            } else {
+                reps[3] = reps[2];
+                reps[2] = reps[1];
+                reps[1] = reps[0];
+                len = self.len_decoder.decode(rc, pos_state)?;
+                state = if state < 7 { 7 } else { 10 };
+                let distance = self.decode_distance(rc, len)?;
+                if distance == u32::MAX {
+                    // end marker before the output is full
+                    return Err(DecompressError::CorruptedData);
+                }
+                reps[0] = distance as usize;
+                if reps[0] >= out_pos {
+                    return Err(DecompressError::CorruptedData);
+                }
+            }
+
+            let len = len + MATCH_MIN_LEN;
+            if len > output.len() - out_pos {
+                return Err(DecompressError::CorruptedData);
+            }
+            let distance = reps[0] + 1;
+            for _ in 0..len {
+                output[out_pos] = output[out_pos - distance];
+                out_pos += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// Return the decompressed size of LZMA compressed data.
+///
+pub fn lzma_decompress_get_info(source: &[u8]) -> Result<usize, DecompressError> {
+    if source.len() < HEADER_SIZE {
+        return Err(DecompressError::InvalidHeader);
+    }
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&source[5..HEADER_SIZE]);
+    let size = u64::from_le_bytes(size);
+    // the size must be known, an unknown size is all 0xFF.
+    if size > u32::MAX as u64 {
+        return Err(DecompressError::InvalidHeader);
+    }
+    Ok(size as usize)
+}
+
+///
+/// Decompress LZMA compressed data.
+///
+/// destination must hold the size returned by lzma_decompress_get_info.
+///
+pub fn lzma_decompress(source: &[u8], destination: &mut [u8]) -> Result<(), DecompressError> {
+    let size = lzma_decompress_get_info(source)?;
+    if destination.len() < size {
+        return Err(DecompressError::BufferTooSmall);
+    }
+    if size == 0 {
+        return Ok(());
+    }
+    let mut decoder = Decoder::new(source[0])?;
+    let mut rc = RangeDecoder::new(&source[HEADER_SIZE..])?;
+    decoder.decode(&mut rc, &mut destination[..size])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compressed_data::*;
+
+    #[test]
+    fn test_lzma_decompress() {
+        assert_eq!(
+            lzma_decompress_get_info(&LZMA_COMPRESSED_DATA),
+            Ok(UNCOMPRESSED_DATA.len())
+        );
+        let mut buffer = [0u8; UNCOMPRESSED_DATA.len()];
+        assert_eq!(lzma_decompress(&LZMA_COMPRESSED_DATA, &mut buffer), Ok(()));
+        assert_eq!(&buffer[..], &UNCOMPRESSED_DATA[..]);
+
+        assert_eq!(
+            lzma_decompress(&LZMA_COMPRESSED_DATA, &mut buffer[1..]),
+            Err(DecompressError::BufferTooSmall)
+        );
+        assert!(lzma_decompress(&LZMA_COMPRESSED_DATA[..HEADER_SIZE + 8], &mut buffer).is_err());
+
+        // unknown decompressed size
+        let mut unknown_size = LZMA_COMPRESSED_DATA;
+        unknown_size[5..HEADER_SIZE].copy_from_slice(&[0xff; 8]);
+        assert_eq!(
+            lzma_decompress_get_info(&unknown_size),
+            Err(DecompressError::InvalidHeader)
+        );
+
+        // lc + lp > 4
+        let mut properties = LZMA_COMPRESSED_DATA;
+        properties[0] = (2 * 5 + 1) * 9 + 4;
+        assert_eq!(
+            lzma_decompress(&properties, &mut buffer),
+            Err(DecompressError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn test_lzma_decompress_corrupted() {
+        let mut buffer = [0u8; UNCOMPRESSED_DATA.len()];
+        for offset in HEADER_SIZE..LZMA_COMPRESSED_DATA.len() {
+            for bit in 0..8 {
+                let mut compressed = LZMA_COMPRESSED_DATA;
+                compressed[offset] ^= 1 << bit;
+                let _ = lzma_decompress(&compressed, &mut buffer);
+            }
+        }
+    }
+}
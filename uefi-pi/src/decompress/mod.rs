@@ -0,0 +1,24 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Decompression of the encapsulation sections found in FVs.
+//!
+
+mod efi;
+mod lzma;
+
+pub use efi::{tiano_decompress, uefi_decompress, uefi_decompress_get_info};
+pub use lzma::{lzma_decompress, lzma_decompress_get_info};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecompressError {
+    /// The header of the compressed data is truncated or invalid.
+    InvalidHeader,
+    /// The destination is smaller than the decompressed size.
+    BufferTooSmall,
+    CorruptedData,
+    /// The data uses a feature which is not supported.
+    Unsupported,
+}
@@ -11,12 +11,238 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use core::ops::Range;
 use r_uefi_pi::fv::{
-    CommonSectionHeader, FfsFileHeader, FirmwareVolumeHeader, FvFileType, SectionType,
-    FVH_SIGNATURE,
+    CommonSectionHeader, CommonSectionHeader2, FfsFileAttributes, FfsFileHeader, FfsFileHeader2,
+    FirmwareVolumeHeader, FvFileType, SectionType, COMPRESSION_SECTION_SIZE,
+    EFI_GUIDED_SECTION_PROCESSING_REQUIRED, EFI_NOT_COMPRESSED, EFI_STANDARD_COMPRESSION,
+    FFS_ATTRIB_CHECKSUM, FFS_ATTRIB_LARGE_FILE, FFS_FIXED_CHECKSUM, FVH_SIGNATURE,
+    LZMA_CUSTOM_DECOMPRESS_GUID, SECTION_COMPRESSION, SECTION_GUID_DEFINED,
+    TIANO_CUSTOM_DECOMPRESS_GUID,
 };
 use scroll::Pread;
 
+use crate::decompress::{self, DecompressError};
+
+// Byte offset of FirmwareVolumeHeader.checksum
+const FV_HEADER_CHECKSUM_OFFSET: usize = 0x32;
+// Byte offset of FfsFileHeader.integrity_check
+const FFS_INTEGRITY_CHECK_OFFSET: usize = 0x10;
+// Byte offset of FfsFileHeader.state
+const FFS_STATE_OFFSET: usize = 0x17;
+
+/// Return the 8-bit sum of all elements in the buffer.
+pub fn calculate_sum8(buffer: &[u8]) -> u8 {
+    buffer.iter().fold(0u8, |sum, v| sum.wrapping_add(*v))
+}
+
+/// Return the 8-bit checksum which makes the 8-bit sum of the buffer zero.
+pub fn calculate_checksum8(buffer: &[u8]) -> u8 {
+    0u8.wrapping_sub(calculate_sum8(buffer))
+}
+
+/// Return the 16-bit sum of all little-endian 16-bit words in the buffer.
+/// The buffer length must be a multiple of 2.
+pub fn calculate_sum16(buffer: &[u8]) -> u16 {
+    assert!(buffer.len() % 2 == 0);
+    buffer
+        .chunks(2)
+        .fold(0u16, |sum, v| sum.wrapping_add(v[0] as u16 | (v[1] as u16) << 8))
+}
+
+/// Return the 16-bit checksum which makes the 16-bit sum of the buffer zero.
+pub fn calculate_checksum16(buffer: &[u8]) -> u16 {
+    0u16.wrapping_sub(calculate_sum16(buffer))
+}
+
+///
+/// Calculate FirmwareVolumeHeader.checksum.
+///
+/// fv_header: the header_length bytes of the FV header, including the block map.
+/// The current value of the checksum field is ignored.
+///
+pub fn calculate_fv_header_checksum(fv_header: &[u8]) -> u16 {
+    let sum = calculate_sum16(fv_header);
+    let checksum = fv_header
+        .pread::<u16>(FV_HEADER_CHECKSUM_OFFSET)
+        .unwrap();
+    0u16.wrapping_sub(sum.wrapping_sub(checksum))
+}
+
+///
+/// Calculate the header part of FfsFileHeader.integrity_check.
+///
+/// ffs_header: the whole FFS header, FfsFileHeader2 for large files.
+/// The integrity_check and state fields are treated as zero.
+///
+pub fn calculate_ffs_header_checksum(ffs_header: &[u8]) -> u8 {
+    let sum = calculate_sum8(ffs_header)
+        .wrapping_sub(ffs_header[FFS_INTEGRITY_CHECK_OFFSET])
+        .wrapping_sub(ffs_header[FFS_INTEGRITY_CHECK_OFFSET + 1])
+        .wrapping_sub(ffs_header[FFS_STATE_OFFSET]);
+    0u8.wrapping_sub(sum)
+}
+
+///
+/// Calculate the file part of FfsFileHeader.integrity_check.
+///
+/// file_data: the file content following the FFS header.
+/// If FFS_ATTRIB_CHECKSUM is clear, the checksum is FFS_FIXED_CHECKSUM.
+///
+pub fn calculate_ffs_file_checksum(attributes: FfsFileAttributes, file_data: &[u8]) -> u8 {
+    if attributes & FFS_ATTRIB_CHECKSUM != 0 {
+        calculate_checksum8(file_data)
+    } else {
+        FFS_FIXED_CHECKSUM
+    }
+}
+
+/// Verify the checksum of a FV header.
+pub fn verify_fv_header_checksum(fv_data: &[u8]) -> bool {
+    let fv_header: FirmwareVolumeHeader = match fv_data.pread(0) {
+        Ok(fv_header) => fv_header,
+        Err(_) => return false,
+    };
+    let header_length = fv_header.header_length as usize;
+    if fv_header.signature != FVH_SIGNATURE
+        || header_length < core::mem::size_of::<FirmwareVolumeHeader>()
+        || header_length % 2 != 0
+        || header_length > fv_data.len()
+    {
+        return false;
+    }
+    calculate_sum16(&fv_data[..header_length]) == 0
+}
+
+/// Verify both the header checksum and the file checksum of a FFS file.
+/// ffs_file: buffer starting with the FFS header.
+pub fn verify_ffs_checksum(ffs_file: &[u8]) -> bool {
+    let (header_size, file_size) = match get_ffs_file_sizes(ffs_file) {
+        Some(sizes) => sizes,
+        None => return false,
+    };
+    let header: FfsFileHeader = ffs_file.pread(0).unwrap();
+    let header_checksum = (header.integrity_check & 0xff) as u8;
+    let file_checksum = (header.integrity_check >> 8) as u8;
+
+    header_checksum == calculate_ffs_header_checksum(&ffs_file[..header_size])
+        && file_checksum
+            == calculate_ffs_file_checksum(header.attributes, &ffs_file[header_size..file_size])
+}
+
+///
+/// Verify the FV header checksum and the checksums of all the FFS files in the FV.
+///
+/// The walk stops at the end of the FV or at the first free space (erased FFS header).
+///
+pub fn verify_fv_checksums(fv_data: &[u8]) -> bool {
+    if !verify_fv_header_checksum(fv_data) {
+        return false;
+    }
+    let mut files = match FileRanges::parse(fv_data) {
+        Some(files) => files,
+        None => return false,
+    };
+    for range in &mut files {
+        if !verify_ffs_checksum(&fv_data[range]) {
+            return false;
+        }
+    }
+    !files.malformed
+}
+
+///
+/// Find an FFS file by name.
+///
+/// Return the range of the whole file, FFS header included, in the FV.
+///
+pub fn get_file_range_from_fv(fv_data: &[u8], name: &[u8; 16]) -> Option<Range<usize>> {
+    let files = FileRanges::parse(fv_data)?;
+    for range in files {
+        let header: FfsFileHeader = fv_data.pread(range.start).ok()?;
+        if &header.name == name {
+            return Some(range);
+        }
+    }
+    None
+}
+
+///
+/// Return the ranges of the FFS files in the FV, FFS headers included.
+///
+/// The walk stops at the free space or at the first malformed file.
+///
+pub fn get_file_ranges_from_fv(fv_data: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    FileRanges::parse(fv_data).into_iter().flatten()
+}
+
+///
+/// Return the 8 bytes aligned offset of the free space following the last FFS file.
+///
+/// None is returned if a malformed FFS file is found before the free space.
+///
+pub fn get_fv_free_space_offset(fv_data: &[u8]) -> Option<usize> {
+    let mut files = FileRanges::parse(fv_data)?;
+    for _ in &mut files {}
+    if files.malformed {
+        return None;
+    }
+    Some(files.offset)
+}
+
+fn get_size24(size: &[u8; 3]) -> usize {
+    size[0] as usize + ((size[1] as usize) << 8) + ((size[2] as usize) << 16)
+}
+
+///
+/// Return (header size, file size) of the FFS file at the start of the buffer.
+///
+/// Large files (FFS_ATTRIB_LARGE_FILE) have a FfsFileHeader2 and the file size
+/// in extended_size. None is returned if the file does not fit in the buffer.
+///
+pub fn get_ffs_file_sizes(ffs_file: &[u8]) -> Option<(usize, usize)> {
+    let header: FfsFileHeader = ffs_file.pread(0).ok()?;
+    let (header_size, file_size) = if header.attributes & FFS_ATTRIB_LARGE_FILE != 0 {
+        let header: FfsFileHeader2 = ffs_file.pread(0).ok()?;
+        (
+            core::mem::size_of::<FfsFileHeader2>(),
+            header.extended_size as usize,
+        )
+    } else {
+        (core::mem::size_of::<FfsFileHeader>(), get_size24(&header.size))
+    };
+    if file_size < header_size || file_size > ffs_file.len() {
+        return None;
+    }
+    Some((header_size, file_size))
+}
+
+///
+/// Return (header size, section size) of the section at the start of the buffer.
+///
+/// A size field of 0xffffff means a CommonSectionHeader2 with the section size
+/// in extended_size. None is returned if the section does not fit in the buffer.
+///
+pub fn get_section_sizes(section: &[u8]) -> Option<(usize, usize)> {
+    let header: CommonSectionHeader = section.pread(0).ok()?;
+    let (header_size, section_size) = if header.size == [0xff, 0xff, 0xff] {
+        let header: CommonSectionHeader2 = section.pread(0).ok()?;
+        (
+            core::mem::size_of::<CommonSectionHeader2>(),
+            header.extended_size as usize,
+        )
+    } else {
+        (
+            core::mem::size_of::<CommonSectionHeader>(),
+            get_size24(&header.size),
+        )
+    };
+    if section_size < header_size || section_size > section.len() {
+        return None;
+    }
+    Some((header_size, section_size))
+}
+
 fn get_image_from_sections(sections_data: &[u8], section_type: SectionType) -> Option<&[u8]> {
     let sections = Sections::parse(sections_data, 0)?;
     for (section_header, section_data) in sections {
@@ -48,6 +274,217 @@ pub fn get_image_from_fv(
     None
 }
 
+// Nesting limit of the encapsulation sections.
+const MAX_ENCAPSULATION_DEPTH: usize = 4;
+
+/// Location of an image found by find_image_in_sections.
+enum ImageLocation {
+    /// Range in the sections data.
+    Sections(Range<usize>),
+    /// Range in the decompression buffer, the buffer is free below `used`.
+    Buffer { range: Range<usize>, used: usize },
+}
+
+/// Encapsulated sections of a compression or GUID defined section.
+enum Encapsulation<'a> {
+    /// Range of the sections in the sections data.
+    Plain(Range<usize>),
+    /// Compressed sections, with the GUID of GUID defined sections.
+    Compressed(Option<[u8; 16]>, &'a [u8]),
+}
+
+///
+/// Decompress to the end of the buffer, 8 bytes aligned in memory.
+///
+/// Return the range of the decompressed data in the buffer.
+///
+fn decompress_section(
+    guid: Option<[u8; 16]>,
+    source: &[u8],
+    buffer: &mut [u8],
+) -> Result<Range<usize>, DecompressError> {
+    let lzma = guid.as_ref() == Some(LZMA_CUSTOM_DECOMPRESS_GUID.as_bytes());
+    let tiano = guid.as_ref() == Some(TIANO_CUSTOM_DECOMPRESS_GUID.as_bytes());
+    if guid.is_some() && !lzma && !tiano {
+        return Err(DecompressError::Unsupported);
+    }
+    let size = if lzma {
+        decompress::lzma_decompress_get_info(source)?
+    } else {
+        decompress::uefi_decompress_get_info(source)?
+    };
+
+    let base_address = buffer as *const [u8] as *const u8 as usize;
+    let start = match (base_address + buffer.len()).checked_sub(size) {
+        Some(start) if start & !7 >= base_address => (start & !7) - base_address,
+        _ => return Err(DecompressError::BufferTooSmall),
+    };
+    let destination = &mut buffer[start..start + size];
+    if lzma {
+        decompress::lzma_decompress(source, destination)?;
+    } else if tiano {
+        decompress::tiano_decompress(source, destination)?;
+    } else {
+        decompress::uefi_decompress(source, destination)?;
+    }
+    Ok(start..start + size)
+}
+
+fn get_encapsulation(section: &[u8], header_size: usize) -> Option<Encapsulation<'_>> {
+    let section_data = &section[header_size..];
+    match section.pread::<CommonSectionHeader>(0).ok()?.r#type {
+        SECTION_COMPRESSION => {
+            // uncompressed_length is checked by the decompressor.
+            let data_offset = header_size + COMPRESSION_SECTION_SIZE - 4;
+            let compression_type: u8 = section_data.pread(4).ok()?;
+            match compression_type {
+                EFI_NOT_COMPRESSED => Some(Encapsulation::Plain(data_offset..section.len())),
+                EFI_STANDARD_COMPRESSION => {
+                    Some(Encapsulation::Compressed(None, section.get(data_offset..)?))
+                }
+                _ => {
+                    log::error!("unknown compression type {}\n", compression_type);
+                    None
+                }
+            }
+        }
+        SECTION_GUID_DEFINED => {
+            let mut guid = [0u8; 16];
+            guid.copy_from_slice(section_data.get(..16)?);
+            // data_offset is from the start of the section.
+            let data_offset = section_data.pread::<u16>(16).ok()? as usize;
+            let attributes: u16 = section_data.pread(18).ok()?;
+            if data_offset < header_size + 20 || data_offset > section.len() {
+                return None;
+            }
+            if attributes & EFI_GUIDED_SECTION_PROCESSING_REQUIRED == 0 {
+                Some(Encapsulation::Plain(data_offset..section.len()))
+            } else {
+                Some(Encapsulation::Compressed(
+                    Some(guid),
+                    &section[data_offset..],
+                ))
+            }
+        }
+        _ => None,
+    }
+}
+
+///
+/// Search the image section in the sections, and in the encapsulated
+/// sections, which are decompressed to the end of the buffer.
+///
+fn find_image_in_sections(
+    sections_data: &[u8],
+    section_type: SectionType,
+    buffer: &mut [u8],
+    depth: usize,
+) -> Option<ImageLocation> {
+    let mut offset = 0;
+    loop {
+        // required 4 bytes alginment, from the start of the encapsulated sections
+        offset = (offset + 3) & !3;
+        if offset >= sections_data.len() {
+            return None;
+        }
+        let (header_size, section_size) = get_section_sizes(&sections_data[offset..])?;
+        let section = &sections_data[offset..offset + section_size];
+        let header: CommonSectionHeader = section.pread(0).ok()?;
+        if header.r#type == section_type {
+            return Some(ImageLocation::Sections(
+                offset + header_size..offset + section_size,
+            ));
+        }
+
+        let encapsulation = if depth < MAX_ENCAPSULATION_DEPTH {
+            get_encapsulation(section, header_size)
+        } else {
+            None
+        };
+        let location = match encapsulation {
+            Some(Encapsulation::Plain(range)) => {
+                match find_image_in_sections(
+                    &section[range.clone()],
+                    section_type,
+                    buffer,
+                    depth + 1,
+                ) {
+                    Some(ImageLocation::Sections(image)) => {
+                        let start = offset + range.start;
+                        Some(ImageLocation::Sections(
+                            start + image.start..start + image.end,
+                        ))
+                    }
+                    location => location,
+                }
+            }
+            Some(Encapsulation::Compressed(guid, source)) => {
+                let range = match decompress_section(guid, source, buffer) {
+                    Ok(range) => range,
+                    Err(e) => {
+                        log::error!("fail to decompress section - {:?}\n", e);
+                        return None;
+                    }
+                };
+                let (free, decompressed) = buffer.split_at_mut(range.start);
+                match find_image_in_sections(
+                    &decompressed[..range.len()],
+                    section_type,
+                    free,
+                    depth + 1,
+                ) {
+                    Some(ImageLocation::Sections(image)) => Some(ImageLocation::Buffer {
+                        range: range.start + image.start..range.start + image.end,
+                        used: range.start,
+                    }),
+                    location => location,
+                }
+            }
+            None => None,
+        };
+        if location.is_some() {
+            return location;
+        }
+        offset += section_size;
+    }
+}
+
+///
+/// Find the image of a file in the FV, compression and GUID defined
+/// sections are unwrapped, compressed ones are decompressed to the end
+/// of the buffer.
+///
+/// Return the image and the free part of the buffer, where the image can be
+/// loaded.
+///
+pub fn extract_image_from_fv<'a>(
+    fv_data: &'a [u8],
+    fv_file_type: FvFileType,
+    section_type: SectionType,
+    buffer: &'a mut [u8],
+) -> Option<(&'a [u8], &'a mut [u8])> {
+    let fv_header: FirmwareVolumeHeader = fv_data.pread(0).ok()?;
+    if fv_header.signature != FVH_SIGNATURE {
+        return None;
+    }
+
+    let files = Files::parse(fv_data, fv_header.header_length as usize)?;
+    for (file_header, file_data) in files {
+        if file_header.r#type != fv_file_type {
+            continue;
+        }
+        return match find_image_in_sections(file_data, section_type, buffer, 0)? {
+            ImageLocation::Sections(range) => Some((&file_data[range], buffer)),
+            ImageLocation::Buffer { range, used } => {
+                let (free, decompressed) = buffer.split_at_mut(used);
+                let decompressed: &'a [u8] = decompressed;
+                Some((&decompressed[range.start - used..range.end - used], free))
+            }
+        };
+    }
+    None
+}
+
 struct Sections<'a> {
     offset: usize,
     buffer: &'a [u8],
@@ -69,17 +506,14 @@ impl<'a> Iterator for Sections<'a> {
         // required 4 bytes alginment
         let offset = ((self.offset + 3 + base_address) & (core::usize::MAX - 3)) - base_address;
 
-        let header_size = core::mem::size_of::<CommonSectionHeader>();
-        if offset > self.buffer.len() - header_size {
+        if offset > self.buffer.len() {
             return None;
         }
         let bytes = &self.buffer[offset..];
         let header: CommonSectionHeader = bytes.pread(0).ok()?;
-        let section_size = header.size[0] as usize
-            + ((header.size[1] as usize) << 8)
-            + ((header.size[2] as usize) << 16);
-        assert!(section_size >= header_size);
+        let (header_size, section_size) = get_section_sizes(bytes)?;
 
+        self.offset = offset;
         self.offset += section_size;
 
         Some((header, &bytes[header_size..section_size]))
@@ -109,19 +543,13 @@ impl<'a> Iterator for Files<'a> {
         // required 8 bytes alginment
         let offset = ((self.offset + 7 + base_address) & (core::usize::MAX - 7)) - base_address;
 
-        let header_size = core::mem::size_of::<FfsFileHeader>();
         if offset > self.buffer.len() {
             return None;
         }
 
         let buffer = &self.buffer[offset..];
         let header: FfsFileHeader = buffer.pread(0).ok()?;
-
-        let data_size = header.size[0] as usize
-            + ((header.size[1] as usize) << 8)
-            + ((header.size[2] as usize) << 16);
-
-        assert!(data_size < self.buffer.len());
+        let (header_size, data_size) = get_ffs_file_sizes(buffer)?;
 
         self.offset = offset;
         self.offset += data_size;
@@ -129,3 +557,316 @@ impl<'a> Iterator for Files<'a> {
         Some((header, &buffer[header_size..data_size]))
     }
 }
+
+///
+/// Walk the FFS files of an FV and yield the range of each file.
+///
+/// The walk stops at the end of the FV, at the first free space (erased
+/// FFS header) or at the first malformed file, which sets `malformed`.
+///
+struct FileRanges<'a> {
+    buffer: &'a [u8],
+    fv_length: usize,
+    offset: usize,
+    malformed: bool,
+}
+
+impl<'a> FileRanges<'a> {
+    pub fn parse(fv_buffer: &'a [u8]) -> Option<Self> {
+        let fv_header: FirmwareVolumeHeader = fv_buffer.pread(0).ok()?;
+        if fv_header.signature != FVH_SIGNATURE {
+            return None;
+        }
+        Some(FileRanges {
+            buffer: fv_buffer,
+            fv_length: core::cmp::min(fv_header.fv_length as usize, fv_buffer.len()),
+            offset: fv_header.header_length as usize,
+            malformed: false,
+        })
+    }
+}
+
+impl<'a> Iterator for FileRanges<'a> {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_size = core::mem::size_of::<FfsFileHeader>();
+        // required 8 bytes alginment
+        self.offset = (self.offset + 7) & !7;
+        if self.malformed || self.offset + header_size > self.fv_length {
+            return None;
+        }
+        let header: FfsFileHeader = self.buffer.pread(self.offset).ok()?;
+        if header.r#type == 0xff && header.size == [0xff, 0xff, 0xff] {
+            return None;
+        }
+        let file_size = match get_ffs_file_sizes(&self.buffer[self.offset..self.fv_length]) {
+            Some((_, file_size)) => file_size,
+            None => {
+                self.malformed = true;
+                return None;
+            }
+        };
+        let range = self.offset..self.offset + file_size;
+        self.offset += file_size;
+        Some(range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use r_uefi_pi::fv::FvBlockMap;
+    use scroll::Pwrite;
+
+    fn build_test_fv() -> [u8; 0x100] {
+        let mut fv = [0xffu8; 0x100];
+        let header_length = core::mem::size_of::<FirmwareVolumeHeader>()
+            + core::mem::size_of::<[FvBlockMap; 2]>();
+        let fv_header = FirmwareVolumeHeader {
+            fv_length: fv.len() as u64,
+            signature: FVH_SIGNATURE,
+            attributes: 0x0004feff,
+            header_length: header_length as u16,
+            revision: 2,
+            ..Default::default()
+        };
+        fv.pwrite(fv_header, 0).unwrap();
+        fv.pwrite(FvBlockMap { num_blocks: 1, length: 0x100 }, 0x38).unwrap();
+        fv.pwrite(FvBlockMap::default(), 0x40).unwrap();
+
+        let ffs_header = FfsFileHeader {
+            name: [0x5a; 16],
+            r#type: 0x01,
+            attributes: FFS_ATTRIB_CHECKSUM,
+            size: [0x20, 0, 0],
+            state: 0xF8,
+            ..Default::default()
+        };
+        fv.pwrite(ffs_header, header_length).unwrap();
+        for (i, b) in fv[header_length + 0x18..header_length + 0x20]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+
+        let ffs_file = &fv[header_length..header_length + 0x20];
+        let integrity_check = calculate_ffs_header_checksum(&ffs_file[..0x18]) as u16
+            | (calculate_ffs_file_checksum(FFS_ATTRIB_CHECKSUM, &ffs_file[0x18..]) as u16) << 8;
+        fv.pwrite(integrity_check, header_length + FFS_INTEGRITY_CHECK_OFFSET)
+            .unwrap();
+        let checksum = calculate_fv_header_checksum(&fv[..header_length]);
+        fv.pwrite(checksum, FV_HEADER_CHECKSUM_OFFSET).unwrap();
+        fv
+    }
+
+    #[test]
+    fn test_fv_checksums() {
+        let fv = build_test_fv();
+        assert!(verify_fv_header_checksum(&fv));
+        assert!(verify_fv_checksums(&fv));
+
+        // the state field is not covered by the header checksum
+        let mut fv_state = fv;
+        fv_state[0x48 + FFS_STATE_OFFSET] = 0xF0;
+        assert!(verify_fv_checksums(&fv_state));
+
+        let mut fv_bad_header = fv;
+        fv_bad_header[0x30] ^= 0x01;
+        assert!(!verify_fv_checksums(&fv_bad_header));
+
+        let mut fv_bad_ffs_header = fv;
+        fv_bad_ffs_header[0x48] ^= 0x01;
+        assert!(!verify_fv_checksums(&fv_bad_ffs_header));
+
+        let mut fv_bad_ffs_data = fv;
+        fv_bad_ffs_data[0x48 + 0x1f] ^= 0x01;
+        assert!(!verify_fv_checksums(&fv_bad_ffs_data));
+    }
+
+    #[test]
+    fn test_get_file_range() {
+        let fv = build_test_fv();
+        assert_eq!(get_file_range_from_fv(&fv, &[0x5a; 16]), Some(0x48..0x68));
+        assert_eq!(get_file_range_from_fv(&fv, &[0xa5; 16]), None);
+        assert_eq!(get_fv_free_space_offset(&fv), Some(0x68));
+        let mut file_ranges = get_file_ranges_from_fv(&fv);
+        assert_eq!(file_ranges.next(), Some(0x48..0x68));
+        assert_eq!(file_ranges.next(), None);
+
+        let mut fv_bad_size = fv;
+        fv_bad_size[0x48 + 0x14] = 0xff;
+        assert_eq!(get_fv_free_space_offset(&fv_bad_size), None);
+        assert!(!verify_fv_checksums(&fv_bad_size));
+    }
+
+    #[test]
+    fn test_large_file_and_section() {
+        let mut fv = build_test_fv();
+        let file_offset = 0x48;
+        let header_size = core::mem::size_of::<FfsFileHeader2>();
+        let file_size = header_size + 0x20;
+        let ffs_header = FfsFileHeader2 {
+            name: [0xa5; 16],
+            r#type: 0x07,
+            attributes: FFS_ATTRIB_LARGE_FILE | FFS_ATTRIB_CHECKSUM,
+            state: 0xF8,
+            extended_size: file_size as u32,
+            ..Default::default()
+        };
+        fv[file_offset..].iter_mut().for_each(|b| *b = 0xff);
+        fv.pwrite(ffs_header, file_offset).unwrap();
+
+        let section_offset = file_offset + header_size;
+        let section_header = CommonSectionHeader2 {
+            size: [0xff, 0xff, 0xff],
+            r#type: 0x10,
+            extended_size: 0x20,
+        };
+        fv.pwrite(section_header, section_offset).unwrap();
+        for (i, b) in fv[section_offset + 8..section_offset + 0x20]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+
+        let ffs_file = &fv[file_offset..file_offset + file_size];
+        assert_eq!(get_ffs_file_sizes(ffs_file), Some((header_size, file_size)));
+        assert_eq!(get_section_sizes(&ffs_file[header_size..]), Some((8, 0x20)));
+        let integrity_check = calculate_ffs_header_checksum(&ffs_file[..header_size]) as u16
+            | (calculate_ffs_file_checksum(ffs_header.attributes, &ffs_file[header_size..])
+                as u16)
+                << 8;
+        fv.pwrite(integrity_check, file_offset + FFS_INTEGRITY_CHECK_OFFSET)
+            .unwrap();
+
+        assert!(verify_fv_checksums(&fv));
+        assert_eq!(
+            get_file_range_from_fv(&fv, &[0xa5; 16]),
+            Some(file_offset..file_offset + file_size)
+        );
+        assert_eq!(get_fv_free_space_offset(&fv), Some(0x88));
+        let image = get_image_from_fv(&fv, 0x07, 0x10).unwrap();
+        assert_eq!(image.len(), 0x18);
+        assert_eq!(image[0x17], 0x17);
+
+        // extended_size includes the FfsFileHeader2 and is covered by the header checksum
+        let mut fv_bad_size = fv;
+        fv_bad_size[file_offset + 0x18] = 0x10;
+        assert!(!verify_fv_checksums(&fv_bad_size));
+        let mut fv_bad_header = fv;
+        fv_bad_header[file_offset + 0x18] = (file_size + 8) as u8;
+        assert!(!verify_fv_checksums(&fv_bad_header));
+    }
+
+    fn build_sections_fv(sections: &[u8]) -> Vec<u8> {
+        let mut fv = vec![0xffu8; 0x48 + 0x18 + sections.len() + 8];
+        fv[..0x48].copy_from_slice(&build_test_fv()[..0x48]);
+        fv.pwrite(fv.len() as u64, 0x20).unwrap();
+        let file_size = 0x18 + sections.len();
+        let ffs_header = FfsFileHeader {
+            name: [0xa5; 16],
+            r#type: 0x05,
+            size: [file_size as u8, (file_size >> 8) as u8, 0],
+            state: 0xF8,
+            ..Default::default()
+        };
+        fv.pwrite(ffs_header, 0x48).unwrap();
+        fv[0x48 + 0x18..0x48 + file_size].copy_from_slice(sections);
+        fv
+    }
+
+    fn build_guid_defined_section(guid: &[u8; 16], attributes: u16, data: &[u8]) -> Vec<u8> {
+        let mut section = vec![0u8; 24];
+        section
+            .pwrite((24 + data.len()) as u32 | (0x02 << 24), 0)
+            .unwrap();
+        section[4..20].copy_from_slice(guid);
+        section.pwrite(24u16, 20).unwrap();
+        section.pwrite(attributes, 22).unwrap();
+        section.extend_from_slice(data);
+        section
+    }
+
+    #[test]
+    fn test_extract_image() {
+        use crate::compressed_data::*;
+
+        let image = &UNCOMPRESSED_DATA[4..];
+        let mut buffer = vec![0u8; 0x1000];
+        let mut raw_section = vec![0x08, 0x00, 0x00, 0x19, 0x5a, 0x5a, 0x5a, 0x5a];
+
+        // EFI_STANDARD_COMPRESSION, after a RAW section
+        let mut sections = raw_section.clone();
+        sections.extend_from_slice(&[0; 4]);
+        sections
+            .pwrite((9 + UEFI_COMPRESSED_DATA.len()) as u32 | (0x01 << 24), 8)
+            .unwrap();
+        sections.extend_from_slice(&(UNCOMPRESSED_DATA.len() as u32).to_le_bytes());
+        sections.push(EFI_STANDARD_COMPRESSION);
+        sections.extend_from_slice(&UEFI_COMPRESSED_DATA);
+        let fv = build_sections_fv(&sections);
+        let (extracted, free) = extract_image_from_fv(&fv, 0x05, 0x10, &mut buffer).unwrap();
+        assert_eq!(extracted, image);
+        assert!(free.len() <= 0x1000 - UNCOMPRESSED_DATA.len());
+        assert!(free.len() >= 0x1000 - UNCOMPRESSED_DATA.len() - 8);
+
+        // the buffer is too small for the decompressed sections
+        assert!(extract_image_from_fv(&fv, 0x05, 0x10, &mut buffer[..0x100]).is_none());
+        // a RAW image is found before the compressed sections
+        let (extracted, free) = extract_image_from_fv(&fv, 0x05, 0x19, &mut buffer).unwrap();
+        assert_eq!(extracted, &[0x5a; 4]);
+        assert_eq!(free.len(), 0x1000);
+
+        // LZMA and Tiano GUID defined sections
+        for (guid, data) in &[
+            (LZMA_CUSTOM_DECOMPRESS_GUID, &LZMA_COMPRESSED_DATA[..]),
+            (TIANO_CUSTOM_DECOMPRESS_GUID, &TIANO_COMPRESSED_DATA[..]),
+        ] {
+            let sections = build_guid_defined_section(
+                guid.as_bytes(),
+                EFI_GUIDED_SECTION_PROCESSING_REQUIRED,
+                data,
+            );
+            let fv = build_sections_fv(&sections);
+            let (extracted, _) = extract_image_from_fv(&fv, 0x05, 0x10, &mut buffer).unwrap();
+            assert_eq!(extracted, image);
+
+            let mut fv_corrupted = fv.clone();
+            let offset = fv.len() - 8 - data.len() / 2;
+            fv_corrupted[offset] ^= 0x55;
+            if let Some((extracted, _)) =
+                extract_image_from_fv(&fv_corrupted, 0x05, 0x10, &mut buffer)
+            {
+                assert_ne!(extracted, image);
+            }
+        }
+
+        // unknown GUID defined sections are not processed
+        let sections = build_guid_defined_section(
+            &[0x5a; 16],
+            EFI_GUIDED_SECTION_PROCESSING_REQUIRED,
+            &LZMA_COMPRESSED_DATA,
+        );
+        let fv = build_sections_fv(&sections);
+        assert!(extract_image_from_fv(&fv, 0x05, 0x10, &mut buffer).is_none());
+
+        // the sections of GUID defined sections without processing required and of
+        // EFI_NOT_COMPRESSED sections are not copied.
+        let mut sections = UNCOMPRESSED_DATA.to_vec();
+        let mut header = [0u8; 9];
+        header
+            .pwrite((9 + UNCOMPRESSED_DATA.len() as u32) | (0x01 << 24), 0)
+            .unwrap();
+        header.pwrite(UNCOMPRESSED_DATA.len() as u32, 4).unwrap();
+        header[8] = EFI_NOT_COMPRESSED;
+        sections.splice(0..0, header.iter().cloned());
+        raw_section.extend_from_slice(&build_guid_defined_section(&[0x5a; 16], 0, &sections));
+        let fv = build_sections_fv(&raw_section);
+        let (extracted, free) = extract_image_from_fv(&fv, 0x05, 0x10, &mut buffer).unwrap();
+        assert_eq!(extracted, image);
+        assert_eq!(free.len(), 0x1000);
+        assert_eq!(get_image_from_fv(&fv, 0x05, 0x10), None);
+    }
+}
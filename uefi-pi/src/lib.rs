@@ -5,10 +5,18 @@
 #![cfg_attr(not(test), no_std)]
 #![forbid(unsafe_code)]
 
+pub mod decompress;
 pub mod fv_lib;
 pub mod hob_lib;
+pub mod variable_lib;
+
+#[cfg(test)]
+#[path = "../test_data/compressed_data.rs"]
+mod compressed_data;
 
 pub mod pi {
+    pub use crate::decompress;
     pub use crate::fv_lib;
     pub use crate::hob_lib;
+    pub use crate::variable_lib;
 }
@@ -0,0 +1,484 @@
+// Copyright (c) 2021 Intel Corporation
+//
+// SPDX-License-Identifier: BSD-2-Clause-Patent
+
+//!
+//! Format and parse the EDK2 NV storage: a FV with the
+//! SYSTEM_NV_DATA_FV_GUID file system, holding the variable store and the
+//! FTW working block, followed by the FTW spare block.
+//!
+//! Variables are written in the authenticated format, both formats are
+//! parsed.
+//!
+
+use core::mem::size_of;
+use r_uefi_pi::fv::{FirmwareVolumeHeader, FvBlockMap, FVH_SIGNATURE};
+use r_uefi_pi::variable::*;
+use scroll::{Pread, Pwrite};
+
+use crate::fv_lib;
+
+// the erase polarity of the flash, free space is 0xff.
+const ERASED_BYTE: u8 = 0xff;
+const NV_STORAGE_BLOCK_SIZE: usize = 0x1000;
+// FirmwareVolumeHeader and a block map of one entry and the terminator
+const NV_STORAGE_FV_HEADER_LENGTH: usize =
+    size_of::<FirmwareVolumeHeader>() + 2 * size_of::<FvBlockMap>();
+// EFI_FVB2_READ_ENABLED_CAP | EFI_FVB2_READ_STATUS | ... | EFI_FVB2_ALIGNMENT_16, as OVMF
+const NV_STORAGE_FV_ATTRIBUTES: u32 = 0x0004feff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VariableError {
+    /// The NV storage or the variable store is malformed, or the layout is invalid.
+    InvalidStore,
+    /// The name is empty, or the variable already exists.
+    InvalidParameter,
+    /// No space for the variable in the variable store.
+    OutOfResources,
+}
+
+/// Return the CRC32 (IEEE 802.3) of the buffer, as gBS->CalculateCrc32.
+pub fn calculate_crc32(buffer: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in buffer {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn align_up(offset: usize) -> usize {
+    (offset + HEADER_ALIGNMENT - 1) & !(HEADER_ALIGNMENT - 1)
+}
+
+///
+/// Format the NV storage with an empty variable store of
+/// variable_store_size bytes (including the FV header), the FTW working
+/// block of ftw_working_size bytes, and the FTW spare block in the rest.
+///
+/// The FV covers the whole NV storage, as OVMF does.
+///
+pub fn format_nv_storage(
+    nv_storage: &mut [u8],
+    variable_store_size: usize,
+    ftw_working_size: usize,
+) -> Result<(), VariableError> {
+    let nv_storage_size = nv_storage.len();
+    let spare_size = nv_storage_size
+        .checked_sub(variable_store_size + ftw_working_size)
+        .ok_or(VariableError::InvalidStore)?;
+    if nv_storage_size % NV_STORAGE_BLOCK_SIZE != 0
+        || variable_store_size <= NV_STORAGE_FV_HEADER_LENGTH + VARIABLE_STORE_HEADER_SIZE
+        || ftw_working_size <= FTW_WORKING_BLOCK_HEADER_SIZE
+        // the spare block holds a copy of the variable store and the working block
+        || spare_size < variable_store_size + ftw_working_size
+    {
+        return Err(VariableError::InvalidStore);
+    }
+    for byte in nv_storage.iter_mut() {
+        *byte = ERASED_BYTE;
+    }
+
+    let mut fv_header = FirmwareVolumeHeader::default();
+    fv_header
+        .file_system_guid
+        .copy_from_slice(SYSTEM_NV_DATA_FV_GUID.as_bytes());
+    fv_header.fv_length = nv_storage_size as u64;
+    fv_header.signature = FVH_SIGNATURE;
+    fv_header.attributes = NV_STORAGE_FV_ATTRIBUTES;
+    fv_header.header_length = NV_STORAGE_FV_HEADER_LENGTH as u16;
+    fv_header.revision = 0x02;
+    let block_map = [
+        FvBlockMap {
+            num_blocks: (nv_storage_size / NV_STORAGE_BLOCK_SIZE) as u32,
+            length: NV_STORAGE_BLOCK_SIZE as u32,
+        },
+        FvBlockMap::default(),
+    ];
+    let mut offset = nv_storage.pwrite(fv_header, 0).unwrap();
+    for entry in block_map.iter() {
+        offset += nv_storage.pwrite(*entry, offset).unwrap();
+    }
+    let checksum = fv_lib::calculate_fv_header_checksum(&nv_storage[..offset]);
+    nv_storage.pwrite(checksum, 0x32).unwrap();
+
+    let mut store_header = VariableStoreHeader::default();
+    store_header
+        .signature
+        .copy_from_slice(AUTHENTICATED_VARIABLE_GUID.as_bytes());
+    store_header.size = (variable_store_size - NV_STORAGE_FV_HEADER_LENGTH) as u32;
+    store_header.format = VARIABLE_STORE_FORMATTED;
+    store_header.state = VARIABLE_STORE_HEALTHY;
+    nv_storage
+        .pwrite(store_header, NV_STORAGE_FV_HEADER_LENGTH)
+        .unwrap();
+
+    // the CRC covers the header with the crc and the state still erased.
+    let mut working_header = FtwWorkingBlockHeader {
+        signature: [0u8; 16],
+        crc: 0xffffffff,
+        working_block_state: ERASED_BYTE,
+        reserved: [ERASED_BYTE; 3],
+        write_queue_size: (ftw_working_size - FTW_WORKING_BLOCK_HEADER_SIZE) as u64,
+    };
+    working_header
+        .signature
+        .copy_from_slice(WORKING_BLOCK_SIGNATURE_GUID.as_bytes());
+    let working_block = &mut nv_storage[variable_store_size..];
+    working_block.pwrite(working_header, 0).unwrap();
+    working_header.crc = calculate_crc32(&working_block[..FTW_WORKING_BLOCK_HEADER_SIZE]);
+    // WorkingBlockValid = FTW_VALID_STATE (0), WorkingBlockInvalid = FTW_INVALID_STATE (1)
+    working_header.working_block_state = ERASED_BYTE & !WORKING_BLOCK_VALID;
+    working_block.pwrite(working_header, 0).unwrap();
+    Ok(())
+}
+
+///
+/// Verify the FTW working block header written by format_nv_storage or by
+/// the EDK2 FaultTolerantWriteDxe.
+///
+pub fn verify_ftw_working_block(working_block: &[u8]) -> bool {
+    let header: FtwWorkingBlockHeader = match working_block.pread(0) {
+        Ok(header) => header,
+        Err(_) => return false,
+    };
+    if header.signature != *WORKING_BLOCK_SIGNATURE_GUID.as_bytes()
+        || header.working_block_state & (WORKING_BLOCK_VALID | WORKING_BLOCK_INVALID)
+            != WORKING_BLOCK_INVALID
+        || header.write_queue_size as usize + FTW_WORKING_BLOCK_HEADER_SIZE > working_block.len()
+    {
+        return false;
+    }
+    let mut erased = [0u8; FTW_WORKING_BLOCK_HEADER_SIZE];
+    erased.copy_from_slice(&working_block[..FTW_WORKING_BLOCK_HEADER_SIZE]);
+    erased[16..21].copy_from_slice(&[ERASED_BYTE; 5]);
+    calculate_crc32(&erased) == header.crc
+}
+
+///
+/// A variable of the variable store.
+///
+/// name is the null-terminated UCS-2 name.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct Variable<'a> {
+    pub vendor_guid: [u8; 16],
+    pub attributes: u32,
+    pub state: u8,
+    pub name: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> Variable<'a> {
+    pub fn name_eq(&self, name: &str) -> bool {
+        self.name
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .eq(name.encode_utf16().chain(core::iter::once(0)))
+    }
+
+    /// A variable is live until its deletion completes.
+    pub fn is_valid(&self) -> bool {
+        self.state == VAR_ADDED || self.state == VAR_ADDED & VAR_IN_DELETED_TRANSITION
+    }
+}
+
+///
+/// The variable store of a NV storage FV.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct VariableStore<'a> {
+    // the variable store, starting with VariableStoreHeader
+    buffer: &'a [u8],
+    authenticated: bool,
+}
+
+impl<'a> VariableStore<'a> {
+    ///
+    /// Return the variable store of a NV storage FV, None if the FV header or
+    /// the variable store header is invalid.
+    ///
+    pub fn parse(nv_storage: &'a [u8]) -> Option<Self> {
+        let fv_header: FirmwareVolumeHeader = nv_storage.pread(0).ok()?;
+        if !fv_lib::verify_fv_header_checksum(nv_storage)
+            || fv_header.file_system_guid != *SYSTEM_NV_DATA_FV_GUID.as_bytes()
+            || fv_header.fv_length as usize > nv_storage.len()
+        {
+            return None;
+        }
+        let store_offset = fv_header.header_length as usize;
+        let store_header: VariableStoreHeader = nv_storage.pread(store_offset).ok()?;
+        let authenticated = if store_header.signature == *AUTHENTICATED_VARIABLE_GUID.as_bytes() {
+            true
+        } else if store_header.signature == *VARIABLE_GUID.as_bytes() {
+            false
+        } else {
+            return None;
+        };
+        let store_size = store_header.size as usize;
+        if store_header.format != VARIABLE_STORE_FORMATTED
+            || store_header.state != VARIABLE_STORE_HEALTHY
+            || store_size < VARIABLE_STORE_HEADER_SIZE
+            || store_offset + store_size > fv_header.fv_length as usize
+        {
+            return None;
+        }
+        Some(VariableStore {
+            buffer: &nv_storage[store_offset..store_offset + store_size],
+            authenticated,
+        })
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Size of the variable store, including its header.
+    pub fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Iterate over all variable headers, including the deleted ones.
+    pub fn iter_all(&self) -> Variables<'a> {
+        Variables {
+            buffer: self.buffer,
+            authenticated: self.authenticated,
+            offset: align_up(VARIABLE_STORE_HEADER_SIZE),
+        }
+    }
+
+    /// Iterate over the valid variables.
+    pub fn iter(&self) -> impl Iterator<Item = Variable<'a>> {
+        self.iter_all().filter(|variable| variable.is_valid())
+    }
+
+    pub fn find_variable(&self, name: &str, vendor_guid: &[u8; 16]) -> Option<Variable<'a>> {
+        self.iter()
+            .find(|variable| variable.vendor_guid == *vendor_guid && variable.name_eq(name))
+    }
+
+    /// Offset of the free space in the variable store.
+    pub fn free_space_offset(&self) -> usize {
+        let mut variables = self.iter_all();
+        while variables.next().is_some() {}
+        variables.offset
+    }
+}
+
+pub struct Variables<'a> {
+    buffer: &'a [u8],
+    authenticated: bool,
+    offset: usize,
+}
+
+impl<'a> Iterator for Variables<'a> {
+    type Item = Variable<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let (header_size, start_id, state, attributes, name_size, data_size, vendor_guid) =
+            if self.authenticated {
+                let header: AuthenticatedVariableHeader = self.buffer.pread(offset).ok()?;
+                (
+                    AUTHENTICATED_VARIABLE_HEADER_SIZE,
+                    header.start_id,
+                    header.state,
+                    header.attributes,
+                    header.name_size,
+                    header.data_size,
+                    header.vendor_guid,
+                )
+            } else {
+                let header: VariableHeader = self.buffer.pread(offset).ok()?;
+                (
+                    VARIABLE_HEADER_SIZE,
+                    header.start_id,
+                    header.state,
+                    header.attributes,
+                    header.name_size,
+                    header.data_size,
+                    header.vendor_guid,
+                )
+            };
+        if start_id != VARIABLE_DATA {
+            return None;
+        }
+        let name_offset = offset + header_size;
+        let data_offset = align_up(name_offset.checked_add(name_size as usize)?);
+        let end = data_offset.checked_add(data_size as usize)?;
+        if end > self.buffer.len() {
+            return None;
+        }
+        self.offset = align_up(end);
+        Some(Variable {
+            vendor_guid,
+            attributes,
+            state,
+            name: &self.buffer[name_offset..name_offset + name_size as usize],
+            data: &self.buffer[data_offset..end],
+        })
+    }
+}
+
+///
+/// Add a variable to the variable store of a NV storage formatted by
+/// format_nv_storage.
+///
+pub fn add_variable(
+    nv_storage: &mut [u8],
+    name: &str,
+    vendor_guid: &[u8; 16],
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), VariableError> {
+    let store = VariableStore::parse(nv_storage).ok_or(VariableError::InvalidStore)?;
+    if !store.is_authenticated() {
+        return Err(VariableError::InvalidStore);
+    }
+    if name.is_empty() || store.find_variable(name, vendor_guid).is_some() {
+        return Err(VariableError::InvalidParameter);
+    }
+    let name_size = (name.encode_utf16().count() + 1) * 2;
+    let offset = store.free_space_offset();
+    let data_offset = align_up(offset + AUTHENTICATED_VARIABLE_HEADER_SIZE + name_size);
+    let store_size = store.size();
+    if data_offset + data.len() > store_size {
+        return Err(VariableError::OutOfResources);
+    }
+
+    let store_offset = nv_storage
+        .pread::<FirmwareVolumeHeader>(0)
+        .unwrap()
+        .header_length as usize;
+    let buffer = &mut nv_storage[store_offset..store_offset + store_size];
+    let header = AuthenticatedVariableHeader {
+        start_id: VARIABLE_DATA,
+        state: VAR_ADDED,
+        reserved: 0,
+        attributes,
+        name_size: name_size as u32,
+        data_size: data.len() as u32,
+        vendor_guid: *vendor_guid,
+        ..Default::default()
+    };
+    buffer.pwrite(header, offset).unwrap();
+    let name_offset = offset + AUTHENTICATED_VARIABLE_HEADER_SIZE;
+    for (i, c) in name.encode_utf16().chain(core::iter::once(0)).enumerate() {
+        buffer.pwrite(c, name_offset + i * 2).unwrap();
+    }
+    // the padding between the name and the data
+    for byte in buffer[name_offset + name_size..data_offset].iter_mut() {
+        *byte = 0;
+    }
+    buffer[data_offset..data_offset + data.len()].copy_from_slice(data);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VARIABLE_STORE_SIZE: usize = 0xe000;
+    const FTW_WORKING_SIZE: usize = 0x2000;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(calculate_crc32(b""), 0);
+        assert_eq!(calculate_crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_format_nv_storage() {
+        let mut nv_storage = vec![0u8; 0x20000];
+        format_nv_storage(&mut nv_storage, VARIABLE_STORE_SIZE, FTW_WORKING_SIZE).unwrap();
+        assert!(fv_lib::verify_fv_header_checksum(&nv_storage));
+        // the header of an OVMF_VARS.fd of the same size, OvmfPkg/VarStore.fdf.inc
+        assert_eq!(nv_storage.pread::<u16>(0x32).unwrap(), 0xf919);
+        assert_eq!(nv_storage.pread::<u32>(0x20).unwrap(), 0x20000);
+        assert_eq!(nv_storage.pread::<u32>(0x38).unwrap(), 0x20);
+        assert_eq!(nv_storage.pread::<u32>(0x3c).unwrap(), 0x1000);
+        assert_eq!(nv_storage.pread::<u32>(0x58).unwrap(), 0xdfb8);
+        assert_eq!(&nv_storage[0x5c..0x60], &[0x5a, 0xfe, 0x00, 0x00]);
+        assert!(nv_storage[0x64..0xe000].iter().all(|b| *b == 0xff));
+
+        let working_block =
+            &nv_storage[VARIABLE_STORE_SIZE..VARIABLE_STORE_SIZE + FTW_WORKING_SIZE];
+        assert!(verify_ftw_working_block(working_block));
+        assert_eq!(working_block[0x14], 0xfe);
+        assert_eq!(working_block.pread::<u64>(0x18).unwrap(), 0x1fe0);
+
+        let store = VariableStore::parse(&nv_storage).unwrap();
+        assert!(store.is_authenticated());
+        assert_eq!(store.size(), 0xdfb8);
+        assert_eq!(store.iter().count(), 0);
+        assert_eq!(store.free_space_offset(), 0x1c);
+
+        // the spare block must hold the variable store and the working block
+        let mut small = vec![0u8; 0x10000];
+        assert_eq!(
+            format_nv_storage(&mut small, VARIABLE_STORE_SIZE, FTW_WORKING_SIZE),
+            Err(VariableError::InvalidStore)
+        );
+    }
+
+    #[test]
+    fn test_add_variable() {
+        let mut nv_storage = vec![0u8; 0x20000];
+        format_nv_storage(&mut nv_storage, VARIABLE_STORE_SIZE, FTW_WORKING_SIZE).unwrap();
+        let global = EFI_GLOBAL_VARIABLE_GUID.as_bytes();
+        let attributes = EFI_VARIABLE_NON_VOLATILE
+            | EFI_VARIABLE_BOOTSERVICE_ACCESS
+            | EFI_VARIABLE_RUNTIME_ACCESS;
+        add_variable(
+            &mut nv_storage,
+            "BootOrder",
+            global,
+            attributes,
+            &[0, 0, 1, 0],
+        )
+        .unwrap();
+        add_variable(&mut nv_storage, "Timeout", global, attributes, &[5, 0]).unwrap();
+        add_variable(&mut nv_storage, "Lang", &[0x5a; 16], attributes, b"eng").unwrap();
+        assert_eq!(
+            add_variable(&mut nv_storage, "Timeout", global, attributes, &[3, 0]),
+            Err(VariableError::InvalidParameter)
+        );
+        assert_eq!(
+            add_variable(&mut nv_storage, "Big", global, attributes, &[0u8; 0xe000]),
+            Err(VariableError::OutOfResources)
+        );
+
+        let store = VariableStore::parse(&nv_storage).unwrap();
+        assert_eq!(store.iter().count(), 3);
+        let boot_order = store.find_variable("BootOrder", global).unwrap();
+        assert_eq!(boot_order.data, &[0, 0, 1, 0]);
+        assert_eq!(boot_order.attributes, attributes);
+        assert_eq!(boot_order.state, VAR_ADDED);
+        assert_eq!(boot_order.name.len(), 20);
+        assert!(store.find_variable("BootOrder", &[0x5a; 16]).is_none());
+        assert_eq!(
+            store.find_variable("Lang", &[0x5a; 16]).unwrap().data,
+            b"eng"
+        );
+
+        // "BootOrder" at 0x1c: header 60, name 20, data 4 -> "Timeout" at 0x70
+        let timeout_offset = 0x48 + 0x70;
+        assert_eq!(
+            nv_storage.pread::<u16>(timeout_offset).unwrap(),
+            VARIABLE_DATA
+        );
+        // a deleted variable is skipped, the next one is still found
+        nv_storage[timeout_offset + 2] = VAR_ADDED & VAR_DELETED;
+        let store = VariableStore::parse(&nv_storage).unwrap();
+        assert!(store.find_variable("Timeout", global).is_none());
+        assert_eq!(store.iter_all().count(), 3);
+        assert_eq!(store.iter().count(), 2);
+        assert!(store.find_variable("Lang", &[0x5a; 16]).is_some());
+
+        // a corrupted FV header
+        nv_storage[0x28] ^= 1;
+        assert!(VariableStore::parse(&nv_storage).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+// UNCOMPRESSED_DATA is a PE32 section, it is compressed with UEFI and Tiano
+// compression, and with LZMA in the LZMA_Alone format used by the LZMA GUID
+// defined sections.
+
+#[allow(dead_code)]
+pub const UNCOMPRESSED_DATA: [u8; 408] = [
+    0x98, 0x01, 0x00, 0x10, 0x72, 0x75, 0x73, 0x74, 0x2D, 0x66, 0x69, 0x72, 0x6D, 0x77, 0x61, 0x72,
+    0x65, 0x3A, 0x20, 0x74, 0x68, 0x65, 0x20, 0x49, 0x50, 0x4C, 0x20, 0x6C, 0x6F, 0x61, 0x64, 0x73,
+    0x20, 0x74, 0x68, 0x65, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x66, 0x72, 0x6F,
+    0x6D, 0x20, 0x74, 0x68, 0x65, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x46, 0x56,
+    0x2E, 0x0A, 0x72, 0x75, 0x73, 0x74, 0x2D, 0x66, 0x69, 0x72, 0x6D, 0x77, 0x61, 0x72, 0x65, 0x3A,
+    0x20, 0x74, 0x68, 0x65, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x46, 0x56, 0x20,
+    0x6D, 0x61, 0x79, 0x20, 0x68, 0x6F, 0x6C, 0x64, 0x20, 0x61, 0x20, 0x63, 0x6F, 0x6D, 0x70, 0x72,
+    0x65, 0x73, 0x73, 0x65, 0x64, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x2E, 0x0A, 0x72,
+    0x75, 0x73, 0x74, 0x2D, 0x66, 0x69, 0x72, 0x6D, 0x77, 0x61, 0x72, 0x65, 0x3A, 0x20, 0x74, 0x68,
+    0x65, 0x20, 0x49, 0x50, 0x4C, 0x20, 0x64, 0x65, 0x63, 0x6F, 0x6D, 0x70, 0x72, 0x65, 0x73, 0x73,
+    0x65, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x74,
+    0x6F, 0x20, 0x74, 0x68, 0x65, 0x20, 0x72, 0x75, 0x6E, 0x74, 0x69, 0x6D, 0x65, 0x20, 0x70, 0x61,
+    0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x72, 0x65, 0x67, 0x69, 0x6F, 0x6E, 0x2E, 0x0A, 0x72, 0x75,
+    0x73, 0x74, 0x2D, 0x66, 0x69, 0x72, 0x6D, 0x77, 0x61, 0x72, 0x65, 0x3A, 0x20, 0x74, 0x68, 0x65,
+    0x20, 0x49, 0x50, 0x4C, 0x20, 0x6C, 0x6F, 0x61, 0x64, 0x73, 0x20, 0x74, 0x68, 0x65, 0x20, 0x70,
+    0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x66, 0x72, 0x6F, 0x6D, 0x20, 0x74, 0x68, 0x65, 0x20,
+    0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x46, 0x56, 0x2E, 0x0A, 0x72, 0x75, 0x73, 0x74,
+    0x2D, 0x66, 0x69, 0x72, 0x6D, 0x77, 0x61, 0x72, 0x65, 0x3A, 0x20, 0x74, 0x68, 0x65, 0x20, 0x70,
+    0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x46, 0x56, 0x20, 0x6D, 0x61, 0x79, 0x20, 0x68, 0x6F,
+    0x6C, 0x64, 0x20, 0x61, 0x20, 0x63, 0x6F, 0x6D, 0x70, 0x72, 0x65, 0x73, 0x73, 0x65, 0x64, 0x20,
+    0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x2E, 0x0A, 0x72, 0x75, 0x73, 0x74, 0x2D, 0x66, 0x69,
+    0x72, 0x6D, 0x77, 0x61, 0x72, 0x65, 0x3A, 0x20, 0x74, 0x68, 0x65, 0x20, 0x49, 0x50, 0x4C, 0x20,
+    0x64, 0x65, 0x63, 0x6F, 0x6D, 0x70, 0x72, 0x65, 0x73, 0x73, 0x65, 0x73, 0x20, 0x74, 0x68, 0x65,
+    0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20, 0x74, 0x6F, 0x20, 0x74, 0x68, 0x65, 0x20,
+    0x72, 0x75, 0x6E, 0x74, 0x69, 0x6D, 0x65, 0x20, 0x70, 0x61, 0x79, 0x6C, 0x6F, 0x61, 0x64, 0x20,
+    0x72, 0x65, 0x67, 0x69, 0x6F, 0x6E, 0x2E, 0x0A,
+];
+
+#[allow(dead_code)]
+pub const UEFI_COMPRESSED_DATA: [u8; 127] = [
+    0x77, 0x00, 0x00, 0x00, 0x98, 0x01, 0x00, 0x00, 0x00, 0x60, 0x52, 0x73, 0x8D, 0x3D, 0x8E, 0xD1,
+    0x74, 0x2D, 0x33, 0xE4, 0xED, 0x23, 0x48, 0xC1, 0x83, 0x40, 0xD0, 0xB4, 0x7F, 0x35, 0xF7, 0x34,
+    0x1F, 0x7D, 0x3E, 0xDA, 0x25, 0xE0, 0x53, 0xC5, 0x4B, 0x41, 0x68, 0x2C, 0x14, 0x60, 0x3C, 0x85,
+    0x64, 0x00, 0x1B, 0x49, 0x61, 0xCF, 0x97, 0x55, 0x83, 0x9F, 0xDA, 0xA6, 0xA9, 0xDE, 0x2A, 0x5E,
+    0x01, 0xF5, 0x90, 0x7A, 0xF9, 0xEC, 0x2C, 0x41, 0x63, 0xA7, 0xAD, 0xC3, 0x19, 0x68, 0x15, 0x2A,
+    0x1E, 0x7C, 0x3C, 0xFD, 0xEF, 0xD3, 0x53, 0xB4, 0xFC, 0x06, 0x31, 0x0A, 0xD2, 0xC6, 0x00, 0x87,
+    0xE4, 0x3B, 0x94, 0x9C, 0xE2, 0x66, 0x2A, 0x1B, 0x1C, 0x93, 0xD3, 0x0B, 0x21, 0x9A, 0x40, 0x5E,
+    0x93, 0x8F, 0x2C, 0x2D, 0x7B, 0x4E, 0x91, 0x85, 0x2F, 0xED, 0x4B, 0x77, 0x4E, 0xE2, 0x92,
+];
+
+#[allow(dead_code)]
+pub const TIANO_COMPRESSED_DATA: [u8; 127] = [
+    0x77, 0x00, 0x00, 0x00, 0x98, 0x01, 0x00, 0x00, 0x00, 0x60, 0x52, 0x73, 0x8D, 0x3D, 0x8E, 0xD1,
+    0x74, 0x2D, 0x33, 0xE4, 0xED, 0x23, 0x48, 0xC1, 0x83, 0x40, 0xD0, 0xB4, 0x7F, 0x35, 0xF7, 0x34,
+    0x1F, 0x7D, 0x3E, 0xDA, 0x25, 0xE0, 0x53, 0xC5, 0x4B, 0x41, 0x68, 0x2C, 0x14, 0x60, 0x3C, 0x85,
+    0x52, 0x00, 0x0D, 0xA4, 0xB0, 0xE7, 0xCB, 0xAA, 0xC1, 0xCF, 0xED, 0x53, 0x54, 0xEF, 0x15, 0x2F,
+    0x00, 0xFA, 0xC8, 0x3D, 0x7C, 0xF6, 0x16, 0x20, 0xB1, 0xD3, 0xD6, 0xE1, 0x8C, 0xB4, 0x0A, 0x95,
+    0x0F, 0x3E, 0x1E, 0x7E, 0xF7, 0xE9, 0xA9, 0xDA, 0x7E, 0x03, 0x18, 0x85, 0x69, 0x63, 0x00, 0x43,
+    0xF2, 0x1D, 0xCA, 0x4E, 0x71, 0x33, 0x15, 0x0D, 0x8E, 0x49, 0xE9, 0x85, 0x90, 0xCD, 0x20, 0x2F,
+    0x49, 0xC7, 0x96, 0x16, 0xBD, 0xA7, 0x48, 0xC2, 0x97, 0xF6, 0xA5, 0xBB, 0xA7, 0x71, 0x49,
+];
+
+#[allow(dead_code)]
+pub const LZMA_COMPRESSED_DATA: [u8; 131] = [
+    0x5D, 0x00, 0x00, 0x00, 0x04, 0x98, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4C, 0x00,
+    0x3C, 0x01, 0x14, 0x0E, 0x03, 0xFD, 0x58, 0x70, 0xB9, 0xBB, 0xC9, 0xE8, 0xB6, 0x75, 0xAF, 0x60,
+    0xA1, 0xCE, 0x30, 0x7B, 0x82, 0x2A, 0x39, 0xDF, 0x6B, 0x4B, 0x88, 0xD4, 0x16, 0x6A, 0x07, 0xD7,
+    0x0E, 0xC7, 0x29, 0x42, 0x10, 0x8B, 0x55, 0x1F, 0x7D, 0x6C, 0x86, 0xB9, 0x5C, 0x2C, 0x83, 0x20,
+    0x95, 0x9B, 0x39, 0xD8, 0x6A, 0x1C, 0x18, 0x0A, 0xF8, 0xA3, 0x82, 0xB2, 0x6D, 0x7B, 0x2A, 0xB8,
+    0x6F, 0xFC, 0x99, 0xF4, 0x98, 0xAB, 0xBB, 0xB6, 0x1F, 0x2D, 0xA5, 0xAF, 0xA0, 0x41, 0x8D, 0x94,
+    0x52, 0x10, 0xF8, 0x1A, 0x39, 0xE7, 0xAB, 0xA9, 0x43, 0xB7, 0xA1, 0xA0, 0x8C, 0x13, 0x0C, 0x56,
+    0xA4, 0x37, 0xA2, 0xB1, 0x6D, 0x94, 0x44, 0x40, 0x60, 0x4B, 0x0C, 0xCD, 0x69, 0x5F, 0xFF, 0xF8,
+    0x16, 0xB3, 0x27,
+];